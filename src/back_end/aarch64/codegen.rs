@@ -0,0 +1,924 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Eof,
+    Overflow,
+    Tape,
+};
+use back_end::{
+    Emitter,
+    Labels,
+    walk,
+    label_for,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+};
+use back_end::linux::{
+    PROT_READ_WRITE,
+    MAP_FLAGS,
+    RLIMIT_STACK,
+    STACK_CAP,
+};
+use super::{
+    Aarch64,
+    GUARD_SIZE,
+};
+use super::inst::{
+    Inst,
+    Reg,
+    Size,
+    Width,
+    Cond,
+    unscaled,
+};
+
+
+/// The pointer to the current cell.
+const TAPE: Reg = Reg::X(19);
+/// The first cell of a checked stack tape, or the start of a checked tape
+/// in memory.
+const START: Reg = Reg::X(20);
+/// The end of a checked tape in memory, or the lowest address a checked
+/// stack tape may use.
+const END: Reg = Reg::X(21);
+/// The I/O state, when buffered.
+const IO: Reg = Reg::X(22);
+/// Keeps the return address of routines which call other routines.
+const LINK: Reg = Reg::X(23);
+/// Scratch register of the helpers which build addresses and immediates.
+const SCRATCH: Reg = Reg::X(16);
+const LR: Reg = Reg::X(30);
+
+const TAPE_GROW: &str = "_tape_grow";
+const FAIL: &str = "_fail";
+/// Enters `_fail` past its flush, before the output buffer is set up.
+const FAIL_EARLY: &str = "_fail_early";
+const PUT: &str = "_put";
+const GET: &str = "_get";
+const FLUSH: &str = "_flush";
+const WRITE_FAILED: &str = "_write_failed";
+const READ_FAILED: &str = "_read_failed";
+
+/// Size of each I/O buffer.
+const IO_BUFFER: i64 = 4096;
+/// Offsets from `x22` of the I/O state: the length of the output buffer,
+/// the position and the length of the input buffer, and the buffers
+/// themselves.
+const IO_OUT_LEN: i32 = 0;
+const IO_IN_POS: i32 = 8;
+const IO_IN_LEN: i32 = 16;
+const IO_OUT: i64 = 32;
+const IO_IN: i64 = IO_OUT + IO_BUFFER;
+const IO_END: i64 = IO_IN + IO_BUFFER;
+
+/// Generates a standalone program starting at `_start`, with the tape on
+/// the stack or in a region given by `mmap`, see `Tape`, and I/O done
+/// through system calls. The pointer lives in `x19`, and the bounds of a
+/// checked tape in `x20` and `x21`.
+#[derive(Clone, Debug)]
+pub struct Codegen {
+    config: Config,
+    insts: Vec<Inst>,
+    labels: Labels,
+    /// Whether `_fail` is called.
+    fails: bool,
+    /// Whether the program writes, through `_put` if buffered.
+    puts: bool,
+    /// Whether the program reads, through `_get` if buffered.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator. The configuration must be supported, see
+    /// `Aarch64::check`.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            insts: Vec::new(),
+            labels: Labels::default(),
+            fails: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    /// Width of the cells in memory.
+    fn cw(&self) -> Width {
+        Aarch64::cell_width(self.config.cell_size)
+    }
+
+    /// Size of the registers holding cells.
+    fn cs(&self) -> Size {
+        self.cw().size()
+    }
+
+    /// Size of a cell in bytes.
+    fn stride(&self) -> i64 {
+        self.config.cell_size.bytes() as i64
+    }
+
+    /// Whether the tape is on the stack, which grows downwards with it.
+    fn on_stack(&self) -> bool {
+        self.config.tape == Tape::Stack
+    }
+
+    /// Whether the tape grows at both ends.
+    fn growable(&self) -> bool {
+        self.config.tape.bidirectional()
+    }
+
+    /// Whether the pointer is checked against both ends of the tape, which
+    /// are kept in `x20` and `x21`. The first cell of a stack tape is kept in
+    /// `x20` instead, when checked.
+    fn checked(&self) -> bool {
+        !self.on_stack() && (self.growable() || self.config.checked)
+    }
+
+    /// Whether I/O goes through the buffers, whose state is pointed by
+    /// `x22`.
+    fn buffered(&self) -> bool {
+        !self.config.unbuffered
+    }
+
+    /// The displacement of a cell from the pointer. Cells follow the stack
+    /// downwards when the tape is on the stack, and go upwards otherwise.
+    fn disp(&self, offset: i64) -> i64 {
+        if self.on_stack() {-offset * self.stride()} else {offset * self.stride()}
+    }
+
+    /// Moves an immediate into a register, with as few instructions as the
+    /// wide moves allow.
+    fn mov_imm(&mut self, size: Size, reg: Reg, value: i64) {
+        let count = if size == Size::X {4} else {2};
+        let parts: Vec<u16> = (0 .. count)
+            .map(|i| (value >> (16 * i)) as u16)
+            .collect();
+        let zeros = parts.iter().filter(|&&p| p == 0).count();
+        let ones = parts.iter().filter(|&&p| p == 0xFFFF).count();
+        let (skip, first) = if ones > zeros {(0xFFFF, true)} else {(0, false)};
+        let mut started = false;
+        for (i, &part) in parts.iter().enumerate() {
+            let shift = 16 * i as u8;
+            if part == skip {
+                continue;
+            }
+            self.emit(if started {
+                Inst::Movk(size, reg, part, shift)
+            } else if first {
+                Inst::Movn(size, reg, !part, shift)
+            } else {
+                Inst::Movz(size, reg, part, shift)
+            });
+            started = true;
+        }
+        if !started {
+            self.emit(if first {
+                Inst::Movn(size, reg, 0, 0)
+            } else {
+                Inst::Movz(size, reg, 0, 0)
+            });
+        }
+    }
+
+    /// Adds a signed immediate to a 64-bit register, through `x16` if it
+    /// does not fit in 24 bits.
+    fn add_imm(&mut self, rd: Reg, rn: Reg, value: i64) {
+        let magnitude = value.unsigned_abs();
+        if magnitude >= 1 << 24 {
+            self.mov_imm(Size::X, SCRATCH, value);
+            self.emit(Inst::AddReg(Size::X, rd, rn, SCRATCH));
+            return;
+        }
+        let high = (magnitude >> 12) as u16;
+        let low = (magnitude & 0xFFF) as u16;
+        let op = if value < 0 {Inst::SubImm} else {Inst::AddImm};
+        if high != 0 {
+            self.emit(op(Size::X, rd, rn, high, true));
+        }
+        if low != 0 || high == 0 {
+            let rn = if high != 0 {rd} else {rn};
+            if low != 0 || rd != rn {
+                self.emit(op(Size::X, rd, rn, low, false));
+            }
+        }
+    }
+
+    /// Compares a register against an immediate, through `x16` if it does
+    /// not fit in 12 bits.
+    fn cmp_imm(&mut self, size: Size, reg: Reg, value: i64) {
+        if (0 .. 4096).contains(&value) {
+            self.emit(Inst::CmpImm(size, reg, value as u16));
+        } else {
+            self.mov_imm(size, SCRATCH, value);
+            self.emit(Inst::CmpReg(size, reg, SCRATCH));
+        }
+    }
+
+    /// The base register and displacement of a cell, computing its address
+    /// into `x16` if it is too far for a load or a store.
+    fn cell(&mut self, offset: i64) -> (Reg, i32) {
+        let disp = self.disp(offset);
+        let cw = self.cw();
+        if disp == disp as i32 as i64 && (cw.scaled(disp as i32) || unscaled(disp as i32)) {
+            (TAPE, disp as i32)
+        } else {
+            self.add_imm(SCRATCH, TAPE, disp);
+            (SCRATCH, 0)
+        }
+    }
+
+    /// Loads a cell, zero extended, into a register.
+    fn load(&mut self, reg: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Ldr(self.cw(), reg, base, disp));
+    }
+
+    fn store(&mut self, reg: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Str(self.cw(), reg, base, disp));
+    }
+
+    /// Puts the address of a cell into a register.
+    fn cell_addr(&mut self, reg: Reg, offset: i64) {
+        let disp = self.disp(offset);
+        self.add_imm(reg, TAPE, disp);
+    }
+
+    /// Emits a system call, whose arguments must be in `x0` to `x5` already.
+    fn syscall(&mut self, number: i64) {
+        self.mov_imm(Size::X, Reg::X(8), number);
+        self.emit(Inst::Svc(0));
+    }
+
+    /// Reports `message` and exits with status 1 if the last system call
+    /// failed, that is, if it returned a value from -4095 to -1. Calls made
+    /// while the program starts fail through `_fail_early`.
+    fn check_syscall(&mut self, label: &str, message: &str, early: bool) {
+        self.mov_imm(Size::X, SCRATCH, -4095);
+        self.emit(Inst::CmpReg(Size::X, Reg::X(0), SCRATCH));
+        self.emit(Inst::BCond(Cond::Lo, String::from(label)));
+        let entry = if early {FAIL_EARLY} else {FAIL};
+        self.report_at(entry, IO_EXIT, String::from(message));
+        self.emit(Inst::Label(String::from(label)));
+    }
+
+    /// Maps `x1` bytes, readable and writable if `prot` is set.
+    fn mmap(&mut self, prot: bool) {
+        self.mov_imm(Size::X, Reg::X(0), 0);
+        self.mov_imm(Size::X, Reg::X(2), if prot {PROT_READ_WRITE} else {0});
+        self.mov_imm(Size::X, Reg::X(3), MAP_FLAGS);
+        self.mov_imm(Size::X, Reg::X(4), -1);
+        self.mov_imm(Size::X, Reg::X(5), 0);
+        self.syscall(Aarch64::SYS_MMAP);
+    }
+
+    /// Keeps the lowest address a checked stack tape may use. The arguments
+    /// and the environment take at most a quarter of the stack size limit,
+    /// so half of it is left for the tape.
+    fn limit_stack(&mut self) {
+        let x = |n| Reg::X(n);
+        let capped = String::from("_stack_capped");
+        // The current and the maximum limit are written on the stack.
+        self.emit(Inst::StpPre(Reg::Zr, Reg::Zr, Reg::Sp, -16));
+        self.mov_imm(Size::X, x(0), 0);
+        self.mov_imm(Size::X, x(1), RLIMIT_STACK);
+        self.mov_imm(Size::X, x(2), 0);
+        self.emit(Inst::Mov(Size::X, x(3), Reg::Sp));
+        self.syscall(Aarch64::SYS_PRLIMIT64);
+        self.check_syscall("_prlimit_ok", "Could not read the stack size limit.\n", true);
+        self.emit(Inst::Ldr(Width::Double, x(0), Reg::Sp, 0));
+        self.add_imm(Reg::Sp, Reg::Sp, 16);
+        self.mov_imm(Size::X, x(1), STACK_CAP);
+        self.emit(Inst::CmpReg(Size::X, x(0), x(1)));
+        self.emit(Inst::BCond(Cond::Ls, capped.clone()));
+        self.emit(Inst::Mov(Size::X, x(0), x(1)));
+        self.emit(Inst::Label(capped));
+        self.emit(Inst::Lsr(Size::X, x(0), x(0), 1));
+        self.emit(Inst::Mov(Size::X, x(1), Reg::Sp));
+        self.emit(Inst::SubReg(Size::X, END, x(1), x(0)));
+    }
+
+    /// Maps the heap tape with a guard region at each end, which are left
+    /// inaccessible, and points to its first cell. When checked, its bounds
+    /// are kept.
+    fn map_tape(&mut self, cells: u64) {
+        let bytes = Aarch64::heap_bytes(cells, self.config.cell_size) as i64;
+        self.mov_imm(Size::X, Reg::X(1), bytes + 2 * GUARD_SIZE);
+        self.mmap(false);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.add_imm(TAPE, Reg::X(0), GUARD_SIZE);
+        self.emit(Inst::Mov(Size::X, Reg::X(0), TAPE));
+        self.mov_imm(Size::X, Reg::X(1), bytes);
+        self.mov_imm(Size::X, Reg::X(2), PROT_READ_WRITE);
+        self.syscall(Aarch64::SYS_MPROTECT);
+        self.check_syscall("_mprotect_ok", "Could not map the tape.\n", true);
+        if self.config.checked {
+            // The end of the last cell, not of its page.
+            let end = cells as i64 * self.stride();
+            self.emit(Inst::Mov(Size::X, START, TAPE));
+            self.add_imm(END, TAPE, end);
+        }
+    }
+
+    /// Maps the first region of a bidirectional tape, keeps its bounds and
+    /// points to its middle.
+    fn map_growable(&mut self, cells: u64) {
+        let bytes = Aarch64::heap_bytes(cells, self.config.cell_size) as i64;
+        self.mov_imm(Size::X, Reg::X(1), bytes);
+        self.mmap(true);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.emit(Inst::Mov(Size::X, START, Reg::X(0)));
+        self.add_imm(END, START, bytes);
+        self.add_imm(TAPE, START, bytes / 2);
+    }
+
+    /// Emits the `_tape_grow` routine, called with the address of a cell out
+    /// of a bidirectional tape in `x0`. It maps a region three times as big
+    /// as the tape, moves the tape to its middle and frees the old one,
+    /// until the cell is in the tape. The pointer is moved along.
+    fn tape_grow(&mut self) {
+        let x = |n| Reg::X(n);
+        let again = String::from("_tape_grow_again");
+        let copy = String::from("_tape_grow_copy");
+        self.emit(Inst::Label(String::from(TAPE_GROW)));
+        self.emit(Inst::Mov(Size::X, x(10), x(0)));
+        self.emit(Inst::Label(again.clone()));
+        self.emit(Inst::SubReg(Size::X, x(9), END, START));
+        self.emit(Inst::AddReg(Size::X, x(1), x(9), x(9)));
+        self.emit(Inst::AddReg(Size::X, x(1), x(1), x(9)));
+        self.mmap(true);
+        self.check_syscall("_tape_grow_ok", "Could not grow the tape.\n", false);
+        self.emit(Inst::AddReg(Size::X, x(2), x(0), x(9)));
+        self.emit(Inst::Mov(Size::X, x(1), START));
+        self.emit(Inst::Mov(Size::X, x(3), x(9)));
+        self.emit(Inst::Label(copy.clone()));
+        self.emit(Inst::LdrPost(Width::Double, x(4), x(1), 8));
+        self.emit(Inst::StrPost(Width::Double, x(4), x(2), 8));
+        self.emit(Inst::SubImm(Size::X, x(3), x(3), 8, false));
+        self.emit(Inst::Cbnz(Size::X, x(3), copy));
+        // How far the cells moved.
+        self.emit(Inst::AddReg(Size::X, x(2), x(0), x(9)));
+        self.emit(Inst::SubReg(Size::X, x(2), x(2), START));
+        self.emit(Inst::AddReg(Size::X, TAPE, TAPE, x(2)));
+        self.emit(Inst::AddReg(Size::X, x(10), x(10), x(2)));
+        self.emit(Inst::Mov(Size::X, x(11), x(0)));
+        self.emit(Inst::Mov(Size::X, x(0), START));
+        self.emit(Inst::Mov(Size::X, x(1), x(9)));
+        self.syscall(Aarch64::SYS_MUNMAP);
+        self.emit(Inst::Mov(Size::X, START, x(11)));
+        self.emit(Inst::AddReg(Size::X, END, x(11), x(9)));
+        self.emit(Inst::AddReg(Size::X, END, END, x(9)));
+        self.emit(Inst::AddReg(Size::X, END, END, x(9)));
+        self.emit(Inst::CmpReg(Size::X, x(10), START));
+        self.emit(Inst::BCond(Cond::Lo, again.clone()));
+        self.emit(Inst::CmpReg(Size::X, x(10), END));
+        self.emit(Inst::BCond(Cond::Hs, again));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_fail` routine, called right before a message laid out as
+    /// the exit status in one byte, a padding byte, the length of the text in
+    /// two bytes, and the text. It writes the text to stderr and exits.
+    fn fail(&mut self) {
+        let x = |n| Reg::X(n);
+        self.emit(Inst::Label(String::from(FAIL)));
+        // What was written before is shown first.
+        if self.buffered() && self.puts {
+            self.emit(Inst::Mov(Size::X, LINK, LR));
+            self.emit(Inst::Bl(String::from(FLUSH)));
+            self.emit(Inst::Mov(Size::X, LR, LINK));
+        }
+        self.emit(Inst::Label(String::from(FAIL_EARLY)));
+        self.emit(Inst::Mov(Size::X, LINK, LR));
+        self.mov_imm(Size::X, x(0), 2);
+        self.emit(Inst::AddImm(Size::X, x(1), LINK, 4, false));
+        self.emit(Inst::Ldr(Width::Half, x(2), LINK, 2));
+        self.syscall(Aarch64::SYS_WRITE);
+        self.emit(Inst::Ldr(Width::Byte, x(0), LINK, 0));
+        self.syscall(Aarch64::SYS_EXIT);
+    }
+
+    /// Stops the program with the given exit status and message, through
+    /// `_fail`.
+    fn report(&mut self, status: i64, message: String) {
+        self.report_at(FAIL, status, message);
+    }
+
+    /// Stops the program through the given entry of `_fail`.
+    fn report_at(&mut self, entry: &str, status: i64, message: String) {
+        let mut data = vec![status as u8, 0];
+        data.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        data.extend_from_slice(message.as_bytes());
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        self.emit(Inst::Bl(String::from(entry)));
+        self.emit(Inst::Data(data));
+        self.fails = true;
+    }
+
+    /// Reports that the pointer left the tape at `loc` and exits.
+    fn report_bounds(&mut self, loc: &Location) {
+        let message = format!("Tape pointer out of bounds {}.\n", loc);
+        self.report(BOUNDS_EXIT, message);
+    }
+
+    /// Reports that the pointer left the tape, unless `ok` holds after the
+    /// comparison emitted before.
+    fn out_of_bounds(&mut self, ok: Cond, loc: &Location) {
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.emit(Inst::BCond(ok, label.clone()));
+        self.report_bounds(loc);
+        self.emit(Inst::Label(label));
+    }
+
+    /// Grows a bidirectional tape if the cell at `offset` is out of it, or
+    /// reports the error if the tape cannot grow. The comparison of its
+    /// address against the bound must have been emitted already, and `ok` is
+    /// the condition under which the cell is fine.
+    fn bounds(&mut self, ok: Cond, offset: i64, loc: &Location) {
+        if !self.growable() {
+            self.out_of_bounds(ok, loc);
+            return;
+        }
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.emit(Inst::BCond(ok, label.clone()));
+        self.cell_addr(Reg::X(0), offset);
+        self.emit(Inst::Bl(String::from(TAPE_GROW)));
+        self.emit(Inst::Label(label));
+    }
+
+    /// Emits the `_flush` routine, which writes the whole output buffer.
+    fn flush(&mut self) {
+        let x = |n| Reg::X(n);
+        let again = String::from("_flush_again");
+        let done = String::from("_flush_done");
+        self.emit(Inst::Label(String::from(FLUSH)));
+        self.add_imm(x(1), IO, IO_OUT);
+        self.emit(Inst::Label(again.clone()));
+        self.emit(Inst::Ldr(Width::Double, x(2), IO, IO_OUT_LEN));
+        self.emit(Inst::Cbz(Size::X, x(2), done.clone()));
+        self.mov_imm(Size::X, x(0), 1);
+        self.syscall(Aarch64::SYS_WRITE);
+        self.emit(Inst::CmpImm(Size::X, x(0), 0));
+        self.emit(Inst::BCond(Cond::Le, String::from(WRITE_FAILED)));
+        self.emit(Inst::AddReg(Size::X, x(1), x(1), x(0)));
+        self.emit(Inst::Ldr(Width::Double, x(2), IO, IO_OUT_LEN));
+        self.emit(Inst::SubReg(Size::X, x(2), x(2), x(0)));
+        self.emit(Inst::Str(Width::Double, x(2), IO, IO_OUT_LEN));
+        self.emit(Inst::B(again));
+        self.emit(Inst::Label(done));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_put` routine, which appends the byte in `w0` to the output
+    /// buffer, flushing it on newlines and when full.
+    fn put(&mut self) {
+        let x = |n| Reg::X(n);
+        self.emit(Inst::Label(String::from(PUT)));
+        self.emit(Inst::Ldr(Width::Double, x(9), IO, IO_OUT_LEN));
+        self.add_imm(x(10), IO, IO_OUT);
+        self.emit(Inst::AddReg(Size::X, x(10), x(10), x(9)));
+        self.emit(Inst::Str(Width::Byte, x(0), x(10), 0));
+        self.emit(Inst::AddImm(Size::X, x(9), x(9), 1, false));
+        self.emit(Inst::Str(Width::Double, x(9), IO, IO_OUT_LEN));
+        self.emit(Inst::Ldr(Width::Byte, x(11), x(10), 0));
+        self.emit(Inst::CmpImm(Size::W, x(11), b'\n' as u16));
+        self.emit(Inst::BCond(Cond::Eq, String::from(FLUSH)));
+        self.emit(Inst::CmpImm(Size::X, x(9), (IO_BUFFER - 1) as u16));
+        self.emit(Inst::BCond(Cond::Hi, String::from(FLUSH)));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_get` routine, which takes the next byte of the input
+    /// buffer into `x0`, or -1 at the end of the input. The output is
+    /// flushed before the buffer is filled again.
+    fn get(&mut self) {
+        let x = |n| Reg::X(n);
+        let byte = String::from("_get_byte");
+        let eof = String::from("_get_eof");
+        self.emit(Inst::Label(String::from(GET)));
+        self.emit(Inst::Ldr(Width::Double, x(9), IO, IO_IN_POS));
+        self.emit(Inst::Ldr(Width::Double, x(10), IO, IO_IN_LEN));
+        self.emit(Inst::CmpReg(Size::X, x(9), x(10)));
+        self.emit(Inst::BCond(Cond::Lo, byte.clone()));
+        self.emit(Inst::Mov(Size::X, LINK, LR));
+        self.emit(Inst::Bl(String::from(FLUSH)));
+        self.emit(Inst::Mov(Size::X, LR, LINK));
+        self.mov_imm(Size::X, x(0), 0);
+        self.add_imm(x(1), IO, IO_IN);
+        self.mov_imm(Size::X, x(2), IO_BUFFER);
+        self.syscall(Aarch64::SYS_READ);
+        self.emit(Inst::CmpImm(Size::X, x(0), 0));
+        self.emit(Inst::BCond(Cond::Lt, String::from(READ_FAILED)));
+        self.emit(Inst::BCond(Cond::Eq, eof.clone()));
+        self.emit(Inst::Str(Width::Double, x(0), IO, IO_IN_LEN));
+        self.mov_imm(Size::X, x(9), 0);
+        self.emit(Inst::Label(byte));
+        self.add_imm(x(10), IO, IO_IN);
+        self.emit(Inst::AddReg(Size::X, x(10), x(10), x(9)));
+        self.emit(Inst::Ldr(Width::Byte, x(0), x(10), 0));
+        self.emit(Inst::AddImm(Size::X, x(9), x(9), 1, false));
+        self.emit(Inst::Str(Width::Double, x(9), IO, IO_IN_POS));
+        self.emit(Inst::Ret);
+        self.emit(Inst::Label(eof));
+        self.mov_imm(Size::X, x(0), -1);
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits a routine reporting that I/O failed. The output buffer is
+    /// dropped, so that `_fail` does not write it again.
+    fn io_failed(&mut self, label: &str, message: &str) {
+        self.emit(Inst::Label(String::from(label)));
+        if self.buffered() {
+            self.emit(Inst::Str(Width::Double, Reg::Zr, IO, IO_OUT_LEN));
+        }
+        self.report(IO_EXIT, String::from(message));
+    }
+
+    fn prologue(&mut self) {
+        if self.on_stack() && self.config.checked {
+            self.limit_stack();
+        }
+        if self.buffered() {
+            self.add_imm(Reg::Sp, Reg::Sp, -IO_END);
+            self.emit(Inst::Mov(Size::X, IO, Reg::Sp));
+            for &field in [IO_OUT_LEN, IO_IN_POS, IO_IN_LEN].iter() {
+                self.emit(Inst::Str(Width::Double, Reg::Zr, IO, field));
+            }
+        }
+        match self.config.tape {
+            Tape::Stack => {
+                // The first cell is at the top of a zeroed pair of words.
+                self.emit(Inst::StpPre(Reg::Zr, Reg::Zr, Reg::Sp, -16));
+                self.add_imm(TAPE, Reg::Sp, 16 - self.stride());
+                if self.config.checked {
+                    self.emit(Inst::Mov(Size::X, START, TAPE));
+                }
+            },
+            Tape::Heap(cells) => self.map_tape(cells),
+            Tape::Bidirectional(cells) => self.map_growable(cells),
+        }
+    }
+
+    fn epilogue(&mut self) {
+        self.exit();
+        if self.growable() {
+            self.tape_grow();
+        }
+        let buffered = self.buffered();
+        if buffered && (self.puts || self.gets) {
+            self.flush();
+        }
+        if buffered && self.puts {
+            self.put();
+        }
+        if buffered && self.gets {
+            self.get();
+        }
+        // `_flush` is also called before reading.
+        if self.puts || (buffered && self.gets) {
+            self.io_failed(WRITE_FAILED, "Could not write the output.\n");
+        }
+        if self.gets {
+            self.io_failed(READ_FAILED, "Could not read the input.\n");
+        }
+        if self.fails {
+            self.fail();
+        }
+    }
+
+    fn exit(&mut self) {
+        if self.buffered() && self.puts {
+            self.emit(Inst::Bl(String::from(FLUSH)));
+        }
+        if self.config.exit_cell {
+            self.load(Reg::X(0), 0);
+        } else {
+            self.mov_imm(Size::X, Reg::X(0), 0);
+        }
+        self.syscall(Aarch64::SYS_EXIT);
+    }
+
+    fn next(&mut self, n: i64, loc: &Location) {
+        self.shift(n, loc);
+        if self.on_stack() {
+            let start = self.labels.fresh(loc, "check_sp_start");
+            let end = self.labels.fresh(loc, "check_sp_end");
+            if self.config.checked {
+                self.emit(Inst::CmpReg(Size::X, TAPE, END));
+                self.out_of_bounds(Cond::Hs, loc);
+            }
+            self.emit(Inst::B(end.clone()));
+            self.emit(Inst::Label(start.clone()));
+            self.emit(Inst::StpPre(Reg::Zr, Reg::Zr, Reg::Sp, -16));
+            self.emit(Inst::Label(end));
+            self.emit(Inst::Mov(Size::X, Reg::X(10), Reg::Sp));
+            self.emit(Inst::CmpReg(Size::X, TAPE, Reg::X(10)));
+            self.emit(Inst::BCond(Cond::Lo, start));
+        } else if self.checked() {
+            self.emit(Inst::CmpReg(Size::X, TAPE, END));
+            self.bounds(Cond::Lo, 0, loc);
+        }
+    }
+
+    fn previous(&mut self, n: i64, loc: &Location) {
+        self.shift(-n, loc);
+        if self.on_stack() && self.config.checked {
+            self.emit(Inst::CmpReg(Size::X, TAPE, START));
+            self.out_of_bounds(Cond::Ls, loc);
+        } else if self.checked() {
+            self.emit(Inst::CmpReg(Size::X, TAPE, START));
+            self.bounds(Cond::Hs, 0, loc);
+        }
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// is found by comparing the cell against the largest value the addition
+    /// keeps in range, or against the amount of a subtraction.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let x = |n| Reg::X(n);
+        let cs = self.cs();
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let limit = if amount < 0 {0} else {mask as i64};
+        if magnitude == 0 {
+            return;
+        }
+        if magnitude > mask {
+            self.overflow(limit, offset, loc, None);
+            return;
+        }
+        let ok = self.labels.fresh(loc, "add_ok");
+        let store = self.labels.fresh(loc, "add_store");
+        self.load(x(0), offset);
+        if amount < 0 {
+            self.cmp_imm(cs, x(0), magnitude as i64);
+            self.emit(Inst::BCond(Cond::Hs, ok.clone()));
+        } else {
+            self.cmp_imm(cs, x(0), (mask - magnitude) as i64);
+            self.emit(Inst::BCond(Cond::Ls, ok.clone()));
+        }
+        self.overflow(limit, offset, loc, Some(store.clone()));
+        self.emit(Inst::Label(ok));
+        if magnitude < 4096 {
+            let op = if amount < 0 {Inst::SubImm} else {Inst::AddImm};
+            self.emit(op(cs, x(0), x(0), magnitude as u16, false));
+        } else {
+            self.mov_imm(cs, x(1), magnitude as i64);
+            let op = if amount < 0 {Inst::SubReg} else {Inst::AddReg};
+            self.emit(op(cs, x(0), x(0), x(1)));
+        }
+        self.emit(Inst::Label(store));
+        self.store(x(0), offset);
+    }
+
+    /// Handles an overflowing cell, which saturates to `limit` or traps. A
+    /// saturated value is stored at once, or left in `x0` for the store at
+    /// the given label.
+    fn overflow(&mut self, limit: i64, offset: i64, loc: &Location, store: Option<String>) {
+        if self.config.overflow == Overflow::Trap {
+            let message = format!("Cell overflow {}.\n", loc);
+            self.report(OVERFLOW_EXIT, message);
+            return;
+        }
+        let cs = self.cs();
+        self.mov_imm(cs, Reg::X(0), limit);
+        match store {
+            Some(label) => self.emit(Inst::B(label)),
+            _ => self.store(Reg::X(0), offset),
+        }
+    }
+
+    /// Generates the instructions for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> Vec<Inst> {
+        self.prologue();
+        walk(&mut self, ir);
+        self.epilogue();
+        self.insts
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    /// The labels of the start and of the end of the loop.
+    type Loop = (String, String);
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of the tape are only detected by checked tapes, and the guard
+    /// regions of a heap tape fault on cells past either end.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        let addr = Reg::X(9);
+        if self.on_stack() && offset > 0 {
+            let start = self.labels.fresh(loc, "reach_start");
+            let end = self.labels.fresh(loc, "reach_end");
+            self.cell_addr(addr, offset);
+            if self.config.checked {
+                self.emit(Inst::CmpReg(Size::X, addr, END));
+                self.out_of_bounds(Cond::Hs, loc);
+            }
+            self.emit(Inst::B(end.clone()));
+            self.emit(Inst::Label(start.clone()));
+            self.emit(Inst::StpPre(Reg::Zr, Reg::Zr, Reg::Sp, -16));
+            self.emit(Inst::Label(end));
+            self.emit(Inst::Mov(Size::X, Reg::X(10), Reg::Sp));
+            self.emit(Inst::CmpReg(Size::X, addr, Reg::X(10)));
+            self.emit(Inst::BCond(Cond::Lo, start));
+        } else if self.on_stack() && self.config.checked && offset < 0 {
+            self.cell_addr(addr, offset);
+            self.emit(Inst::CmpReg(Size::X, addr, START));
+            self.out_of_bounds(Cond::Ls, loc);
+        } else if self.checked() && offset != 0 {
+            self.cell_addr(addr, offset);
+            if offset > 0 {
+                self.emit(Inst::CmpReg(Size::X, addr, END));
+                self.bounds(Cond::Lo, offset, loc);
+            } else {
+                self.emit(Inst::CmpReg(Size::X, addr, START));
+                self.bounds(Cond::Hs, offset, loc);
+            }
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, _loc: &Location) {
+        let disp = self.disp(n);
+        self.add_imm(TAPE, TAPE, disp);
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        if n > 0 {
+            self.next(n, loc);
+        } else if n < 0 {
+            self.previous(-n, loc);
+        }
+    }
+
+    /// Moves by `n` cells until a zero cell is found.
+    fn scan(&mut self, n: i64, loc: &Location) {
+        let body = self.labels.fresh(loc, "scan_body");
+        let test = self.labels.fresh(loc, "scan_test");
+        self.emit(Inst::B(test.clone()));
+        self.emit(Inst::Label(body.clone()));
+        self.move_by(n, loc);
+        self.emit(Inst::Label(test));
+        self.load(Reg::X(0), 0);
+        self.emit(Inst::Cbnz(self.cs(), Reg::X(0), body));
+    }
+
+    fn put_char(&mut self, offset: i64, _loc: &Location) {
+        let x = |n| Reg::X(n);
+        if self.buffered() {
+            self.load(x(0), offset);
+            self.emit(Inst::Bl(String::from(PUT)));
+        } else {
+            self.cell_addr(x(1), offset);
+            self.mov_imm(Size::X, x(0), 1);
+            self.mov_imm(Size::X, x(2), 1);
+            self.syscall(Aarch64::SYS_WRITE);
+            self.emit(Inst::CmpImm(Size::X, x(0), 1));
+            self.emit(Inst::BCond(Cond::Ne, String::from(WRITE_FAILED)));
+        }
+        self.puts = true;
+    }
+
+    fn get_char(&mut self, offset: i64, loc: &Location) {
+        let x = |n| Reg::X(n);
+        let cs = self.cs();
+        let end = self.labels.fresh(loc, "getc_end");
+        self.gets = true;
+        if self.buffered() {
+            self.emit(Inst::Bl(String::from(GET)));
+            match self.config.eof {
+                Eof::MinusOne => (),
+                Eof::Zero => {
+                    self.emit(Inst::CmpImm(Size::X, x(0), 0));
+                    self.emit(Inst::BCond(Cond::Ge, end.clone()));
+                    self.mov_imm(Size::X, x(0), 0);
+                    self.emit(Inst::Label(end.clone()));
+                },
+                Eof::Unchanged => {
+                    self.emit(Inst::CmpImm(Size::X, x(0), 0));
+                    self.emit(Inst::BCond(Cond::Lt, end.clone()));
+                },
+            }
+            self.store(x(0), offset);
+            if self.config.eof == Eof::Unchanged {
+                self.emit(Inst::Label(end));
+            }
+            return;
+        }
+        // The byte read only fills the lowest byte of the cell.
+        if self.config.eof != Eof::Unchanged {
+            self.store(Reg::Zr, offset);
+        }
+        self.cell_addr(x(1), offset);
+        self.mov_imm(Size::X, x(0), 0);
+        self.mov_imm(Size::X, x(2), 1);
+        self.syscall(Aarch64::SYS_READ);
+        self.emit(Inst::CmpImm(Size::X, x(0), 0));
+        self.emit(Inst::BCond(Cond::Lt, String::from(READ_FAILED)));
+        match self.config.eof {
+            Eof::MinusOne => {
+                self.emit(Inst::BCond(Cond::Ne, end.clone()));
+                self.mov_imm(cs, x(0), -1);
+                self.store(x(0), offset);
+            },
+            Eof::Zero => (),
+            Eof::Unchanged => if self.cw() != Width::Byte {
+                self.emit(Inst::BCond(Cond::Eq, end.clone()));
+                self.emit(Inst::Ldr(Width::Byte, x(0), x(1), 0));
+                self.store(x(0), offset);
+            },
+        }
+        self.emit(Inst::Label(end));
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let x = |n| Reg::X(n);
+        let cs = self.cs();
+        let amount = self.config.cell_size.wrap(amount);
+        if amount == 0 {
+            return;
+        }
+        self.load(x(0), offset);
+        let magnitude = amount.unsigned_abs();
+        if magnitude < 4096 {
+            let op = if amount < 0 {Inst::SubImm} else {Inst::AddImm};
+            self.emit(op(cs, x(0), x(0), magnitude as u16, false));
+        } else {
+            self.mov_imm(cs, x(1), amount);
+            self.emit(Inst::AddReg(cs, x(0), x(0), x(1)));
+        }
+        self.store(x(0), offset);
+    }
+
+    fn set(&mut self, offset: i64, value: i64, _loc: &Location) {
+        let value = self.config.cell_size.wrap(value);
+        if value == 0 {
+            self.store(Reg::Zr, offset);
+        } else {
+            let cs = self.cs();
+            self.mov_imm(cs, Reg::X(0), value);
+            self.store(Reg::X(0), offset);
+        }
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, _loc: &Location) {
+        let x = |n| Reg::X(n);
+        let cs = self.cs();
+        let factor = self.config.cell_size.wrap(factor);
+        if factor == 0 {
+            return;
+        }
+        self.load(x(0), src);
+        self.load(x(1), dst);
+        match factor {
+            1 => self.emit(Inst::AddReg(cs, x(1), x(1), x(0))),
+            -1 => self.emit(Inst::SubReg(cs, x(1), x(1), x(0))),
+            _ => {
+                self.mov_imm(cs, x(2), factor);
+                self.emit(Inst::Madd(cs, x(1), x(0), x(2), x(1)));
+            },
+        }
+        self.store(x(1), dst);
+    }
+
+    fn open_loop(&mut self, loc: &Location) -> (String, String) {
+        let label = label_for(loc);
+        let start = label.clone() + "_loop_start";
+        let end = label + "_loop_end";
+        self.emit(Inst::B(end.clone()));
+        self.emit(Inst::Label(start.clone()));
+        (start, end)
+    }
+
+    fn close_loop(&mut self, (start, end): (String, String)) {
+        self.emit(Inst::Label(end));
+        self.load(Reg::X(0), 0);
+        self.emit(Inst::Cbnz(self.cs(), Reg::X(0), start));
+    }
+
+}
@@ -0,0 +1,434 @@
+use std::io::{
+    Error,
+    ErrorKind,
+};
+use std::collections::{
+    HashMap,
+};
+use super::inst::{
+    Inst,
+    Reg,
+    Size,
+    Width,
+    unscaled,
+};
+
+
+/// Machine code produced from a list of instructions, together with the
+/// offset of every label defined by it.
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn size(inst: &Inst) -> usize {
+    match *inst {
+        Inst::Label(_) => 0,
+        Inst::Data(ref bytes) => bytes.len(),
+        _ => 4,
+    }
+}
+
+/// The label of a conditional branch, whose 19-bit offset reaches 1 MiB
+/// either way.
+fn conditional(inst: &Inst) -> Option<&str> {
+    match *inst {
+        Inst::BCond(_, ref label)
+        | Inst::Cbz(_, _, ref label)
+        | Inst::Cbnz(_, _, ref label) => Some(label),
+        _ => None,
+    }
+}
+
+/// Computes the offset of every label, with the far branches taking the
+/// `b` they jump over too.
+fn layout(insts: &[Inst], far: &[bool]) -> HashMap<String, usize> {
+    let mut labels = HashMap::new();
+    let mut pos = 0;
+    for (inst, &far) in insts.iter().zip(far) {
+        if let Inst::Label(ref label) = *inst {
+            labels.insert(label.clone(), pos);
+        }
+        pos += size(inst) + if far {4} else {0};
+    }
+    labels
+}
+
+/// Rewrites the conditional branches whose target is out of reach as an
+/// inverted branch over a `b`, which reaches 128 MiB. Starts with every
+/// branch near and makes far the ones out of reach, until nothing changes.
+/// A far branch only makes the others longer, so this always terminates.
+pub fn relax(insts: &[Inst]) -> Vec<Inst> {
+    let mut far = vec![false; insts.len()];
+    loop {
+        let labels = layout(insts, &far);
+        let mut changed = false;
+        let mut pos = 0;
+        for (i, inst) in insts.iter().enumerate() {
+            // Undefined labels are reported by the encoding.
+            let target = conditional(inst).and_then(|label| labels.get(label));
+            if let Some(&target) = target {
+                let words = (target as i64 - pos as i64) / 4;
+                if !far[i] && !(-(1 << 18) .. 1 << 18).contains(&words) {
+                    far[i] = true;
+                    changed = true;
+                }
+            }
+            pos += size(inst) + if far[i] {4} else {0};
+        }
+        if !changed {
+            break;
+        }
+    }
+    let mut relaxed = Vec::with_capacity(insts.len());
+    for (inst, far) in insts.iter().zip(far) {
+        if !far {
+            relaxed.push(inst.clone());
+            continue;
+        }
+        let skip = format!("_far{}", relaxed.len());
+        let (inverse, label) = match *inst {
+            Inst::BCond(cond, ref label) => (Inst::BCond(cond.inverse(), skip.clone()), label),
+            Inst::Cbz(s, rt, ref label) => (Inst::Cbnz(s, rt, skip.clone()), label),
+            Inst::Cbnz(s, rt, ref label) => (Inst::Cbz(s, rt, skip.clone()), label),
+            _ => unreachable!(),
+        };
+        relaxed.push(inverse);
+        relaxed.push(Inst::B(label.clone()));
+        relaxed.push(Inst::Label(skip));
+    }
+    relaxed
+}
+
+/// Encodes the offset from `pos` to the label in words, as a signed field of
+/// `bits` bits.
+fn branch(
+    labels: &HashMap<String, usize>,
+    label: &str,
+    pos: usize,
+    bits: u32
+) -> Result<u32, Error> {
+    let target = match labels.get(label) {
+        Some(&t) => t,
+        _ => return Err(invalid(format!("Undefined label {}.", label))),
+    };
+    let words = (target as i64 - pos as i64) / 4;
+    let limit = 1 << (bits - 1);
+    if words < -limit || words >= limit {
+        return Err(invalid(format!("Label {} is out of reach.", label)));
+    }
+    Ok(words as u32 & ((1 << bits) - 1))
+}
+
+fn mem(width: Width, load: bool, rt: Reg, rn: Reg, disp: i32) -> Result<u32, Error> {
+    let opc = if load {1} else {0};
+    let base = width.log2() << 30 | opc << 22 | rn.code() << 5 | rt.code();
+    if width.scaled(disp) {
+        let imm = (disp / width.bytes()) as u32;
+        Ok(0x3900_0000 | base | imm << 10)
+    } else if unscaled(disp) {
+        Ok(0x3800_0000 | base | (disp as u32 & 0x1FF) << 12)
+    } else {
+        Err(invalid(format!("Offset {} is out of reach.", disp)))
+    }
+}
+
+fn post(width: Width, load: bool, rt: Reg, rn: Reg, disp: i32) -> Result<u32, Error> {
+    if !unscaled(disp) {
+        return Err(invalid(format!("Offset {} is out of reach.", disp)));
+    }
+    let opc = if load {1} else {0};
+    Ok(0x3800_0400 | width.log2() << 30 | opc << 22
+        | (disp as u32 & 0x1FF) << 12 | rn.code() << 5 | rt.code())
+}
+
+fn imm12(base: u32, size: Size, rd: Reg, rn: Reg, imm: u16, shift: bool) -> Result<u32, Error> {
+    if imm >= 4096 {
+        return Err(invalid(format!("Immediate {} does not fit in 12 bits.", imm)));
+    }
+    Ok(base | size.sf() << 31 | (shift as u32) << 22 | (imm as u32) << 10
+        | rn.code() << 5 | rd.code())
+}
+
+fn wide(base: u32, size: Size, rd: Reg, imm: u16, shift: u8) -> Result<u32, Error> {
+    let limit = if size == Size::X {64} else {32};
+    if shift % 16 != 0 || shift >= limit {
+        return Err(invalid(format!("Shift {} is not supported.", shift)));
+    }
+    Ok(base | size.sf() << 31 | (shift as u32 / 16) << 21 | (imm as u32) << 5 | rd.code())
+}
+
+fn reg3(base: u32, size: Size, rd: Reg, rn: Reg, rm: Reg) -> u32 {
+    base | size.sf() << 31 | rm.code() << 16 | rn.code() << 5 | rd.code()
+}
+
+fn encode(
+    inst: &Inst,
+    pos: usize,
+    labels: &HashMap<String, usize>
+) -> Result<u32, Error> {
+    Ok(match *inst {
+        Inst::Movz(s, rd, imm, shift) => wide(0x5280_0000, s, rd, imm, shift)?,
+        Inst::Movn(s, rd, imm, shift) => wide(0x1280_0000, s, rd, imm, shift)?,
+        Inst::Movk(s, rd, imm, shift) => wide(0x7280_0000, s, rd, imm, shift)?,
+        // The stack pointer is only reachable through `add`.
+        Inst::Mov(s, rd, rm) if rd == Reg::Sp || rm == Reg::Sp =>
+            imm12(0x1100_0000, s, rd, rm, 0, false)?,
+        Inst::Mov(s, rd, rm) => reg3(0x2A00_0000, s, rd, Reg::Zr, rm),
+        Inst::AddImm(s, rd, rn, imm, shift) =>
+            imm12(0x1100_0000, s, rd, rn, imm, shift)?,
+        Inst::SubImm(s, rd, rn, imm, shift) =>
+            imm12(0x5100_0000, s, rd, rn, imm, shift)?,
+        Inst::AddReg(s, rd, rn, rm) => reg3(0x0B00_0000, s, rd, rn, rm),
+        Inst::SubReg(s, rd, rn, rm) => reg3(0x4B00_0000, s, rd, rn, rm),
+        Inst::CmpImm(s, rn, imm) => imm12(0x7100_0000, s, Reg::Zr, rn, imm, false)?,
+        Inst::CmpReg(s, rn, rm) => reg3(0x6B00_0000, s, Reg::Zr, rn, rm),
+        Inst::Madd(s, rd, rn, rm, ra) =>
+            reg3(0x1B00_0000, s, rd, rn, rm) | ra.code() << 10,
+        Inst::Lsr(s, rd, rn, shift) => {
+            // An alias of `ubfm`, taking the bits from `shift` to the top.
+            let top = if s == Size::X {63} else {31};
+            if shift as u32 > top {
+                return Err(invalid(format!("Shift {} is not supported.", shift)));
+            }
+            0x5300_0000 | s.sf() << 31 | s.sf() << 22 | (shift as u32) << 16
+                | top << 10 | rn.code() << 5 | rd.code()
+        },
+        Inst::Ldr(w, rt, rn, disp) => mem(w, true, rt, rn, disp)?,
+        Inst::Str(w, rt, rn, disp) => mem(w, false, rt, rn, disp)?,
+        Inst::LdrPost(w, rt, rn, disp) => post(w, true, rt, rn, disp)?,
+        Inst::StrPost(w, rt, rn, disp) => post(w, false, rt, rn, disp)?,
+        Inst::StpPre(rt, rt2, rn, disp) => {
+            if disp % 8 != 0 || !(-512 .. 512).contains(&disp) {
+                return Err(invalid(format!("Offset {} is out of reach.", disp)));
+            }
+            0xA980_0000 | ((disp / 8) as u32 & 0x7F) << 15 | rt2.code() << 10
+                | rn.code() << 5 | rt.code()
+        },
+        Inst::B(ref label) => 0x1400_0000 | branch(labels, label, pos, 26)?,
+        Inst::Bl(ref label) => 0x9400_0000 | branch(labels, label, pos, 26)?,
+        Inst::BCond(cond, ref label) =>
+            0x5400_0000 | branch(labels, label, pos, 19)? << 5 | cond.code(),
+        Inst::Cbz(s, rt, ref label) => 0x3400_0000 | s.sf() << 31
+            | branch(labels, label, pos, 19)? << 5 | rt.code(),
+        Inst::Cbnz(s, rt, ref label) => 0x3500_0000 | s.sf() << 31
+            | branch(labels, label, pos, 19)? << 5 | rt.code(),
+        Inst::Ret => 0xD65F_03C0,
+        Inst::Svc(n) => 0xD400_0001 | (n as u32) << 5,
+        Inst::Label(_) | Inst::Data(_) => unreachable!(),
+    })
+}
+
+/// Encodes the given instructions into machine code. Labels are resolved
+/// here, and conditional branches to far labels are relaxed.
+pub fn assemble(insts: &[Inst]) -> Result<Program, Error> {
+    let insts = relax(insts);
+    let mut labels = HashMap::new();
+    let mut pos = 0;
+    for inst in &insts {
+        if let Inst::Label(ref label) = *inst {
+            labels.insert(label.clone(), pos);
+        }
+        pos += size(inst);
+    }
+    let mut code = Vec::with_capacity(pos);
+    for inst in &insts {
+        match *inst {
+            Inst::Label(_) => (),
+            Inst::Data(ref bytes) => code.extend_from_slice(bytes),
+            _ => match encode(inst, code.len(), &labels) {
+                Ok(word) => code.extend_from_slice(&word.to_le_bytes()),
+                Err(e) => return Err(invalid(format!(
+                    "Cannot encode `{}`: {}",
+                    inst.to_string().trim(),
+                    e
+                ))),
+            },
+        }
+    }
+    Ok(Program {code, labels})
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        assemble,
+    };
+    use super::super::inst::{
+        Inst,
+        Reg,
+        Size,
+        Width,
+        Cond,
+    };
+
+    fn x(n: u8) -> Reg {
+        Reg::X(n)
+    }
+
+    fn label(name: &str) -> Inst {
+        Inst::Label(name.to_string())
+    }
+
+    fn code(insts: &[Inst]) -> Vec<u8> {
+        assemble(insts).unwrap().code
+    }
+
+    // The expected bytes are the output of `llvm-mc -triple=aarch64`.
+    #[test]
+    fn moves_and_arithmetic() {
+        let cases = vec![
+            (Inst::Movz(Size::X, x(0), 0x1234, 16), vec![0x80, 0x46, 0xA2, 0xD2]),
+            (Inst::Movn(Size::W, x(1), 0, 0), vec![0x01, 0x00, 0x80, 0x12]),
+            (Inst::Movk(Size::X, x(2), 0xFFFF, 48), vec![0xE2, 0xFF, 0xFF, 0xF2]),
+            (Inst::Mov(Size::X, x(19), Reg::Sp), vec![0xF3, 0x03, 0x00, 0x91]),
+            (Inst::Mov(Size::W, x(3), x(4)), vec![0xE3, 0x03, 0x04, 0x2A]),
+            (
+                Inst::AddImm(Size::X, x(0), x(0), 4095, false),
+                vec![0x00, 0xFC, 0x3F, 0x91],
+            ),
+            (
+                Inst::SubImm(Size::X, Reg::Sp, Reg::Sp, 1, true),
+                vec![0xFF, 0x07, 0x40, 0xD1],
+            ),
+            (Inst::AddReg(Size::X, x(0), x(1), x(2)), vec![0x20, 0x00, 0x02, 0x8B]),
+            (Inst::SubReg(Size::W, x(5), x(6), x(7)), vec![0xC5, 0x00, 0x07, 0x4B]),
+            (Inst::CmpImm(Size::X, x(19), 0), vec![0x7F, 0x02, 0x00, 0xF1]),
+            (Inst::CmpReg(Size::X, x(19), x(20)), vec![0x7F, 0x02, 0x14, 0xEB]),
+            (
+                Inst::Madd(Size::W, x(0), x(1), x(2), x(3)),
+                vec![0x20, 0x0C, 0x02, 0x1B],
+            ),
+            (Inst::Lsr(Size::X, x(0), x(1), 8), vec![0x20, 0xFC, 0x48, 0xD3]),
+            (Inst::Lsr(Size::W, x(2), x(3), 31), vec![0x62, 0x7C, 0x1F, 0x53]),
+            (Inst::Ret, vec![0xC0, 0x03, 0x5F, 0xD6]),
+            (Inst::Svc(0), vec![0x01, 0x00, 0x00, 0xD4]),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(std::slice::from_ref(&inst)), bytes, "{}", inst);
+        }
+    }
+
+    #[test]
+    fn memory_access() {
+        let cases = vec![
+            (Inst::Ldr(Width::Half, x(0), x(19), 2), vec![0x60, 0x06, 0x40, 0x79]),
+            (Inst::Ldr(Width::Half, x(0), x(19), -2), vec![0x60, 0xE2, 0x5F, 0x78]),
+            (Inst::Ldr(Width::Half, x(0), x(19), 3), vec![0x60, 0x32, 0x40, 0x78]),
+            (Inst::Str(Width::Double, x(1), x(19), 8), vec![0x61, 0x06, 0x00, 0xF9]),
+            (Inst::Ldr(Width::Byte, x(0), x(19), 4095), vec![0x60, 0xFE, 0x7F, 0x39]),
+            (Inst::Str(Width::Word, x(2), x(20), -256), vec![0x82, 0x02, 0x10, 0xB8]),
+            (Inst::LdrPost(Width::Byte, x(0), x(1), 1), vec![0x20, 0x14, 0x40, 0x38]),
+            (
+                Inst::StrPost(Width::Double, x(0), x(1), -8),
+                vec![0x20, 0x84, 0x1F, 0xF8],
+            ),
+            (
+                Inst::StpPre(x(29), x(30), Reg::Sp, -16),
+                vec![0xFD, 0x7B, 0xBF, 0xA9],
+            ),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(std::slice::from_ref(&inst)), bytes, "{}", inst);
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        let cases = [
+            Inst::Ldr(Width::Half, x(0), x(19), 8192),
+            Inst::Str(Width::Byte, x(0), x(19), -257),
+            Inst::LdrPost(Width::Byte, x(0), x(1), 256),
+            Inst::StpPre(x(29), x(30), Reg::Sp, -12),
+            Inst::AddImm(Size::X, x(0), x(0), 4096, false),
+            Inst::Movz(Size::W, x(0), 1, 32),
+            Inst::Movz(Size::X, x(0), 1, 8),
+            Inst::Lsr(Size::W, x(0), x(0), 32),
+        ];
+        for inst in &cases {
+            assert!(assemble(std::slice::from_ref(inst)).is_err(), "{}", inst);
+        }
+    }
+
+    #[test]
+    fn branches() {
+        let insts = [
+            label("start"),
+            Inst::BCond(Cond::Ne, String::from("start")),
+            Inst::Cbz(Size::X, x(0), String::from("end")),
+            Inst::Cbnz(Size::W, x(1), String::from("start")),
+            Inst::Bl(String::from("end")),
+            Inst::B(String::from("start")),
+            label("end"),
+        ];
+        let program = assemble(&insts).unwrap();
+        assert_eq!(program.code, vec![
+            0x01, 0x00, 0x00, 0x54,
+            0x80, 0x00, 0x00, 0xB4,
+            0xC1, 0xFF, 0xFF, 0x35,
+            0x02, 0x00, 0x00, 0x94,
+            0xFC, 0xFF, 0xFF, 0x17,
+        ]);
+        assert_eq!(program.labels["end"], 20);
+    }
+
+    #[test]
+    fn branch_reach() {
+        // Conditional branches reach 1 MiB either way.
+        let far = |gap: usize| [
+            Inst::BCond(Cond::Eq, String::from("far")),
+            Inst::Data(vec![0; gap]),
+            label("far"),
+        ];
+        assert_eq!(code(&far((1 << 20) - 8))[.. 4], [0xE0, 0xFF, 0x7F, 0x54]);
+        // Further, they become an inverted branch over a `b`.
+        assert_eq!(code(&far((1 << 20) - 4))[.. 8], [
+            0x41, 0x00, 0x00, 0x54,
+            0x00, 0x00, 0x04, 0x14,
+        ]);
+        let back = |gap: usize| [
+            label("back"),
+            Inst::Data(vec![0; gap]),
+            Inst::BCond(Cond::Eq, String::from("back")),
+        ];
+        assert_eq!(code(&back(1 << 20))[1 << 20 ..], [0x00, 0x00, 0x80, 0x54]);
+        assert_eq!(code(&back((1 << 20) + 4))[(1 << 20) + 4 ..], [
+            0x41, 0x00, 0x00, 0x54,
+            0xFE, 0xFF, 0xFB, 0x17,
+        ]);
+        let undefined = [Inst::B(String::from("nowhere"))];
+        assert!(assemble(&undefined).is_err());
+        let undefined = [Inst::Cbz(Size::X, x(0), String::from("nowhere"))];
+        assert!(assemble(&undefined).is_err());
+    }
+
+    #[test]
+    fn large_loop() {
+        // A loop whose body is larger than 1 MiB, in both directions.
+        let body = 1 << 20;
+        let insts = [
+            label("start"),
+            Inst::Cbz(Size::W, x(0), String::from("end")),
+            Inst::Data(vec![0; body]),
+            Inst::Cbnz(Size::W, x(0), String::from("start")),
+            label("end"),
+        ];
+        let program = assemble(&insts).unwrap();
+        assert_eq!(program.code[.. 8], [
+            0x40, 0x00, 0x00, 0x35,
+            0x03, 0x00, 0x04, 0x14,
+        ]);
+        assert_eq!(program.code[body + 8 ..], [
+            0x40, 0x00, 0x00, 0x34,
+            0xFD, 0xFF, 0xFB, 0x17,
+        ]);
+        assert_eq!(program.labels["end"], body + 16);
+    }
+
+}
@@ -0,0 +1,338 @@
+use std::{
+    fmt,
+};
+
+
+/// General purpose registers. Register 31 is either the stack pointer or the
+/// zero register, depending on the instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reg {
+    X(u8),
+    Sp,
+    Zr,
+}
+
+impl Reg {
+
+    /// The 5-bit register number used by the encoding.
+    pub fn code(self) -> u32 {
+        match self {
+            Reg::X(n) => n as u32,
+            Reg::Sp | Reg::Zr => 31,
+        }
+    }
+
+    pub fn name(self, size: Size) -> String {
+        match (self, size) {
+            (Reg::X(n), Size::W) => format!("w{}", n),
+            (Reg::X(n), Size::X) => format!("x{}", n),
+            (Reg::Sp, Size::W) => String::from("wsp"),
+            (Reg::Sp, Size::X) => String::from("sp"),
+            (Reg::Zr, Size::W) => String::from("wzr"),
+            (Reg::Zr, Size::X) => String::from("xzr"),
+        }
+    }
+
+}
+
+/// Size of the registers an operation works on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Size {
+    W,
+    X,
+}
+
+impl Size {
+
+    /// The `sf` bit of the encoding.
+    pub fn sf(self) -> u32 {
+        match self {
+            Size::W => 0,
+            Size::X => 1,
+        }
+    }
+
+}
+
+/// Size of a memory access. Loads zero extend into the register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+impl Width {
+
+    pub fn bytes(self) -> i32 {
+        1 << self.log2()
+    }
+
+    /// The `size` field of the encoding.
+    pub fn log2(self) -> u32 {
+        match self {
+            Width::Byte => 0,
+            Width::Half => 1,
+            Width::Word => 2,
+            Width::Double => 3,
+        }
+    }
+
+    /// The size of the register transferred.
+    pub fn size(self) -> Size {
+        if self == Width::Double {Size::X} else {Size::W}
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Width::Byte => "b",
+            Width::Half => "h",
+            Width::Word | Width::Double => "",
+        }
+    }
+
+    /// Whether `disp` fits the scaled, unsigned offset of `ldr` and `str`.
+    pub fn scaled(self, disp: i32) -> bool {
+        disp >= 0 && disp % self.bytes() == 0 && disp / self.bytes() < 4096
+    }
+
+}
+
+/// Whether `disp` fits the unscaled, signed offset of `ldur` and `stur`.
+pub fn unscaled(disp: i32) -> bool {
+    (-256 .. 256).contains(&disp)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Eq = 0x0,
+    Ne = 0x1,
+    Hs = 0x2,
+    Lo = 0x3,
+    Hi = 0x8,
+    Ls = 0x9,
+    Ge = 0xA,
+    Lt = 0xB,
+    Gt = 0xC,
+    Le = 0xD,
+}
+
+impl Cond {
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn inverse(self) -> Cond {
+        match self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Hs => Cond::Lo,
+            Cond::Lo => Cond::Hs,
+            Cond::Hi => Cond::Ls,
+            Cond::Ls => Cond::Hi,
+            Cond::Ge => Cond::Lt,
+            Cond::Lt => Cond::Ge,
+            Cond::Gt => Cond::Le,
+            Cond::Le => Cond::Gt,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Hs => "hs",
+            Cond::Lo => "lo",
+            Cond::Hi => "hi",
+            Cond::Ls => "ls",
+            Cond::Ge => "ge",
+            Cond::Lt => "lt",
+            Cond::Gt => "gt",
+            Cond::Le => "le",
+        }
+    }
+
+}
+
+/// The subset of AArch64 instructions the code generator emits. Operands
+/// are in assembly order: destination first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inst {
+    Label(String),
+    /// Moves a 16-bit immediate shifted left by the given amount, clearing
+    /// the rest of the register.
+    Movz(Size, Reg, u16, u8),
+    /// Moves the inverse of a shifted 16-bit immediate.
+    Movn(Size, Reg, u16, u8),
+    /// Replaces 16 bits of the register, keeping the rest.
+    Movk(Size, Reg, u16, u8),
+    /// Copies a register, which may be the stack pointer.
+    Mov(Size, Reg, Reg),
+    /// Adds a 12-bit immediate, shifted left by 12 bits if the flag is set.
+    AddImm(Size, Reg, Reg, u16, bool),
+    SubImm(Size, Reg, Reg, u16, bool),
+    AddReg(Size, Reg, Reg, Reg),
+    SubReg(Size, Reg, Reg, Reg),
+    /// Subtracts without keeping the result, setting the flags.
+    CmpImm(Size, Reg, u16),
+    CmpReg(Size, Reg, Reg),
+    /// Multiplies the first two source registers and adds the third one.
+    Madd(Size, Reg, Reg, Reg, Reg),
+    /// Logical right shift by a constant.
+    Lsr(Size, Reg, Reg, u8),
+    /// Loads from the base register plus a displacement, with `ldr` if it
+    /// fits its scaled offset and with `ldur` otherwise.
+    Ldr(Width, Reg, Reg, i32),
+    Str(Width, Reg, Reg, i32),
+    /// Loads from the base register, which is then moved by the offset.
+    LdrPost(Width, Reg, Reg, i32),
+    StrPost(Width, Reg, Reg, i32),
+    /// Stores a pair of 64-bit registers below the base register, which is
+    /// moved there first.
+    StpPre(Reg, Reg, Reg, i32),
+    B(String),
+    BCond(Cond, String),
+    Cbz(Size, Reg, String),
+    Cbnz(Size, Reg, String),
+    /// Calls a label, leaving the return address in `x30`.
+    Bl(String),
+    Ret,
+    Svc(u16),
+    /// Raw bytes placed among the instructions, a multiple of 4 bytes long.
+    Data(Vec<u8>),
+}
+
+impl Inst {
+
+    fn mem(width: Width, store: bool, rt: Reg, rn: Reg, disp: i32) -> String {
+        let name = match (width.scaled(disp), store) {
+            (true, false) => "ldr",
+            (true, true) => "str",
+            (false, false) => "ldur",
+            (false, true) => "stur",
+        };
+        let base = rn.name(Size::X);
+        if disp == 0 {
+            format!("  {}{} {}, [{}]", name, width.suffix(), rt.name(width.size()), base)
+        } else {
+            format!(
+                "  {}{} {}, [{}, #{}]",
+                name,
+                width.suffix(),
+                rt.name(width.size()),
+                base,
+                disp
+            )
+        }
+    }
+
+    fn post(width: Width, store: bool, rt: Reg, rn: Reg, disp: i32) -> String {
+        format!(
+            "  {}{} {}, [{}], #{}",
+            if store {"str"} else {"ldr"},
+            width.suffix(),
+            rt.name(width.size()),
+            rn.name(Size::X),
+            disp
+        )
+    }
+
+    fn imm(
+        name: &str,
+        size: Size,
+        rd: Reg,
+        rn: Reg,
+        imm: u16,
+        shift: bool
+    ) -> String {
+        format!(
+            "  {} {}, {}, #{}{}",
+            name,
+            rd.name(size),
+            rn.name(size),
+            imm,
+            if shift {", lsl #12"} else {""}
+        )
+    }
+
+    fn wide(name: &str, size: Size, rd: Reg, imm: u16, shift: u8) -> String {
+        if shift == 0 {
+            format!("  {} {}, #{}", name, rd.name(size), imm)
+        } else {
+            format!("  {} {}, #{}, lsl #{}", name, rd.name(size), imm, shift)
+        }
+    }
+
+}
+
+impl fmt::Display for Inst {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", match *self {
+            Inst::Label(ref label) => format!("  {}:", label),
+            Inst::Movz(s, rd, imm, shift) => Inst::wide("movz", s, rd, imm, shift),
+            Inst::Movn(s, rd, imm, shift) => Inst::wide("movn", s, rd, imm, shift),
+            Inst::Movk(s, rd, imm, shift) => Inst::wide("movk", s, rd, imm, shift),
+            Inst::Mov(s, rd, rm) => format!("  mov {}, {}", rd.name(s), rm.name(s)),
+            Inst::AddImm(s, rd, rn, imm, shift) =>
+                Inst::imm("add", s, rd, rn, imm, shift),
+            Inst::SubImm(s, rd, rn, imm, shift) =>
+                Inst::imm("sub", s, rd, rn, imm, shift),
+            Inst::AddReg(s, rd, rn, rm) => format!(
+                "  add {}, {}, {}",
+                rd.name(s),
+                rn.name(s),
+                rm.name(s)
+            ),
+            Inst::SubReg(s, rd, rn, rm) => format!(
+                "  sub {}, {}, {}",
+                rd.name(s),
+                rn.name(s),
+                rm.name(s)
+            ),
+            Inst::CmpImm(s, rn, imm) => format!("  cmp {}, #{}", rn.name(s), imm),
+            Inst::CmpReg(s, rn, rm) =>
+                format!("  cmp {}, {}", rn.name(s), rm.name(s)),
+            Inst::Madd(s, rd, rn, rm, ra) => format!(
+                "  madd {}, {}, {}, {}",
+                rd.name(s),
+                rn.name(s),
+                rm.name(s),
+                ra.name(s)
+            ),
+            Inst::Lsr(s, rd, rn, shift) =>
+                format!("  lsr {}, {}, #{}", rd.name(s), rn.name(s), shift),
+            Inst::Ldr(w, rt, rn, disp) => Inst::mem(w, false, rt, rn, disp),
+            Inst::Str(w, rt, rn, disp) => Inst::mem(w, true, rt, rn, disp),
+            Inst::LdrPost(w, rt, rn, disp) => Inst::post(w, false, rt, rn, disp),
+            Inst::StrPost(w, rt, rn, disp) => Inst::post(w, true, rt, rn, disp),
+            Inst::StpPre(rt, rt2, rn, disp) => format!(
+                "  stp {}, {}, [{}, #{}]!",
+                rt.name(Size::X),
+                rt2.name(Size::X),
+                rn.name(Size::X),
+                disp
+            ),
+            Inst::B(ref label) => format!("  b {}", label),
+            Inst::BCond(cond, ref label) =>
+                format!("  b.{} {}", cond.name(), label),
+            Inst::Cbz(s, rt, ref label) =>
+                format!("  cbz {}, {}", rt.name(s), label),
+            Inst::Cbnz(s, rt, ref label) =>
+                format!("  cbnz {}, {}", rt.name(s), label),
+            Inst::Bl(ref label) => format!("  bl {}", label),
+            Inst::Ret => String::from("  ret"),
+            Inst::Svc(n) => format!("  svc #{}", n),
+            Inst::Data(ref bytes) => format!(
+                "  .byte {}",
+                bytes.iter()
+                    .map(|b| b.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        })
+    }
+
+}
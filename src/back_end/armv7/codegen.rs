@@ -0,0 +1,1010 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Eof,
+    Overflow,
+    Tape,
+};
+use back_end::{
+    Emitter,
+    Labels,
+    walk,
+    label_for,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+};
+use back_end::linux::{
+    PROT_READ_WRITE,
+    MAP_FLAGS,
+    RLIMIT_STACK,
+    STACK_CAP,
+};
+use super::{
+    Armv7,
+    GUARD_SIZE,
+};
+use super::inst::{
+    Inst,
+    Reg,
+    Width,
+    Cond,
+    modified_imm,
+};
+
+
+/// The pointer to the current cell.
+const TAPE: Reg = Reg::R8;
+/// The first cell of a checked stack tape, or the start of a checked tape
+/// in memory.
+const START: Reg = Reg::R9;
+/// The end of a checked tape in memory, or the lowest address a checked
+/// stack tape may use.
+const END: Reg = Reg::R10;
+/// The I/O state, when buffered.
+const IO: Reg = Reg::R11;
+/// Keeps the return address of routines which call other routines.
+const LINK: Reg = Reg::R6;
+/// Scratch register of the helpers which build addresses and immediates.
+const SCRATCH: Reg = Reg::IP;
+const LR: Reg = Reg::LR;
+
+const TAPE_GROW: &str = "_tape_grow";
+const FAIL: &str = "_fail";
+/// Enters `_fail` past its flush, before the output buffer is set up.
+const FAIL_EARLY: &str = "_fail_early";
+const PUT: &str = "_put";
+const GET: &str = "_get";
+const FLUSH: &str = "_flush";
+const WRITE_FAILED: &str = "_write_failed";
+const READ_FAILED: &str = "_read_failed";
+
+/// Size of each I/O buffer.
+const IO_BUFFER: i64 = 4096;
+/// Offsets from `r11` of the I/O state: the length of the output buffer,
+/// the position and the length of the input buffer, and the buffers
+/// themselves.
+const IO_OUT_LEN: i32 = 0;
+const IO_IN_POS: i32 = 4;
+const IO_IN_LEN: i32 = 8;
+const IO_OUT: i64 = 16;
+const IO_IN: i64 = IO_OUT + IO_BUFFER;
+const IO_END: i64 = IO_IN + IO_BUFFER;
+
+/// Generates a standalone program starting at `_start`, with the tape on
+/// the stack or in a region given by `mmap2`, see `Tape`, and I/O done
+/// through system calls. The pointer lives in `r8`, and the bounds of a
+/// checked tape in `r9` and `r10`.
+#[derive(Clone, Debug)]
+pub struct Codegen {
+    config: Config,
+    insts: Vec<Inst>,
+    labels: Labels,
+    /// Whether `_fail` is called.
+    fails: bool,
+    /// Whether the program writes, through `_put` if buffered.
+    puts: bool,
+    /// Whether the program reads, through `_get` if buffered.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator. The configuration must be supported, see
+    /// `Armv7::check`.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            insts: Vec::new(),
+            labels: Labels::default(),
+            fails: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    /// Width of the cells in memory.
+    fn cw(&self) -> Width {
+        Armv7::cell_width(self.config.cell_size)
+    }
+
+    /// Size of a cell in bytes.
+    fn stride(&self) -> i64 {
+        self.config.cell_size.bytes() as i64
+    }
+
+    /// Whether cells are 64 bits wide, so each one is a pair of words, the
+    /// low one first.
+    fn pair(&self) -> bool {
+        self.config.cell_size == CellSize::Bits64
+    }
+
+    /// Whether the tape is on the stack, which grows downwards with it.
+    fn on_stack(&self) -> bool {
+        self.config.tape == Tape::Stack
+    }
+
+    /// Whether the tape grows at both ends.
+    fn growable(&self) -> bool {
+        self.config.tape.bidirectional()
+    }
+
+    /// Whether the pointer is checked against both ends of the tape, which
+    /// are kept in `r9` and `r10`. The first cell of a stack tape is kept in
+    /// `r9` instead, when checked.
+    fn checked(&self) -> bool {
+        !self.on_stack() && (self.growable() || self.config.checked)
+    }
+
+    /// Whether I/O goes through the buffers, whose state is pointed by
+    /// `r11`.
+    fn buffered(&self) -> bool {
+        !self.config.unbuffered
+    }
+
+    /// The displacement of a cell from the pointer. Cells follow the stack
+    /// downwards when the tape is on the stack, and go upwards otherwise.
+    fn disp(&self, offset: i64) -> i64 {
+        if self.on_stack() {-offset * self.stride()} else {offset * self.stride()}
+    }
+
+    /// Moves the lower 32 bits of an immediate into a register, with a
+    /// single instruction if the immediate or its inverse can be encoded.
+    fn mov_imm(&mut self, reg: Reg, value: i64) {
+        let value = value as u32;
+        if modified_imm(value).is_some() {
+            self.emit(Inst::MovImm(reg, value));
+        } else if modified_imm(!value).is_some() {
+            self.emit(Inst::MvnImm(reg, !value));
+        } else {
+            self.emit(Inst::Movw(reg, value as u16));
+            if value >> 16 != 0 {
+                self.emit(Inst::Movt(reg, (value >> 16) as u16));
+            }
+        }
+    }
+
+    /// Adds an immediate to a register, modulo 2^32, through `ip` if neither
+    /// it nor its negation can be encoded.
+    fn add_imm(&mut self, rd: Reg, rn: Reg, value: i64) {
+        let value = value as u32;
+        if value == 0 {
+            if rd != rn {
+                self.emit(Inst::Mov(rd, rn));
+            }
+        } else if modified_imm(value).is_some() {
+            self.emit(Inst::AddImm(rd, rn, value));
+        } else if modified_imm(value.wrapping_neg()).is_some() {
+            self.emit(Inst::SubImm(rd, rn, value.wrapping_neg()));
+        } else {
+            self.mov_imm(SCRATCH, value as i64);
+            self.emit(Inst::AddReg(rd, rn, SCRATCH));
+        }
+    }
+
+    /// Subtracts an immediate from a register, modulo 2^32.
+    fn sub_imm(&mut self, rd: Reg, rn: Reg, value: i64) {
+        self.add_imm(rd, rn, (value as u32).wrapping_neg() as i64);
+    }
+
+    /// Compares a register against an immediate, through `ip` if it cannot
+    /// be encoded.
+    fn cmp_imm(&mut self, reg: Reg, value: i64) {
+        let value = value as u32;
+        if modified_imm(value).is_some() {
+            self.emit(Inst::CmpImm(reg, value));
+        } else {
+            self.mov_imm(SCRATCH, value as i64);
+            self.emit(Inst::CmpReg(reg, SCRATCH));
+        }
+    }
+
+    /// The base register and displacement of a cell, computing its address
+    /// into `ip` if it is too far for a load or a store.
+    fn cell(&mut self, offset: i64) -> (Reg, i32) {
+        let disp = self.disp(offset);
+        // The high word of a 64-bit cell must be in reach too.
+        let high = if self.pair() {4} else {0};
+        let fits = |disp: i64| disp == disp as i32 as i64 && self.cw().fits(disp as i32);
+        if fits(disp) && fits(disp + high) {
+            (TAPE, disp as i32)
+        } else {
+            self.add_imm(SCRATCH, TAPE, disp);
+            (SCRATCH, 0)
+        }
+    }
+
+    /// Loads a cell, zero extended, into a register.
+    fn load(&mut self, reg: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Ldr(self.cw(), reg, base, disp));
+    }
+
+    fn store(&mut self, reg: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Str(self.cw(), reg, base, disp));
+    }
+
+    /// Loads a 64-bit cell into two registers, the low word into the first.
+    fn load_pair(&mut self, low: Reg, high: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Ldr(Width::Word, low, base, disp));
+        self.emit(Inst::Ldr(Width::Word, high, base, disp + 4));
+    }
+
+    fn store_pair(&mut self, low: Reg, high: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Str(Width::Word, low, base, disp));
+        self.emit(Inst::Str(Width::Word, high, base, disp + 4));
+    }
+
+    /// Stores a value wrapped around the cell size into a cell, through `r0`,
+    /// and `r1` for the high word of a 64-bit cell.
+    fn store_imm(&mut self, value: i64, offset: i64) {
+        let value = self.config.cell_size.wrap(value);
+        self.mov_imm(Reg::R0, value);
+        if self.pair() {
+            self.mov_imm(Reg::R1, value >> 32);
+            self.store_pair(Reg::R0, Reg::R1, offset);
+        } else {
+            self.store(Reg::R0, offset);
+        }
+    }
+
+    /// Stores the value in `r0` into a cell, sign extended.
+    fn store_int(&mut self, offset: i64) {
+        if self.pair() {
+            self.emit(Inst::Asr(Reg::R1, Reg::R0, 31));
+            self.store_pair(Reg::R0, Reg::R1, offset);
+        } else {
+            self.store(Reg::R0, offset);
+        }
+    }
+
+    /// Compares the current cell with zero, setting the zero flag. The words
+    /// of a 64-bit cell are or'ed together.
+    fn test_zero(&mut self) {
+        if self.pair() {
+            self.load_pair(Reg::R0, Reg::R1, 0);
+            self.emit(Inst::Orrs(Reg::R0, Reg::R0, Reg::R1));
+        } else {
+            self.load(Reg::R0, 0);
+            self.emit(Inst::CmpImm(Reg::R0, 0));
+        }
+    }
+
+    /// Adds a value to the 64-bit cell in `r0` and `r1`, or subtracts it,
+    /// carrying from the low word into the high one. The carry flag is set
+    /// on unsigned overflow, and cleared on borrow.
+    fn add_pair(&mut self, value: u64, sub: bool) {
+        let r = Reg;
+        self.mov_imm(r(2), value as i64);
+        self.mov_imm(r(3), (value >> 32) as i64);
+        if sub {
+            self.emit(Inst::Subs(r(0), r(0), r(2)));
+            self.emit(Inst::Sbcs(r(1), r(1), r(3)));
+        } else {
+            self.emit(Inst::Adds(r(0), r(0), r(2)));
+            self.emit(Inst::Adcs(r(1), r(1), r(3)));
+        }
+    }
+
+    /// Adds a 64-bit cell times a factor to another. The low words multiply
+    /// into a pair, and the products with a high word only add to its high
+    /// word.
+    fn mul_pair(&mut self, src: i64, dst: i64, factor: i64) {
+        let r = Reg;
+        self.load_pair(r(0), r(1), src);
+        // The low word of the product.
+        let mut low = r(0);
+        if factor != 1 && factor != -1 {
+            self.mov_imm(r(4), factor);
+            self.emit(Inst::Mul(r(3), r(1), r(4)));
+            if factor >> 32 != 0 {
+                self.mov_imm(r(5), factor >> 32);
+                self.emit(Inst::Mla(r(3), r(0), r(5), r(3)));
+            }
+            self.emit(Inst::Umull(r(2), r(1), r(0), r(4)));
+            self.emit(Inst::AddReg(r(1), r(1), r(3)));
+            low = r(2);
+        }
+        self.load_pair(r(4), r(5), dst);
+        if factor == -1 {
+            self.emit(Inst::Subs(r(4), r(4), low));
+            self.emit(Inst::Sbcs(r(5), r(5), r(1)));
+        } else {
+            self.emit(Inst::Adds(r(4), r(4), low));
+            self.emit(Inst::Adcs(r(5), r(5), r(1)));
+        }
+        self.store_pair(r(4), r(5), dst);
+    }
+
+    /// Puts the address of a cell into a register.
+    fn cell_addr(&mut self, reg: Reg, offset: i64) {
+        let disp = self.disp(offset);
+        self.add_imm(reg, TAPE, disp);
+    }
+
+    /// Pushes eight zeroed bytes, keeping the stack aligned, through `r3`.
+    fn push_zeros(&mut self) {
+        self.emit(Inst::MovImm(Reg::R3, 0));
+        self.emit(Inst::StrPre(Width::Word, Reg::R3, Reg::SP, -8));
+        self.emit(Inst::Str(Width::Word, Reg::R3, Reg::SP, 4));
+    }
+
+    /// Emits a system call, whose arguments must be in `r0` to `r5` already.
+    /// Only `r0` is changed by it.
+    fn syscall(&mut self, number: i64) {
+        self.mov_imm(Reg::R7, number);
+        self.emit(Inst::Svc(0));
+    }
+
+    /// Reports `message` and exits with status 1 if the last system call
+    /// failed, that is, if it returned a value from -4095 to -1. Calls made
+    /// while the program starts fail through `_fail_early`.
+    fn check_syscall(&mut self, label: &str, message: &str, early: bool) {
+        self.mov_imm(SCRATCH, -4095);
+        self.emit(Inst::CmpReg(Reg::R0, SCRATCH));
+        self.emit(Inst::BCond(Cond::Lo, String::from(label)));
+        let entry = if early {FAIL_EARLY} else {FAIL};
+        self.report_at(entry, IO_EXIT, String::from(message));
+        self.emit(Inst::Label(String::from(label)));
+    }
+
+    /// Maps `r1` bytes, readable and writable if `prot` is set.
+    fn mmap(&mut self, prot: bool) {
+        self.mov_imm(Reg::R0, 0);
+        self.mov_imm(Reg::R2, if prot {PROT_READ_WRITE} else {0});
+        self.mov_imm(Reg::R3, MAP_FLAGS);
+        self.mov_imm(Reg::R4, -1);
+        self.mov_imm(Reg::R5, 0);
+        self.syscall(Armv7::SYS_MMAP2);
+    }
+
+    /// Keeps the lowest address a checked stack tape may use. The arguments
+    /// and the environment take at most a quarter of the stack size limit,
+    /// so half of it is left for the tape.
+    fn limit_stack(&mut self) {
+        let capped = String::from("_stack_capped");
+        // The current and the maximum limit are written on the stack.
+        self.push_zeros();
+        self.mov_imm(Reg::R0, RLIMIT_STACK);
+        self.emit(Inst::Mov(Reg::R1, Reg::SP));
+        self.syscall(Armv7::SYS_UGETRLIMIT);
+        self.check_syscall("_getrlimit_ok", "Could not read the stack size limit.\n", true);
+        self.emit(Inst::LdrPost(Width::Word, Reg::R0, Reg::SP, 8));
+        self.mov_imm(Reg::R1, STACK_CAP);
+        self.emit(Inst::CmpReg(Reg::R0, Reg::R1));
+        self.emit(Inst::BCond(Cond::Ls, capped.clone()));
+        self.emit(Inst::Mov(Reg::R0, Reg::R1));
+        self.emit(Inst::Label(capped));
+        self.emit(Inst::Lsr(Reg::R0, Reg::R0, 1));
+        self.emit(Inst::SubReg(END, Reg::SP, Reg::R0));
+    }
+
+    /// Maps the heap tape with a guard region at each end, which are left
+    /// inaccessible, and points to its first cell. When checked, its bounds
+    /// are kept.
+    fn map_tape(&mut self, cells: u64) {
+        let bytes = Armv7::heap_bytes(cells, self.config.cell_size) as i64;
+        self.mov_imm(Reg::R1, bytes + 2 * GUARD_SIZE);
+        self.mmap(false);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.add_imm(TAPE, Reg::R0, GUARD_SIZE);
+        self.emit(Inst::Mov(Reg::R0, TAPE));
+        self.mov_imm(Reg::R1, bytes);
+        self.mov_imm(Reg::R2, PROT_READ_WRITE);
+        self.syscall(Armv7::SYS_MPROTECT);
+        self.check_syscall("_mprotect_ok", "Could not map the tape.\n", true);
+        if self.config.checked {
+            // The end of the last cell, not of its page.
+            let end = cells as i64 * self.stride();
+            self.emit(Inst::Mov(START, TAPE));
+            self.add_imm(END, TAPE, end);
+        }
+    }
+
+    /// Maps the first region of a bidirectional tape, keeps its bounds and
+    /// points to its middle.
+    fn map_growable(&mut self, cells: u64) {
+        let bytes = Armv7::heap_bytes(cells, self.config.cell_size) as i64;
+        self.mov_imm(Reg::R1, bytes);
+        self.mmap(true);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.emit(Inst::Mov(START, Reg::R0));
+        self.add_imm(END, START, bytes);
+        self.add_imm(TAPE, START, bytes / 2);
+    }
+
+    /// Emits the `_tape_grow` routine, called with the address of a cell out
+    /// of a bidirectional tape in `r0`. It maps a region three times as big
+    /// as the tape, moves the tape to its middle and frees the old one,
+    /// until the cell is in the tape. The pointer is moved along. As the
+    /// system calls take six registers, `lr` is saved on the stack and keeps
+    /// the size of the tape.
+    fn tape_grow(&mut self) {
+        let r = Reg;
+        let again = String::from("_tape_grow_again");
+        let copy = String::from("_tape_grow_copy");
+        self.emit(Inst::Label(String::from(TAPE_GROW)));
+        self.emit(Inst::StrPre(Width::Word, LR, Reg::SP, -8));
+        self.emit(Inst::Mov(LINK, r(0)));
+        self.emit(Inst::Label(again.clone()));
+        self.emit(Inst::SubReg(LR, END, START));
+        self.emit(Inst::AddReg(r(1), LR, LR));
+        self.emit(Inst::AddReg(r(1), r(1), LR));
+        self.mmap(true);
+        self.check_syscall("_tape_grow_ok", "Could not grow the tape.\n", false);
+        self.emit(Inst::AddReg(r(2), r(0), LR));
+        self.emit(Inst::Mov(r(1), START));
+        self.emit(Inst::Mov(r(3), LR));
+        self.emit(Inst::Label(copy.clone()));
+        self.emit(Inst::LdrPost(Width::Word, r(4), r(1), 4));
+        self.emit(Inst::StrPost(Width::Word, r(4), r(2), 4));
+        self.emit(Inst::SubImm(r(3), r(3), 4));
+        self.emit(Inst::CmpImm(r(3), 0));
+        self.emit(Inst::BCond(Cond::Ne, copy));
+        // How far the cells moved.
+        self.emit(Inst::AddReg(r(2), r(0), LR));
+        self.emit(Inst::SubReg(r(2), r(2), START));
+        self.emit(Inst::AddReg(TAPE, TAPE, r(2)));
+        self.emit(Inst::AddReg(LINK, LINK, r(2)));
+        self.emit(Inst::Mov(r(2), r(0)));
+        self.emit(Inst::Mov(r(0), START));
+        self.emit(Inst::Mov(r(1), LR));
+        self.syscall(Armv7::SYS_MUNMAP);
+        self.emit(Inst::Mov(START, r(2)));
+        self.emit(Inst::AddReg(END, r(2), LR));
+        self.emit(Inst::AddReg(END, END, LR));
+        self.emit(Inst::AddReg(END, END, LR));
+        self.emit(Inst::CmpReg(LINK, START));
+        self.emit(Inst::BCond(Cond::Lo, again.clone()));
+        self.emit(Inst::CmpReg(LINK, END));
+        self.emit(Inst::BCond(Cond::Hs, again));
+        self.emit(Inst::LdrPost(Width::Word, LR, Reg::SP, 8));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_fail` routine, called right before a message laid out as
+    /// the exit status in one byte, a padding byte, the length of the text in
+    /// two bytes, and the text. It writes the text to stderr and exits.
+    fn fail(&mut self) {
+        let r = Reg;
+        self.emit(Inst::Label(String::from(FAIL)));
+        // What was written before is shown first.
+        if self.buffered() && self.puts {
+            self.emit(Inst::Mov(LINK, LR));
+            self.emit(Inst::Bl(String::from(FLUSH)));
+            self.emit(Inst::Mov(LR, LINK));
+        }
+        self.emit(Inst::Label(String::from(FAIL_EARLY)));
+        self.emit(Inst::Mov(LINK, LR));
+        self.mov_imm(r(0), 2);
+        self.emit(Inst::AddImm(r(1), LINK, 4));
+        self.emit(Inst::Ldr(Width::Half, r(2), LINK, 2));
+        self.syscall(Armv7::SYS_WRITE);
+        self.emit(Inst::Ldr(Width::Byte, r(0), LINK, 0));
+        self.syscall(Armv7::SYS_EXIT);
+    }
+
+    /// Stops the program with the given exit status and message, through
+    /// `_fail`.
+    fn report(&mut self, status: i64, message: String) {
+        self.report_at(FAIL, status, message);
+    }
+
+    /// Stops the program through the given entry of `_fail`.
+    fn report_at(&mut self, entry: &str, status: i64, message: String) {
+        let mut data = vec![status as u8, 0];
+        data.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        data.extend_from_slice(message.as_bytes());
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        self.emit(Inst::Bl(String::from(entry)));
+        self.emit(Inst::Data(data));
+        self.fails = true;
+    }
+
+    /// Reports that the pointer left the tape at `loc` and exits.
+    fn report_bounds(&mut self, loc: &Location) {
+        let message = format!("Tape pointer out of bounds {}.\n", loc);
+        self.report(BOUNDS_EXIT, message);
+    }
+
+    /// Reports that the pointer left the tape, unless `ok` holds after the
+    /// comparison emitted before.
+    fn out_of_bounds(&mut self, ok: Cond, loc: &Location) {
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.emit(Inst::BCond(ok, label.clone()));
+        self.report_bounds(loc);
+        self.emit(Inst::Label(label));
+    }
+
+    /// Grows a bidirectional tape if the cell at `offset` is out of it, or
+    /// reports the error if the tape cannot grow. The comparison of its
+    /// address against the bound must have been emitted already, and `ok` is
+    /// the condition under which the cell is fine.
+    fn bounds(&mut self, ok: Cond, offset: i64, loc: &Location) {
+        if !self.growable() {
+            self.out_of_bounds(ok, loc);
+            return;
+        }
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.emit(Inst::BCond(ok, label.clone()));
+        self.cell_addr(Reg::R0, offset);
+        self.emit(Inst::Bl(String::from(TAPE_GROW)));
+        self.emit(Inst::Label(label));
+    }
+
+    /// Emits the `_flush` routine, which writes the whole output buffer.
+    fn flush(&mut self) {
+        let r = Reg;
+        let again = String::from("_flush_again");
+        let done = String::from("_flush_done");
+        self.emit(Inst::Label(String::from(FLUSH)));
+        self.add_imm(r(1), IO, IO_OUT);
+        self.emit(Inst::Label(again.clone()));
+        self.emit(Inst::Ldr(Width::Word, r(2), IO, IO_OUT_LEN));
+        self.emit(Inst::CmpImm(r(2), 0));
+        self.emit(Inst::BCond(Cond::Eq, done.clone()));
+        self.mov_imm(r(0), 1);
+        self.syscall(Armv7::SYS_WRITE);
+        self.emit(Inst::CmpImm(r(0), 0));
+        self.emit(Inst::BCond(Cond::Le, String::from(WRITE_FAILED)));
+        self.emit(Inst::AddReg(r(1), r(1), r(0)));
+        self.emit(Inst::Ldr(Width::Word, r(2), IO, IO_OUT_LEN));
+        self.emit(Inst::SubReg(r(2), r(2), r(0)));
+        self.emit(Inst::Str(Width::Word, r(2), IO, IO_OUT_LEN));
+        self.emit(Inst::B(again));
+        self.emit(Inst::Label(done));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_put` routine, which appends the byte in `r0` to the output
+    /// buffer, flushing it on newlines and when full.
+    fn put(&mut self) {
+        let r = Reg;
+        self.emit(Inst::Label(String::from(PUT)));
+        self.emit(Inst::Ldr(Width::Word, r(1), IO, IO_OUT_LEN));
+        self.add_imm(r(2), IO, IO_OUT);
+        self.emit(Inst::AddReg(r(2), r(2), r(1)));
+        self.emit(Inst::Str(Width::Byte, r(0), r(2), 0));
+        self.emit(Inst::AddImm(r(1), r(1), 1));
+        self.emit(Inst::Str(Width::Word, r(1), IO, IO_OUT_LEN));
+        self.emit(Inst::Ldr(Width::Byte, r(3), r(2), 0));
+        self.emit(Inst::CmpImm(r(3), b'\n' as u32));
+        self.emit(Inst::BCond(Cond::Eq, String::from(FLUSH)));
+        self.emit(Inst::CmpImm(r(1), IO_BUFFER as u32));
+        self.emit(Inst::BCond(Cond::Hs, String::from(FLUSH)));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_get` routine, which takes the next byte of the input
+    /// buffer into `r0`, or -1 at the end of the input. The output is
+    /// flushed before the buffer is filled again.
+    fn get(&mut self) {
+        let r = Reg;
+        let byte = String::from("_get_byte");
+        let eof = String::from("_get_eof");
+        self.emit(Inst::Label(String::from(GET)));
+        self.emit(Inst::Ldr(Width::Word, r(1), IO, IO_IN_POS));
+        self.emit(Inst::Ldr(Width::Word, r(2), IO, IO_IN_LEN));
+        self.emit(Inst::CmpReg(r(1), r(2)));
+        self.emit(Inst::BCond(Cond::Lo, byte.clone()));
+        self.emit(Inst::Mov(LINK, LR));
+        self.emit(Inst::Bl(String::from(FLUSH)));
+        self.emit(Inst::Mov(LR, LINK));
+        self.mov_imm(r(0), 0);
+        self.add_imm(r(1), IO, IO_IN);
+        self.mov_imm(r(2), IO_BUFFER);
+        self.syscall(Armv7::SYS_READ);
+        self.emit(Inst::CmpImm(r(0), 0));
+        self.emit(Inst::BCond(Cond::Lt, String::from(READ_FAILED)));
+        self.emit(Inst::BCond(Cond::Eq, eof.clone()));
+        self.emit(Inst::Str(Width::Word, r(0), IO, IO_IN_LEN));
+        self.mov_imm(r(1), 0);
+        self.emit(Inst::Label(byte));
+        self.add_imm(r(2), IO, IO_IN);
+        self.emit(Inst::AddReg(r(2), r(2), r(1)));
+        self.emit(Inst::Ldr(Width::Byte, r(0), r(2), 0));
+        self.emit(Inst::AddImm(r(1), r(1), 1));
+        self.emit(Inst::Str(Width::Word, r(1), IO, IO_IN_POS));
+        self.emit(Inst::Ret);
+        self.emit(Inst::Label(eof));
+        self.mov_imm(r(0), -1);
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits a routine reporting that I/O failed. The output buffer is
+    /// dropped, so that `_fail` does not write it again.
+    fn io_failed(&mut self, label: &str, message: &str) {
+        self.emit(Inst::Label(String::from(label)));
+        if self.buffered() {
+            self.mov_imm(Reg::R0, 0);
+            self.emit(Inst::Str(Width::Word, Reg::R0, IO, IO_OUT_LEN));
+        }
+        self.report(IO_EXIT, String::from(message));
+    }
+
+    fn prologue(&mut self) {
+        if self.on_stack() && self.config.checked {
+            self.limit_stack();
+        }
+        if self.buffered() {
+            self.sub_imm(Reg::SP, Reg::SP, IO_END);
+            self.emit(Inst::Mov(IO, Reg::SP));
+            self.mov_imm(Reg::R0, 0);
+            for &field in [IO_OUT_LEN, IO_IN_POS, IO_IN_LEN].iter() {
+                self.emit(Inst::Str(Width::Word, Reg::R0, IO, field));
+            }
+        }
+        match self.config.tape {
+            Tape::Stack => {
+                // The first cell is at the top of a zeroed pair of words.
+                self.push_zeros();
+                self.add_imm(TAPE, Reg::SP, 8 - self.stride());
+                if self.config.checked {
+                    self.emit(Inst::Mov(START, TAPE));
+                }
+            },
+            Tape::Heap(cells) => self.map_tape(cells),
+            Tape::Bidirectional(cells) => self.map_growable(cells),
+        }
+    }
+
+    fn epilogue(&mut self) {
+        self.exit();
+        if self.growable() {
+            self.tape_grow();
+        }
+        let buffered = self.buffered();
+        if buffered && (self.puts || self.gets) {
+            self.flush();
+        }
+        if buffered && self.puts {
+            self.put();
+        }
+        if buffered && self.gets {
+            self.get();
+        }
+        // `_flush` is also called before reading.
+        if self.puts || (buffered && self.gets) {
+            self.io_failed(WRITE_FAILED, "Could not write the output.\n");
+        }
+        if self.gets {
+            self.io_failed(READ_FAILED, "Could not read the input.\n");
+        }
+        if self.fails {
+            self.fail();
+        }
+    }
+
+    fn exit(&mut self) {
+        if self.buffered() && self.puts {
+            self.emit(Inst::Bl(String::from(FLUSH)));
+        }
+        if self.config.exit_cell {
+            self.load(Reg::R0, 0);
+        } else {
+            self.mov_imm(Reg::R0, 0);
+        }
+        self.syscall(Armv7::SYS_EXIT);
+    }
+
+    /// Grows the stack with zeroed words until it covers the address in the
+    /// given register.
+    fn grow_stack(&mut self, addr: Reg, loc: &Location) {
+        let start = self.labels.fresh(loc, "grow_start");
+        let end = self.labels.fresh(loc, "grow_end");
+        self.emit(Inst::B(end.clone()));
+        self.emit(Inst::Label(start.clone()));
+        self.push_zeros();
+        self.emit(Inst::Label(end));
+        self.emit(Inst::Mov(Reg::R3, Reg::SP));
+        self.emit(Inst::CmpReg(addr, Reg::R3));
+        self.emit(Inst::BCond(Cond::Lo, start));
+    }
+
+    fn next(&mut self, n: i64, loc: &Location) {
+        self.shift(n, loc);
+        if self.on_stack() {
+            if self.config.checked {
+                self.emit(Inst::CmpReg(TAPE, END));
+                self.out_of_bounds(Cond::Hs, loc);
+            }
+            self.grow_stack(TAPE, loc);
+        } else if self.checked() {
+            self.emit(Inst::CmpReg(TAPE, END));
+            self.bounds(Cond::Lo, 0, loc);
+        }
+    }
+
+    fn previous(&mut self, n: i64, loc: &Location) {
+        self.shift(-n, loc);
+        if self.on_stack() && self.config.checked {
+            self.emit(Inst::CmpReg(TAPE, START));
+            self.out_of_bounds(Cond::Ls, loc);
+        } else if self.checked() {
+            self.emit(Inst::CmpReg(TAPE, START));
+            self.bounds(Cond::Hs, 0, loc);
+        }
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// is found by comparing the cell against the largest value the addition
+    /// keeps in range, or against the amount of a subtraction.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let limit = if amount < 0 {0} else {mask as i64};
+        if magnitude == 0 {
+            return;
+        }
+        if magnitude > mask {
+            self.overflow(limit, offset, loc, None);
+            return;
+        }
+        let ok = self.labels.fresh(loc, "add_ok");
+        if self.pair() {
+            // The carry flag tells the overflow of the pair.
+            self.load_pair(Reg::R0, Reg::R1, offset);
+            self.add_pair(magnitude, amount < 0);
+            self.emit(Inst::BCond(if amount < 0 {Cond::Hs} else {Cond::Lo}, ok.clone()));
+            self.overflow(limit, offset, loc, Some(ok.clone()));
+            self.emit(Inst::Label(ok));
+            self.store_pair(Reg::R0, Reg::R1, offset);
+            return;
+        }
+        let store = self.labels.fresh(loc, "add_store");
+        self.load(Reg::R0, offset);
+        if amount < 0 {
+            self.cmp_imm(Reg::R0, magnitude as i64);
+            self.emit(Inst::BCond(Cond::Hs, ok.clone()));
+        } else {
+            self.cmp_imm(Reg::R0, (mask - magnitude) as i64);
+            self.emit(Inst::BCond(Cond::Ls, ok.clone()));
+        }
+        self.overflow(limit, offset, loc, Some(store.clone()));
+        self.emit(Inst::Label(ok));
+        self.add_imm(Reg::R0, Reg::R0, amount);
+        self.emit(Inst::Label(store));
+        self.store(Reg::R0, offset);
+    }
+
+    /// Handles an overflowing cell, which saturates to `limit` or traps. A
+    /// saturated value is stored at once, or left in `r0`, and `r1` for the
+    /// high word of a 64-bit cell, for the store at the given label.
+    fn overflow(&mut self, limit: i64, offset: i64, loc: &Location, store: Option<String>) {
+        if self.config.overflow == Overflow::Trap {
+            let message = format!("Cell overflow {}.\n", loc);
+            self.report(OVERFLOW_EXIT, message);
+            return;
+        }
+        match store {
+            Some(label) => {
+                self.mov_imm(Reg::R0, limit);
+                if self.pair() {
+                    self.mov_imm(Reg::R1, limit >> 32);
+                }
+                self.emit(Inst::B(label));
+            },
+            _ => self.store_imm(limit, offset),
+        }
+    }
+
+    /// Generates the instructions for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> Vec<Inst> {
+        self.prologue();
+        walk(&mut self, ir);
+        self.epilogue();
+        self.insts
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    /// The labels of the start and of the end of the loop.
+    type Loop = (String, String);
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of the tape are only detected by checked tapes, and the guard
+    /// regions of a heap tape fault on cells past either end.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        let addr = Reg::R2;
+        if self.on_stack() && offset > 0 {
+            self.cell_addr(addr, offset);
+            if self.config.checked {
+                self.emit(Inst::CmpReg(addr, END));
+                self.out_of_bounds(Cond::Hs, loc);
+            }
+            self.grow_stack(addr, loc);
+        } else if self.on_stack() && self.config.checked && offset < 0 {
+            self.cell_addr(addr, offset);
+            self.emit(Inst::CmpReg(addr, START));
+            self.out_of_bounds(Cond::Ls, loc);
+        } else if self.checked() && offset != 0 {
+            self.cell_addr(addr, offset);
+            if offset > 0 {
+                self.emit(Inst::CmpReg(addr, END));
+                self.bounds(Cond::Lo, offset, loc);
+            } else {
+                self.emit(Inst::CmpReg(addr, START));
+                self.bounds(Cond::Hs, offset, loc);
+            }
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, _loc: &Location) {
+        let disp = self.disp(n);
+        self.add_imm(TAPE, TAPE, disp);
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        if n > 0 {
+            self.next(n, loc);
+        } else if n < 0 {
+            self.previous(-n, loc);
+        }
+    }
+
+    /// Moves by `n` cells until a zero cell is found.
+    fn scan(&mut self, n: i64, loc: &Location) {
+        let body = self.labels.fresh(loc, "scan_body");
+        let test = self.labels.fresh(loc, "scan_test");
+        self.emit(Inst::B(test.clone()));
+        self.emit(Inst::Label(body.clone()));
+        self.move_by(n, loc);
+        self.emit(Inst::Label(test));
+        self.test_zero();
+        self.emit(Inst::BCond(Cond::Ne, body));
+    }
+
+    fn put_char(&mut self, offset: i64, _loc: &Location) {
+        let r = Reg;
+        if self.buffered() {
+            self.load(r(0), offset);
+            self.emit(Inst::Bl(String::from(PUT)));
+        } else {
+            self.cell_addr(r(1), offset);
+            self.mov_imm(r(0), 1);
+            self.mov_imm(r(2), 1);
+            self.syscall(Armv7::SYS_WRITE);
+            self.emit(Inst::CmpImm(r(0), 1));
+            self.emit(Inst::BCond(Cond::Ne, String::from(WRITE_FAILED)));
+        }
+        self.puts = true;
+    }
+
+    fn get_char(&mut self, offset: i64, loc: &Location) {
+        let r = Reg;
+        let end = self.labels.fresh(loc, "getc_end");
+        self.gets = true;
+        if self.buffered() {
+            self.emit(Inst::Bl(String::from(GET)));
+            match self.config.eof {
+                Eof::MinusOne => (),
+                Eof::Zero => {
+                    self.emit(Inst::CmpImm(r(0), 0));
+                    self.emit(Inst::BCond(Cond::Ge, end.clone()));
+                    self.mov_imm(r(0), 0);
+                    self.emit(Inst::Label(end.clone()));
+                },
+                Eof::Unchanged => {
+                    self.emit(Inst::CmpImm(r(0), 0));
+                    self.emit(Inst::BCond(Cond::Lt, end.clone()));
+                },
+            }
+            self.store_int(offset);
+            if self.config.eof == Eof::Unchanged {
+                self.emit(Inst::Label(end));
+            }
+            return;
+        }
+        // The byte read only fills the lowest byte of the cell.
+        if self.config.eof != Eof::Unchanged {
+            self.store_imm(0, offset);
+        }
+        self.cell_addr(r(1), offset);
+        self.mov_imm(r(0), 0);
+        self.mov_imm(r(2), 1);
+        self.syscall(Armv7::SYS_READ);
+        self.emit(Inst::CmpImm(r(0), 0));
+        self.emit(Inst::BCond(Cond::Lt, String::from(READ_FAILED)));
+        match self.config.eof {
+            Eof::MinusOne => {
+                self.emit(Inst::BCond(Cond::Ne, end.clone()));
+                self.store_imm(-1, offset);
+            },
+            Eof::Zero => (),
+            Eof::Unchanged => if self.cw() != Width::Byte {
+                self.emit(Inst::BCond(Cond::Eq, end.clone()));
+                self.emit(Inst::Ldr(Width::Byte, r(0), r(1), 0));
+                self.store_int(offset);
+            },
+        }
+        self.emit(Inst::Label(end));
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let amount = self.config.cell_size.wrap(amount);
+        if amount == 0 {
+            return;
+        }
+        if self.pair() {
+            self.load_pair(Reg::R0, Reg::R1, offset);
+            self.add_pair(amount as u64, false);
+            self.store_pair(Reg::R0, Reg::R1, offset);
+            return;
+        }
+        self.load(Reg::R0, offset);
+        self.add_imm(Reg::R0, Reg::R0, amount);
+        self.store(Reg::R0, offset);
+    }
+
+    fn set(&mut self, offset: i64, value: i64, _loc: &Location) {
+        self.store_imm(value, offset);
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, _loc: &Location) {
+        let r = Reg;
+        let factor = self.config.cell_size.wrap(factor);
+        if factor == 0 {
+            return;
+        }
+        if self.pair() {
+            self.mul_pair(src, dst, factor);
+            return;
+        }
+        self.load(r(0), src);
+        self.load(r(1), dst);
+        match factor as u32 {
+            1 => self.emit(Inst::AddReg(r(1), r(1), r(0))),
+            0xFFFF_FFFF => self.emit(Inst::SubReg(r(1), r(1), r(0))),
+            _ => {
+                self.mov_imm(r(2), factor);
+                self.emit(Inst::Mul(r(0), r(0), r(2)));
+                self.emit(Inst::AddReg(r(1), r(1), r(0)));
+            },
+        }
+        self.store(r(1), dst);
+    }
+
+    fn open_loop(&mut self, loc: &Location) -> (String, String) {
+        let label = label_for(loc);
+        let start = label.clone() + "_loop_start";
+        let end = label + "_loop_end";
+        self.emit(Inst::B(end.clone()));
+        self.emit(Inst::Label(start.clone()));
+        (start, end)
+    }
+
+    fn close_loop(&mut self, (start, end): (String, String)) {
+        self.emit(Inst::Label(end));
+        self.test_zero();
+        self.emit(Inst::BCond(Cond::Ne, start));
+    }
+
+}
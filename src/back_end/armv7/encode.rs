@@ -0,0 +1,331 @@
+use std::io::{
+    Error,
+    ErrorKind,
+};
+use std::collections::{
+    HashMap,
+};
+use super::inst::{
+    Inst,
+    Reg,
+    Width,
+    modified_imm,
+};
+
+
+/// Machine code produced from a list of instructions, together with the
+/// offset of every label defined by it.
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+}
+
+/// The condition field of instructions which always execute.
+const AL: u32 = 0xE000_0000;
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn size(inst: &Inst) -> usize {
+    match *inst {
+        Inst::Label(_) => 0,
+        Inst::Data(ref bytes) => bytes.len(),
+        _ => 4,
+    }
+}
+
+/// Encodes the offset from `pos` to the label in words. The offset is
+/// relative to the instruction after the next one, as the program counter
+/// reads ahead.
+fn branch(labels: &HashMap<String, usize>, label: &str, pos: usize) -> Result<u32, Error> {
+    let target = match labels.get(label) {
+        Some(&t) => t,
+        _ => return Err(invalid(format!("Undefined label {}.", label))),
+    };
+    let words = (target as i64 - pos as i64 - 8) / 4;
+    if !(-(1 << 23) .. 1 << 23).contains(&words) {
+        return Err(invalid(format!("Label {} is out of reach.", label)));
+    }
+    Ok(words as u32 & 0xFF_FFFF)
+}
+
+fn imm(value: u32) -> Result<u32, Error> {
+    match modified_imm(value) {
+        Some(field) => Ok(field),
+        _ => Err(invalid(format!("Immediate {} cannot be encoded.", value))),
+    }
+}
+
+fn wide(base: u32, rd: Reg, imm: u16) -> u32 {
+    let imm = imm as u32;
+    AL | base | (imm >> 12) << 16 | rd.code() << 12 | (imm & 0xFFF)
+}
+
+fn data(base: u32, rd: Reg, rn: Reg, op2: u32) -> u32 {
+    AL | base | rn.code() << 16 | rd.code() << 12 | op2
+}
+
+/// Encodes a `mov` whose operand is shifted right by a constant, logically
+/// if `kind` is 0x20 and arithmetically if it is 0x40.
+fn shifted(rd: Reg, rm: Reg, kind: u32, shift: u8) -> Result<u32, Error> {
+    if shift == 0 || shift >= 32 {
+        return Err(invalid(format!("Shift {} is not supported.", shift)));
+    }
+    Ok(data(0x01A0_0000, rd, Reg(0), (shift as u32) << 7 | kind | rm.code()))
+}
+
+/// Encodes a load or a store. `index` is 0 for post-indexing, 2 for an
+/// offset and 3 for pre-indexing, which are the P and W bits.
+fn mem(
+    width: Width,
+    load: bool,
+    index: u32,
+    rt: Reg,
+    rn: Reg,
+    disp: i32
+) -> Result<u32, Error> {
+    if !width.fits(disp) {
+        return Err(invalid(format!("Offset {} is out of reach.", disp)));
+    }
+    let up = (disp >= 0) as u32;
+    let magnitude = disp.unsigned_abs();
+    let base = AL | (index >> 1) << 24 | up << 23 | (index & 1) << 21
+        | (load as u32) << 20 | rn.code() << 16 | rt.code() << 12;
+    Ok(match width {
+        Width::Half => base | 0x0040_00B0 | (magnitude >> 4) << 8 | (magnitude & 0xF),
+        Width::Byte => base | 0x0440_0000 | magnitude,
+        Width::Word => base | 0x0400_0000 | magnitude,
+    })
+}
+
+fn encode(
+    inst: &Inst,
+    pos: usize,
+    labels: &HashMap<String, usize>
+) -> Result<u32, Error> {
+    Ok(match *inst {
+        Inst::Movw(rd, value) => wide(0x0300_0000, rd, value),
+        Inst::Movt(rd, value) => wide(0x0340_0000, rd, value),
+        Inst::MovImm(rd, value) => data(0x03A0_0000, rd, Reg(0), imm(value)?),
+        Inst::MvnImm(rd, value) => data(0x03E0_0000, rd, Reg(0), imm(value)?),
+        Inst::Mov(rd, rm) => data(0x01A0_0000, rd, Reg(0), rm.code()),
+        Inst::Lsr(rd, rm, shift) => shifted(rd, rm, 0x20, shift)?,
+        Inst::Asr(rd, rm, shift) => shifted(rd, rm, 0x40, shift)?,
+        Inst::AddImm(rd, rn, value) => data(0x0280_0000, rd, rn, imm(value)?),
+        Inst::SubImm(rd, rn, value) => data(0x0240_0000, rd, rn, imm(value)?),
+        Inst::AddReg(rd, rn, rm) => data(0x0080_0000, rd, rn, rm.code()),
+        Inst::SubReg(rd, rn, rm) => data(0x0040_0000, rd, rn, rm.code()),
+        Inst::Adds(rd, rn, rm) => data(0x0090_0000, rd, rn, rm.code()),
+        Inst::Adcs(rd, rn, rm) => data(0x00B0_0000, rd, rn, rm.code()),
+        Inst::Subs(rd, rn, rm) => data(0x0050_0000, rd, rn, rm.code()),
+        Inst::Sbcs(rd, rn, rm) => data(0x00D0_0000, rd, rn, rm.code()),
+        Inst::Orrs(rd, rn, rm) => data(0x0190_0000, rd, rn, rm.code()),
+        Inst::CmpImm(rn, value) => data(0x0350_0000, Reg(0), rn, imm(value)?),
+        Inst::CmpReg(rn, rm) => data(0x0150_0000, Reg(0), rn, rm.code()),
+        Inst::Mul(rd, rn, rm) => AL | 0x90 | rd.code() << 16 | rm.code() << 8 | rn.code(),
+        Inst::Mla(rd, rn, rm, ra) => AL | 0x0020_0090 | rd.code() << 16
+            | ra.code() << 12 | rm.code() << 8 | rn.code(),
+        Inst::Umull(lo, hi, rn, rm) => AL | 0x0080_0090 | hi.code() << 16
+            | lo.code() << 12 | rm.code() << 8 | rn.code(),
+        Inst::Ldr(w, rt, rn, disp) => mem(w, true, 2, rt, rn, disp)?,
+        Inst::Str(w, rt, rn, disp) => mem(w, false, 2, rt, rn, disp)?,
+        Inst::LdrPost(w, rt, rn, disp) => mem(w, true, 0, rt, rn, disp)?,
+        Inst::StrPost(w, rt, rn, disp) => mem(w, false, 0, rt, rn, disp)?,
+        Inst::StrPre(w, rt, rn, disp) => mem(w, false, 3, rt, rn, disp)?,
+        Inst::B(ref label) => AL | 0x0A00_0000 | branch(labels, label, pos)?,
+        Inst::Bl(ref label) => AL | 0x0B00_0000 | branch(labels, label, pos)?,
+        Inst::BCond(cond, ref label) =>
+            cond.code() << 28 | 0x0A00_0000 | branch(labels, label, pos)?,
+        Inst::Ret => 0xE12F_FF1E,
+        Inst::Svc(n) => {
+            if n >= 1 << 24 {
+                return Err(invalid(format!("Immediate {} does not fit in 24 bits.", n)));
+            }
+            AL | 0x0F00_0000 | n
+        },
+        Inst::Label(_) | Inst::Data(_) => unreachable!(),
+    })
+}
+
+/// Encodes the given instructions into machine code. Labels are resolved
+/// here.
+pub fn assemble(insts: &[Inst]) -> Result<Program, Error> {
+    let mut labels = HashMap::new();
+    let mut pos = 0;
+    for inst in insts {
+        if let Inst::Label(ref label) = *inst {
+            labels.insert(label.clone(), pos);
+        }
+        pos += size(inst);
+    }
+    let mut code = Vec::with_capacity(pos);
+    for inst in insts {
+        match *inst {
+            Inst::Label(_) => (),
+            Inst::Data(ref bytes) => code.extend_from_slice(bytes),
+            _ => match encode(inst, code.len(), &labels) {
+                Ok(word) => code.extend_from_slice(&word.to_le_bytes()),
+                Err(e) => return Err(invalid(format!(
+                    "Cannot encode `{}`: {}",
+                    inst.to_string().trim(),
+                    e
+                ))),
+            },
+        }
+    }
+    Ok(Program {code, labels})
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        assemble,
+    };
+    use super::super::inst::{
+        Inst,
+        Reg,
+        Width,
+        Cond,
+    };
+
+    fn r(n: u8) -> Reg {
+        Reg(n)
+    }
+
+    fn label(name: &str) -> Inst {
+        Inst::Label(name.to_string())
+    }
+
+    fn code(insts: &[Inst]) -> Vec<u8> {
+        assemble(insts).unwrap().code
+    }
+
+    // The expected bytes are the output of `llvm-mc -triple=armv7`.
+    #[test]
+    fn data_processing() {
+        let cases = vec![
+            (Inst::Movw(r(0), 0xABCD), vec![0xCD, 0x0B, 0x0A, 0xE3]),
+            (Inst::Movt(r(1), 0xFFFF), vec![0xFF, 0x1F, 0x4F, 0xE3]),
+            (Inst::MovImm(r(2), 255), vec![0xFF, 0x20, 0xA0, 0xE3]),
+            (Inst::MovImm(r(2), 0xFF00_0000), vec![0xFF, 0x24, 0xA0, 0xE3]),
+            (Inst::MovImm(r(3), 0x3FC), vec![0xFF, 0x3F, 0xA0, 0xE3]),
+            (Inst::MvnImm(r(4), 0), vec![0x00, 0x40, 0xE0, 0xE3]),
+            (Inst::Mov(r(5), r(6)), vec![0x06, 0x50, 0xA0, 0xE1]),
+            (Inst::Lsr(r(7), r(8), 1), vec![0xA8, 0x70, 0xA0, 0xE1]),
+            (Inst::Lsr(r(0), r(1), 31), vec![0xA1, 0x0F, 0xA0, 0xE1]),
+            (Inst::AddImm(r(9), r(10), 1020), vec![0xFF, 0x9F, 0x8A, 0xE2]),
+            (Inst::SubImm(Reg::SP, Reg::SP, 4096), vec![0x01, 0xDA, 0x4D, 0xE2]),
+            (Inst::AddReg(r(0), r(1), r(2)), vec![0x02, 0x00, 0x81, 0xE0]),
+            (Inst::SubReg(r(3), r(4), r(5)), vec![0x05, 0x30, 0x44, 0xE0]),
+            (Inst::CmpImm(r(6), 0), vec![0x00, 0x00, 0x56, 0xE3]),
+            (Inst::CmpReg(r(6), r(7)), vec![0x07, 0x00, 0x56, 0xE1]),
+            (Inst::Mul(r(0), r(1), r(2)), vec![0x91, 0x02, 0x00, 0xE0]),
+            (Inst::Asr(r(1), r(0), 31), vec![0xC0, 0x1F, 0xA0, 0xE1]),
+            (Inst::Adds(r(0), r(1), r(2)), vec![0x02, 0x00, 0x91, 0xE0]),
+            (Inst::Adcs(r(3), r(4), r(5)), vec![0x05, 0x30, 0xB4, 0xE0]),
+            (Inst::Subs(r(0), r(1), r(2)), vec![0x02, 0x00, 0x51, 0xE0]),
+            (Inst::Sbcs(r(3), r(4), r(5)), vec![0x05, 0x30, 0xD4, 0xE0]),
+            (Inst::Orrs(r(0), r(0), r(1)), vec![0x01, 0x00, 0x90, 0xE1]),
+            (Inst::Umull(r(2), r(1), r(0), r(4)), vec![0x90, 0x24, 0x81, 0xE0]),
+            (Inst::Mla(r(3), r(0), r(5), r(3)), vec![0x90, 0x35, 0x23, 0xE0]),
+            (Inst::Ret, vec![0x1E, 0xFF, 0x2F, 0xE1]),
+            (Inst::Svc(0), vec![0x00, 0x00, 0x00, 0xEF]),
+            (Inst::Svc(0xFF_FFFF), vec![0xFF, 0xFF, 0xFF, 0xEF]),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(std::slice::from_ref(&inst)), bytes, "{}", inst);
+        }
+        let cases = [
+            Inst::MovImm(r(0), 257),
+            Inst::AddImm(r(0), r(0), 0x102),
+            Inst::CmpImm(r(0), 0xFFFF),
+            Inst::Lsr(r(0), r(0), 0),
+            Inst::Lsr(r(0), r(0), 32),
+            Inst::Asr(r(0), r(0), 0),
+            Inst::Svc(1 << 24),
+        ];
+        for inst in &cases {
+            assert!(assemble(std::slice::from_ref(inst)).is_err(), "{}", inst);
+        }
+    }
+
+    #[test]
+    fn memory_access() {
+        let cases = vec![
+            (Inst::Ldr(Width::Half, r(0), r(4), 255), vec![0xBF, 0x0F, 0xD4, 0xE1]),
+            (Inst::Ldr(Width::Half, r(0), r(4), -2), vec![0xB2, 0x00, 0x54, 0xE1]),
+            (Inst::Str(Width::Half, r(1), r(4), 0), vec![0xB0, 0x10, 0xC4, 0xE1]),
+            (Inst::Ldr(Width::Byte, r(2), r(4), 4095), vec![0xFF, 0x2F, 0xD4, 0xE5]),
+            (Inst::Str(Width::Byte, r(2), r(4), -4095), vec![0xFF, 0x2F, 0x44, 0xE5]),
+            (Inst::Ldr(Width::Word, r(3), Reg::SP, 8), vec![0x08, 0x30, 0x9D, 0xE5]),
+            (Inst::Str(Width::Word, r(3), r(4), -4), vec![0x04, 0x30, 0x04, 0xE5]),
+            (Inst::LdrPost(Width::Byte, r(0), r(1), 1), vec![0x01, 0x00, 0xD1, 0xE4]),
+            (Inst::StrPost(Width::Half, r(0), r(1), -2), vec![0xB2, 0x00, 0x41, 0xE0]),
+            (
+                Inst::StrPre(Width::Word, Reg::LR, Reg::SP, -4),
+                vec![0x04, 0xE0, 0x2D, 0xE5],
+            ),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(std::slice::from_ref(&inst)), bytes, "{}", inst);
+        }
+        let cases = [
+            Inst::Ldr(Width::Half, r(0), r(4), 256),
+            Inst::Str(Width::Byte, r(0), r(4), -4096),
+            Inst::LdrPost(Width::Word, r(0), r(4), 4096),
+        ];
+        for inst in &cases {
+            assert!(assemble(std::slice::from_ref(inst)).is_err(), "{}", inst);
+        }
+    }
+
+    #[test]
+    fn branches_and_calls() {
+        // Offsets are relative to the instruction after the next one.
+        let insts = [
+            label("start"),
+            Inst::BCond(Cond::Eq, String::from("start")),
+            Inst::BCond(Cond::Ne, String::from("end")),
+            Inst::Bl(String::from("end")),
+            Inst::B(String::from("start")),
+            Inst::BCond(Cond::Hs, String::from("start")),
+            label("end"),
+        ];
+        let program = assemble(&insts).unwrap();
+        assert_eq!(program.code, vec![
+            0xFE, 0xFF, 0xFF, 0x0A,
+            0x02, 0x00, 0x00, 0x1A,
+            0x01, 0x00, 0x00, 0xEB,
+            0xFB, 0xFF, 0xFF, 0xEA,
+            0xFA, 0xFF, 0xFF, 0x2A,
+        ]);
+        assert_eq!(program.labels["end"], 20);
+        let undefined = [Inst::Bl(String::from("nowhere"))];
+        assert!(assemble(&undefined).is_err());
+    }
+
+    #[test]
+    fn branch_reach() {
+        // Branches reach 32 MiB either way.
+        let far = |gap: usize| assemble(&[
+            Inst::B(String::from("far")),
+            Inst::Data(vec![0; gap]),
+            label("far"),
+        ]);
+        let gap = 1 << 25;
+        assert_eq!(far(gap).unwrap().code[.. 4], [0xFF, 0xFF, 0x7F, 0xEA]);
+        assert!(far(gap + 4).is_err());
+        let back = |gap: usize| assemble(&[
+            label("back"),
+            Inst::Data(vec![0; gap]),
+            Inst::BCond(Cond::Lo, String::from("back")),
+        ]);
+        let gap = (1 << 25) - 8;
+        assert_eq!(back(gap).unwrap().code[gap ..], [0x00, 0x00, 0x80, 0x3A]);
+        assert!(back(gap + 4).is_err());
+    }
+
+}
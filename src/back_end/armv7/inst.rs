@@ -0,0 +1,273 @@
+use std::{
+    fmt,
+};
+
+
+/// A core register, from `r0` to `r15`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reg(pub u8);
+
+impl Reg {
+
+    pub const R0: Reg = Reg(0);
+    pub const R1: Reg = Reg(1);
+    pub const R2: Reg = Reg(2);
+    pub const R3: Reg = Reg(3);
+    pub const R4: Reg = Reg(4);
+    pub const R5: Reg = Reg(5);
+    pub const R6: Reg = Reg(6);
+    pub const R7: Reg = Reg(7);
+    pub const R8: Reg = Reg(8);
+    pub const R9: Reg = Reg(9);
+    pub const R10: Reg = Reg(10);
+    pub const R11: Reg = Reg(11);
+    pub const IP: Reg = Reg(12);
+    pub const SP: Reg = Reg(13);
+    pub const LR: Reg = Reg(14);
+
+    /// The 4-bit register number used by the encoding.
+    pub fn code(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn name(self) -> String {
+        match self.0 {
+            12 => String::from("ip"),
+            13 => String::from("sp"),
+            14 => String::from("lr"),
+            15 => String::from("pc"),
+            n => format!("r{}", n),
+        }
+    }
+
+}
+
+/// Size of a memory access. Loads zero extend into the register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Width::Byte => "b",
+            Width::Half => "h",
+            Width::Word => "",
+        }
+    }
+
+    /// Whether `disp` fits the offset of a load or a store of this width.
+    pub fn fits(self, disp: i32) -> bool {
+        let limit = if self == Width::Half {256} else {4096};
+        -limit < disp && disp < limit
+    }
+
+}
+
+/// Encodes a value as the modified immediate of data processing
+/// instructions, an 8-bit value rotated right by an even amount.
+pub fn modified_imm(value: u32) -> Option<u32> {
+    (0 .. 16)
+        .find(|&rot| value.rotate_left(2 * rot) < 256)
+        .map(|rot| rot << 8 | value.rotate_left(2 * rot))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Eq = 0x0,
+    Ne = 0x1,
+    Hs = 0x2,
+    Lo = 0x3,
+    Hi = 0x8,
+    Ls = 0x9,
+    Ge = 0xA,
+    Lt = 0xB,
+    Gt = 0xC,
+    Le = 0xD,
+}
+
+impl Cond {
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Hs => "hs",
+            Cond::Lo => "lo",
+            Cond::Hi => "hi",
+            Cond::Ls => "ls",
+            Cond::Ge => "ge",
+            Cond::Lt => "lt",
+            Cond::Gt => "gt",
+            Cond::Le => "le",
+        }
+    }
+
+}
+
+/// The subset of ARMv7 instructions, in the ARM instruction set, the code
+/// generator emits. Operands are in assembly order: destination first.
+/// Immediates of data processing instructions must fit `modified_imm`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inst {
+    Label(String),
+    /// Moves a 16-bit immediate, clearing the upper half.
+    Movw(Reg, u16),
+    /// Replaces the upper half with a 16-bit immediate.
+    Movt(Reg, u16),
+    MovImm(Reg, u32),
+    /// Moves the inverse of an immediate.
+    MvnImm(Reg, u32),
+    Mov(Reg, Reg),
+    /// Logical right shift by a constant from 1 to 31.
+    Lsr(Reg, Reg, u8),
+    /// Arithmetic right shift by a constant from 1 to 31.
+    Asr(Reg, Reg, u8),
+    AddImm(Reg, Reg, u32),
+    SubImm(Reg, Reg, u32),
+    AddReg(Reg, Reg, Reg),
+    SubReg(Reg, Reg, Reg),
+    /// Adds, setting the carry flag on unsigned overflow.
+    Adds(Reg, Reg, Reg),
+    /// Adds with the carry flag, setting it again.
+    Adcs(Reg, Reg, Reg),
+    /// Subtracts, clearing the carry flag on borrow.
+    Subs(Reg, Reg, Reg),
+    /// Subtracts with the borrow the carry flag tells, setting it again.
+    Sbcs(Reg, Reg, Reg),
+    /// Ors, setting the zero flag.
+    Orrs(Reg, Reg, Reg),
+    /// Subtracts without keeping the result, setting the flags.
+    CmpImm(Reg, u32),
+    CmpReg(Reg, Reg),
+    Mul(Reg, Reg, Reg),
+    /// Multiplies the last two registers and adds the product to the
+    /// fourth one.
+    Mla(Reg, Reg, Reg, Reg),
+    /// Multiplies the last two registers, unsigned, into the first one, the
+    /// low word, and the second one.
+    Umull(Reg, Reg, Reg, Reg),
+    /// Loads from the base register plus a displacement.
+    Ldr(Width, Reg, Reg, i32),
+    Str(Width, Reg, Reg, i32),
+    /// Loads from the base register, which is then moved by the offset.
+    LdrPost(Width, Reg, Reg, i32),
+    StrPost(Width, Reg, Reg, i32),
+    /// Stores at the base register plus the offset, which is moved there
+    /// first.
+    StrPre(Width, Reg, Reg, i32),
+    B(String),
+    BCond(Cond, String),
+    /// Calls a label, leaving the return address in `lr`.
+    Bl(String),
+    /// Returns through `lr`.
+    Ret,
+    Svc(u32),
+    /// Raw bytes placed among the instructions, a multiple of 4 bytes long.
+    Data(Vec<u8>),
+}
+
+impl Inst {
+
+    fn mem(name: &str, width: Width, rt: Reg, rn: Reg, disp: i32) -> String {
+        if disp == 0 {
+            format!("  {}{} {}, [{}]", name, width.suffix(), rt.name(), rn.name())
+        } else {
+            format!(
+                "  {}{} {}, [{}, #{}]",
+                name,
+                width.suffix(),
+                rt.name(),
+                rn.name(),
+                disp
+            )
+        }
+    }
+
+    fn post(name: &str, width: Width, rt: Reg, rn: Reg, disp: i32) -> String {
+        format!("  {}{} {}, [{}], #{}", name, width.suffix(), rt.name(), rn.name(), disp)
+    }
+
+    fn reg3(name: &str, rd: Reg, rn: Reg, rm: Reg) -> String {
+        format!("  {} {}, {}, {}", name, rd.name(), rn.name(), rm.name())
+    }
+
+}
+
+impl fmt::Display for Inst {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", match *self {
+            Inst::Label(ref label) => format!("  {}:", label),
+            Inst::Movw(rd, imm) => format!("  movw {}, #{}", rd.name(), imm),
+            Inst::Movt(rd, imm) => format!("  movt {}, #{}", rd.name(), imm),
+            Inst::MovImm(rd, imm) => format!("  mov {}, #{}", rd.name(), imm),
+            Inst::MvnImm(rd, imm) => format!("  mvn {}, #{}", rd.name(), imm),
+            Inst::Mov(rd, rm) => format!("  mov {}, {}", rd.name(), rm.name()),
+            Inst::Lsr(rd, rm, shift) =>
+                format!("  lsr {}, {}, #{}", rd.name(), rm.name(), shift),
+            Inst::Asr(rd, rm, shift) =>
+                format!("  asr {}, {}, #{}", rd.name(), rm.name(), shift),
+            Inst::AddImm(rd, rn, imm) =>
+                format!("  add {}, {}, #{}", rd.name(), rn.name(), imm),
+            Inst::SubImm(rd, rn, imm) =>
+                format!("  sub {}, {}, #{}", rd.name(), rn.name(), imm),
+            Inst::AddReg(rd, rn, rm) => Inst::reg3("add", rd, rn, rm),
+            Inst::SubReg(rd, rn, rm) => Inst::reg3("sub", rd, rn, rm),
+            Inst::Adds(rd, rn, rm) => Inst::reg3("adds", rd, rn, rm),
+            Inst::Adcs(rd, rn, rm) => Inst::reg3("adcs", rd, rn, rm),
+            Inst::Subs(rd, rn, rm) => Inst::reg3("subs", rd, rn, rm),
+            Inst::Sbcs(rd, rn, rm) => Inst::reg3("sbcs", rd, rn, rm),
+            Inst::Orrs(rd, rn, rm) => Inst::reg3("orrs", rd, rn, rm),
+            Inst::CmpImm(rn, imm) => format!("  cmp {}, #{}", rn.name(), imm),
+            Inst::CmpReg(rn, rm) => format!("  cmp {}, {}", rn.name(), rm.name()),
+            Inst::Mul(rd, rn, rm) => Inst::reg3("mul", rd, rn, rm),
+            Inst::Mla(rd, rn, rm, ra) => format!(
+                "  mla {}, {}, {}, {}",
+                rd.name(),
+                rn.name(),
+                rm.name(),
+                ra.name()
+            ),
+            Inst::Umull(lo, hi, rn, rm) => format!(
+                "  umull {}, {}, {}, {}",
+                lo.name(),
+                hi.name(),
+                rn.name(),
+                rm.name()
+            ),
+            Inst::Ldr(w, rt, rn, disp) => Inst::mem("ldr", w, rt, rn, disp),
+            Inst::Str(w, rt, rn, disp) => Inst::mem("str", w, rt, rn, disp),
+            Inst::LdrPost(w, rt, rn, disp) => Inst::post("ldr", w, rt, rn, disp),
+            Inst::StrPost(w, rt, rn, disp) => Inst::post("str", w, rt, rn, disp),
+            Inst::StrPre(w, rt, rn, disp) => format!(
+                "  str{} {}, [{}, #{}]!",
+                w.suffix(),
+                rt.name(),
+                rn.name(),
+                disp
+            ),
+            Inst::B(ref label) => format!("  b {}", label),
+            Inst::BCond(cond, ref label) => format!("  b{} {}", cond.name(), label),
+            Inst::Bl(ref label) => format!("  bl {}", label),
+            Inst::Ret => String::from("  bx lr"),
+            Inst::Svc(n) => format!("  svc #{}", n),
+            Inst::Data(ref bytes) => format!(
+                "  .byte {}",
+                bytes.iter()
+                    .map(|b| b.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        })
+    }
+
+}
@@ -0,0 +1,179 @@
+pub mod inst;
+pub mod encode;
+pub mod codegen;
+
+pub use self::inst::{
+    Inst,
+    Reg,
+    Width,
+    Cond,
+};
+pub use self::encode::{
+    assemble,
+    Program,
+};
+pub use self::codegen::{
+    Codegen,
+};
+
+use std::io::{
+    Write,
+    Error,
+    ErrorKind,
+};
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Tape,
+};
+use super::{
+    Arch,
+    Format,
+    create_output,
+};
+use super::elf::{
+    self,
+    Executable,
+};
+use std::os::unix::fs::{
+    PermissionsExt,
+};
+use std::{
+    fs,
+};
+
+
+/// Size of the guard regions around a heap tape, and the size heap tapes
+/// are rounded to, one page.
+pub const GUARD_SIZE: i64 = 0x1000;
+
+/// The 32-bit ARM architecture, from ARMv7 on, running Linux with the EABI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Armv7;
+
+impl Armv7 {
+
+    const SYS_EXIT: i64 = 1;
+    const SYS_READ: i64 = 3;
+    const SYS_WRITE: i64 = 4;
+    const SYS_MUNMAP: i64 = 91;
+    const SYS_MPROTECT: i64 = 125;
+    /// `ugetrlimit`, which gives unsigned limits.
+    const SYS_UGETRLIMIT: i64 = 191;
+    const SYS_MMAP2: i64 = 192;
+
+    /// The memory access width of cells of the given size. 64-bit cells are
+    /// accessed a word at a time.
+    pub fn cell_width(size: CellSize) -> Width {
+        match size {
+            CellSize::Bits8 => Width::Byte,
+            CellSize::Bits16 => Width::Half,
+            CellSize::Bits32 | CellSize::Bits64 => Width::Word,
+        }
+    }
+
+    /// Fails if the configuration cannot be generated.
+    pub fn check(&self, config: &Config) -> Result<(), Error> {
+        if let Tape::Heap(cells) | Tape::Bidirectional(cells) = config.tape {
+            let fits = cells.checked_mul(config.cell_size.bytes() as u64)
+                .is_some_and(|bytes| bytes <= 1 << 30);
+            if !fits {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The tape is too big for the architecture."
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The size in bytes of a heap tape, rounded up to whole guard regions.
+    /// The size must have been checked.
+    pub fn heap_bytes(cells: u64, size: CellSize) -> u64 {
+        let bytes = cells * size.bytes() as u64;
+        let guard = GUARD_SIZE as u64;
+        (bytes + guard - 1) & !(guard - 1)
+    }
+
+    /// Generates the instructions for a standalone program, starting at the
+    /// `_start` entry point.
+    pub fn gen_insts(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Vec<Inst>, Error> {
+        self.check(config)?;
+        Ok(Codegen::new(config).generate(ir))
+    }
+
+    pub fn gen_asm<T: Write>(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        out: &mut T
+    ) -> Result<usize, Error> {
+        let mut text = String::from(
+            ".syntax unified\n.arm\n.text\n.globl _start\n_start:\n"
+        );
+        for inst in self.gen_insts(ir, config)? {
+            text += &inst.to_string();
+            text.push('\n');
+        }
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    /// Generates a complete, statically linked executable for the program.
+    pub fn gen_elf(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Executable, Error> {
+        let program = assemble(&self.gen_insts(ir, config)?)?;
+        Ok(Executable {
+            class: elf::Class::Elf32,
+            machine: elf::EM_ARM,
+            flags: elf::EF_ARM_EABI_VER5,
+            text: program.code,
+            entry: 0,
+        })
+    }
+
+}
+
+impl Arch for Armv7 {
+
+    fn generate(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        format: Format,
+        out: String
+    ) -> Result<usize, Error> {
+        match format {
+            Format::Asm => self.gen_asm(ir, config, &mut create_output(&out)?),
+            Format::Elf => {
+                let exe = self.gen_elf(ir, config)?;
+                let amount = exe.write(&mut create_output(&out)?)?;
+                fs::set_permissions(&out, fs::Permissions::from_mode(0o755))?;
+                Ok(amount)
+            },
+            Format::Object(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The `obj` format is not supported by the armv7 back end."
+            )),
+            Format::Bin => {
+                let program = assemble(&self.gen_insts(ir, config)?)?;
+                create_output(&out)?.write_all(&program.code)?;
+                Ok(program.code.len())
+            },
+        }
+    }
+
+}
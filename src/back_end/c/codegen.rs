@@ -0,0 +1,696 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Eof,
+    Overflow,
+    Tape,
+};
+use back_end::{
+    Emitter,
+    walk,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+    STACK_CELLS,
+};
+use back_end::linux::{
+    STACK_CAP,
+};
+
+
+/// Escapes text for a string literal. Question marks are escaped too, so
+/// that no trigraph is formed.
+pub fn literal(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for &byte in text.as_bytes() {
+        match byte {
+            b'\\' | b'"' | b'?' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            },
+            b'\n' => escaped += "\\n",
+            b' ' ..= b'~' => escaped.push(byte as char),
+            _ => escaped += &format!("\\{:03o}", byte),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Generates a C99 translation unit. Standalone programs are a `main`
+/// doing I/O through stdio, with the tape allocated by `calloc`. Functions
+/// take the tape and the I/O callbacks, like the `obj` format of x86:
+/// `bf_cell *f(bf_cell *tape, int (*get)(void), int (*put)(int))`.
+/// Cells are unsigned integers of the configured width, so they wrap
+/// without undefined behavior.
+#[derive(Clone, Debug)]
+pub struct Codegen {
+    config: Config,
+    /// The name of the function the program is, if it is not `main`.
+    symbol: Option<String>,
+    /// The lines of the function body.
+    body: String,
+    depth: usize,
+    /// The file and line the next line of the body is numbered with, since
+    /// the last `#line` directive.
+    mapped: Option<(String, u64)>,
+    /// Whether `bf_fail` is called.
+    fails: bool,
+    /// Whether `bf_grow` is called.
+    grows: bool,
+    /// Whether the program writes.
+    puts: bool,
+    /// Whether the program reads.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator for a `main` function, or for a function
+    /// named `symbol`.
+    pub fn new(config: &Config, symbol: Option<String>) -> Self {
+        Self {
+            config: config.clone(),
+            symbol,
+            body: String::new(),
+            depth: 1,
+            mapped: None,
+            fails: false,
+            grows: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    /// Adds a line to the body, numbered as the line after the previous
+    /// one.
+    fn line(&mut self, text: &str) {
+        self.body += &"    ".repeat(self.depth);
+        self.body += text;
+        self.body.push('\n');
+        if let Some((_, ref mut line)) = self.mapped {
+            *line += 1;
+        }
+    }
+
+    /// Adds a line for an operation at `loc`, preceded by a `#line`
+    /// directive if it would not be numbered with the line of `loc`.
+    fn stmt(&mut self, loc: &Location, text: &str) {
+        let mapped = match self.mapped {
+            Some((ref file, line)) => *file == loc.file && line == loc.line,
+            None => false,
+        };
+        if !mapped {
+            self.body += &format!("#line {} {}\n", loc.line, literal(&loc.file));
+            self.mapped = Some((loc.file.clone(), loc.line));
+        }
+        self.line(text);
+    }
+
+    /// Opens a block for an operation at `loc`.
+    fn open(&mut self, loc: &Location, text: &str) {
+        self.stmt(loc, &format!("{} {{", text));
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn is_main(&self) -> bool {
+        self.symbol.is_none()
+    }
+
+    /// Whether the tape only grows right.
+    fn on_stack(&self) -> bool {
+        self.is_main() && self.config.tape == Tape::Stack
+    }
+
+    /// Whether the tape grows at both ends.
+    fn growable(&self) -> bool {
+        self.config.tape.bidirectional()
+    }
+
+    /// Whether the pointer is checked against both ends of the tape.
+    fn checked(&self) -> bool {
+        !self.on_stack() && (self.growable() || self.config.checked)
+    }
+
+    fn buffered(&self) -> bool {
+        !self.config.unbuffered
+    }
+
+    /// A constant of the cell type.
+    fn lit(&self, value: u64) -> String {
+        match self.config.cell_size {
+            CellSize::Bits64 => format!("UINT64_C({})", value),
+            _ => format!("{}u", value),
+        }
+    }
+
+    /// The cell at `offset` from the pointer.
+    fn cell(&self, offset: i64) -> String {
+        if offset == 0 {
+            String::from("*p")
+        } else {
+            format!("p[{}]", offset)
+        }
+    }
+
+    /// A statement calling `bf_fail`, which exits with the given status
+    /// after writing the message.
+    fn fail_call(&mut self, status: i64, message: String) -> String {
+        self.fails = true;
+        format!("bf_fail({}, {});", status, literal(&message))
+    }
+
+    fn bounds_call(&mut self, loc: &Location) -> String {
+        self.fail_call(BOUNDS_EXIT, format!("Tape pointer out of bounds {}.\n", loc))
+    }
+
+    fn overflow_call(&mut self, loc: &Location) -> String {
+        self.fail_call(OVERFLOW_EXIT, format!("Cell overflow {}.\n", loc))
+    }
+
+    /// Makes sure the cell at `offset`, which is positive, is not past the
+    /// end of the tape, growing the tape or reporting the error.
+    fn right(&mut self, offset: i64, loc: &Location) {
+        if self.on_stack() && self.config.checked {
+            // `bf_grow` gives NULL past the largest tape.
+            self.grows = true;
+            let call = self.bounds_call(loc);
+            self.stmt(loc, &format!(
+                "if (bf_end - p <= {} && (p = bf_grow(p, {})) == NULL) {}",
+                offset,
+                offset,
+                call
+            ));
+            return;
+        }
+        let action = if self.on_stack() || self.growable() {
+            self.grows = true;
+            format!("p = bf_grow(p, {});", offset)
+        } else {
+            self.bounds_call(loc)
+        };
+        self.stmt(loc, &format!("if (bf_end - p <= {}) {}", offset, action));
+    }
+
+    /// Makes sure the cell at `offset`, which is negative, is not before the
+    /// start of the tape, growing the tape or reporting the error.
+    fn left(&mut self, offset: i64, loc: &Location) {
+        let action = if self.growable() {
+            self.grows = true;
+            format!("p = bf_grow(p, {});", offset)
+        } else {
+            self.bounds_call(loc)
+        };
+        self.stmt(loc, &format!("if (p - bf_start < {}) {}", offset.unsigned_abs(), action));
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// is found by comparing the cell against the largest value the addition
+    /// keeps in range, or against the amount of a subtraction.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let cell = self.cell(offset);
+        let limit = self.lit(if amount < 0 {0} else {mask});
+        if magnitude == 0 {
+            return;
+        }
+        if magnitude > mask {
+            if self.config.overflow == Overflow::Trap {
+                let call = self.overflow_call(loc);
+                self.stmt(loc, &call);
+            } else {
+                self.stmt(loc, &format!("{} = {};", cell, limit));
+            }
+            return;
+        }
+        let (op, overflows) = if amount < 0 {
+            ("-", format!("{} < {}", cell, self.lit(magnitude)))
+        } else {
+            ("+", format!("{} > {}", cell, self.lit(mask - magnitude)))
+        };
+        let magnitude = self.lit(magnitude);
+        if self.config.overflow == Overflow::Trap {
+            let call = self.overflow_call(loc);
+            self.stmt(loc, &format!("if ({}) {}", overflows, call));
+            self.stmt(loc, &format!("{} {}= {};", cell, op, magnitude));
+        } else {
+            self.stmt(loc, &format!(
+                "{} = {} ? {} : {} {} {};",
+                cell,
+                overflows,
+                limit,
+                cell,
+                op,
+                magnitude
+            ));
+        }
+    }
+
+    fn exit(&mut self) {
+        if !self.is_main() {
+            self.line("return p;");
+            return;
+        }
+        // The pointer is only unused by empty programs.
+        if self.body.is_empty() && !self.config.exit_cell {
+            self.line("(void) p;");
+        }
+        if self.puts {
+            self.line("if (fflush(stdout) == EOF) bf_io_failed(\"Could not write the output.\\n\");");
+        }
+        if self.config.exit_cell {
+            self.line("return (int) (*p & 255);");
+        } else {
+            self.line("return 0;");
+        }
+    }
+
+    /// The declarations, the helper functions and the start of the program
+    /// function.
+    fn prologue(&self) -> String {
+        let bits = self.config.cell_size.bits();
+        let mut text = String::new();
+        text += "#include <stddef.h>\n";
+        text += "#include <stdint.h>\n";
+        text += "#include <stdio.h>\n";
+        text += "#include <stdlib.h>\n";
+        text += "#include <string.h>\n\n";
+        text += &format!("typedef uint{}_t bf_cell;\n\n", bits);
+        if self.is_main() {
+            text += "static bf_cell *bf_start;\n";
+            text += "static bf_cell *bf_end;\n\n";
+        }
+        if self.fails {
+            text += &self.fail();
+        }
+        if self.is_main() && (self.puts || self.gets) {
+            text += IO_FAILED;
+        }
+        if self.grows {
+            text += &if self.growable() {String::from(GROW_BIDIRECTIONAL)} else {self.grow_right()};
+        }
+        if self.is_main() && self.puts {
+            text += PUT;
+        }
+        if self.is_main() && self.gets {
+            text += GET;
+        }
+        match self.symbol {
+            Some(ref symbol) => {
+                text += &format!(
+                    "bf_cell *{}(bf_cell *tape, int (*get)(void), int (*put)(int))\n{{\n",
+                    symbol
+                );
+                text += "    bf_cell *p = tape;\n";
+                if self.gets {
+                    text += "    int c;\n\n";
+                } else {
+                    text += "\n    (void) get;\n";
+                }
+                if !self.puts {
+                    text += "    (void) put;\n";
+                }
+            },
+            None => {
+                text += "int main(void)\n{\n";
+                text += "    bf_cell *p;\n";
+                if self.gets {
+                    text += "    int c;\n";
+                }
+                text += "\n";
+                if self.buffered() {
+                    text += "    setvbuf(stdout, NULL, _IOLBF, 4096);\n";
+                } else {
+                    text += "    setvbuf(stdout, NULL, _IONBF, 0);\n";
+                    text += "    setvbuf(stdin, NULL, _IONBF, 0);\n";
+                }
+                let cells = match self.config.tape {
+                    Tape::Stack => STACK_CELLS,
+                    Tape::Heap(cells) | Tape::Bidirectional(cells) => cells.max(1),
+                };
+                text += &format!("    bf_start = calloc({}, sizeof(bf_cell));\n", cells);
+                text += &format!(
+                    "    if (bf_start == NULL) bf_fail({}, {});\n",
+                    IO_EXIT,
+                    literal("Could not allocate the tape.\n")
+                );
+                text += &format!("    bf_end = bf_start + {};\n", cells);
+                if self.config.tape.bidirectional() {
+                    text += &format!("    p = bf_start + {};\n", cells / 2);
+                } else {
+                    text += "    p = bf_start;\n";
+                }
+            },
+        }
+        text
+    }
+
+    /// The `bf_fail` function, which shows the output before the message.
+    fn fail(&self) -> String {
+        let mut text = String::from("static void bf_fail(int status, const char *message)\n{\n");
+        text += "    fflush(stdout);\n";
+        text += "    fputs(message, stderr);\n";
+        text += "    exit(status);\n";
+        text += "}\n\n";
+        text
+    }
+
+    /// The `bf_grow` function of a tape which only grows right, until it has
+    /// the cell at `offset` from `p`, giving the new address of `p`. When
+    /// checked, the tape takes at most half of `STACK_CAP` bytes, like the
+    /// stack tape of executables, and NULL is given instead of growing it
+    /// further.
+    fn grow_right(&self) -> String {
+        let cap = STACK_CAP as usize / 2 / self.config.cell_size.bytes();
+        let mut text = String::from("static bf_cell *bf_grow(bf_cell *p, ptrdiff_t offset)\n{\n");
+        text += "    size_t pos = p - bf_start;\n";
+        text += "    size_t size = bf_end - bf_start;\n";
+        text += "    size_t grown = size;\n";
+        text += "    bf_cell *tape;\n\n";
+        if self.config.checked {
+            text += &format!("    if (pos + offset >= {}) return NULL;\n", cap);
+        }
+        text += "    while (pos + offset >= grown) {\n";
+        text += "        grown *= 2;\n";
+        text += "    }\n";
+        if self.config.checked {
+            text += &format!("    if (grown > {}) grown = {};\n", cap, cap);
+        }
+        text += "    tape = realloc(bf_start, grown * sizeof(bf_cell));\n";
+        text += &format!(
+            "    if (tape == NULL) bf_fail({}, {});\n",
+            IO_EXIT,
+            literal("Could not grow the tape.\n")
+        );
+        text += "    memset(tape + size, 0, (grown - size) * sizeof(bf_cell));\n";
+        text += "    bf_start = tape;\n";
+        text += "    bf_end = tape + grown;\n";
+        text += "    return tape + pos;\n";
+        text += "}\n\n";
+        text
+    }
+
+    /// Generates the translation unit for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> String {
+        walk(&mut self, ir);
+        self.exit();
+        // Allocating the tape fails through `bf_fail` too.
+        if self.is_main() || self.grows {
+            self.fails = true;
+        }
+        let mut text = self.prologue();
+        text += &self.body;
+        text += "}\n";
+        text
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    type Loop = ();
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of a stack tape are only detected when checked, and cells out
+    /// of an unchecked heap tape are not detected.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        let left = self.checked() || (self.on_stack() && self.config.checked);
+        if offset > 0 && (self.on_stack() || self.checked()) {
+            self.right(offset, loc);
+        } else if offset < 0 && left {
+            self.left(offset, loc);
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, loc: &Location) {
+        if n > 0 {
+            self.stmt(loc, &format!("p += {};", n));
+        } else if n < 0 {
+            self.stmt(loc, &format!("p -= {};", n.unsigned_abs()));
+        }
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        self.reach(n, loc);
+        self.shift(n, loc);
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let amount = self.config.cell_size.wrap(amount);
+        let cell = self.cell(offset);
+        let op = if amount < 0 {"-="} else {"+="};
+        if amount != 0 {
+            let magnitude = self.lit(amount.unsigned_abs());
+            self.stmt(loc, &format!("{} {} {};", cell, op, magnitude));
+        }
+    }
+
+    fn set(&mut self, offset: i64, value: i64, loc: &Location) {
+        let value = self.lit(value as u64 & self.config.cell_size.mask());
+        let cell = self.cell(offset);
+        self.stmt(loc, &format!("{} = {};", cell, value));
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, loc: &Location) {
+        let factor = self.config.cell_size.wrap(factor);
+        let (src, dst) = (self.cell(src), self.cell(dst));
+        let op = if factor < 0 {"-="} else {"+="};
+        // The factor is unsigned, so that the product of promoted cells does
+        // not overflow an `int`.
+        match factor.unsigned_abs() {
+            0 => (),
+            1 => self.stmt(loc, &format!("{} {} {};", dst, op, src)),
+            magnitude => {
+                let magnitude = self.lit(magnitude);
+                self.stmt(loc, &format!("{} {} {} * {};", dst, op, src, magnitude));
+            },
+        }
+    }
+
+    fn put_char(&mut self, offset: i64, loc: &Location) {
+        let cell = self.cell(offset);
+        let call = if self.is_main() {"bf_put"} else {"put"};
+        self.stmt(loc, &format!("{}((unsigned char) {});", call, cell));
+        self.puts = true;
+    }
+
+    fn get_char(&mut self, offset: i64, loc: &Location) {
+        let cell = self.cell(offset);
+        let call = if self.is_main() {"bf_get"} else {"get"};
+        self.stmt(loc, &format!("c = {}();", call));
+        match self.config.eof {
+            Eof::MinusOne => self.stmt(loc, &format!("{} = (bf_cell) c;", cell)),
+            Eof::Zero => self.stmt(loc, &format!("{} = c < 0 ? 0 : (bf_cell) c;", cell)),
+            Eof::Unchanged => self.stmt(loc, &format!("if (c >= 0) {} = (bf_cell) c;", cell)),
+        }
+        self.gets = true;
+    }
+
+    fn scan(&mut self, n: i64, loc: &Location) {
+        self.open(loc, "while (*p)");
+        self.move_by(n, loc);
+        self.close();
+    }
+
+    fn open_loop(&mut self, loc: &Location) {
+        self.open(loc, "while (*p)");
+    }
+
+    fn close_loop(&mut self, _: ()) {
+        self.close();
+    }
+
+}
+
+/// Reports that I/O failed. The output is not flushed again.
+const IO_FAILED: &str = "\
+static void bf_io_failed(const char *message)
+{
+    fputs(message, stderr);
+    _Exit(1);
+}
+
+";
+
+/// Grows a bidirectional tape to three times its size, moving its cells to
+/// the middle, until it has the cell at `offset` from `p`, giving the new
+/// address of `p`. Failing to grow it exits with `IO_EXIT`.
+const GROW_BIDIRECTIONAL: &str = "\
+static bf_cell *bf_grow(bf_cell *p, ptrdiff_t offset)
+{
+    ptrdiff_t pos = p - bf_start;
+    ptrdiff_t size = bf_end - bf_start;
+    bf_cell *tape;
+
+    while (pos + offset < 0 || pos + offset >= size) {
+        tape = realloc(bf_start, 3 * size * sizeof(bf_cell));
+        if (tape == NULL) bf_fail(1, \"Could not grow the tape.\\n\");
+        memmove(tape + size, tape, size * sizeof(bf_cell));
+        memset(tape, 0, size * sizeof(bf_cell));
+        memset(tape + 2 * size, 0, size * sizeof(bf_cell));
+        bf_start = tape;
+        pos += size;
+        size *= 3;
+        bf_end = tape + size;
+    }
+    return bf_start + pos;
+}
+
+";
+
+/// Writes a byte. Output is line buffered, so it is flushed on newlines and
+/// when the buffer is full.
+const PUT: &str = "\
+static void bf_put(int byte)
+{
+    if (putchar(byte) == EOF) bf_io_failed(\"Could not write the output.\\n\");
+}
+
+";
+
+/// Reads a byte, or gives -1 at the end of the input, flushing the output
+/// first.
+const GET: &str = "\
+static int bf_get(void)
+{
+    int c;
+
+    if (fflush(stdout) == EOF) bf_io_failed(\"Could not write the output.\\n\");
+    c = getchar();
+    if (c == EOF && ferror(stdin)) bf_io_failed(\"Could not read the input.\\n\");
+    return c == EOF ? -1 : c;
+}
+
+";
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        Codegen,
+    };
+    use front_end::{
+        self,
+        ByteStream,
+    };
+    use ir;
+    use config::{
+        CellSize,
+        Config,
+        Eof,
+    };
+
+    fn generate(src: &str, config: &Config, symbol: Option<&str>) -> String {
+        let stream = ByteStream::from_str(String::from("test.bf"), src);
+        let ir = ir::lower(front_end::parse(stream).unwrap());
+        Codegen::new(config, symbol.map(String::from)).generate(ir)
+    }
+
+    /// The lines of `main` after the tape is set up.
+    fn body(text: &str) -> Vec<&str> {
+        let start = text.find("    p = bf_start;\n").unwrap();
+        text[start ..].lines().skip(1).collect()
+    }
+
+    #[test]
+    fn line_directives() {
+        // A directive is only needed when the next line would be numbered
+        // with another line of the source.
+        let text = generate("+\n+.\n\n,", &Config::default(), None);
+        assert_eq!(body(&text)[.. 9], [
+            "#line 1 \"test.bf\"",
+            "    *p += 1u;",
+            "    *p += 1u;",
+            "#line 2 \"test.bf\"",
+            "    bf_put((unsigned char) *p);",
+            "#line 4 \"test.bf\"",
+            "    c = bf_get();",
+            "#line 4 \"test.bf\"",
+            "    *p = (bf_cell) c;",
+        ]);
+    }
+
+    #[test]
+    fn cell_types() {
+        let cases = [
+            (CellSize::Bits8, "uint8_t", "1u"),
+            (CellSize::Bits16, "uint16_t", "1u"),
+            (CellSize::Bits32, "uint32_t", "1u"),
+            (CellSize::Bits64, "uint64_t", "UINT64_C(1)"),
+        ];
+        for &(cell_size, ty, one) in &cases {
+            let config = Config {cell_size, ..Config::default()};
+            let text = generate("+", &config, None);
+            assert!(text.contains(&format!("typedef {} bf_cell;\n", ty)), "{}", ty);
+            assert!(text.contains(&format!("    *p += {};\n", one)), "{}", ty);
+        }
+    }
+
+    #[test]
+    fn end_of_input() {
+        let cases = [
+            (Eof::MinusOne, "*p = (bf_cell) c;"),
+            (Eof::Zero, "*p = c < 0 ? 0 : (bf_cell) c;"),
+            (Eof::Unchanged, "if (c >= 0) *p = (bf_cell) c;"),
+        ];
+        for &(eof, stmt) in &cases {
+            let config = Config {eof, ..Config::default()};
+            let text = generate(",", &config, None);
+            assert!(text.contains("    c = bf_get();\n"));
+            assert!(text.contains(&format!("\n    {}\n", stmt)), "{}", stmt);
+            let text = generate(",", &config, Some("f"));
+            assert!(text.contains("    c = get();\n"));
+            assert!(text.contains(&format!("\n    {}\n", stmt)), "{}", stmt);
+        }
+    }
+
+    #[test]
+    fn functions() {
+        let text = generate("+.", &Config::default(), Some("name"));
+        assert!(text.contains(
+            "bf_cell *name(bf_cell *tape, int (*get)(void), int (*put)(int))\n{\n"
+        ));
+        assert!(text.contains("    put((unsigned char) *p);\n"));
+        assert!(text.ends_with("    return p;\n}\n"));
+        assert!(!text.contains("main"));
+        assert!(!text.contains("bf_fail"));
+    }
+
+    #[test]
+    fn allocation_failures() {
+        let text = generate("", &Config::default(), None);
+        assert!(text.contains("static void bf_fail(int status, const char *message)\n"));
+        assert!(text.contains(
+            "    if (bf_start == NULL) bf_fail(1, \"Could not allocate the tape.\\n\");\n"
+        ));
+        let text = generate(">", &Config::default(), None);
+        assert!(text.contains(
+            "    if (tape == NULL) bf_fail(1, \"Could not grow the tape.\\n\");\n"
+        ));
+        assert!(!text.contains("exit(1)"));
+    }
+
+}
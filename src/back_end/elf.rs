@@ -0,0 +1,583 @@
+use std::io::{
+    Write,
+    Error,
+};
+
+
+pub const EM_386: u16 = 3;
+pub const EM_ARM: u16 = 40;
+pub const EM_X86_64: u16 = 62;
+pub const EM_AARCH64: u16 = 183;
+pub const EM_RISCV: u16 = 243;
+
+/// Marks ARM executables as following version 5 of the EABI.
+pub const EF_ARM_EABI_VER5: u32 = 0x0500_0000;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const PAGE_SIZE: u64 = 0x1000;
+
+fn align_up(n: u64, align: u64) -> u64 {
+    n.div_ceil(align) * align
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    Elf32,
+    Elf64,
+}
+
+impl Class {
+
+    fn ehdr_size(self) -> u64 {
+        match self {
+            Class::Elf32 => 52,
+            Class::Elf64 => 64,
+        }
+    }
+
+    fn phdr_size(self) -> u64 {
+        match self {
+            Class::Elf32 => 32,
+            Class::Elf64 => 56,
+        }
+    }
+
+    fn shdr_size(self) -> u64 {
+        match self {
+            Class::Elf32 => 40,
+            Class::Elf64 => 64,
+        }
+    }
+
+    fn sym_size(self) -> u64 {
+        match self {
+            Class::Elf32 => 16,
+            Class::Elf64 => 24,
+        }
+    }
+
+}
+
+/// A statically linked executable made of a single `.text` section, loaded
+/// at a fixed address.
+#[derive(Clone, Debug)]
+pub struct Executable {
+    pub class: Class,
+    pub machine: u16,
+    pub flags: u32,
+    pub text: Vec<u8>,
+    /// Offset of the entry point inside `text`.
+    pub entry: u64,
+}
+
+/// A relocatable object made of a single `.text` section, which exports a
+/// global function symbol at its start.
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub class: Class,
+    pub machine: u16,
+    pub flags: u32,
+    pub text: Vec<u8>,
+    pub symbol: String,
+}
+
+/// Little-endian byte buffer which knows the size of addresses.
+struct Buf {
+    class: Class,
+    bytes: Vec<u8>,
+}
+
+impl Buf {
+
+    fn new(class: Class) -> Self {
+        Self {class, bytes: Vec::new()}
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    fn u16(&mut self, n: u16) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    /// Writes an address, offset or size field.
+    fn word(&mut self, n: u64) {
+        match self.class {
+            Class::Elf32 => self.u32(n as u32),
+            Class::Elf64 => self.bytes.extend_from_slice(&n.to_le_bytes()),
+        }
+    }
+
+    fn align(&mut self, align: u64) {
+        let len = align_up(self.bytes.len() as u64, align);
+        self.bytes.resize(len as usize, 0);
+    }
+
+    fn sym(&mut self, name: u32, info: u8, shndx: u16, value: u64, size: u64) {
+        match self.class {
+            Class::Elf32 => {
+                self.u32(name);
+                self.u32(value as u32);
+                self.u32(size as u32);
+                self.u8(info);
+                self.u8(0);
+                self.u16(shndx);
+            },
+            Class::Elf64 => {
+                self.u32(name);
+                self.u8(info);
+                self.u8(0);
+                self.u16(shndx);
+                self.word(value);
+                self.word(size);
+            },
+        }
+    }
+
+}
+
+/// A section, as laid out by `write_file`.
+struct Section {
+    name: &'static str,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    data: Vec<u8>,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl Section {
+
+    fn new(name: &'static str, kind: u32, data: Vec<u8>) -> Self {
+        Self {
+            name,
+            kind,
+            flags: 0,
+            addr: 0,
+            data,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        }
+    }
+
+}
+
+/// A segment. Loadable ones cover the file from its start up to `filesz`.
+struct Segment {
+    kind: u32,
+    vaddr: u64,
+    filesz: u64,
+    flags: u32,
+    align: u64,
+}
+
+struct Header {
+    class: Class,
+    kind: u16,
+    machine: u16,
+    flags: u32,
+    entry: u64,
+}
+
+fn headers_size(class: Class, segments: usize) -> u64 {
+    class.ehdr_size() + class.phdr_size() * segments as u64
+}
+
+/// Lays out a whole file: the ELF header, the program headers, the contents
+/// of every section in order, the section name table and the section
+/// headers. A null section is added at index 0, so the section given first
+/// gets index 1.
+fn write_file(header: Header, segments: &[Segment], sections: Vec<Section>) -> Vec<u8> {
+    let class = header.class;
+    let mut shstrtab = vec![0];
+    let mut names = Vec::new();
+    for section in sections.iter().map(|s| s.name).chain(Some(".shstrtab")) {
+        names.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(section.as_bytes());
+        shstrtab.push(0);
+    }
+    let mut sections = sections;
+    sections.push(Section::new(".shstrtab", SHT_STRTAB, shstrtab));
+
+    let mut offsets = Vec::new();
+    let mut pos = headers_size(class, segments.len());
+    for section in &sections {
+        pos = align_up(pos, section.align);
+        offsets.push(pos);
+        pos += section.data.len() as u64;
+    }
+    let shoff = align_up(pos, 8);
+
+    let mut buf = Buf::new(class);
+    buf.bytes.extend_from_slice(b"\x7fELF");
+    buf.u8(match class {
+        Class::Elf32 => 1,
+        Class::Elf64 => 2,
+    });
+    // Little endian, current version, System V ABI.
+    buf.u8(1);
+    buf.u8(1);
+    buf.u8(0);
+    buf.bytes.extend_from_slice(&[0; 8]);
+    buf.u16(header.kind);
+    buf.u16(header.machine);
+    buf.u32(1);
+    buf.word(header.entry);
+    buf.word(if segments.is_empty() {0} else {class.ehdr_size()});
+    buf.word(shoff);
+    buf.u32(header.flags);
+    buf.u16(class.ehdr_size() as u16);
+    buf.u16(if segments.is_empty() {0} else {class.phdr_size() as u16});
+    buf.u16(segments.len() as u16);
+    buf.u16(class.shdr_size() as u16);
+    buf.u16(sections.len() as u16 + 1);
+    buf.u16(sections.len() as u16);
+
+    for seg in segments {
+        match class {
+            Class::Elf32 => {
+                buf.u32(seg.kind);
+                buf.word(0);
+                buf.word(seg.vaddr);
+                buf.word(seg.vaddr);
+                buf.word(seg.filesz);
+                buf.word(seg.filesz);
+                buf.u32(seg.flags);
+                buf.word(seg.align);
+            },
+            Class::Elf64 => {
+                buf.u32(seg.kind);
+                buf.u32(seg.flags);
+                buf.word(0);
+                buf.word(seg.vaddr);
+                buf.word(seg.vaddr);
+                buf.word(seg.filesz);
+                buf.word(seg.filesz);
+                buf.word(seg.align);
+            },
+        }
+    }
+
+    for section in &sections {
+        buf.align(section.align);
+        buf.bytes.extend_from_slice(&section.data);
+    }
+    buf.align(8);
+
+    buf.bytes.extend_from_slice(&vec![0; class.shdr_size() as usize]);
+    for (i, section) in sections.iter().enumerate() {
+        buf.u32(names[i]);
+        buf.u32(section.kind);
+        buf.word(section.flags);
+        buf.word(section.addr);
+        buf.word(offsets[i]);
+        buf.word(section.data.len() as u64);
+        buf.u32(section.link);
+        buf.u32(section.info);
+        buf.word(section.align);
+        buf.word(section.entsize);
+    }
+    buf.bytes
+}
+
+impl Executable {
+
+    /// The address the file is loaded at.
+    pub fn base(&self) -> u64 {
+        match self.class {
+            Class::Elf32 => 0x0804_8000,
+            Class::Elf64 => 0x0040_0000,
+        }
+    }
+
+    /// The offset of `.text` inside the file.
+    pub fn text_offset(&self) -> u64 {
+        align_up(headers_size(self.class, 2), 16)
+    }
+
+    /// The address `.text` is loaded at.
+    pub fn text_addr(&self) -> u64 {
+        self.base() + self.text_offset()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut text = Section::new(".text", SHT_PROGBITS, self.text.clone());
+        text.flags = SHF_ALLOC | SHF_EXECINSTR;
+        text.addr = self.text_addr();
+        text.align = 16;
+        let header = Header {
+            class: self.class,
+            kind: ET_EXEC,
+            machine: self.machine,
+            flags: self.flags,
+            entry: self.text_addr() + self.entry,
+        };
+        let segment = Segment {
+            kind: PT_LOAD,
+            vaddr: self.base(),
+            filesz: self.text_offset() + self.text.len() as u64,
+            flags: PF_R | PF_X,
+            align: PAGE_SIZE,
+        };
+        // Without it, the kernel maps the stack executable, and on some
+        // architectures every readable mapping too.
+        let stack = Segment {
+            kind: PT_GNU_STACK,
+            vaddr: 0,
+            filesz: 0,
+            flags: PF_R | PF_W,
+            align: 16,
+        };
+        write_file(header, &[segment, stack], vec![text])
+    }
+
+    pub fn write<T: Write>(&self, out: &mut T) -> Result<usize, Error> {
+        let bytes = self.to_bytes();
+        out.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+}
+
+impl Object {
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let class = self.class;
+        let mut text = Section::new(".text", SHT_PROGBITS, self.text.clone());
+        text.flags = SHF_ALLOC | SHF_EXECINSTR;
+        text.align = 16;
+        // An empty `.note.GNU-stack` tells the linker the stack need not be
+        // executable.
+        let note = Section::new(".note.GNU-stack", SHT_PROGBITS, Vec::new());
+
+        let mut strtab = vec![0];
+        strtab.extend_from_slice(self.symbol.as_bytes());
+        strtab.push(0);
+
+        let mut symbols = Buf::new(class);
+        symbols.sym(0, 0, 0, 0, 0);
+        symbols.sym(0, STB_LOCAL << 4 | STT_SECTION, 1, 0, 0);
+        symbols.sym(1, STB_GLOBAL << 4 | STT_FUNC, 1, 0, self.text.len() as u64);
+        let mut symtab = Section::new(".symtab", SHT_SYMTAB, symbols.bytes);
+        symtab.link = 4;
+        symtab.info = 2;
+        symtab.align = 8;
+        symtab.entsize = class.sym_size();
+
+        let strtab = Section::new(".strtab", SHT_STRTAB, strtab);
+        let header = Header {
+            class,
+            kind: ET_REL,
+            machine: self.machine,
+            flags: self.flags,
+            entry: 0,
+        };
+        write_file(header, &[], vec![text, note, symtab, strtab])
+    }
+
+    pub fn write<T: Write>(&self, out: &mut T) -> Result<usize, Error> {
+        let bytes = self.to_bytes();
+        out.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        Class,
+        Executable,
+        Object,
+        EM_386,
+        EM_X86_64,
+        PT_LOAD,
+        PT_GNU_STACK,
+        PF_X,
+        PF_W,
+        PF_R,
+        SHT_SYMTAB,
+        SHT_STRTAB,
+    };
+
+    /// Reads the fields of a file, which are at different offsets in each
+    /// class.
+    struct Reader<'a> {
+        class: Class,
+        bytes: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+
+        fn u16(&self, at: usize) -> u64 {
+            u16::from_le_bytes([self.bytes[at], self.bytes[at + 1]]) as u64
+        }
+
+        fn u32(&self, at: usize) -> u64 {
+            let mut field = [0; 4];
+            field.copy_from_slice(&self.bytes[at .. at + 4]);
+            u32::from_le_bytes(field) as u64
+        }
+
+        fn word(&self, at: usize) -> u64 {
+            if self.class == Class::Elf32 {
+                return self.u32(at);
+            }
+            let mut field = [0; 8];
+            field.copy_from_slice(&self.bytes[at .. at + 8]);
+            u64::from_le_bytes(field)
+        }
+
+        /// Picks the offset of a field in 32-bit or 64-bit files.
+        fn pick(&self, elf32: usize, elf64: usize) -> usize {
+            if self.class == Class::Elf32 {elf32} else {elf64}
+        }
+
+        fn entry(&self) -> u64 {
+            self.word(24)
+        }
+
+        /// The type, flags and file size of a program header.
+        fn segment(&self, index: usize) -> (u64, u64, u64) {
+            let at = self.word(self.pick(28, 32)) as usize
+                + index * self.u16(self.pick(42, 54)) as usize;
+            let flags = self.u32(at + self.pick(24, 4));
+            (self.u32(at), flags, self.word(at + self.pick(16, 32)))
+        }
+
+        fn sections(&self) -> usize {
+            self.u16(self.pick(48, 60)) as usize
+        }
+
+        fn shstrndx(&self) -> usize {
+            self.u16(self.pick(50, 62)) as usize
+        }
+
+        /// The offset of a section header.
+        fn section(&self, index: usize) -> usize {
+            self.word(self.pick(32, 40)) as usize
+                + index * self.u16(self.pick(46, 58)) as usize
+        }
+
+        fn kind(&self, index: usize) -> u64 {
+            self.u32(self.section(index) + 4)
+        }
+
+        fn link_info(&self, index: usize) -> (u64, u64) {
+            let at = self.section(index) + self.pick(24, 40);
+            (self.u32(at), self.u32(at + 4))
+        }
+
+        fn name(&self, index: usize) -> String {
+            let names = self.section(self.shstrndx());
+            let start = self.word(names + self.pick(16, 24)) as usize
+                + self.u32(self.section(index)) as usize;
+            let len = self.bytes[start ..].iter().position(|&b| b == 0).unwrap();
+            String::from_utf8(self.bytes[start .. start + len].to_vec()).unwrap()
+        }
+
+        fn find(&self, name: &str) -> usize {
+            (0 .. self.sections()).find(|&i| self.name(i) == name).unwrap()
+        }
+
+    }
+
+    #[test]
+    fn executables() {
+        let cases = [
+            (Class::Elf32, EM_386, 1, 3),
+            (Class::Elf64, EM_X86_64, 2, 62),
+        ];
+        for &(class, machine, ident, e_machine) in &cases {
+            let exe = Executable {
+                class,
+                machine,
+                flags: 0,
+                text: vec![0x90; 10],
+                entry: 4,
+            };
+            let bytes = exe.to_bytes();
+            let elf = Reader {class, bytes: &bytes};
+            assert_eq!(bytes[.. 4], *b"\x7fELF");
+            assert_eq!(bytes[4], ident);
+            assert_eq!(elf.u16(16), 2);
+            assert_eq!(elf.u16(18), e_machine);
+            assert_eq!(elf.entry(), exe.text_addr() + 4);
+            let load = (PT_LOAD as u64, (PF_R | PF_X) as u64, exe.text_offset() + 10);
+            assert_eq!(elf.segment(0), load);
+            let (kind, flags, _) = elf.segment(1);
+            assert_eq!((kind, flags), (PT_GNU_STACK as u64, (PF_R | PF_W) as u64));
+            assert_eq!(elf.sections(), 3);
+            assert_eq!(elf.shstrndx(), 2);
+            assert_eq!(elf.kind(2), SHT_STRTAB as u64);
+            assert_eq!(elf.name(1), ".text");
+            let text = elf.section(1);
+            assert_eq!(elf.word(text + elf.pick(12, 16)), exe.text_addr());
+            assert_eq!(elf.word(text + elf.pick(16, 24)), exe.text_offset());
+            let start = exe.text_offset() as usize;
+            assert_eq!(bytes[start .. start + 10], exe.text[..]);
+        }
+    }
+    #[test]
+    fn objects() {
+        for &class in &[Class::Elf32, Class::Elf64] {
+            let obj = Object {
+                class,
+                machine: EM_X86_64,
+                flags: 0,
+                text: vec![0xC3],
+                symbol: String::from("bf_main"),
+            };
+            let bytes = obj.to_bytes();
+            let elf = Reader {class, bytes: &bytes};
+            assert_eq!(elf.u16(16), 1);
+            assert_eq!(elf.entry(), 0);
+            assert_eq!(elf.u16(elf.pick(44, 56)), 0);
+            assert_eq!(elf.name(elf.shstrndx()), ".shstrtab");
+            let names: Vec<_> = (0 .. elf.sections()).map(|i| elf.name(i)).collect();
+            assert_eq!(names, [
+                "",
+                ".text",
+                ".note.GNU-stack",
+                ".symtab",
+                ".strtab",
+                ".shstrtab",
+            ]);
+            let symtab = elf.find(".symtab");
+            assert_eq!(elf.kind(symtab), SHT_SYMTAB as u64);
+            // The symbols are linked to their names, and the first global
+            // one follows the null and the section symbols.
+            assert_eq!(elf.link_info(symtab), (elf.find(".strtab") as u64, 2));
+            let strtab = elf.section(elf.find(".strtab"));
+            let start = elf.word(strtab + elf.pick(16, 24)) as usize;
+            assert_eq!(bytes[start .. start + 9], *b"\0bf_main\0");
+        }
+    }
+
+}
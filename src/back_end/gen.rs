@@ -1,24 +1,108 @@
 use std::io::{
     Error,
+    ErrorKind,
+};
+use std::{
+    fs,
 };
 use front_end::{
-    AstNode,
     Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Overflow,
+    Tape,
 };
 
+/// Exit status of a checked program whose pointer left the tape.
+pub const BOUNDS_EXIT: i64 = 3;
+
+/// Exit status of a program whose cell overflowed, when overflow traps.
+pub const OVERFLOW_EXIT: i64 = 4;
+
+/// Exit status of a program which could not write its output, read its input
+/// or get the memory for its tape.
+pub const IO_EXIT: i64 = 1;
+
+/// Number of cells a stack tape starts with, where it is allocated.
+pub const STACK_CELLS: u64 = 0x10000;
+
 pub enum Format {
     Asm,
     Elf,
+    /// Raw machine code, starting at the entry point.
+    Bin,
+    /// A relocatable object exporting the program as a function with the
+    /// given symbol name.
+    Object(String),
 }
 
 pub trait Arch {
 
     fn generate(
         &self,
-        ast: Vec<Node<AstNode>>,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
         format: Format,
         out: String
     ) -> Result<usize, Error>;
 
+    /// The format used when none is given.
+    fn default_format(&self) -> Format {
+        Format::Elf
+    }
+
+}
+
+
+/// Opens the output file for writing, truncating any previous contents.
+pub fn create_output(out: &str) -> Result<fs::File, Error> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(out)
+}
+
+/// Fails if the program needs something the `obj` format cannot give it.
+/// The function works on the tape of its caller and returns to it, so it can
+/// neither check the pointer, stop the program nor choose its exit status.
+pub fn check_function(config: &Config) -> Result<(), Error> {
+    let message = if config.checked {
+        "Bounds checks are not supported by the `obj` format."
+    } else if config.overflow == Overflow::Trap {
+        "Overflow traps are not supported by the `obj` format."
+    } else if config.tape != Tape::Stack {
+        "Heap and bidirectional tapes are not supported by the `obj` format."
+    } else if config.exit_cell {
+        "Exiting with the current cell is not supported by the `obj` format."
+    } else {
+        return Ok(());
+    };
+    Err(Error::new(ErrorKind::InvalidInput, message))
+}
+
+/// The label of the operation at `loc`.
+pub fn label_for(loc: &Location) -> String {
+    format!("_at_{}_{}", loc.line, loc.column)
 }
 
+/// Gives labels which were not given before.
+#[derive(Clone, Debug, Default)]
+pub struct Labels {
+    count: usize,
+}
+
+impl Labels {
+
+    /// A label based on the location which was not given before.
+    pub fn fresh(&mut self, loc: &Location, suffix: &str) -> String {
+        self.count += 1;
+        format!("{}_{}{}", label_for(loc), suffix, self.count)
+    }
+
+}
@@ -0,0 +1,12 @@
+/// `PROT_READ | PROT_WRITE`, for `mmap` and `mprotect`.
+pub const PROT_READ_WRITE: i64 = 3;
+
+/// `MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE`.
+pub const MAP_FLAGS: i64 = 0x4022;
+
+/// The resource of `getrlimit` and `prlimit64` limiting the stack size.
+pub const RLIMIT_STACK: i64 = 3;
+
+/// The most a checked stack tape takes is half of the stack size limit, or
+/// of this when the limit is higher or unlimited.
+pub const STACK_CAP: i64 = 1 << 30;
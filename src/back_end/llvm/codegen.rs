@@ -0,0 +1,1140 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Eof,
+    Overflow,
+    Tape,
+};
+use std::collections::{
+    HashMap,
+};
+use back_end::{
+    Emitter,
+    walk,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+    STACK_CELLS,
+};
+use back_end::linux::{
+    STACK_CAP,
+};
+
+
+/// Size of each I/O buffer.
+const IO_BUFFER: u64 = 4096;
+
+/// The metadata nodes every module has: the compile unit, the file, the
+/// function and its type, and the module flags. The locations follow them.
+const CU: usize = 0;
+const FILE: usize = 1;
+const SUBPROGRAM: usize = 2;
+const LOCATIONS: usize = 7;
+
+/// The greatest column a debug location may have. Greater columns, as well as
+/// lines that do not fit 32 bits, are emitted as unknown (zero).
+const MAX_COLUMN: u64 = 0xFFFF;
+
+/// An open loop: the blocks of its head and of its end, and where it is.
+#[derive(Clone, Debug)]
+pub struct Loop {
+    head: String,
+    end: String,
+    loc: Location,
+}
+
+/// Escapes text for a string constant or a metadata string.
+pub fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        if (b' ' ..= b'~').contains(&byte) && byte != b'"' && byte != b'\\' {
+            escaped.push(byte as char);
+        } else {
+            escaped += &format!("\\{:02X}", byte);
+        }
+    }
+    escaped
+}
+
+/// Generates a module of textual LLVM IR, with typed pointers, for 64-bit
+/// targets only, since `size_t` is declared as `i64` in the C functions
+/// called. Standalone programs are a `main` function doing I/O through
+/// `read` and `write`, with the tape allocated by `calloc`. Functions take
+/// the tape and the I/O callbacks, like the `obj` format of x86:
+/// `cell *f(cell *tape, int (*get)(void), int (*put)(int))`. The pointer is
+/// kept in a stack slot, which `mem2reg` promotes, and each operation has a
+/// debug location with the line and column it comes from.
+#[derive(Clone, Debug)]
+pub struct Codegen {
+    config: Config,
+    /// The name of the function the program is, if it is not `main`.
+    symbol: Option<String>,
+    /// The instructions of the program function.
+    body: String,
+    /// The number of the next temporary and of the next label.
+    temps: usize,
+    labels: usize,
+    /// The file the debug information names.
+    file: Option<String>,
+    /// The metadata of the debug locations, by line and column.
+    locations: HashMap<(u64, u64), usize>,
+    /// The debug location of the current operation.
+    dbg: Option<usize>,
+    /// The messages of the errors, as constants.
+    messages: Vec<String>,
+    /// Whether `bf_fail` is called.
+    fails: bool,
+    /// Whether `bf_grow` is called.
+    grows: bool,
+    /// Whether the program writes.
+    puts: bool,
+    /// Whether the program reads.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator for a `main` function, or for a function
+    /// named `symbol`.
+    pub fn new(config: &Config, symbol: Option<String>) -> Self {
+        Self {
+            config: config.clone(),
+            symbol,
+            body: String::new(),
+            temps: 0,
+            labels: 0,
+            file: None,
+            locations: HashMap::new(),
+            dbg: None,
+            messages: Vec::new(),
+            fails: false,
+            grows: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    fn is_main(&self) -> bool {
+        self.symbol.is_none()
+    }
+
+    /// Whether the tape only grows right.
+    fn on_stack(&self) -> bool {
+        self.is_main() && self.config.tape == Tape::Stack
+    }
+
+    /// Whether the tape grows at both ends.
+    fn growable(&self) -> bool {
+        self.config.tape.bidirectional()
+    }
+
+    /// Whether the pointer is checked against both ends of the tape.
+    fn checked(&self) -> bool {
+        !self.on_stack() && (self.growable() || self.config.checked)
+    }
+
+    fn buffered(&self) -> bool {
+        !self.config.unbuffered
+    }
+
+    /// The type of cells.
+    fn cty(&self) -> String {
+        format!("i{}", self.config.cell_size.bits())
+    }
+
+    /// The type of pointers to cells.
+    fn cptr(&self) -> String {
+        format!("{}*", self.cty())
+    }
+
+    /// Size of a cell in bytes.
+    fn stride(&self) -> i64 {
+        self.config.cell_size.bytes() as i64
+    }
+
+    /// A constant of the cell type, in the signed form LLVM prints.
+    fn lit(&self, value: i64) -> i64 {
+        self.config.cell_size.wrap(value)
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t{}", self.temps - 1)
+    }
+
+    fn label(&mut self, name: &str) -> String {
+        self.labels += 1;
+        format!("{}{}", name, self.labels - 1)
+    }
+
+    /// Adds an instruction with the current debug location.
+    fn inst(&mut self, text: &str) {
+        self.body += "  ";
+        self.body += text;
+        if let Some(id) = self.dbg {
+            self.body += &format!(", !dbg !{}", id);
+        }
+        self.body.push('\n');
+    }
+
+    /// Adds an instruction giving a value, named by a new temporary.
+    fn value(&mut self, text: &str) -> String {
+        let temp = self.temp();
+        self.inst(&format!("{} = {}", temp, text));
+        temp
+    }
+
+    fn block(&mut self, label: &str) {
+        self.body += &format!("{}:\n", label);
+    }
+
+    fn br(&mut self, label: &str) {
+        self.inst(&format!("br label %{}", label));
+    }
+
+    fn cond_br(&mut self, cond: &str, then: &str, otherwise: &str) {
+        self.inst(&format!("br i1 {}, label %{}, label %{}", cond, then, otherwise));
+    }
+
+    /// Loads the pointer.
+    fn pointer(&mut self) -> String {
+        let cp = self.cptr();
+        self.value(&format!("load {}, {}* %p", cp, cp))
+    }
+
+    /// Converts a pointer to another pointer type, unless it is one already.
+    fn cast(&mut self, value: &str, from: &str, to: &str) -> String {
+        if from == to {
+            String::from(value)
+        } else {
+            self.value(&format!("bitcast {} {} to {}", from, value, to))
+        }
+    }
+
+    /// The address of the cell at `offset` from the pointer.
+    fn cell_ptr(&mut self, offset: i64) -> String {
+        let (c, cp) = (self.cty(), self.cptr());
+        let p = self.pointer();
+        if offset == 0 {
+            p
+        } else {
+            self.value(&format!("getelementptr {}, {} {}, i64 {}", c, cp, p, offset))
+        }
+    }
+
+    fn load(&mut self, offset: i64) -> (String, String) {
+        let (c, cp) = (self.cty(), self.cptr());
+        let addr = self.cell_ptr(offset);
+        let value = self.value(&format!("load {}, {} {}", c, cp, addr));
+        (addr, value)
+    }
+
+    fn store(&mut self, value: &str, addr: &str) {
+        let (c, cp) = (self.cty(), self.cptr());
+        self.inst(&format!("store {} {}, {} {}", c, value, cp, addr));
+    }
+
+    /// A pointer to the first byte of a message constant.
+    fn message(&mut self, text: String) -> String {
+        let id = match self.messages.iter().position(|m| *m == text) {
+            Some(id) => id,
+            _ => {
+                self.messages.push(text.clone());
+                self.messages.len() - 1
+            },
+        };
+        format!(
+            "i8* getelementptr inbounds ([{} x i8], [{} x i8]* @bf_msg.{}, i64 0, i64 0), i64 {}",
+            text.len(),
+            text.len(),
+            id,
+            text.len()
+        )
+    }
+
+    /// Calls `bf_fail`, which exits with the given status after writing the
+    /// message.
+    fn report(&mut self, status: i64, text: String) {
+        let message = self.message(text);
+        self.inst(&format!("call void @bf_fail(i32 {}, {})", status, message));
+        self.inst("unreachable");
+        self.fails = true;
+    }
+
+    /// Reports the error if the condition holds.
+    fn report_if(&mut self, cond: &str, status: i64, text: String) {
+        let (bad, ok) = (self.label("bad"), self.label("ok"));
+        self.cond_br(cond, &bad, &ok);
+        self.block(&bad);
+        self.report(status, text);
+        self.block(&ok);
+    }
+
+    /// Grows the tape if the condition holds, until it has the cell at
+    /// `offset`, or reports that the pointer left the tape if it cannot
+    /// grow that way, or past the largest checked stack tape.
+    fn bounds(&mut self, cond: &str, offset: i64, grows: bool, loc: &Location) {
+        let text = format!("Tape pointer out of bounds {}.\n", loc);
+        if !grows {
+            self.report_if(cond, BOUNDS_EXIT, text);
+            return;
+        }
+        let (grow, ok) = (self.label("grow"), self.label("ok"));
+        self.cond_br(cond, &grow, &ok);
+        self.block(&grow);
+        let cp = self.cptr();
+        let p = self.pointer();
+        let p = self.cast(&p, &cp, "i8*");
+        let moved = self.value(&format!(
+            "call i8* @bf_grow(i8* {}, i64 {})",
+            p,
+            offset.wrapping_mul(self.stride())
+        ));
+        if self.on_stack() && self.config.checked {
+            let full = self.value(&format!("icmp eq i8* {}, null", moved));
+            self.report_if(&full, BOUNDS_EXIT, text);
+        }
+        let moved = self.cast(&moved, "i8*", &cp);
+        self.inst(&format!("store {} {}, {}* %p", cp, moved, cp));
+        self.br(&ok);
+        self.block(&ok);
+        self.grows = true;
+    }
+
+    /// The distance in bytes from the pointer to the end of the tape, or
+    /// from the start of the tape to the pointer.
+    fn room(&mut self, end: bool) -> String {
+        let global = if end {"@bf_end"} else {"@bf_start"};
+        let bound = self.value(&format!("load i8*, i8** {}", global));
+        let bound = self.value(&format!("ptrtoint i8* {} to i64", bound));
+        let cp = self.cptr();
+        let p = self.pointer();
+        let p = self.value(&format!("ptrtoint {} {} to i64", cp, p));
+        if end {
+            self.value(&format!("sub i64 {}, {}", bound, p))
+        } else {
+            self.value(&format!("sub i64 {}, {}", p, bound))
+        }
+    }
+
+    /// Makes sure the cell at `offset`, which is positive, is not past the
+    /// end of the tape, growing the tape or reporting the error.
+    fn right(&mut self, offset: i64, loc: &Location) {
+        let room = self.room(true);
+        let bytes = offset.wrapping_mul(self.stride());
+        let cond = self.value(&format!("icmp sle i64 {}, {}", room, bytes));
+        let grows = self.on_stack() || self.growable();
+        self.bounds(&cond, offset, grows, loc);
+    }
+
+    /// Makes sure the cell at `offset`, which is negative, is not before the
+    /// start of the tape, growing the tape or reporting the error.
+    fn left(&mut self, offset: i64, loc: &Location) {
+        let room = self.room(false);
+        let bytes = offset.wrapping_mul(self.stride()).wrapping_neg();
+        let cond = self.value(&format!("icmp slt i64 {}, {}", room, bytes));
+        let grows = self.growable();
+        self.bounds(&cond, offset, grows, loc);
+    }
+
+    /// Opens a loop running while the current cell is not zero.
+    fn open(&mut self) -> (String, String) {
+        let (head, body, end) = (self.label("head"), self.label("body"), self.label("end"));
+        self.br(&head);
+        self.block(&head);
+        let (_, value) = self.load(0);
+        let c = self.cty();
+        let nonzero = self.value(&format!("icmp ne {} {}, 0", c, value));
+        self.cond_br(&nonzero, &body, &end);
+        self.block(&body);
+        (head, end)
+    }
+
+    fn close(&mut self, head: &str, end: &str) {
+        self.br(head);
+        self.block(end);
+    }
+
+    /// Converts a byte or -1 to a cell.
+    fn int_cell(&mut self, int: &str) -> String {
+        let c = self.cty();
+        match self.config.cell_size {
+            CellSize::Bits32 => String::from(int),
+            CellSize::Bits64 => self.value(&format!("sext i32 {} to i64", int)),
+            _ => self.value(&format!("trunc i32 {} to {}", int, c)),
+        }
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// is found by comparing the cell against the largest value the addition
+    /// keeps in range, or against the amount of a subtraction.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let c = self.cty();
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let limit = if amount < 0 {0} else {self.lit(mask as i64)};
+        let text = format!("Cell overflow {}.\n", loc);
+        if magnitude == 0 {
+            return;
+        }
+        if magnitude > mask {
+            if self.config.overflow == Overflow::Trap {
+                let cont = self.label("cont");
+                self.report(OVERFLOW_EXIT, text);
+                self.block(&cont);
+            } else {
+                let addr = self.cell_ptr(offset);
+                self.store(&limit.to_string(), &addr);
+            }
+            return;
+        }
+        let (op, cmp, bound) = if amount < 0 {
+            ("sub", "ult", magnitude)
+        } else {
+            ("add", "ugt", mask - magnitude)
+        };
+        let (addr, value) = self.load(offset);
+        let bound = self.lit(bound as i64);
+        let magnitude = self.lit(magnitude as i64);
+        let overflows = self.value(&format!("icmp {} {} {}, {}", cmp, c, value, bound));
+        if self.config.overflow == Overflow::Trap {
+            self.report_if(&overflows, OVERFLOW_EXIT, text);
+            let result = self.value(&format!("{} {} {}, {}", op, c, value, magnitude));
+            self.store(&result, &addr);
+        } else {
+            let result = self.value(&format!("{} {} {}, {}", op, c, value, magnitude));
+            let result = self.value(&format!(
+                "select i1 {}, {} {}, {} {}",
+                overflows,
+                c,
+                limit,
+                c,
+                result
+            ));
+            self.store(&result, &addr);
+        }
+    }
+
+    fn exit(&mut self) {
+        let c = self.cty();
+        if !self.is_main() {
+            let cp = self.cptr();
+            let p = self.pointer();
+            self.inst(&format!("ret {} {}", cp, p));
+            return;
+        }
+        if self.buffered() && self.puts {
+            self.inst("call void @bf_flush()");
+        }
+        if self.config.exit_cell {
+            let (_, value) = self.load(0);
+            let status = match self.config.cell_size {
+                CellSize::Bits32 => value,
+                CellSize::Bits64 => self.value(&format!("trunc i64 {} to i32", value)),
+                _ => self.value(&format!("zext {} {} to i32", c, value)),
+            };
+            let status = self.value(&format!("and i32 {}, 255", status));
+            self.inst(&format!("ret i32 {}", status));
+        } else {
+            self.inst("ret i32 0");
+        }
+    }
+
+    /// The globals, the declarations and the helper functions.
+    fn prologue(&self) -> String {
+        let file = self.file.clone().unwrap_or_default();
+        let mut text = format!("source_filename = \"{}\"\n\n", escape(file.as_bytes()));
+        let buffered = self.is_main() && self.buffered();
+        if self.is_main() {
+            text += "@bf_start = internal global i8* null\n";
+            text += "@bf_end = internal global i8* null\n";
+        }
+        if buffered && (self.puts || self.gets) {
+            text += &format!("@bf_out = internal global [{} x i8] zeroinitializer\n", IO_BUFFER);
+            text += "@bf_out_len = internal global i64 0\n";
+        }
+        if buffered && self.gets {
+            text += &format!("@bf_in = internal global [{} x i8] zeroinitializer\n", IO_BUFFER);
+            text += "@bf_in_pos = internal global i64 0\n";
+            text += "@bf_in_len = internal global i64 0\n";
+        }
+        for (id, message) in self.messages.iter().enumerate() {
+            text += &format!(
+                "@bf_msg.{} = private unnamed_addr constant [{} x i8] c\"{}\"\n",
+                id,
+                message.len(),
+                escape(message.as_bytes())
+            );
+        }
+        text += "\n";
+        text += DECLARATIONS;
+
+        if self.fails {
+            text += "define internal void @bf_fail(i32 %status, i8* %message, i64 %length) {\n";
+            text += "entry:\n";
+            // What was written before is shown first.
+            if buffered && self.puts {
+                text += "  call void @bf_flush()\n";
+            }
+            text += "  %n = call i64 @write(i32 2, i8* %message, i64 %length)\n";
+            text += "  call void @exit(i32 %status)\n";
+            text += "  unreachable\n";
+            text += "}\n\n";
+        }
+        if self.grows {
+            text += &if self.growable() {String::from(GROW_BIDIRECTIONAL)} else {self.grow_right()};
+        }
+        if buffered && (self.puts || self.gets) {
+            text += FLUSH;
+        }
+        if self.is_main() {
+            if self.puts {
+                text += if buffered {PUT} else {PUT_UNBUFFERED};
+            }
+            if self.gets {
+                text += if buffered {GET} else {GET_UNBUFFERED};
+            }
+        }
+        text
+    }
+
+    /// The `bf_grow` function of a tape which only grows right, until it has
+    /// the cell at `offset` bytes from `p`, giving the new address of `p`.
+    /// When checked, the tape takes at most half of `STACK_CAP` bytes, like
+    /// the stack tape of executables, and null is given instead of growing
+    /// it further.
+    fn grow_right(&self) -> String {
+        let cap = STACK_CAP / 2;
+        let mut text = String::from("define internal i8* @bf_grow(i8* %p, i64 %offset) {\n");
+        text += "entry:\n";
+        text += "  %start = load i8*, i8** @bf_start\n";
+        text += "  %end = load i8*, i8** @bf_end\n";
+        text += "  %first = ptrtoint i8* %start to i64\n";
+        text += "  %last = ptrtoint i8* %end to i64\n";
+        text += "  %here = ptrtoint i8* %p to i64\n";
+        text += "  %pos = sub i64 %here, %first\n";
+        text += "  %size = sub i64 %last, %first\n";
+        text += "  %at = add i64 %pos, %offset\n";
+        if self.config.checked {
+            text += &format!("  %full = icmp uge i64 %at, {}\n", cap);
+            text += "  br i1 %full, label %none, label %double\n";
+            text += "none:\n";
+            text += "  ret i8* null\n";
+        } else {
+            text += "  br label %double\n";
+        }
+        text += "double:\n";
+        text += "  %grown = phi i64 [ %size, %entry ], [ %twice, %double ]\n";
+        text += "  %twice = shl i64 %grown, 1\n";
+        text += "  %small = icmp uge i64 %at, %grown\n";
+        text += "  br i1 %small, label %double, label %alloc\n";
+        text += "alloc:\n";
+        let bytes = if self.config.checked {"%bytes"} else {"%grown"};
+        if self.config.checked {
+            text += &format!("  %over = icmp ugt i64 %grown, {}\n", cap);
+            text += &format!("  %bytes = select i1 %over, i64 {}, i64 %grown\n", cap);
+        }
+        text += &format!("  %tape = call i8* @realloc(i8* %start, i64 {})\n", bytes);
+        text += "  %null = icmp eq i8* %tape, null\n";
+        text += "  br i1 %null, label %nomem, label %clear\n";
+        text += "nomem:\n";
+        text += "  call void @bf_grow_failed()\n";
+        text += "  unreachable\n";
+        text += "clear:\n";
+        text += "  %tail = getelementptr i8, i8* %tape, i64 %size\n";
+        text += &format!("  %rest = sub i64 {}, %size\n", bytes);
+        text += "  %cleared = call i8* @memset(i8* %tail, i32 0, i64 %rest)\n";
+        text += "  store i8* %tape, i8** @bf_start\n";
+        text += &format!("  %new.end = getelementptr i8, i8* %tape, i64 {}\n", bytes);
+        text += "  store i8* %new.end, i8** @bf_end\n";
+        text += "  %new.p = getelementptr i8, i8* %tape, i64 %pos\n";
+        text += "  ret i8* %new.p\n";
+        text += "}\n\n";
+        text
+    }
+
+    /// The I/O error functions, which call `bf_fail`, and the constants of
+    /// their messages.
+    fn io_failed(&mut self) -> String {
+        let mut text = String::new();
+        if !self.is_main() || !(self.puts || self.gets) {
+            return text;
+        }
+        let mut errors = vec![("bf_write_failed", "Could not write the output.\n")];
+        if self.gets {
+            errors.push(("bf_read_failed", "Could not read the input.\n"));
+        }
+        for (name, error) in errors {
+            let message = self.message(String::from(error));
+            text += &format!("define internal void @{}() {{\nentry:\n", name);
+            // The output buffer is dropped, so that it is not written again.
+            if self.buffered() {
+                text += "  store i64 0, i64* @bf_out_len\n";
+            }
+            text += &format!("  call void @bf_fail(i32 {}, {})\n", IO_EXIT, message);
+            text += "  unreachable\n}\n\n";
+        }
+        self.fails = true;
+        text
+    }
+
+    /// The functions reporting that the tape could not be allocated or
+    /// grown, which call `bf_fail`, and the constants of their messages.
+    fn alloc_failed(&mut self) -> String {
+        let mut text = String::new();
+        if !self.is_main() {
+            return text;
+        }
+        let mut errors = vec![("bf_alloc_failed", "Could not allocate the tape.\n")];
+        if self.grows {
+            errors.push(("bf_grow_failed", "Could not grow the tape.\n"));
+        }
+        for (name, error) in errors {
+            let message = self.message(String::from(error));
+            text += &format!("define internal void @{}() {{\nentry:\n", name);
+            text += &format!("  call void @bf_fail(i32 {}, {})\n", IO_EXIT, message);
+            text += "  unreachable\n}\n\n";
+        }
+        self.fails = true;
+        text
+    }
+
+    /// The start of the program function, which sets the pointer up.
+    fn entry(&self) -> String {
+        let cp = self.cptr();
+        let mut text = String::new();
+        match self.symbol {
+            Some(ref symbol) => {
+                text += &format!(
+                    "define {} @{}({} %tape, i32 ()* %get, i32 (i32)* %put) !dbg !{} {{\n",
+                    cp,
+                    symbol,
+                    cp,
+                    SUBPROGRAM
+                );
+                text += "entry:\n";
+                text += &format!("  %p = alloca {}\n", cp);
+                text += &format!("  store {} %tape, {}* %p\n", cp, cp);
+            },
+            None => {
+                text += &format!("define i32 @main() !dbg !{} {{\n", SUBPROGRAM);
+                text += "entry:\n";
+                text += &format!("  %p = alloca {}\n", cp);
+                let cells = match self.config.tape {
+                    Tape::Stack => STACK_CELLS,
+                    Tape::Heap(cells) | Tape::Bidirectional(cells) => cells.max(1),
+                };
+                let first = if self.config.tape.bidirectional() {cells / 2} else {0};
+                text += &format!("  %tape = call i8* @calloc(i64 {}, i64 {})\n", cells, self.stride());
+                text += "  %null = icmp eq i8* %tape, null\n";
+                text += "  br i1 %null, label %nomem, label %start\n";
+                text += "nomem:\n";
+                text += "  call void @bf_alloc_failed()\n";
+                text += "  unreachable\n";
+                text += "start:\n";
+                let stride = self.stride() as u64;
+                text += "  store i8* %tape, i8** @bf_start\n";
+                text += &format!("  %last = getelementptr i8, i8* %tape, i64 {}\n", cells * stride);
+                text += "  store i8* %last, i8** @bf_end\n";
+                text += &format!("  %middle = getelementptr i8, i8* %tape, i64 {}\n", first * stride);
+                text += &format!("  %first = bitcast i8* %middle to {}\n", cp);
+                text += &format!("  store {} %first, {}* %p\n", cp, cp);
+            },
+        }
+        text
+    }
+
+    /// The debug information: the source file, the program function and the
+    /// locations of the operations.
+    fn metadata(&self) -> String {
+        let file = self.file.clone().unwrap_or_default();
+        let name = self.symbol.clone().unwrap_or_else(|| String::from("main"));
+        let mut text = String::from("\n!llvm.dbg.cu = !{!0}\n!llvm.module.flags = !{!5, !6}\n\n");
+        // There is no language code for Brainfuck, and C is the closest to
+        // the functions generated.
+        text += &format!(
+            "!{} = distinct !DICompileUnit(language: DW_LANG_C99, file: !{}, producer: \"bfc\", \
+             isOptimized: false, runtimeVersion: 0, emissionKind: FullDebug)\n",
+            CU,
+            FILE
+        );
+        text += &format!(
+            "!{} = !DIFile(filename: \"{}\", directory: \".\")\n",
+            FILE,
+            escape(file.as_bytes())
+        );
+        text += &format!(
+            "!{} = distinct !DISubprogram(name: \"{}\", scope: !{}, file: !{}, line: 1, type: !3, \
+             scopeLine: 1, spFlags: DISPFlagDefinition, unit: !{})\n",
+            SUBPROGRAM,
+            name,
+            FILE,
+            FILE,
+            CU
+        );
+        text += "!3 = !DISubroutineType(types: !4)\n";
+        text += "!4 = !{}\n";
+        text += "!5 = !{i32 2, !\"Debug Info Version\", i32 3}\n";
+        text += "!6 = !{i32 7, !\"Dwarf Version\", i32 4}\n";
+        let mut locations: Vec<_> = self.locations.iter().collect();
+        locations.sort_by_key(|&(_, &id)| id);
+        for (&(line, column), &id) in locations {
+            text += &format!(
+                "!{} = !DILocation(line: {}, column: {}, scope: !{})\n",
+                id,
+                line,
+                column,
+                SUBPROGRAM
+            );
+        }
+        text
+    }
+
+    /// Generates the module for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> String {
+        walk(&mut self, ir);
+        self.dbg = None;
+        self.exit();
+        let io_failed = self.io_failed();
+        let alloc_failed = self.alloc_failed();
+        let mut text = self.prologue();
+        text += &io_failed;
+        text += &alloc_failed;
+        text += &self.entry();
+        text += &self.body;
+        text += "}\n";
+        text += &self.metadata();
+        text
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    type Loop = Loop;
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Sets the debug location of the following instructions.
+    fn at(&mut self, loc: &Location) {
+        if self.file.is_none() {
+            self.file = Some(loc.file.clone());
+        }
+        let line = if loc.line > u32::MAX as u64 { 0 } else { loc.line };
+        let column = if loc.column > MAX_COLUMN { 0 } else { loc.column };
+        let next = LOCATIONS + self.locations.len();
+        let id = *self.locations.entry((line, column)).or_insert(next);
+        self.dbg = Some(id);
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of a stack tape are only detected when checked, and cells out
+    /// of an unchecked heap tape are not detected.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        let left = self.checked() || (self.on_stack() && self.config.checked);
+        if offset > 0 && (self.on_stack() || self.checked()) {
+            self.right(offset, loc);
+        } else if offset < 0 && left {
+            self.left(offset, loc);
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, _loc: &Location) {
+        if n != 0 {
+            let cp = self.cptr();
+            let moved = self.cell_ptr(n);
+            self.inst(&format!("store {} {}, {}* %p", cp, moved, cp));
+        }
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        self.reach(n, loc);
+        self.shift(n, loc);
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let c = self.cty();
+        let amount = self.lit(amount);
+        if amount != 0 {
+            let (addr, value) = self.load(offset);
+            let sum = self.value(&format!("add {} {}, {}", c, value, amount));
+            self.store(&sum, &addr);
+        }
+    }
+
+    fn set(&mut self, offset: i64, value: i64, _loc: &Location) {
+        let value = self.lit(value);
+        let addr = self.cell_ptr(offset);
+        self.store(&value.to_string(), &addr);
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, _loc: &Location) {
+        let c = self.cty();
+        let factor = self.lit(factor);
+        if factor == 0 {
+            return;
+        }
+        let (_, value) = self.load(src);
+        let product = if factor == 1 {
+            value
+        } else {
+            self.value(&format!("mul {} {}, {}", c, value, factor))
+        };
+        let (addr, value) = self.load(dst);
+        let sum = self.value(&format!("add {} {}, {}", c, value, product));
+        self.store(&sum, &addr);
+    }
+
+    fn put_char(&mut self, offset: i64, _loc: &Location) {
+        let c = self.cty();
+        let (_, value) = self.load(offset);
+        let byte = if self.config.cell_size == CellSize::Bits8 {
+            value
+        } else {
+            self.value(&format!("trunc {} {} to i8", c, value))
+        };
+        if self.is_main() {
+            self.inst(&format!("call void @bf_put(i8 {})", byte));
+        } else {
+            let int = self.value(&format!("zext i8 {} to i32", byte));
+            self.value(&format!("call i32 %put(i32 {})", int));
+        }
+        self.puts = true;
+    }
+
+    fn get_char(&mut self, offset: i64, _loc: &Location) {
+        let int = if self.is_main() {
+            self.value("call i32 @bf_get()")
+        } else {
+            self.value("call i32 %get()")
+        };
+        match self.config.eof {
+            Eof::MinusOne => {
+                let value = self.int_cell(&int);
+                let addr = self.cell_ptr(offset);
+                self.store(&value, &addr);
+            },
+            Eof::Zero => {
+                let eof = self.value(&format!("icmp slt i32 {}, 0", int));
+                let int = self.value(&format!("select i1 {}, i32 0, i32 {}", eof, int));
+                let value = self.int_cell(&int);
+                let addr = self.cell_ptr(offset);
+                self.store(&value, &addr);
+            },
+            Eof::Unchanged => {
+                let (read, end) = (self.label("read"), self.label("eof"));
+                let eof = self.value(&format!("icmp slt i32 {}, 0", int));
+                self.cond_br(&eof, &end, &read);
+                self.block(&read);
+                let value = self.int_cell(&int);
+                let addr = self.cell_ptr(offset);
+                self.store(&value, &addr);
+                self.br(&end);
+                self.block(&end);
+            },
+        }
+        self.gets = true;
+    }
+
+    fn scan(&mut self, n: i64, loc: &Location) {
+        let (head, end) = self.open();
+        self.move_by(n, loc);
+        self.close(&head, &end);
+    }
+
+    fn open_loop(&mut self, loc: &Location) -> Loop {
+        let (head, end) = self.open();
+        Loop {
+            head,
+            end,
+            loc: loc.clone(),
+        }
+    }
+
+    fn close_loop(&mut self, lp: Loop) {
+        self.at(&lp.loc);
+        self.close(&lp.head, &lp.end);
+    }
+
+}
+
+const DECLARATIONS: &str = "\
+; `size_t` is `i64`, so the module is for 64-bit targets only.
+declare i8* @calloc(i64, i64)
+declare i8* @realloc(i8*, i64)
+declare i8* @memset(i8*, i32, i64)
+declare i8* @memmove(i8*, i8*, i64)
+declare i64 @read(i32, i8*, i64)
+declare i64 @write(i32, i8*, i64)
+declare void @exit(i32) noreturn
+
+";
+
+/// Grows a bidirectional tape to three times its size, moving its cells to
+/// the middle, until it has the cell at `offset` bytes from `p`, giving the
+/// new address of `p`.
+const GROW_BIDIRECTIONAL: &str = "\
+define internal i8* @bf_grow(i8* %p, i64 %offset) {
+entry:
+  %start = load i8*, i8** @bf_start
+  %end = load i8*, i8** @bf_end
+  %first = ptrtoint i8* %start to i64
+  %last = ptrtoint i8* %end to i64
+  %here = ptrtoint i8* %p to i64
+  %pos.0 = sub i64 %here, %first
+  %size.0 = sub i64 %last, %first
+  br label %check
+check:
+  %tape = phi i8* [ %start, %entry ], [ %grown, %move ]
+  %pos = phi i64 [ %pos.0, %entry ], [ %moved, %move ]
+  %size = phi i64 [ %size.0, %entry ], [ %tripled, %move ]
+  %at = add i64 %pos, %offset
+  %below = icmp slt i64 %at, 0
+  %above = icmp sge i64 %at, %size
+  %out = or i1 %below, %above
+  br i1 %out, label %grow, label %done
+grow:
+  %tripled = mul i64 %size, 3
+  %grown = call i8* @realloc(i8* %tape, i64 %tripled)
+  %null = icmp eq i8* %grown, null
+  br i1 %null, label %nomem, label %move
+nomem:
+  call void @bf_grow_failed()
+  unreachable
+move:
+  %middle = getelementptr i8, i8* %grown, i64 %size
+  %double = shl i64 %size, 1
+  %right = getelementptr i8, i8* %grown, i64 %double
+  %m = call i8* @memmove(i8* %middle, i8* %grown, i64 %size)
+  %l = call i8* @memset(i8* %grown, i32 0, i64 %size)
+  %r = call i8* @memset(i8* %right, i32 0, i64 %size)
+  %moved = add i64 %pos, %size
+  br label %check
+done:
+  store i8* %tape, i8** @bf_start
+  %new.end = getelementptr i8, i8* %tape, i64 %size
+  store i8* %new.end, i8** @bf_end
+  %new.p = getelementptr i8, i8* %tape, i64 %pos
+  ret i8* %new.p
+}
+
+";
+
+/// Writes the whole output buffer.
+const FLUSH: &str = "\
+define internal void @bf_flush() {
+entry:
+  br label %loop
+loop:
+  %pos = phi i64 [ 0, %entry ], [ %next, %wrote ]
+  %len = load i64, i64* @bf_out_len
+  %more = icmp ult i64 %pos, %len
+  br i1 %more, label %write, label %done
+write:
+  %from = getelementptr inbounds [4096 x i8], [4096 x i8]* @bf_out, i64 0, i64 %pos
+  %rest = sub i64 %len, %pos
+  %n = call i64 @write(i32 1, i8* %from, i64 %rest)
+  %failed = icmp sle i64 %n, 0
+  br i1 %failed, label %fail, label %wrote
+fail:
+  call void @bf_write_failed()
+  unreachable
+wrote:
+  %next = add i64 %pos, %n
+  br label %loop
+done:
+  store i64 0, i64* @bf_out_len
+  ret void
+}
+
+";
+
+/// Appends a byte to the output buffer, flushing it on newlines and when
+/// full.
+const PUT: &str = "\
+define internal void @bf_put(i8 %byte) {
+entry:
+  %len = load i64, i64* @bf_out_len
+  %at = getelementptr inbounds [4096 x i8], [4096 x i8]* @bf_out, i64 0, i64 %len
+  store i8 %byte, i8* %at
+  %next = add i64 %len, 1
+  store i64 %next, i64* @bf_out_len
+  %newline = icmp eq i8 %byte, 10
+  %full = icmp uge i64 %next, 4096
+  %now = or i1 %newline, %full
+  br i1 %now, label %flush, label %done
+flush:
+  call void @bf_flush()
+  br label %done
+done:
+  ret void
+}
+
+";
+
+/// Gives the next byte of the input buffer, or -1 at the end of the input.
+/// The output is flushed before the buffer is filled again.
+const GET: &str = "\
+define internal i32 @bf_get() {
+entry:
+  %pos = load i64, i64* @bf_in_pos
+  %len = load i64, i64* @bf_in_len
+  %empty = icmp uge i64 %pos, %len
+  br i1 %empty, label %fill, label %take
+fill:
+  call void @bf_flush()
+  %buffer = getelementptr inbounds [4096 x i8], [4096 x i8]* @bf_in, i64 0, i64 0
+  %n = call i64 @read(i32 0, i8* %buffer, i64 4096)
+  %failed = icmp slt i64 %n, 0
+  br i1 %failed, label %fail, label %filled
+fail:
+  call void @bf_read_failed()
+  unreachable
+filled:
+  %eof = icmp eq i64 %n, 0
+  br i1 %eof, label %end, label %refilled
+end:
+  ret i32 -1
+refilled:
+  store i64 %n, i64* @bf_in_len
+  br label %take
+take:
+  %at = phi i64 [ %pos, %entry ], [ 0, %refilled ]
+  %from = getelementptr inbounds [4096 x i8], [4096 x i8]* @bf_in, i64 0, i64 %at
+  %byte = load i8, i8* %from
+  %next = add i64 %at, 1
+  store i64 %next, i64* @bf_in_pos
+  %c = zext i8 %byte to i32
+  ret i32 %c
+}
+
+";
+
+/// Writes a byte.
+const PUT_UNBUFFERED: &str = "\
+define internal void @bf_put(i8 %byte) {
+entry:
+  %buffer = alloca i8
+  store i8 %byte, i8* %buffer
+  %n = call i64 @write(i32 1, i8* %buffer, i64 1)
+  %failed = icmp ne i64 %n, 1
+  br i1 %failed, label %fail, label %done
+fail:
+  call void @bf_write_failed()
+  unreachable
+done:
+  ret void
+}
+
+";
+
+/// Reads a byte, or gives -1 at the end of the input.
+const GET_UNBUFFERED: &str = "\
+define internal i32 @bf_get() {
+entry:
+  %buffer = alloca i8
+  %n = call i64 @read(i32 0, i8* %buffer, i64 1)
+  %failed = icmp slt i64 %n, 0
+  br i1 %failed, label %fail, label %read
+fail:
+  call void @bf_read_failed()
+  unreachable
+read:
+  %eof = icmp eq i64 %n, 0
+  br i1 %eof, label %end, label %take
+end:
+  ret i32 -1
+take:
+  %byte = load i8, i8* %buffer
+  %c = zext i8 %byte to i32
+  ret i32 %c
+}
+
+";
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        Codegen,
+    };
+    use front_end::{
+        self,
+        ByteStream,
+    };
+    use ir;
+    use config::{
+        Config,
+    };
+
+    fn generate(src: &str, symbol: Option<&str>) -> String {
+        let stream = ByteStream::from_str(String::from("test.bf"), src);
+        let ir = ir::lower(front_end::parse(stream).unwrap());
+        Codegen::new(&Config::default(), symbol.map(String::from)).generate(ir)
+    }
+
+    #[test]
+    fn module_header() {
+        let text = generate("+", None);
+        assert!(text.starts_with("source_filename = \"test.bf\"\n\n"));
+        assert!(text.contains("@bf_start = internal global i8* null\n"));
+        assert!(text.contains("declare i8* @calloc(i64, i64)\n"));
+        assert!(text.contains("define i32 @main() !dbg !2 {\nentry:\n  %p = alloca i16*\n"));
+        // The pointers are typed, which LLVM 14 reads by default.
+        assert!(!text.contains(" ptr "));
+        assert!(!text.contains("ptr,"));
+    }
+
+    #[test]
+    fn debug_locations() {
+        let text = generate("+\n >.", None);
+        assert!(text.contains("  %t12 = add i16 %t11, 1, !dbg !7\n"));
+        assert!(text.contains("  %t14 = getelementptr i16, i16* %t13, i64 1, !dbg !8\n"));
+        assert!(text.contains("  call void @bf_put(i8 %t17), !dbg !9\n"));
+        assert!(text.contains("!7 = !DILocation(line: 1, column: 1, scope: !2)\n"));
+        assert!(text.contains("!8 = !DILocation(line: 2, column: 2, scope: !2)\n"));
+        assert!(text.contains("!9 = !DILocation(line: 2, column: 3, scope: !2)\n"));
+        // Columns past what DWARF keeps are unknown.
+        let text = generate(&format!("{}+", " ".repeat(0x10000)), None);
+        assert!(text.contains("!7 = !DILocation(line: 1, column: 0, scope: !2)\n"));
+    }
+
+    #[test]
+    fn functions() {
+        let text = generate("+.", Some("name"));
+        assert!(text.contains(
+            "define i16* @name(i16* %tape, i32 ()* %get, i32 (i32)* %put) !dbg !2 {\n"
+        ));
+        assert!(text.contains("  %t7 = call i32 %put(i32 %t6), !dbg !8\n"));
+        assert!(text.contains("!DISubprogram(name: \"name\","));
+        assert!(!text.contains("main"));
+        assert!(!text.contains("@bf_start"));
+        assert!(!text.contains("@bf_fail"));
+    }
+
+    #[test]
+    fn allocation_failures() {
+        let text = generate(">", None);
+        assert!(text.contains("nomem:\n  call void @bf_alloc_failed()\n  unreachable\n"));
+        assert!(text.contains("nomem:\n  call void @bf_grow_failed()\n  unreachable\n"));
+        assert!(text.contains("c\"Could not allocate the tape.\\0A\""));
+        assert!(text.contains("c\"Could not grow the tape.\\0A\""));
+        assert!(!text.contains("@exit(i32 1)"));
+    }
+
+}
@@ -0,0 +1,91 @@
+pub mod codegen;
+
+pub use self::codegen::{
+    Codegen,
+};
+
+use std::io::{
+    Write,
+    Error,
+    ErrorKind,
+};
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+};
+use super::{
+    Arch,
+    Format,
+    create_output,
+    check_function,
+};
+
+
+/// Textual LLVM IR, for `opt`, `llc` or `lli` on 64-bit targets. The `asm`
+/// format outputs a program with a `main` function, and the `obj` format a
+/// function to embed in another program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Llvm;
+
+impl Llvm {
+
+    /// Generates a module defining `main`, or a function named `symbol`.
+    pub fn gen_module(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        symbol: Option<String>
+    ) -> String {
+        Codegen::new(config, symbol).generate(ir)
+    }
+
+    fn write_module(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        symbol: Option<String>,
+        out: String
+    ) -> Result<usize, Error> {
+        let text = self.gen_module(ir, config, symbol);
+        create_output(&out)?.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+}
+
+impl Arch for Llvm {
+
+    fn generate(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        format: Format,
+        out: String
+    ) -> Result<usize, Error> {
+        match format {
+            Format::Asm => self.write_module(ir, config, None, out),
+            Format::Object(symbol) => {
+                check_function(config)?;
+                self.write_module(ir, config, Some(symbol), out)
+            },
+            Format::Elf => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The `elf` format is not supported by the llvm back end."
+            )),
+            Format::Bin => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The `bin` format is not supported by the llvm back end."
+            )),
+        }
+    }
+
+    fn default_format(&self) -> Format {
+        Format::Asm
+    }
+
+}
@@ -1,7 +1,33 @@
 pub mod gen;
+pub mod walk;
+pub mod linux;
+pub mod elf;
 pub mod x86;
+pub mod aarch64;
+pub mod riscv64;
+pub mod armv7;
+pub mod wasm;
+pub mod c;
+pub mod llvm;
 
 pub use self::gen::Arch;
 pub use self::gen::Format;
+pub use self::gen::create_output;
+pub use self::gen::check_function;
+pub use self::gen::label_for;
+pub use self::gen::Labels;
+pub use self::gen::BOUNDS_EXIT;
+pub use self::gen::OVERFLOW_EXIT;
+pub use self::gen::IO_EXIT;
+pub use self::gen::STACK_CELLS;
+pub use self::walk::Emitter;
+pub use self::walk::walk;
+pub use self::walk::straight_range;
 pub use self::x86::X86Mode;
+pub use self::aarch64::Aarch64;
+pub use self::riscv64::Riscv64;
+pub use self::armv7::Armv7;
+pub use self::wasm::Wasm;
+pub use self::c::C;
+pub use self::llvm::Llvm;
 
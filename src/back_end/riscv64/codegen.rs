@@ -0,0 +1,870 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Eof,
+    Overflow,
+    Tape,
+};
+use back_end::{
+    Emitter,
+    Labels,
+    walk,
+    label_for,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+};
+use back_end::linux::{
+    PROT_READ_WRITE,
+    MAP_FLAGS,
+    RLIMIT_STACK,
+    STACK_CAP,
+};
+use super::{
+    Riscv64,
+};
+use super::inst::{
+    Inst,
+    Reg,
+    Width,
+    Cond,
+    fits_i12,
+};
+
+
+/// The pointer to the current cell.
+const TAPE: Reg = Reg::S1;
+/// The first cell of a checked stack tape, or the start of a checked tape
+/// in memory.
+const START: Reg = Reg::S2;
+/// The end of a checked tape in memory, or the lowest address a checked
+/// stack tape may use.
+const END: Reg = Reg::S3;
+/// The I/O state, when buffered.
+const IO: Reg = Reg::S4;
+/// Keeps the return address of routines which call other routines.
+const LINK: Reg = Reg::S5;
+/// Scratch register of the helpers which build addresses and immediates.
+const SCRATCH: Reg = Reg::T6;
+
+const TAPE_GROW: &str = "_tape_grow";
+const FAIL: &str = "_fail";
+/// Enters `_fail` past its flush, before the output buffer is set up.
+const FAIL_EARLY: &str = "_fail_early";
+const PUT: &str = "_put";
+const GET: &str = "_get";
+const FLUSH: &str = "_flush";
+const WRITE_FAILED: &str = "_write_failed";
+const READ_FAILED: &str = "_read_failed";
+
+/// Size of each I/O buffer.
+const IO_BUFFER: i64 = 4096;
+/// Offsets from `s4` of the I/O state: the length of the output buffer, the
+/// position and the length of the input buffer, and the buffers themselves.
+const IO_OUT_LEN: i32 = 0;
+const IO_IN_POS: i32 = 8;
+const IO_IN_LEN: i32 = 16;
+const IO_OUT: i64 = 32;
+const IO_IN: i64 = IO_OUT + IO_BUFFER;
+const IO_END: i64 = IO_IN + IO_BUFFER;
+
+/// Generates a standalone program starting at `_start`, with the tape on
+/// the stack or in a region given by `mmap`, see `Tape`, and I/O done
+/// through system calls. The pointer lives in `s1`, and the bounds of a
+/// checked tape in `s2` and `s3`.
+#[derive(Clone, Debug)]
+pub struct Codegen {
+    config: Config,
+    insts: Vec<Inst>,
+    labels: Labels,
+    /// Whether `_fail` is called.
+    fails: bool,
+    /// Whether the program writes, through `_put` if buffered.
+    puts: bool,
+    /// Whether the program reads, through `_get` if buffered.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator. The configuration must be supported, see
+    /// `Riscv64::check`.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            insts: Vec::new(),
+            labels: Labels::default(),
+            fails: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    /// Width of the cells in memory.
+    fn cw(&self) -> Width {
+        Riscv64::cell_width(self.config.cell_size)
+    }
+
+    /// Size of a cell in bytes.
+    fn stride(&self) -> i64 {
+        self.config.cell_size.bytes() as i64
+    }
+
+    /// Whether the tape is on the stack, which grows downwards with it.
+    fn on_stack(&self) -> bool {
+        self.config.tape == Tape::Stack
+    }
+
+    /// Whether the tape grows at both ends.
+    fn growable(&self) -> bool {
+        self.config.tape.bidirectional()
+    }
+
+    /// Whether the pointer is checked against both ends of the tape, which
+    /// are kept in `s2` and `s3`. The first cell of a stack tape is kept in
+    /// `s2` instead, when checked.
+    fn checked(&self) -> bool {
+        !self.on_stack() && (self.growable() || self.config.checked)
+    }
+
+    /// Whether I/O goes through the buffers, whose state is pointed by `s4`.
+    fn buffered(&self) -> bool {
+        !self.config.unbuffered
+    }
+
+    /// The displacement of a cell from the pointer. Cells follow the stack
+    /// downwards when the tape is on the stack, and go upwards otherwise.
+    fn disp(&self, offset: i64) -> i64 {
+        if self.on_stack() {-offset * self.stride()} else {offset * self.stride()}
+    }
+
+    /// Loads an immediate into a register: a 32-bit value with `lui` and
+    /// `addiw`, and a wider one by shifting its upper bits in place.
+    fn li(&mut self, rd: Reg, value: i64) {
+        if fits_i12(value) {
+            self.emit(Inst::Addi(rd, Reg::ZERO, value as i32));
+        } else if value == value as i32 as i64 {
+            let upper = value.wrapping_add(0x800) >> 12;
+            let lower = value - (upper << 12);
+            self.emit(Inst::Lui(rd, upper as u32 & 0xF_FFFF));
+            if lower != 0 {
+                self.emit(Inst::Addiw(rd, rd, lower as i32));
+            }
+        } else {
+            let lower = (value << 52) >> 52;
+            let upper = value.wrapping_sub(lower) >> 12;
+            let zeros = upper.trailing_zeros();
+            self.li(rd, upper >> zeros);
+            self.emit(Inst::Slli(rd, rd, 12 + zeros as u8));
+            if lower != 0 {
+                self.emit(Inst::Addi(rd, rd, lower as i32));
+            }
+        }
+    }
+
+    /// Adds a signed immediate to a register, through `t6` if it does not
+    /// fit in 12 bits.
+    fn add_imm(&mut self, rd: Reg, rs: Reg, value: i64) {
+        if fits_i12(value) {
+            if value != 0 || rd != rs {
+                self.emit(Inst::Addi(rd, rs, value as i32));
+            }
+        } else {
+            self.li(SCRATCH, value);
+            self.emit(Inst::Add(rd, rs, SCRATCH));
+        }
+    }
+
+    fn mv(&mut self, rd: Reg, rs: Reg) {
+        self.emit(Inst::Addi(rd, rs, 0));
+    }
+
+    /// The base register and displacement of a cell, computing its address
+    /// into `t6` if it is too far for a load or a store.
+    fn cell(&mut self, offset: i64) -> (Reg, i32) {
+        let disp = self.disp(offset);
+        if fits_i12(disp) {
+            (TAPE, disp as i32)
+        } else {
+            self.add_imm(SCRATCH, TAPE, disp);
+            (SCRATCH, 0)
+        }
+    }
+
+    /// Loads a cell, zero extended, into a register.
+    fn load(&mut self, reg: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Load(self.cw(), reg, base, disp));
+    }
+
+    fn store(&mut self, reg: Reg, offset: i64) {
+        let (base, disp) = self.cell(offset);
+        self.emit(Inst::Store(self.cw(), reg, base, disp));
+    }
+
+    /// Puts the address of a cell into a register.
+    fn cell_addr(&mut self, reg: Reg, offset: i64) {
+        let disp = self.disp(offset);
+        self.add_imm(reg, TAPE, disp);
+    }
+
+    fn branch(&mut self, cond: Cond, rs1: Reg, rs2: Reg, label: &str) {
+        self.emit(Inst::Branch(cond, rs1, rs2, String::from(label)));
+    }
+
+    fn call(&mut self, label: &str) {
+        self.emit(Inst::Jal(Reg::RA, String::from(label)));
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.emit(Inst::Jal(Reg::ZERO, String::from(label)));
+    }
+
+    fn label(&mut self, label: &str) {
+        self.emit(Inst::Label(String::from(label)));
+    }
+
+    /// Emits a system call, whose arguments must be in `a0` to `a5` already.
+    fn syscall(&mut self, number: i64) {
+        self.li(Reg::A7, number);
+        self.emit(Inst::Ecall);
+    }
+
+    /// Reports `message` and exits with status 1 if the last system call
+    /// failed, that is, if it returned a value from -4095 to -1. Calls made
+    /// while the program starts fail through `_fail_early`.
+    fn check_syscall(&mut self, label: &str, message: &str, early: bool) {
+        self.li(SCRATCH, -4095);
+        self.branch(Cond::Ltu, Reg::A0, SCRATCH, label);
+        self.li(Reg::A0, 1);
+        let entry = if early {FAIL_EARLY} else {FAIL};
+        self.report_at(entry, IO_EXIT, String::from(message));
+        self.label(label);
+    }
+
+    /// Maps `a1` bytes, readable and writable if `prot` is set.
+    fn mmap(&mut self, prot: bool) {
+        self.li(Reg::A0, 0);
+        self.li(Reg::A2, if prot {PROT_READ_WRITE} else {0});
+        self.li(Reg::A3, MAP_FLAGS);
+        self.li(Reg::A4, -1);
+        self.li(Reg::A5, 0);
+        self.syscall(Riscv64::SYS_MMAP);
+    }
+
+    /// Keeps the lowest address a checked stack tape may use. The arguments
+    /// and the environment take at most a quarter of the stack size limit,
+    /// so half of it is left for the tape.
+    fn limit_stack(&mut self) {
+        let capped = "_stack_capped";
+        // The current and the maximum limit are written on the stack.
+        self.push_zeros();
+        self.li(Reg::A0, 0);
+        self.li(Reg::A1, RLIMIT_STACK);
+        self.li(Reg::A2, 0);
+        self.mv(Reg::A3, Reg::SP);
+        self.syscall(Riscv64::SYS_PRLIMIT64);
+        self.check_syscall("_prlimit_ok", "Could not read the stack size limit.\n", true);
+        self.emit(Inst::Load(Width::Double, Reg::A0, Reg::SP, 0));
+        self.emit(Inst::Addi(Reg::SP, Reg::SP, 16));
+        self.li(Reg::A1, STACK_CAP);
+        self.branch(Cond::Geu, Reg::A1, Reg::A0, capped);
+        self.mv(Reg::A0, Reg::A1);
+        self.label(capped);
+        self.emit(Inst::Srli(Reg::A0, Reg::A0, 1));
+        self.emit(Inst::Sub(END, Reg::SP, Reg::A0));
+    }
+
+    /// Maps the heap tape with a guard page at each end, which are left
+    /// inaccessible, and points to its first cell. When checked, its bounds
+    /// are kept.
+    fn map_tape(&mut self, cells: u64) {
+        let bytes = Riscv64::heap_bytes(cells, self.config.cell_size) as i64;
+        self.li(Reg::A1, bytes + 2 * 4096);
+        self.mmap(false);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.add_imm(TAPE, Reg::A0, 4096);
+        self.mv(Reg::A0, TAPE);
+        self.li(Reg::A1, bytes);
+        self.li(Reg::A2, PROT_READ_WRITE);
+        self.syscall(Riscv64::SYS_MPROTECT);
+        self.check_syscall("_mprotect_ok", "Could not map the tape.\n", true);
+        if self.config.checked {
+            // The end of the last cell, not of its page.
+            let end = cells as i64 * self.stride();
+            self.mv(START, TAPE);
+            self.add_imm(END, TAPE, end);
+        }
+    }
+
+    /// Maps the first region of a bidirectional tape, keeps its bounds and
+    /// points to its middle.
+    fn map_growable(&mut self, cells: u64) {
+        let bytes = Riscv64::heap_bytes(cells, self.config.cell_size) as i64;
+        self.li(Reg::A1, bytes);
+        self.mmap(true);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.mv(START, Reg::A0);
+        self.add_imm(END, START, bytes);
+        self.add_imm(TAPE, START, bytes / 2);
+    }
+
+    /// Emits the `_tape_grow` routine, called with the address of a cell out
+    /// of a bidirectional tape in `a0`. It maps a region three times as big
+    /// as the tape, moves the tape to its middle and frees the old one,
+    /// until the cell is in the tape. The pointer is moved along.
+    fn tape_grow(&mut self) {
+        let again = "_tape_grow_again";
+        let copy = "_tape_grow_copy";
+        self.label(TAPE_GROW);
+        self.mv(Reg::T3, Reg::A0);
+        self.label(again);
+        self.emit(Inst::Sub(Reg::T2, END, START));
+        self.emit(Inst::Add(Reg::A1, Reg::T2, Reg::T2));
+        self.emit(Inst::Add(Reg::A1, Reg::A1, Reg::T2));
+        self.mmap(true);
+        self.check_syscall("_tape_grow_ok", "Could not grow the tape.\n", false);
+        self.emit(Inst::Add(Reg::T1, Reg::A0, Reg::T2));
+        self.mv(Reg::T0, START);
+        self.mv(Reg::T4, Reg::T2);
+        self.label(copy);
+        self.emit(Inst::Load(Width::Double, Reg::T5, Reg::T0, 0));
+        self.emit(Inst::Store(Width::Double, Reg::T5, Reg::T1, 0));
+        self.emit(Inst::Addi(Reg::T0, Reg::T0, 8));
+        self.emit(Inst::Addi(Reg::T1, Reg::T1, 8));
+        self.emit(Inst::Addi(Reg::T4, Reg::T4, -8));
+        self.branch(Cond::Ne, Reg::T4, Reg::ZERO, copy);
+        // How far the cells moved.
+        self.emit(Inst::Add(Reg::T1, Reg::A0, Reg::T2));
+        self.emit(Inst::Sub(Reg::T1, Reg::T1, START));
+        self.emit(Inst::Add(TAPE, TAPE, Reg::T1));
+        self.emit(Inst::Add(Reg::T3, Reg::T3, Reg::T1));
+        self.mv(Reg::T5, Reg::A0);
+        self.mv(Reg::A0, START);
+        self.mv(Reg::A1, Reg::T2);
+        self.syscall(Riscv64::SYS_MUNMAP);
+        self.mv(START, Reg::T5);
+        self.emit(Inst::Add(END, Reg::T5, Reg::T2));
+        self.emit(Inst::Add(END, END, Reg::T2));
+        self.emit(Inst::Add(END, END, Reg::T2));
+        self.branch(Cond::Ltu, Reg::T3, START, again);
+        self.branch(Cond::Geu, Reg::T3, END, again);
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_fail` routine, called right before a message laid out as
+    /// the exit status in one byte, a padding byte, the length of the text in
+    /// two bytes, and the text. It writes the text to stderr and exits.
+    fn fail(&mut self) {
+        self.label(FAIL);
+        // What was written before is shown first.
+        if self.buffered() && self.puts {
+            self.mv(LINK, Reg::RA);
+            self.call(FLUSH);
+            self.mv(Reg::RA, LINK);
+        }
+        self.label(FAIL_EARLY);
+        self.mv(LINK, Reg::RA);
+        self.li(Reg::A0, 2);
+        self.emit(Inst::Addi(Reg::A1, LINK, 4));
+        self.emit(Inst::Load(Width::Half, Reg::A2, LINK, 2));
+        self.syscall(Riscv64::SYS_WRITE);
+        self.emit(Inst::Load(Width::Byte, Reg::A0, LINK, 0));
+        self.syscall(Riscv64::SYS_EXIT);
+    }
+
+    /// Stops the program with the given exit status and message, through
+    /// `_fail`.
+    fn report(&mut self, status: i64, message: String) {
+        self.report_at(FAIL, status, message);
+    }
+
+    /// Stops the program through the given entry of `_fail`.
+    fn report_at(&mut self, entry: &str, status: i64, message: String) {
+        let mut data = vec![status as u8, 0];
+        data.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        data.extend_from_slice(message.as_bytes());
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        self.call(entry);
+        self.emit(Inst::Data(data));
+        self.fails = true;
+    }
+
+    /// Reports that the pointer left the tape at `loc` and exits.
+    fn report_bounds(&mut self, loc: &Location) {
+        let message = format!("Tape pointer out of bounds {}.\n", loc);
+        self.report(BOUNDS_EXIT, message);
+    }
+
+    /// Reports that the pointer left the tape, unless the registers compare
+    /// as `ok` says.
+    fn out_of_bounds(&mut self, ok: Cond, rs1: Reg, rs2: Reg, loc: &Location) {
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.branch(ok, rs1, rs2, &label);
+        self.report_bounds(loc);
+        self.label(&label);
+    }
+
+    /// Grows a bidirectional tape if the cell at `offset` is out of it, that
+    /// is, unless the registers compare as `ok` says, or reports the error if
+    /// the tape cannot grow.
+    fn bounds(&mut self, ok: Cond, rs1: Reg, rs2: Reg, offset: i64, loc: &Location) {
+        if !self.growable() {
+            self.out_of_bounds(ok, rs1, rs2, loc);
+            return;
+        }
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.branch(ok, rs1, rs2, &label);
+        self.cell_addr(Reg::A0, offset);
+        self.call(TAPE_GROW);
+        self.label(&label);
+    }
+
+    /// Emits the `_flush` routine, which writes the whole output buffer.
+    fn flush(&mut self) {
+        let again = "_flush_again";
+        let done = "_flush_done";
+        self.label(FLUSH);
+        self.add_imm(Reg::A1, IO, IO_OUT);
+        self.label(again);
+        self.emit(Inst::Load(Width::Double, Reg::A2, IO, IO_OUT_LEN));
+        self.branch(Cond::Eq, Reg::A2, Reg::ZERO, done);
+        self.li(Reg::A0, 1);
+        self.syscall(Riscv64::SYS_WRITE);
+        self.branch(Cond::Ge, Reg::ZERO, Reg::A0, WRITE_FAILED);
+        self.emit(Inst::Add(Reg::A1, Reg::A1, Reg::A0));
+        self.emit(Inst::Load(Width::Double, Reg::A2, IO, IO_OUT_LEN));
+        self.emit(Inst::Sub(Reg::A2, Reg::A2, Reg::A0));
+        self.emit(Inst::Store(Width::Double, Reg::A2, IO, IO_OUT_LEN));
+        self.jump(again);
+        self.label(done);
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_put` routine, which appends the byte in `a0` to the output
+    /// buffer, flushing it on newlines and when full.
+    fn put(&mut self) {
+        self.label(PUT);
+        self.emit(Inst::Load(Width::Double, Reg::T0, IO, IO_OUT_LEN));
+        self.add_imm(Reg::T1, IO, IO_OUT);
+        self.emit(Inst::Add(Reg::T1, Reg::T1, Reg::T0));
+        self.emit(Inst::Store(Width::Byte, Reg::A0, Reg::T1, 0));
+        self.emit(Inst::Addi(Reg::T0, Reg::T0, 1));
+        self.emit(Inst::Store(Width::Double, Reg::T0, IO, IO_OUT_LEN));
+        self.emit(Inst::Load(Width::Byte, Reg::T2, Reg::T1, 0));
+        self.li(Reg::T3, b'\n' as i64);
+        self.branch(Cond::Eq, Reg::T2, Reg::T3, FLUSH);
+        self.li(Reg::T3, IO_BUFFER);
+        self.branch(Cond::Geu, Reg::T0, Reg::T3, FLUSH);
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_get` routine, which takes the next byte of the input
+    /// buffer into `a0`, or -1 at the end of the input. The output is
+    /// flushed before the buffer is filled again.
+    fn get(&mut self) {
+        let byte = "_get_byte";
+        let eof = "_get_eof";
+        self.label(GET);
+        self.emit(Inst::Load(Width::Double, Reg::T0, IO, IO_IN_POS));
+        self.emit(Inst::Load(Width::Double, Reg::T1, IO, IO_IN_LEN));
+        self.branch(Cond::Ltu, Reg::T0, Reg::T1, byte);
+        self.mv(LINK, Reg::RA);
+        self.call(FLUSH);
+        self.mv(Reg::RA, LINK);
+        self.li(Reg::A0, 0);
+        self.add_imm(Reg::A1, IO, IO_IN);
+        self.li(Reg::A2, IO_BUFFER);
+        self.syscall(Riscv64::SYS_READ);
+        self.branch(Cond::Lt, Reg::A0, Reg::ZERO, READ_FAILED);
+        self.branch(Cond::Eq, Reg::A0, Reg::ZERO, eof);
+        self.emit(Inst::Store(Width::Double, Reg::A0, IO, IO_IN_LEN));
+        self.li(Reg::T0, 0);
+        self.label(byte);
+        self.add_imm(Reg::T1, IO, IO_IN);
+        self.emit(Inst::Add(Reg::T1, Reg::T1, Reg::T0));
+        self.emit(Inst::Load(Width::Byte, Reg::A0, Reg::T1, 0));
+        self.emit(Inst::Addi(Reg::T0, Reg::T0, 1));
+        self.emit(Inst::Store(Width::Double, Reg::T0, IO, IO_IN_POS));
+        self.emit(Inst::Ret);
+        self.label(eof);
+        self.li(Reg::A0, -1);
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits a routine reporting that I/O failed. The output buffer is
+    /// dropped, so that `_fail` does not write it again.
+    fn io_failed(&mut self, label: &str, message: &str) {
+        self.label(label);
+        if self.buffered() {
+            self.emit(Inst::Store(Width::Double, Reg::ZERO, IO, IO_OUT_LEN));
+        }
+        self.report(IO_EXIT, String::from(message));
+    }
+
+    /// Pushes 16 zeroed bytes, keeping the stack aligned.
+    fn push_zeros(&mut self) {
+        self.emit(Inst::Addi(Reg::SP, Reg::SP, -16));
+        self.emit(Inst::Store(Width::Double, Reg::ZERO, Reg::SP, 0));
+        self.emit(Inst::Store(Width::Double, Reg::ZERO, Reg::SP, 8));
+    }
+
+    fn prologue(&mut self) {
+        if self.on_stack() && self.config.checked {
+            self.limit_stack();
+        }
+        if self.buffered() {
+            self.add_imm(Reg::SP, Reg::SP, -IO_END);
+            self.mv(IO, Reg::SP);
+            for &field in [IO_OUT_LEN, IO_IN_POS, IO_IN_LEN].iter() {
+                self.emit(Inst::Store(Width::Double, Reg::ZERO, IO, field));
+            }
+        }
+        match self.config.tape {
+            Tape::Stack => {
+                // The first cell is at the top of a zeroed pair of words.
+                self.push_zeros();
+                self.add_imm(TAPE, Reg::SP, 16 - self.stride());
+                if self.config.checked {
+                    self.mv(START, TAPE);
+                }
+            },
+            Tape::Heap(cells) => self.map_tape(cells),
+            Tape::Bidirectional(cells) => self.map_growable(cells),
+        }
+    }
+
+    fn epilogue(&mut self) {
+        self.exit();
+        if self.growable() {
+            self.tape_grow();
+        }
+        let buffered = self.buffered();
+        if buffered && (self.puts || self.gets) {
+            self.flush();
+        }
+        if buffered && self.puts {
+            self.put();
+        }
+        if buffered && self.gets {
+            self.get();
+        }
+        // `_flush` is also called before reading.
+        if self.puts || (buffered && self.gets) {
+            self.io_failed(WRITE_FAILED, "Could not write the output.\n");
+        }
+        if self.gets {
+            self.io_failed(READ_FAILED, "Could not read the input.\n");
+        }
+        if self.fails {
+            self.fail();
+        }
+    }
+
+    fn exit(&mut self) {
+        if self.buffered() && self.puts {
+            self.call(FLUSH);
+        }
+        if self.config.exit_cell {
+            self.load(Reg::A0, 0);
+        } else {
+            self.li(Reg::A0, 0);
+        }
+        self.syscall(Riscv64::SYS_EXIT);
+    }
+
+    /// Grows the stack until it holds the address in the register.
+    fn grow_stack(&mut self, addr: Reg, loc: &Location) {
+        let start = self.labels.fresh(loc, "grow_start");
+        let end = self.labels.fresh(loc, "grow_end");
+        self.jump(&end);
+        self.label(&start);
+        self.push_zeros();
+        self.label(&end);
+        self.branch(Cond::Ltu, addr, Reg::SP, &start);
+    }
+
+    fn next(&mut self, n: i64, loc: &Location) {
+        self.shift(n, loc);
+        if self.on_stack() {
+            if self.config.checked {
+                self.out_of_bounds(Cond::Geu, TAPE, END, loc);
+            }
+            self.grow_stack(TAPE, loc);
+        } else if self.checked() {
+            self.bounds(Cond::Ltu, TAPE, END, 0, loc);
+        }
+    }
+
+    fn previous(&mut self, n: i64, loc: &Location) {
+        self.shift(-n, loc);
+        if self.on_stack() && self.config.checked {
+            self.out_of_bounds(Cond::Geu, START, TAPE, loc);
+        } else if self.checked() {
+            self.bounds(Cond::Geu, TAPE, START, 0, loc);
+        }
+    }
+
+    /// Adds an immediate to `t0`, through `t1` if it does not fit in 12
+    /// bits.
+    fn add_t0(&mut self, amount: i64) {
+        if fits_i12(amount) {
+            self.emit(Inst::Addi(Reg::T0, Reg::T0, amount as i32));
+        } else {
+            self.li(Reg::T1, amount);
+            self.emit(Inst::Add(Reg::T0, Reg::T0, Reg::T1));
+        }
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// is found by comparing the cell against the largest value the addition
+    /// keeps in range, or against the amount of a subtraction.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let limit = if amount < 0 {0} else {mask as i64};
+        if magnitude == 0 {
+            return;
+        }
+        if magnitude > mask {
+            self.overflow(limit, offset, loc, None);
+            return;
+        }
+        let ok = self.labels.fresh(loc, "add_ok");
+        let store = self.labels.fresh(loc, "add_store");
+        self.load(Reg::T0, offset);
+        if amount < 0 {
+            self.li(Reg::T1, magnitude as i64);
+            self.branch(Cond::Geu, Reg::T0, Reg::T1, &ok);
+        } else {
+            self.li(Reg::T1, (mask - magnitude) as i64);
+            self.branch(Cond::Geu, Reg::T1, Reg::T0, &ok);
+        }
+        self.overflow(limit, offset, loc, Some(&store));
+        self.label(&ok);
+        if amount < 0 {
+            self.emit(Inst::Sub(Reg::T0, Reg::T0, Reg::T1));
+        } else {
+            self.add_t0(magnitude as i64);
+        }
+        self.label(&store);
+        self.store(Reg::T0, offset);
+    }
+
+    /// Handles an overflowing cell, which saturates to `limit` or traps. A
+    /// saturated value is stored at once, or left in `t0` for the store at
+    /// the given label.
+    fn overflow(&mut self, limit: i64, offset: i64, loc: &Location, store: Option<&str>) {
+        if self.config.overflow == Overflow::Trap {
+            let message = format!("Cell overflow {}.\n", loc);
+            self.report(OVERFLOW_EXIT, message);
+            return;
+        }
+        self.li(Reg::T0, limit);
+        match store {
+            Some(label) => self.jump(label),
+            _ => self.store(Reg::T0, offset),
+        }
+    }
+
+    /// Generates the instructions for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> Vec<Inst> {
+        self.prologue();
+        walk(&mut self, ir);
+        self.epilogue();
+        self.insts
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    /// The labels of the start and of the end of the loop.
+    type Loop = (String, String);
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of the tape are only detected by checked tapes, and the guard
+    /// pages of a heap tape fault on cells past either end.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        let addr = Reg::T0;
+        if self.on_stack() && offset > 0 {
+            self.cell_addr(addr, offset);
+            if self.config.checked {
+                self.out_of_bounds(Cond::Geu, addr, END, loc);
+            }
+            self.grow_stack(addr, loc);
+        } else if self.on_stack() && self.config.checked && offset < 0 {
+            self.cell_addr(addr, offset);
+            self.out_of_bounds(Cond::Geu, START, addr, loc);
+        } else if self.checked() && offset != 0 {
+            self.cell_addr(addr, offset);
+            if offset > 0 {
+                self.bounds(Cond::Ltu, addr, END, offset, loc);
+            } else {
+                self.bounds(Cond::Geu, addr, START, offset, loc);
+            }
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, _loc: &Location) {
+        let disp = self.disp(n);
+        self.add_imm(TAPE, TAPE, disp);
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        if n > 0 {
+            self.next(n, loc);
+        } else if n < 0 {
+            self.previous(-n, loc);
+        }
+    }
+
+    /// Moves by `n` cells until a zero cell is found.
+    fn scan(&mut self, n: i64, loc: &Location) {
+        let body = self.labels.fresh(loc, "scan_body");
+        let test = self.labels.fresh(loc, "scan_test");
+        self.jump(&test);
+        self.label(&body);
+        self.move_by(n, loc);
+        self.label(&test);
+        self.load(Reg::T0, 0);
+        self.branch(Cond::Ne, Reg::T0, Reg::ZERO, &body);
+    }
+
+    fn put_char(&mut self, offset: i64, _loc: &Location) {
+        if self.buffered() {
+            self.load(Reg::A0, offset);
+            self.call(PUT);
+        } else {
+            self.cell_addr(Reg::A1, offset);
+            self.li(Reg::A0, 1);
+            self.li(Reg::A2, 1);
+            self.syscall(Riscv64::SYS_WRITE);
+            self.li(Reg::T0, 1);
+            self.branch(Cond::Ne, Reg::A0, Reg::T0, WRITE_FAILED);
+        }
+        self.puts = true;
+    }
+
+    fn get_char(&mut self, offset: i64, loc: &Location) {
+        let end = self.labels.fresh(loc, "getc_end");
+        self.gets = true;
+        if self.buffered() {
+            self.call(GET);
+            match self.config.eof {
+                Eof::MinusOne => (),
+                Eof::Zero => {
+                    self.branch(Cond::Ge, Reg::A0, Reg::ZERO, &end);
+                    self.li(Reg::A0, 0);
+                    self.label(&end);
+                },
+                Eof::Unchanged => self.branch(Cond::Lt, Reg::A0, Reg::ZERO, &end),
+            }
+            self.store(Reg::A0, offset);
+            if self.config.eof == Eof::Unchanged {
+                self.label(&end);
+            }
+            return;
+        }
+        // The byte read only fills the lowest byte of the cell.
+        if self.config.eof != Eof::Unchanged {
+            self.store(Reg::ZERO, offset);
+        }
+        self.cell_addr(Reg::A1, offset);
+        self.li(Reg::A0, 0);
+        self.li(Reg::A2, 1);
+        self.syscall(Riscv64::SYS_READ);
+        self.branch(Cond::Lt, Reg::A0, Reg::ZERO, READ_FAILED);
+        match self.config.eof {
+            Eof::MinusOne => {
+                self.branch(Cond::Ne, Reg::A0, Reg::ZERO, &end);
+                self.li(Reg::T0, -1);
+                self.store(Reg::T0, offset);
+            },
+            Eof::Zero => (),
+            Eof::Unchanged => if self.cw() != Width::Byte {
+                self.branch(Cond::Eq, Reg::A0, Reg::ZERO, &end);
+                self.emit(Inst::Load(Width::Byte, Reg::T0, Reg::A1, 0));
+                self.store(Reg::T0, offset);
+            },
+        }
+        self.label(&end);
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let amount = self.config.cell_size.wrap(amount);
+        if amount == 0 {
+            return;
+        }
+        self.load(Reg::T0, offset);
+        self.add_t0(amount);
+        self.store(Reg::T0, offset);
+    }
+
+    fn set(&mut self, offset: i64, value: i64, _loc: &Location) {
+        let value = self.config.cell_size.wrap(value);
+        if value == 0 {
+            self.store(Reg::ZERO, offset);
+        } else {
+            self.li(Reg::T0, value);
+            self.store(Reg::T0, offset);
+        }
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, _loc: &Location) {
+        let factor = self.config.cell_size.wrap(factor);
+        if factor == 0 {
+            return;
+        }
+        self.load(Reg::T0, src);
+        self.load(Reg::T1, dst);
+        match factor {
+            1 => self.emit(Inst::Add(Reg::T1, Reg::T1, Reg::T0)),
+            -1 => self.emit(Inst::Sub(Reg::T1, Reg::T1, Reg::T0)),
+            _ => {
+                self.li(Reg::T2, factor);
+                self.emit(Inst::Mul(Reg::T2, Reg::T0, Reg::T2));
+                self.emit(Inst::Add(Reg::T1, Reg::T1, Reg::T2));
+            },
+        }
+        self.store(Reg::T1, dst);
+    }
+
+    fn open_loop(&mut self, loc: &Location) -> (String, String) {
+        let label = label_for(loc);
+        let start = label.clone() + "_loop_start";
+        let end = label + "_loop_end";
+        self.jump(&end);
+        self.label(&start);
+        (start, end)
+    }
+
+    fn close_loop(&mut self, (start, end): (String, String)) {
+        self.label(&end);
+        self.load(Reg::T0, 0);
+        self.branch(Cond::Ne, Reg::T0, Reg::ZERO, &start);
+    }
+
+}
@@ -0,0 +1,495 @@
+use std::io::{
+    Error,
+    ErrorKind,
+};
+use std::collections::{
+    HashMap,
+};
+use super::inst::{
+    Inst,
+    Reg,
+    Width,
+    Cond,
+    fits_i12,
+};
+
+
+/// Machine code produced from a list of instructions, together with the
+/// offset of every label defined by it.
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+}
+
+#[derive(Clone, Debug)]
+enum Fragment {
+    Code(Vec<u8>),
+    Label(String),
+    /// A conditional branch, which becomes an inverted branch over a jump
+    /// when far.
+    Branch {
+        cond: Cond,
+        rs1: Reg,
+        rs2: Reg,
+        label: String,
+        far: bool,
+    },
+    Jal(Reg, String),
+}
+
+impl Fragment {
+
+    fn size(&self) -> usize {
+        match *self {
+            Fragment::Code(ref code) => code.len(),
+            Fragment::Label(_) => 0,
+            Fragment::Branch {far, ..} => if far {8} else {4},
+            Fragment::Jal(..) => 4,
+        }
+    }
+
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn r_type(funct7: u32, rs2: Reg, rs1: Reg, funct3: u32, rd: Reg, opcode: u32) -> u32 {
+    funct7 << 25 | rs2.code() << 20 | rs1.code() << 15 | funct3 << 12 | rd.code() << 7
+        | opcode
+}
+
+fn i_type(imm: i32, rs1: Reg, funct3: u32, rd: Reg, opcode: u32) -> Result<u32, Error> {
+    if !fits_i12(imm as i64) {
+        return Err(invalid(format!("Immediate {} does not fit in 12 bits.", imm)));
+    }
+    Ok((imm as u32 & 0xFFF) << 20 | rs1.code() << 15 | funct3 << 12 | rd.code() << 7
+        | opcode)
+}
+
+fn s_type(imm: i32, rs2: Reg, rs1: Reg, funct3: u32) -> Result<u32, Error> {
+    if !fits_i12(imm as i64) {
+        return Err(invalid(format!("Offset {} does not fit in 12 bits.", imm)));
+    }
+    let imm = imm as u32;
+    Ok((imm >> 5 & 0x7F) << 25 | rs2.code() << 20 | rs1.code() << 15 | funct3 << 12
+        | (imm & 0x1F) << 7 | 0x23)
+}
+
+fn b_type(cond: Cond, rs1: Reg, rs2: Reg, rel: i64) -> u32 {
+    let imm = rel as u32;
+    (imm >> 12 & 1) << 31 | (imm >> 5 & 0x3F) << 25 | rs2.code() << 20
+        | rs1.code() << 15 | cond.code() << 12 | (imm >> 1 & 0xF) << 8
+        | (imm >> 11 & 1) << 7 | 0x63
+}
+
+fn j_type(rd: Reg, rel: i64) -> u32 {
+    let imm = rel as u32;
+    (imm >> 20 & 1) << 31 | (imm >> 1 & 0x3FF) << 21 | (imm >> 11 & 1) << 20
+        | (imm >> 12 & 0xFF) << 12 | rd.code() << 7 | 0x6F
+}
+
+fn fits_branch(rel: i64) -> bool {
+    (-(1 << 12) .. 1 << 12).contains(&rel)
+}
+
+fn fits_jal(rel: i64) -> bool {
+    (-(1 << 20) .. 1 << 20).contains(&rel)
+}
+
+fn encode(inst: &Inst) -> Result<u32, Error> {
+    Ok(match *inst {
+        Inst::Lui(rd, imm) => {
+            if imm >= 1 << 20 {
+                return Err(invalid(format!("Immediate {} does not fit in 20 bits.", imm)));
+            }
+            imm << 12 | rd.code() << 7 | 0x37
+        },
+        Inst::Addi(rd, rs, imm) => i_type(imm, rs, 0, rd, 0x13)?,
+        Inst::Addiw(rd, rs, imm) => i_type(imm, rs, 0, rd, 0x1B)?,
+        Inst::Slli(rd, rs, shamt) => {
+            if shamt >= 64 {
+                return Err(invalid(format!("Shift {} is not supported.", shamt)));
+            }
+            i_type(shamt as i32, rs, 1, rd, 0x13)?
+        },
+        Inst::Srli(rd, rs, shamt) => {
+            if shamt >= 64 {
+                return Err(invalid(format!("Shift {} is not supported.", shamt)));
+            }
+            i_type(shamt as i32, rs, 5, rd, 0x13)?
+        },
+        Inst::Add(rd, rs1, rs2) => r_type(0, rs2, rs1, 0, rd, 0x33),
+        Inst::Sub(rd, rs1, rs2) => r_type(0x20, rs2, rs1, 0, rd, 0x33),
+        Inst::Mul(rd, rs1, rs2) => r_type(1, rs2, rs1, 0, rd, 0x33),
+        Inst::Load(w, rd, rs, disp) => {
+            let funct3 = if w == Width::Double {3} else {w.log2() + 4};
+            i_type(disp, rs, funct3, rd, 0x03)?
+        },
+        Inst::Store(w, src, rs, disp) => s_type(disp, src, rs, w.log2())?,
+        Inst::Ret => 0x0000_8067,
+        Inst::Ecall => 0x0000_0073,
+        Inst::Label(_) | Inst::Data(_) | Inst::Branch(..) | Inst::Jal(..) =>
+            unreachable!(),
+    })
+}
+
+#[derive(Clone, Debug)]
+struct Encoder {
+    frags: Vec<Fragment>,
+}
+
+impl Encoder {
+
+    fn push(&mut self, bytes: &[u8]) {
+        if let Some(&mut Fragment::Code(ref mut code)) = self.frags.last_mut() {
+            code.extend_from_slice(bytes);
+            return;
+        }
+        self.frags.push(Fragment::Code(bytes.to_vec()));
+    }
+
+    fn inst(&mut self, inst: &Inst) -> Result<(), Error> {
+        match *inst {
+            Inst::Label(ref label) => self.frags.push(Fragment::Label(label.clone())),
+            Inst::Data(ref bytes) => self.push(bytes),
+            Inst::Branch(cond, rs1, rs2, ref label) => self.frags.push(Fragment::Branch {
+                cond,
+                rs1,
+                rs2,
+                label: label.clone(),
+                far: false,
+            }),
+            Inst::Jal(rd, ref label) => self.frags.push(Fragment::Jal(rd, label.clone())),
+            _ => {
+                let word = encode(inst)?;
+                self.push(&word.to_le_bytes());
+            },
+        }
+        Ok(())
+    }
+
+    /// Computes the offset of every label with the current branch sizes.
+    fn layout(&self) -> Result<HashMap<String, usize>, Error> {
+        let mut labels = HashMap::new();
+        let mut pos = 0;
+        for frag in &self.frags {
+            if let Fragment::Label(ref label) = *frag {
+                if labels.insert(label.clone(), pos).is_some() {
+                    return Err(invalid(format!("Label {} defined twice.", label)));
+                }
+            }
+            pos += frag.size();
+        }
+        Ok(labels)
+    }
+
+    /// Starts with every branch in its near form and makes far the ones
+    /// whose target is out of reach, until nothing changes. A far branch
+    /// only makes the others longer, so this always terminates.
+    fn relax(&mut self) -> Result<HashMap<String, usize>, Error> {
+        loop {
+            let labels = self.layout()?;
+            let mut changed = false;
+            let mut pos = 0;
+            for frag in &mut self.frags {
+                let size = frag.size();
+                if let Fragment::Branch {ref label, ref mut far, ..} = *frag {
+                    let target = match labels.get(label) {
+                        Some(&t) => t,
+                        _ => return Err(invalid(format!("Undefined label {}.", label))),
+                    };
+                    if !*far && !fits_branch(target as i64 - pos as i64) {
+                        *far = true;
+                        changed = true;
+                    }
+                }
+                pos += size;
+            }
+            if !changed {
+                return Ok(labels);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, Error> {
+        let labels = self.relax()?;
+        let mut code = Vec::new();
+        for frag in self.frags {
+            let (rd, label) = match frag {
+                Fragment::Code(bytes) => {
+                    code.extend_from_slice(&bytes);
+                    continue;
+                },
+                Fragment::Label(_) => continue,
+                Fragment::Branch {cond, rs1, rs2, label, far} => {
+                    if !far {
+                        let rel = labels[&label] as i64 - code.len() as i64;
+                        code.extend_from_slice(&b_type(cond, rs1, rs2, rel).to_le_bytes());
+                        continue;
+                    }
+                    let skip = b_type(cond.inverse(), rs1, rs2, 8);
+                    code.extend_from_slice(&skip.to_le_bytes());
+                    (Reg::ZERO, label)
+                },
+                Fragment::Jal(rd, label) => (rd, label),
+            };
+            let target = match labels.get(&label) {
+                Some(&t) => t,
+                _ => return Err(invalid(format!("Undefined label {}.", label))),
+            };
+            let rel = target as i64 - code.len() as i64;
+            if !fits_jal(rel) {
+                return Err(invalid(format!("Label {} is out of reach.", label)));
+            }
+            code.extend_from_slice(&j_type(rd, rel).to_le_bytes());
+        }
+        Ok(Program {code, labels})
+    }
+
+}
+
+fn encoder(insts: &[Inst]) -> Result<Encoder, Error> {
+    let mut encoder = Encoder {
+        frags: Vec::new(),
+    };
+    for inst in insts {
+        if let Err(e) = encoder.inst(inst) {
+            return Err(invalid(format!(
+                "Cannot encode `{}`: {}",
+                inst.to_string().trim(),
+                e
+            )));
+        }
+    }
+    Ok(encoder)
+}
+
+/// Encodes the given instructions into machine code. Labels are resolved
+/// here, and branches to far labels are relaxed.
+pub fn assemble(insts: &[Inst]) -> Result<Program, Error> {
+    encoder(insts)?.finish()
+}
+
+/// Rewrites the branches that `assemble` relaxes as an inverted branch over
+/// a `j`, so that the assembly says what the machine code does.
+pub fn relax(insts: &[Inst]) -> Result<Vec<Inst>, Error> {
+    let mut encoder = encoder(insts)?;
+    encoder.relax()?;
+    // The branches are in the same order in both lists.
+    let mut far = encoder.frags.iter().filter_map(|frag| match *frag {
+        Fragment::Branch {far, ..} => Some(far),
+        _ => None,
+    });
+    let mut relaxed = Vec::with_capacity(insts.len());
+    for inst in insts {
+        match *inst {
+            Inst::Branch(cond, rs1, rs2, ref label) if far.next() == Some(true) => {
+                let skip = format!("_far{}", relaxed.len());
+                relaxed.push(Inst::Branch(cond.inverse(), rs1, rs2, skip.clone()));
+                relaxed.push(Inst::Jal(Reg::ZERO, label.clone()));
+                relaxed.push(Inst::Label(skip));
+            },
+            _ => relaxed.push(inst.clone()),
+        }
+    }
+    Ok(relaxed)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        assemble,
+        relax,
+    };
+    use super::super::inst::{
+        Inst,
+        Reg,
+        Width,
+        Cond,
+    };
+
+    const A0: Reg = Reg::A0;
+    const A1: Reg = Reg::A1;
+
+    fn label(name: &str) -> Inst {
+        Inst::Label(name.to_string())
+    }
+
+    fn branch(cond: Cond, label: &str) -> Inst {
+        Inst::Branch(cond, A0, A1, label.to_string())
+    }
+
+    fn code(insts: &[Inst]) -> Vec<u8> {
+        assemble(insts).unwrap().code
+    }
+
+    // The expected bytes are the output of `llvm-mc -triple=riscv64
+    // -mattr=+m`.
+    #[test]
+    fn arithmetic() {
+        let cases = vec![
+            (Inst::Lui(Reg::T0, 0xF_FFFF), vec![0xB7, 0xF2, 0xFF, 0xFF]),
+            (Inst::Addi(A0, A0, -2048), vec![0x13, 0x05, 0x05, 0x80]),
+            (Inst::Addiw(Reg::T1, Reg::T2, 2047), vec![0x1B, 0x83, 0xF3, 0x7F]),
+            (Inst::Slli(Reg::S2, Reg::S3, 63), vec![0x13, 0x99, 0xF9, 0x03]),
+            (Inst::Srli(A1, Reg::A2, 8), vec![0x93, 0x55, 0x86, 0x00]),
+            (Inst::Add(Reg::A3, Reg::A4, Reg::A5), vec![0xB3, 0x06, 0xF7, 0x00]),
+            (Inst::Sub(Reg::T3, Reg::T4, Reg::T5), vec![0x33, 0x8E, 0xEE, 0x41]),
+            (Inst::Mul(Reg::T6, Reg::S1, Reg::S4), vec![0xB3, 0x8F, 0x44, 0x03]),
+            (Inst::Ret, vec![0x67, 0x80, 0x00, 0x00]),
+            (Inst::Ecall, vec![0x73, 0x00, 0x00, 0x00]),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(std::slice::from_ref(&inst)), bytes, "{}", inst);
+        }
+    }
+
+    #[test]
+    fn memory_access() {
+        let s2 = Reg::S2;
+        let cases = vec![
+            (Inst::Load(Width::Byte, A0, s2, -1), vec![0x03, 0x45, 0xF9, 0xFF]),
+            (Inst::Load(Width::Half, A0, s2, 2), vec![0x03, 0x55, 0x29, 0x00]),
+            (Inst::Load(Width::Word, A0, s2, 4), vec![0x03, 0x65, 0x49, 0x00]),
+            (
+                Inst::Load(Width::Double, Reg::A7, Reg::SP, 2040),
+                vec![0x83, 0x38, 0x81, 0x7F],
+            ),
+            (Inst::Store(Width::Byte, Reg::T0, s2, -2048), vec![0x23, 0x00, 0x59, 0x80]),
+            (Inst::Store(Width::Half, Reg::T0, s2, 6), vec![0x23, 0x13, 0x59, 0x00]),
+            (Inst::Store(Width::Word, Reg::T0, s2, 0), vec![0x23, 0x20, 0x59, 0x00]),
+            (
+                Inst::Store(Width::Double, Reg::RA, Reg::SP, 8),
+                vec![0x23, 0x34, 0x11, 0x00],
+            ),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(std::slice::from_ref(&inst)), bytes, "{}", inst);
+        }
+        let cases = [
+            Inst::Load(Width::Byte, A0, s2, 2048),
+            Inst::Store(Width::Word, A0, s2, -2049),
+            Inst::Addi(A0, A0, 4096),
+            Inst::Lui(A0, 1 << 20),
+            Inst::Slli(A0, A0, 64),
+        ];
+        for inst in &cases {
+            assert!(assemble(std::slice::from_ref(inst)).is_err(), "{}", inst);
+        }
+    }
+
+    #[test]
+    fn near_branches() {
+        let insts = [
+            label("start"),
+            branch(Cond::Eq, "start"),
+            Inst::Branch(Cond::Ne, Reg::S2, Reg::ZERO, String::from("end")),
+            branch(Cond::Lt, "start"),
+            Inst::Branch(Cond::Geu, Reg::T0, Reg::T1, String::from("end")),
+            Inst::Jal(Reg::RA, String::from("start")),
+            Inst::Jal(Reg::ZERO, String::from("end")),
+            label("end"),
+        ];
+        let program = assemble(&insts).unwrap();
+        assert_eq!(program.code, vec![
+            0x63, 0x00, 0xB5, 0x00,
+            0x63, 0x1A, 0x09, 0x00,
+            0xE3, 0x4C, 0xB5, 0xFE,
+            0x63, 0xF6, 0x62, 0x00,
+            0xEF, 0xF0, 0x1F, 0xFF,
+            0x6F, 0x00, 0x40, 0x00,
+        ]);
+        assert_eq!(program.labels["end"], 24);
+    }
+
+    #[test]
+    fn branch_reach() {
+        let forward = |gap: usize| code(&[
+            branch(Cond::Geu, "far"),
+            Inst::Data(vec![0; gap]),
+            label("far"),
+        ]);
+        assert_eq!(forward(4088)[.. 4], [0xE3, 0x7E, 0xB5, 0x7E]);
+        // Too far, so it becomes `bltu a0, a1, 8` over `j far`.
+        let far = forward(4092);
+        assert_eq!(far.len(), 8 + 4092);
+        assert_eq!(far[.. 8], [0x63, 0x64, 0xB5, 0x00, 0x6F, 0x10, 0x00, 0x00]);
+        let backward = |gap: usize| code(&[
+            label("back"),
+            Inst::Data(vec![0; gap]),
+            branch(Cond::Eq, "back"),
+        ]);
+        assert_eq!(backward(4096)[4096 ..], [0x63, 0x00, 0xB5, 0x80]);
+        let far = backward(4100);
+        assert_eq!(far[4100 ..], [0x63, 0x14, 0xB5, 0x00, 0x6F, 0xE0, 0x9F, 0xFF]);
+    }
+
+    #[test]
+    fn relaxation_cascades() {
+        // Relaxing the second branch pushes the target of the first one out
+        // of reach.
+        let insts = [
+            branch(Cond::Eq, "x"),
+            Inst::Data(vec![0; 4084]),
+            branch(Cond::Eq, "y"),
+            label("x"),
+            Inst::Data(vec![0; 4092]),
+            label("y"),
+        ];
+        let program = assemble(&insts).unwrap();
+        let relaxed = [0x63, 0x14, 0xB5, 0x00, 0x6F, 0x10, 0x00, 0x00];
+        assert_eq!(program.code[.. 8], relaxed);
+        assert_eq!(program.code[4092 .. 4100], relaxed);
+        assert_eq!(program.labels["x"], 4100);
+        assert_eq!(program.labels["y"], 8192);
+    }
+
+    #[test]
+    fn relaxed_listing() {
+        // The assembly lists the far branches the way they are encoded.
+        let insts = [
+            branch(Cond::Eq, "x"),
+            Inst::Data(vec![0; 4084]),
+            branch(Cond::Eq, "y"),
+            label("x"),
+            Inst::Data(vec![0; 4092]),
+            label("y"),
+            branch(Cond::Ne, "x"),
+        ];
+        let relaxed = relax(&insts).unwrap();
+        assert_eq!(relaxed, vec![
+            branch(Cond::Ne, "_far0"),
+            Inst::Jal(Reg::ZERO, String::from("x")),
+            label("_far0"),
+            Inst::Data(vec![0; 4084]),
+            branch(Cond::Ne, "_far4"),
+            Inst::Jal(Reg::ZERO, String::from("y")),
+            label("_far4"),
+            label("x"),
+            Inst::Data(vec![0; 4092]),
+            label("y"),
+            branch(Cond::Ne, "x"),
+        ]);
+        assert_eq!(relaxed[1].to_string().trim(), "j x");
+        assert_eq!(code(&relaxed), code(&insts));
+    }
+
+    #[test]
+    fn jumps_and_label_errors() {
+        let jump = |gap: usize| assemble(&[
+            Inst::Jal(Reg::RA, String::from("far")),
+            Inst::Data(vec![0; gap]),
+            label("far"),
+        ]);
+        assert_eq!(jump(1048568).unwrap().code[.. 4], [0xEF, 0xF0, 0xDF, 0x7F]);
+        assert!(jump(1048572).is_err());
+        let undefined = [branch(Cond::Eq, "nowhere")];
+        assert!(assemble(&undefined).is_err());
+        let twice = [label("a"), label("a")];
+        assert!(assemble(&twice).is_err());
+    }
+
+}
@@ -0,0 +1,223 @@
+use std::{
+    fmt,
+};
+
+
+/// An integer register, from `x0` to `x31`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reg(pub u8);
+
+impl Reg {
+
+    pub const ZERO: Reg = Reg(0);
+    pub const RA: Reg = Reg(1);
+    pub const SP: Reg = Reg(2);
+    pub const T0: Reg = Reg(5);
+    pub const T1: Reg = Reg(6);
+    pub const T2: Reg = Reg(7);
+    pub const S1: Reg = Reg(9);
+    pub const A0: Reg = Reg(10);
+    pub const A1: Reg = Reg(11);
+    pub const A2: Reg = Reg(12);
+    pub const A3: Reg = Reg(13);
+    pub const A4: Reg = Reg(14);
+    pub const A5: Reg = Reg(15);
+    pub const A7: Reg = Reg(17);
+    pub const S2: Reg = Reg(18);
+    pub const S3: Reg = Reg(19);
+    pub const S4: Reg = Reg(20);
+    pub const S5: Reg = Reg(21);
+    pub const T3: Reg = Reg(28);
+    pub const T4: Reg = Reg(29);
+    pub const T5: Reg = Reg(30);
+    pub const T6: Reg = Reg(31);
+
+    /// The 5-bit register number used by the encoding.
+    pub fn code(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// The name given by the calling convention.
+    pub fn name(self) -> &'static str {
+        const NAMES: [&str; 32] = [
+            "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+            "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+            "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+            "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+        ];
+        NAMES[self.0 as usize]
+    }
+
+}
+
+/// Size of a memory access. Loads zero extend into the register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+impl Width {
+
+    /// The `funct3` field of stores. Unsigned loads add 4 to it, except for
+    /// `ld`, which has no unsigned form.
+    pub fn log2(self) -> u32 {
+        match self {
+            Width::Byte => 0,
+            Width::Half => 1,
+            Width::Word => 2,
+            Width::Double => 3,
+        }
+    }
+
+    pub fn load(self) -> &'static str {
+        match self {
+            Width::Byte => "lbu",
+            Width::Half => "lhu",
+            Width::Word => "lwu",
+            Width::Double => "ld",
+        }
+    }
+
+    pub fn store(self) -> &'static str {
+        match self {
+            Width::Byte => "sb",
+            Width::Half => "sh",
+            Width::Word => "sw",
+            Width::Double => "sd",
+        }
+    }
+
+}
+
+/// Whether `imm` fits the signed 12-bit immediate of most instructions.
+pub fn fits_i12(imm: i64) -> bool {
+    (-2048 .. 2048).contains(&imm)
+}
+
+/// Conditions of the branches, comparing two registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Eq = 0,
+    Ne = 1,
+    Lt = 4,
+    Ge = 5,
+    Ltu = 6,
+    Geu = 7,
+}
+
+impl Cond {
+
+    /// The `funct3` field of the encoding.
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn inverse(self) -> Cond {
+        match self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Lt => Cond::Ge,
+            Cond::Ge => Cond::Lt,
+            Cond::Ltu => Cond::Geu,
+            Cond::Geu => Cond::Ltu,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cond::Eq => "beq",
+            Cond::Ne => "bne",
+            Cond::Lt => "blt",
+            Cond::Ge => "bge",
+            Cond::Ltu => "bltu",
+            Cond::Geu => "bgeu",
+        }
+    }
+
+}
+
+/// The subset of RV64IM instructions the code generator emits. Operands
+/// are in assembly order: destination first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inst {
+    Label(String),
+    /// Loads a 20-bit immediate into the upper bits, sign extending it.
+    Lui(Reg, u32),
+    Addi(Reg, Reg, i32),
+    /// Adds in 32 bits, sign extending the result.
+    Addiw(Reg, Reg, i32),
+    Slli(Reg, Reg, u8),
+    Srli(Reg, Reg, u8),
+    Add(Reg, Reg, Reg),
+    Sub(Reg, Reg, Reg),
+    Mul(Reg, Reg, Reg),
+    /// Loads from the base register plus a 12-bit displacement.
+    Load(Width, Reg, Reg, i32),
+    /// Stores the first register at the base register plus a 12-bit
+    /// displacement.
+    Store(Width, Reg, Reg, i32),
+    /// Branches if the registers compare as the condition says. The
+    /// assembler turns it into an inverted branch over a jump when the label
+    /// is too far.
+    Branch(Cond, Reg, Reg, String),
+    /// Jumps to a label, leaving the return address in the register.
+    Jal(Reg, String),
+    /// Returns through `ra`.
+    Ret,
+    Ecall,
+    /// Raw bytes placed among the instructions, a multiple of 4 bytes long.
+    Data(Vec<u8>),
+}
+
+impl fmt::Display for Inst {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inst::Label(ref label) => write!(fmt, "  {}:", label),
+            Inst::Lui(rd, imm) => write!(fmt, "  lui {}, {}", rd.name(), imm),
+            Inst::Addi(rd, rs, imm) =>
+                write!(fmt, "  addi {}, {}, {}", rd.name(), rs.name(), imm),
+            Inst::Addiw(rd, rs, imm) =>
+                write!(fmt, "  addiw {}, {}, {}", rd.name(), rs.name(), imm),
+            Inst::Slli(rd, rs, shamt) =>
+                write!(fmt, "  slli {}, {}, {}", rd.name(), rs.name(), shamt),
+            Inst::Srli(rd, rs, shamt) =>
+                write!(fmt, "  srli {}, {}, {}", rd.name(), rs.name(), shamt),
+            Inst::Add(rd, rs1, rs2) =>
+                write!(fmt, "  add {}, {}, {}", rd.name(), rs1.name(), rs2.name()),
+            Inst::Sub(rd, rs1, rs2) =>
+                write!(fmt, "  sub {}, {}, {}", rd.name(), rs1.name(), rs2.name()),
+            Inst::Mul(rd, rs1, rs2) =>
+                write!(fmt, "  mul {}, {}, {}", rd.name(), rs1.name(), rs2.name()),
+            Inst::Load(w, rd, rs, disp) =>
+                write!(fmt, "  {} {}, {}({})", w.load(), rd.name(), disp, rs.name()),
+            Inst::Store(w, src, rs, disp) =>
+                write!(fmt, "  {} {}, {}({})", w.store(), src.name(), disp, rs.name()),
+            Inst::Branch(cond, rs1, rs2, ref label) => write!(
+                fmt,
+                "  {} {}, {}, {}",
+                cond.name(),
+                rs1.name(),
+                rs2.name(),
+                label
+            ),
+            Inst::Jal(Reg::ZERO, ref label) => write!(fmt, "  j {}", label),
+            Inst::Jal(Reg::RA, ref label) => write!(fmt, "  jal {}", label),
+            Inst::Jal(rd, ref label) => write!(fmt, "  jal {}, {}", rd.name(), label),
+            Inst::Ret => write!(fmt, "  ret"),
+            Inst::Ecall => write!(fmt, "  ecall"),
+            Inst::Data(ref bytes) => write!(
+                fmt,
+                "  .byte {}",
+                bytes.iter()
+                    .map(|b| b.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+}
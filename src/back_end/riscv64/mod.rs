@@ -0,0 +1,174 @@
+pub mod inst;
+pub mod encode;
+pub mod codegen;
+
+pub use self::inst::{
+    Inst,
+    Reg,
+    Width,
+    Cond,
+};
+pub use self::encode::{
+    assemble,
+    relax,
+    Program,
+};
+pub use self::codegen::{
+    Codegen,
+};
+
+use std::io::{
+    Write,
+    Error,
+    ErrorKind,
+};
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Tape,
+};
+use super::{
+    Arch,
+    Format,
+    create_output,
+};
+use super::elf::{
+    self,
+    Executable,
+};
+use std::os::unix::fs::{
+    PermissionsExt,
+};
+use std::{
+    fs,
+};
+
+
+/// The 64-bit RISC-V architecture, running Linux. Code only uses the base
+/// integer instructions and multiplication, so it runs on any RV64GC core.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Riscv64;
+
+impl Riscv64 {
+
+    const SYS_READ: i64 = 63;
+    const SYS_WRITE: i64 = 64;
+    const SYS_EXIT: i64 = 93;
+    const SYS_MUNMAP: i64 = 215;
+    const SYS_MMAP: i64 = 222;
+    const SYS_MPROTECT: i64 = 226;
+    const SYS_PRLIMIT64: i64 = 261;
+
+    /// The memory access width of cells of the given size.
+    pub fn cell_width(size: CellSize) -> Width {
+        match size {
+            CellSize::Bits8 => Width::Byte,
+            CellSize::Bits16 => Width::Half,
+            CellSize::Bits32 => Width::Word,
+            CellSize::Bits64 => Width::Double,
+        }
+    }
+
+    /// Fails if the configuration cannot be generated. Tapes must fit the
+    /// 256 GiB of user space given by Sv39, the smallest paging mode.
+    pub fn check(&self, config: &Config) -> Result<(), Error> {
+        if let Tape::Heap(cells) | Tape::Bidirectional(cells) = config.tape {
+            let fits = cells.checked_mul(config.cell_size.bytes() as u64)
+                .is_some_and(|bytes| bytes <= 1 << 37);
+            if !fits {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The tape is too big for the architecture."
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The size in bytes of a heap tape, rounded up to whole pages. The size
+    /// must have been checked.
+    pub fn heap_bytes(cells: u64, size: CellSize) -> u64 {
+        let bytes = cells * size.bytes() as u64;
+        (bytes + 4095) & !4095
+    }
+
+    /// Generates the instructions for a standalone program, starting at the
+    /// `_start` entry point.
+    pub fn gen_insts(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Vec<Inst>, Error> {
+        self.check(config)?;
+        Ok(Codegen::new(config).generate(ir))
+    }
+
+    pub fn gen_asm<T: Write>(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        out: &mut T
+    ) -> Result<usize, Error> {
+        let mut text = String::from(".text\n.globl _start\n_start:\n");
+        for inst in relax(&self.gen_insts(ir, config)?)? {
+            text += &inst.to_string();
+            text.push('\n');
+        }
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    /// Generates a complete, statically linked executable for the program.
+    pub fn gen_elf(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Executable, Error> {
+        let program = assemble(&self.gen_insts(ir, config)?)?;
+        Ok(Executable {
+            class: elf::Class::Elf64,
+            machine: elf::EM_RISCV,
+            flags: 0,
+            text: program.code,
+            entry: 0,
+        })
+    }
+
+}
+
+impl Arch for Riscv64 {
+
+    fn generate(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        format: Format,
+        out: String
+    ) -> Result<usize, Error> {
+        match format {
+            Format::Asm => self.gen_asm(ir, config, &mut create_output(&out)?),
+            Format::Elf => {
+                let exe = self.gen_elf(ir, config)?;
+                let amount = exe.write(&mut create_output(&out)?)?;
+                fs::set_permissions(&out, fs::Permissions::from_mode(0o755))?;
+                Ok(amount)
+            },
+            Format::Object(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The `obj` format is not supported by the riscv64 back end."
+            )),
+            Format::Bin => {
+                let program = assemble(&self.gen_insts(ir, config)?)?;
+                create_output(&out)?.write_all(&program.code)?;
+                Ok(program.code.len())
+            },
+        }
+    }
+
+}
@@ -0,0 +1,222 @@
+use std::vec::{
+    IntoIter,
+};
+use std::{
+    iter,
+};
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Overflow,
+};
+use utils::{
+    HeadedList,
+};
+
+
+/// The code a back end emits for each operation. `walk` calls it in the
+/// order of the program, after making sure the cells accessed are part of
+/// the tape.
+pub trait Emitter {
+
+    /// What closing a loop needs, such as its labels.
+    type Loop;
+
+    fn config(&self) -> &Config;
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing or checking the tape as needed.
+    fn reach(&mut self, offset: i64, loc: &Location);
+
+    /// Moves the pointer to a cell known to be in the tape.
+    fn shift(&mut self, n: i64, loc: &Location);
+
+    /// Moves the pointer, making sure the new current cell is in the tape.
+    fn move_by(&mut self, n: i64, loc: &Location);
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location);
+
+    fn set(&mut self, offset: i64, value: i64, loc: &Location);
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, loc: &Location);
+
+    /// Moves by `n` cells until a zero cell is found.
+    fn scan(&mut self, n: i64, loc: &Location);
+
+    fn put_char(&mut self, offset: i64, loc: &Location);
+
+    fn get_char(&mut self, offset: i64, loc: &Location);
+
+    /// Opens a loop running while the current cell is not zero.
+    fn open_loop(&mut self, loc: &Location) -> Self::Loop;
+
+    fn close_loop(&mut self, lp: Self::Loop);
+
+    /// Called before the code of each operation.
+    fn at(&mut self, _loc: &Location) {
+    }
+
+}
+
+struct Frame<L> {
+    ops: IntoIter<Node<Ir>>,
+    /// The loop these operations are the body of, if any.
+    open: Option<L>,
+}
+
+struct Walker<'a, E: 'a> {
+    emitter: &'a mut E,
+    /// The range of offsets known to be in the tape.
+    reached: (i64, i64),
+    /// Whether the range of the next straight-line operations must be
+    /// checked.
+    fresh: bool,
+}
+
+impl<'a, E: Emitter> Walker<'a, E> {
+
+    /// Makes sure the cell at `offset` is in the tape, unless that is
+    /// already known.
+    fn ensure(&mut self, offset: i64, loc: &Location) {
+        let (min, max) = self.reached;
+        if offset < min || offset > max {
+            self.emitter.reach(offset, loc);
+            self.reached = (min.min(offset), max.max(offset));
+        }
+    }
+
+    /// Checks the range of the operations from the current one up to the
+    /// next loop, scan or I/O, so that the tape is grown once for all of
+    /// them.
+    fn prepare(&mut self, val: &Ir, rest: &[Node<Ir>], loc: &Location) {
+        let (min, max) = straight_range(val, rest);
+        self.ensure(max, loc);
+        self.ensure(min, loc);
+        self.fresh = false;
+    }
+
+    fn forget(&mut self) {
+        self.reached = (0, 0);
+        self.fresh = true;
+    }
+
+    fn run(&mut self, ir: Vec<Node<Ir>>) {
+        let mut loops = HeadedList::new(Frame {
+            ops: ir.into_iter(),
+            open: None,
+        }, None);
+        'outer: loop {
+            let Node {val, loc} = loop {
+                match loops.val_mut().ops.next() {
+                    Some(v) => break v,
+                    _ => match loops.take() {
+                        Some(Frame {open: Some(lp), ..}) => self.emitter.close_loop(lp),
+                        _ => break 'outer,
+                    },
+                }
+                self.forget();
+            };
+            self.emitter.at(&loc);
+            // Checked and trapping programs reach each cell on its own, so
+            // that errors name the instruction at fault, in order.
+            let config = self.emitter.config();
+            let each = config.checked || config.overflow == Overflow::Trap;
+            if self.fresh && !each {
+                self.prepare(&val, loops.val().ops.as_slice(), &loc);
+            }
+            match val {
+                Ir::Add {offset, amount} => {
+                    self.ensure(offset, &loc);
+                    self.emitter.add(offset, amount, &loc);
+                },
+                Ir::Set {offset, value} => {
+                    self.ensure(offset, &loc);
+                    self.emitter.set(offset, value, &loc);
+                },
+                Ir::Mul {src, dst, factor} => {
+                    self.ensure(src, &loc);
+                    self.ensure(dst, &loc);
+                    self.emitter.mul(src, dst, factor, &loc);
+                },
+                Ir::Move(n) => {
+                    let (min, max) = self.reached;
+                    if min <= n && n <= max {
+                        self.emitter.shift(n, &loc);
+                        self.reached = (min - n, max - n);
+                    } else {
+                        self.emitter.move_by(n, &loc);
+                        self.reached = (0, 0);
+                    }
+                },
+                Ir::Scan(n) => {
+                    self.emitter.scan(n, &loc);
+                    self.forget();
+                },
+                Ir::PutChar {offset} => {
+                    self.ensure(offset, &loc);
+                    self.emitter.put_char(offset, &loc);
+                    self.fresh = true;
+                },
+                Ir::GetChar {offset} => {
+                    self.ensure(offset, &loc);
+                    self.emitter.get_char(offset, &loc);
+                    self.fresh = true;
+                },
+                Ir::Loop(lp) => {
+                    self.forget();
+                    let open = self.emitter.open_loop(&loc);
+                    loops.receive(Frame {
+                        ops: lp.into_iter(),
+                        open: Some(open),
+                    });
+                },
+            }
+        }
+    }
+
+}
+
+/// Emits the program, in order. The tape is checked or grown once for each
+/// run of straight-line operations, from the furthest cell they access, and
+/// pointer movements within the cells already reached are not checked.
+pub fn walk<E: Emitter>(emitter: &mut E, ir: Vec<Node<Ir>>) {
+    Walker {
+        emitter,
+        reached: (0, 0),
+        fresh: true,
+    }.run(ir);
+}
+
+/// The range of offsets, from the current pointer, accessed by `first` and
+/// the operations after it, up to the next loop, scan or I/O. The tape only
+/// needs to be checked or grown once for all of them.
+pub fn straight_range(first: &Ir, rest: &[Node<Ir>]) -> (i64, i64) {
+    let mut pos = 0;
+    let mut min = 0;
+    let mut max = 0;
+    for op in iter::once(first).chain(rest.iter().map(|node| &node.val)) {
+        let (a, b) = match *op {
+            Ir::Add {offset, ..} | Ir::Set {offset, ..} => (offset, offset),
+            Ir::Mul {src, dst, ..} => (src.min(dst), src.max(dst)),
+            Ir::Move(n) => {
+                pos += n;
+                (0, 0)
+            },
+            Ir::PutChar {offset} | Ir::GetChar {offset} => {
+                min = min.min(pos + offset);
+                max = max.max(pos + offset);
+                break;
+            },
+            Ir::Scan(_) | Ir::Loop(_) => break,
+        };
+        min = min.min(pos + a);
+        max = max.max(pos + b);
+    }
+    (min, max)
+}
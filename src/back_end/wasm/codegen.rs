@@ -0,0 +1,947 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Eof,
+    Overflow,
+    Tape,
+};
+use back_end::{
+    Emitter,
+    walk,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+};
+use super::{
+    Wasm,
+    PAGE_SIZE,
+    MAX_PAGES,
+};
+use super::inst::{
+    Inst,
+    Ty,
+    Width,
+    Cmp,
+    Global,
+};
+use super::module::{
+    Module,
+    Func,
+    Import,
+};
+
+
+/// Locals of `_start`: the pointer to the current cell, an address, a byte
+/// and a cell.
+const P: u32 = 0;
+const A: u32 = 1;
+const B: u32 = 2;
+const V: u32 = 3;
+
+const TAPE_GROW: &str = "tape_grow";
+const TAPE_EXTEND: &str = "tape_extend";
+const FAIL: &str = "fail";
+const PUT: &str = "put";
+const GET: &str = "get";
+const FLUSH: &str = "flush";
+const WRITE_FAILED: &str = "write_failed";
+const READ_FAILED: &str = "read_failed";
+const FD_WRITE: &str = "fd_write";
+const FD_READ: &str = "fd_read";
+const PROC_EXIT: &str = "proc_exit";
+
+/// The module WASI functions are imported from.
+const WASI: &str = "wasi_snapshot_preview1";
+
+/// Address of the I/O vector given to `fd_read` and `fd_write`, and of the
+/// number of bytes they report.
+const IOV: i64 = 0;
+const NIO: i64 = 8;
+/// Size of each I/O buffer.
+const IO_BUFFER: i64 = 4096;
+/// Addresses of the output and input buffers.
+const IO_OUT: i64 = 16;
+const IO_IN: i64 = IO_OUT + IO_BUFFER;
+const IO_END: i64 = IO_IN + IO_BUFFER;
+
+/// Generates a module exporting `_start` and its memory, for WASI hosts.
+/// The tape is at the end of the memory, which grows with it, and I/O is
+/// done through `fd_read` and `fd_write`. The static data, the I/O buffers
+/// and the messages, is before the tape.
+#[derive(Clone, Debug)]
+pub struct Codegen {
+    config: Config,
+    insts: Vec<Inst>,
+    /// The messages of the errors, placed after the I/O buffers.
+    data: Vec<u8>,
+    /// Whether `fail` is called.
+    fails: bool,
+    /// Whether the program writes, through `put` if buffered.
+    puts: bool,
+    /// Whether the program reads, through `get` if buffered.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator. The configuration must be supported, see
+    /// `Wasm::check`.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            insts: Vec::new(),
+            data: Vec::new(),
+            fails: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    fn emit_all(&mut self, insts: &[Inst]) {
+        self.insts.extend_from_slice(insts);
+    }
+
+    fn call(&mut self, func: &str) {
+        self.emit(Inst::Call(String::from(func)));
+    }
+
+    /// Width of the cells in memory.
+    fn cw(&self) -> Width {
+        Wasm::cell_width(self.config.cell_size)
+    }
+
+    /// Type of the values of cells.
+    fn ty(&self) -> Ty {
+        if self.config.cell_size == CellSize::Bits64 {Ty::I64} else {Ty::I32}
+    }
+
+    /// Size of a cell in bytes.
+    fn stride(&self) -> i64 {
+        self.config.cell_size.bytes() as i64
+    }
+
+    /// Whether the tape only grows right, as far as the memory can.
+    fn on_stack(&self) -> bool {
+        self.config.tape == Tape::Stack
+    }
+
+    /// Whether the tape grows at both ends.
+    fn growable(&self) -> bool {
+        self.config.tape.bidirectional()
+    }
+
+    /// Whether the pointer is checked against both ends of the tape.
+    fn checked(&self) -> bool {
+        !self.on_stack() && (self.growable() || self.config.checked)
+    }
+
+    /// Whether I/O goes through the buffers.
+    fn buffered(&self) -> bool {
+        !self.config.unbuffered
+    }
+
+    /// The address of the first message.
+    fn data_addr(&self) -> i64 {
+        if self.buffered() {IO_END} else {IO_OUT}
+    }
+
+    /// The local holding the address of a cell, and the offset of the cell
+    /// from that address. Cells left of the pointer have their address
+    /// computed into `A`, as offsets cannot be negative.
+    fn base(&mut self, offset: i64) -> (u32, u32) {
+        let disp = offset * self.stride();
+        if (0 .. 1 << 31).contains(&disp) {
+            (P, disp as u32)
+        } else {
+            self.emit_all(&[
+                Inst::LocalGet(P),
+                Inst::Const(Ty::I32, disp),
+                Inst::Add(Ty::I32),
+                Inst::LocalSet(A),
+            ]);
+            (A, 0)
+        }
+    }
+
+    /// Pushes a cell, zero extended.
+    fn load(&mut self, offset: i64) {
+        let (local, disp) = self.base(offset);
+        self.emit(Inst::LocalGet(local));
+        self.emit(Inst::Load(self.cw(), disp));
+    }
+
+    /// Stores in a cell the value pushed by the given instructions.
+    fn store(&mut self, offset: i64, value: &[Inst]) {
+        let (local, disp) = self.base(offset);
+        self.emit(Inst::LocalGet(local));
+        self.emit_all(value);
+        self.emit(Inst::Store(self.cw(), disp));
+    }
+
+    /// Puts the address of a cell into `A`.
+    fn cell_addr(&mut self, offset: i64) {
+        self.emit_all(&[
+            Inst::LocalGet(P),
+            Inst::Const(Ty::I32, offset * self.stride()),
+            Inst::Add(Ty::I32),
+            Inst::LocalSet(A),
+        ]);
+    }
+
+    /// Points the I/O vector to `len` bytes at the address the given
+    /// instructions push.
+    fn iov(&mut self, addr: &[Inst], len: &[Inst]) {
+        self.emit(Inst::Const(Ty::I32, IOV));
+        self.emit_all(addr);
+        self.emit(Inst::Store(Width::Word, 0));
+        self.emit(Inst::Const(Ty::I32, IOV));
+        self.emit_all(len);
+        self.emit(Inst::Store(Width::Word, 4));
+    }
+
+    /// Calls `fd_read` or `fd_write` on the I/O vector, pushing the error
+    /// code.
+    fn io(&mut self, func: &str, fd: i64) {
+        self.emit_all(&[
+            Inst::Const(Ty::I32, fd),
+            Inst::Const(Ty::I32, IOV),
+            Inst::Const(Ty::I32, 1),
+            Inst::Const(Ty::I32, NIO),
+        ]);
+        self.call(func);
+    }
+
+    /// Pushes the number of bytes the last I/O read or wrote.
+    fn nio(&mut self) {
+        self.emit(Inst::Const(Ty::I32, NIO));
+        self.emit(Inst::Load(Width::Word, 0));
+    }
+
+    /// Exits with status 1 if the memory could not grow, that is, if the
+    /// value pushed by `memory.grow` is -1.
+    fn check_grow(&mut self) {
+        self.emit_all(&[
+            Inst::Const(Ty::I32, -1),
+            Inst::Cmp(Ty::I32, Cmp::Eq),
+            Inst::If,
+            Inst::Const(Ty::I32, IO_EXIT),
+        ]);
+        self.call(PROC_EXIT);
+        self.emit(Inst::End);
+    }
+
+    /// Emits the `tape_extend` function, which grows the memory, and so a
+    /// tape which only grows right, until the address given is in it.
+    fn tape_extend(&mut self) -> Func {
+        self.emit_all(&[
+            Inst::LocalGet(0),
+            Inst::GlobalGet(Global::End),
+            Inst::Sub(Ty::I32),
+            Inst::Const(Ty::I32, 16),
+            Inst::ShrU(Ty::I32),
+            Inst::Const(Ty::I32, 1),
+            Inst::Add(Ty::I32),
+            Inst::MemoryGrow,
+        ]);
+        self.check_grow();
+        self.emit_all(&[
+            Inst::MemorySize,
+            Inst::Const(Ty::I32, 16),
+            Inst::Shl(Ty::I32),
+            Inst::GlobalSet(Global::End),
+        ]);
+        self.func(TAPE_EXTEND, vec![Ty::I32], vec![], vec![])
+    }
+
+    /// Emits the `tape_grow` function, called with the address of a cell out
+    /// of a bidirectional tape. It grows the memory by twice the size of the
+    /// tape, moves the tape to the middle of the space and clears the space
+    /// left behind, until the cell is in the tape. It gives how far the
+    /// cells moved.
+    fn tape_grow(&mut self) -> Func {
+        let (addr, size, moved) = (0, 1, 2);
+        self.emit_all(&[
+            Inst::Loop,
+            Inst::GlobalGet(Global::End),
+            Inst::GlobalGet(Global::Start),
+            Inst::Sub(Ty::I32),
+            Inst::LocalSet(size),
+            Inst::LocalGet(size),
+            Inst::Const(Ty::I32, 15),
+            Inst::ShrU(Ty::I32),
+            Inst::MemoryGrow,
+        ]);
+        self.check_grow();
+        self.emit_all(&[
+            Inst::GlobalGet(Global::Start),
+            Inst::LocalGet(size),
+            Inst::Add(Ty::I32),
+            Inst::GlobalGet(Global::Start),
+            Inst::LocalGet(size),
+            Inst::MemoryCopy,
+            Inst::GlobalGet(Global::Start),
+            Inst::Const(Ty::I32, 0),
+            Inst::LocalGet(size),
+            Inst::MemoryFill,
+            Inst::LocalGet(moved),
+            Inst::LocalGet(size),
+            Inst::Add(Ty::I32),
+            Inst::LocalSet(moved),
+            Inst::LocalGet(addr),
+            Inst::LocalGet(size),
+            Inst::Add(Ty::I32),
+            Inst::LocalSet(addr),
+            Inst::GlobalGet(Global::End),
+            Inst::LocalGet(size),
+            Inst::LocalGet(size),
+            Inst::Add(Ty::I32),
+            Inst::Add(Ty::I32),
+            Inst::GlobalSet(Global::End),
+            Inst::LocalGet(addr),
+            Inst::GlobalGet(Global::Start),
+            Inst::Cmp(Ty::I32, Cmp::LtS),
+            Inst::LocalGet(addr),
+            Inst::GlobalGet(Global::End),
+            Inst::Cmp(Ty::I32, Cmp::GeU),
+            Inst::Or(Ty::I32),
+            Inst::BrIf(0),
+            Inst::End,
+            Inst::LocalGet(moved),
+        ]);
+        self.func(TAPE_GROW, vec![Ty::I32], vec![Ty::I32], vec![Ty::I32, Ty::I32])
+    }
+
+    /// Emits the `fail` function, called with an exit status and the
+    /// address and length of a message. It writes the message to stderr and
+    /// exits.
+    fn fail(&mut self) -> Func {
+        // What was written before is shown first.
+        if self.buffered() && self.puts {
+            self.call(FLUSH);
+        }
+        self.iov(&[Inst::LocalGet(1)], &[Inst::LocalGet(2)]);
+        self.io(FD_WRITE, 2);
+        self.emit(Inst::Drop);
+        self.emit(Inst::LocalGet(0));
+        self.call(PROC_EXIT);
+        self.func(FAIL, vec![Ty::I32; 3], vec![], vec![])
+    }
+
+    /// Stops the program with the given exit status and message, through
+    /// `fail`.
+    fn report(&mut self, status: i64, message: String) {
+        let addr = self.data_addr() + self.data.len() as i64;
+        self.data.extend_from_slice(message.as_bytes());
+        self.emit_all(&[
+            Inst::Const(Ty::I32, status),
+            Inst::Const(Ty::I32, addr),
+            Inst::Const(Ty::I32, message.len() as i64),
+        ]);
+        self.call(FAIL);
+        self.fails = true;
+    }
+
+    /// Reports that the pointer left the tape at `loc` and exits.
+    fn report_bounds(&mut self, loc: &Location) {
+        let message = format!("Tape pointer out of bounds {}.\n", loc);
+        self.report(BOUNDS_EXIT, message);
+    }
+
+    /// Grows a bidirectional tape until it has the cell at the address in
+    /// the given local, moving the pointer along.
+    fn grow(&mut self, addr: u32) {
+        self.emit(Inst::LocalGet(addr));
+        self.call(TAPE_GROW);
+        self.emit_all(&[
+            Inst::LocalGet(P),
+            Inst::Add(Ty::I32),
+            Inst::LocalSet(P),
+        ]);
+    }
+
+    /// Makes sure the cell at the address in the given local is not past
+    /// the end of the tape, growing the tape or reporting the error.
+    fn right(&mut self, addr: u32, loc: &Location) {
+        self.emit_all(&[
+            Inst::LocalGet(addr),
+            Inst::GlobalGet(Global::End),
+            Inst::Cmp(Ty::I32, Cmp::GeU),
+            Inst::If,
+        ]);
+        if self.on_stack() {
+            self.emit(Inst::LocalGet(addr));
+            self.call(TAPE_EXTEND);
+        } else if self.growable() {
+            self.grow(addr);
+        } else {
+            self.report_bounds(loc);
+        }
+        self.emit(Inst::End);
+    }
+
+    /// Makes sure the cell at the address in the given local is not before
+    /// the start of the tape, growing the tape or reporting the error. The
+    /// memory is smaller than 2 GiB, so addresses wrapped below zero are
+    /// negative.
+    fn left(&mut self, addr: u32, loc: &Location) {
+        self.emit_all(&[
+            Inst::LocalGet(addr),
+            Inst::GlobalGet(Global::Start),
+            Inst::Cmp(Ty::I32, Cmp::LtS),
+            Inst::If,
+        ]);
+        if self.growable() {
+            self.grow(addr);
+        } else {
+            self.report_bounds(loc);
+        }
+        self.emit(Inst::End);
+    }
+
+    /// Emits the `flush` function, which writes the whole output buffer.
+    fn flush(&mut self) -> Func {
+        let pos = 0;
+        self.emit_all(&[
+            Inst::Block,
+            Inst::Loop,
+            Inst::LocalGet(pos),
+            Inst::GlobalGet(Global::OutLen),
+            Inst::Cmp(Ty::I32, Cmp::GeU),
+            Inst::BrIf(1),
+        ]);
+        self.iov(
+            &[
+                Inst::Const(Ty::I32, IO_OUT),
+                Inst::LocalGet(pos),
+                Inst::Add(Ty::I32),
+            ],
+            &[
+                Inst::GlobalGet(Global::OutLen),
+                Inst::LocalGet(pos),
+                Inst::Sub(Ty::I32),
+            ]
+        );
+        self.io(FD_WRITE, 1);
+        self.nio();
+        self.emit_all(&[
+            Inst::Eqz(Ty::I32),
+            Inst::Or(Ty::I32),
+            Inst::If,
+        ]);
+        self.call(WRITE_FAILED);
+        self.emit(Inst::End);
+        self.emit(Inst::LocalGet(pos));
+        self.nio();
+        self.emit_all(&[
+            Inst::Add(Ty::I32),
+            Inst::LocalSet(pos),
+            Inst::Br(0),
+            Inst::End,
+            Inst::End,
+            Inst::Const(Ty::I32, 0),
+            Inst::GlobalSet(Global::OutLen),
+        ]);
+        self.func(FLUSH, vec![], vec![], vec![Ty::I32])
+    }
+
+    /// Emits the `put` function, which appends a byte to the output buffer,
+    /// flushing it on newlines and when full.
+    fn put(&mut self) -> Func {
+        self.emit_all(&[
+            Inst::Const(Ty::I32, IO_OUT),
+            Inst::GlobalGet(Global::OutLen),
+            Inst::Add(Ty::I32),
+            Inst::LocalGet(0),
+            Inst::Store(Width::Byte, 0),
+            Inst::GlobalGet(Global::OutLen),
+            Inst::Const(Ty::I32, 1),
+            Inst::Add(Ty::I32),
+            Inst::GlobalSet(Global::OutLen),
+            Inst::LocalGet(0),
+            Inst::Const(Ty::I32, 0xFF),
+            Inst::And(Ty::I32),
+            Inst::Const(Ty::I32, b'\n' as i64),
+            Inst::Cmp(Ty::I32, Cmp::Eq),
+            Inst::GlobalGet(Global::OutLen),
+            Inst::Const(Ty::I32, IO_BUFFER),
+            Inst::Cmp(Ty::I32, Cmp::GeU),
+            Inst::Or(Ty::I32),
+            Inst::If,
+        ]);
+        self.call(FLUSH);
+        self.emit(Inst::End);
+        self.func(PUT, vec![Ty::I32], vec![], vec![])
+    }
+
+    /// Emits the `get` function, which gives the next byte of the input
+    /// buffer, or -1 at the end of the input. The output is flushed before
+    /// the buffer is filled again.
+    fn get(&mut self) -> Func {
+        self.emit_all(&[
+            Inst::GlobalGet(Global::InPos),
+            Inst::GlobalGet(Global::InLen),
+            Inst::Cmp(Ty::I32, Cmp::GeU),
+            Inst::If,
+        ]);
+        self.call(FLUSH);
+        self.iov(&[Inst::Const(Ty::I32, IO_IN)], &[Inst::Const(Ty::I32, IO_BUFFER)]);
+        self.io(FD_READ, 0);
+        self.emit(Inst::If);
+        self.call(READ_FAILED);
+        self.emit(Inst::End);
+        self.nio();
+        self.emit_all(&[
+            Inst::Eqz(Ty::I32),
+            Inst::If,
+            Inst::Const(Ty::I32, -1),
+            Inst::Return,
+            Inst::End,
+        ]);
+        self.nio();
+        self.emit_all(&[
+            Inst::GlobalSet(Global::InLen),
+            Inst::Const(Ty::I32, 0),
+            Inst::GlobalSet(Global::InPos),
+            Inst::End,
+            Inst::Const(Ty::I32, IO_IN),
+            Inst::GlobalGet(Global::InPos),
+            Inst::Add(Ty::I32),
+            Inst::Load(Width::Byte, 0),
+            Inst::GlobalGet(Global::InPos),
+            Inst::Const(Ty::I32, 1),
+            Inst::Add(Ty::I32),
+            Inst::GlobalSet(Global::InPos),
+        ]);
+        self.func(GET, vec![], vec![Ty::I32], vec![])
+    }
+
+    /// Emits a function reporting that I/O failed. The output buffer is
+    /// dropped, so that `fail` does not write it again.
+    fn io_failed(&mut self, name: &str, message: &str) -> Func {
+        if self.buffered() {
+            self.emit(Inst::Const(Ty::I32, 0));
+            self.emit(Inst::GlobalSet(Global::OutLen));
+        }
+        self.report(IO_EXIT, String::from(message));
+        self.func(name, vec![], vec![], vec![])
+    }
+
+    /// Takes the instructions emitted so far as the body of a function.
+    fn func(&mut self, name: &str, params: Vec<Ty>, results: Vec<Ty>, locals: Vec<Ty>) -> Func {
+        Func {
+            name: String::from(name),
+            params,
+            results,
+            locals,
+            body: self.insts.drain(..).collect(),
+            export: None,
+        }
+    }
+
+    fn prologue(&mut self) {
+        self.emit(Inst::GlobalGet(Global::Start));
+        if let Tape::Bidirectional(cells) = self.config.tape {
+            let bytes = Wasm::heap_bytes(cells, self.config.cell_size);
+            self.emit(Inst::Const(Ty::I32, bytes as i64 / 2));
+            self.emit(Inst::Add(Ty::I32));
+        }
+        self.emit(Inst::LocalSet(P));
+    }
+
+    /// Finishes `_start` and emits the functions it uses, building the
+    /// module.
+    fn epilogue(&mut self) -> Module {
+        self.exit();
+        let mut start = self.func("_start", vec![], vec![], vec![Ty::I32, Ty::I32, Ty::I32, self.ty()]);
+        start.export = Some(String::from("_start"));
+        let mut funcs = vec![start];
+        if self.on_stack() {
+            funcs.push(self.tape_extend());
+        }
+        if self.growable() {
+            funcs.push(self.tape_grow());
+        }
+        let buffered = self.buffered();
+        if buffered && (self.puts || self.gets) {
+            funcs.push(self.flush());
+        }
+        if buffered && self.puts {
+            funcs.push(self.put());
+        }
+        if buffered && self.gets {
+            funcs.push(self.get());
+        }
+        // `flush` is also called before reading.
+        if self.puts || (buffered && self.gets) {
+            funcs.push(self.io_failed(WRITE_FAILED, "Could not write the output.\n"));
+        }
+        if self.gets {
+            funcs.push(self.io_failed(READ_FAILED, "Could not read the input.\n"));
+        }
+        if self.fails {
+            funcs.push(self.fail());
+        }
+
+        let page = PAGE_SIZE as i64;
+        let data_end = self.data_addr() + self.data.len() as i64;
+        let start = (data_end + page - 1) / page * page;
+        let bytes = match self.config.tape {
+            Tape::Stack => page,
+            Tape::Heap(cells) | Tape::Bidirectional(cells) =>
+                Wasm::heap_bytes(cells, self.config.cell_size) as i64,
+        };
+        // A heap tape ends with its last cell, not with its page.
+        let end = match self.config.tape {
+            Tape::Heap(cells) => start + cells as i64 * self.stride(),
+            _ => start + bytes,
+        };
+        let import = |name: &str, params: Vec<Ty>, results: Vec<Ty>| Import {
+            module: String::from(WASI),
+            name: String::from(name),
+            params,
+            results,
+        };
+        Module {
+            imports: vec![
+                import(FD_WRITE, vec![Ty::I32; 4], vec![Ty::I32]),
+                import(FD_READ, vec![Ty::I32; 4], vec![Ty::I32]),
+                import(PROC_EXIT, vec![Ty::I32], vec![]),
+            ],
+            funcs,
+            globals: Global::ALL.iter().map(|&global| (global, match global {
+                Global::Start => start as i32,
+                Global::End => end as i32,
+                _ => 0,
+            })).collect(),
+            pages: (((start + bytes) / page) as u32, MAX_PAGES),
+            data: (self.data_addr() as u32, self.data.clone()),
+        }
+    }
+
+    fn exit(&mut self) {
+        if self.buffered() && self.puts {
+            self.call(FLUSH);
+        }
+        if self.config.exit_cell {
+            self.load(0);
+            if self.ty() == Ty::I64 {
+                self.emit(Inst::Wrap);
+            }
+            self.emit(Inst::Const(Ty::I32, 0xFF));
+            self.emit(Inst::And(Ty::I32));
+        } else {
+            self.emit(Inst::Const(Ty::I32, 0));
+        }
+        self.call(PROC_EXIT);
+    }
+
+    fn next(&mut self, n: i64, loc: &Location) {
+        self.shift(n, loc);
+        if self.on_stack() || self.checked() {
+            self.right(P, loc);
+        }
+    }
+
+    fn previous(&mut self, n: i64, loc: &Location) {
+        self.shift(-n, loc);
+        if (self.on_stack() && self.config.checked) || self.checked() {
+            self.left(P, loc);
+        }
+    }
+
+    /// Leaves the innermost block if the current cell is zero.
+    fn break_if_zero(&mut self) {
+        self.load(0);
+        self.emit(Inst::Eqz(self.ty()));
+        self.emit(Inst::BrIf(1));
+    }
+
+    /// The instructions pushing the byte in `B` as a cell.
+    fn byte_cell(&self) -> Vec<Inst> {
+        let mut insts = vec![Inst::LocalGet(B)];
+        if self.ty() == Ty::I64 {
+            insts.push(Inst::Extend);
+        }
+        insts
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// is found by comparing the cell against the largest value the addition
+    /// keeps in range, or against the amount of a subtraction.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let ty = self.ty();
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let limit = if amount < 0 {0} else {mask as i64};
+        if magnitude == 0 {
+            return;
+        }
+        if magnitude > mask {
+            if self.config.overflow == Overflow::Trap {
+                self.report(OVERFLOW_EXIT, format!("Cell overflow {}.\n", loc));
+            } else {
+                self.store(offset, &[Inst::Const(ty, limit)]);
+            }
+            return;
+        }
+        let (op, bound, cmp) = if amount < 0 {
+            (Inst::Sub(ty), magnitude, Cmp::LtU)
+        } else {
+            (Inst::Add(ty), mask - magnitude, Cmp::GtU)
+        };
+        let (local, disp) = self.base(offset);
+        self.emit_all(&[
+            Inst::LocalGet(local),
+            Inst::LocalGet(local),
+            Inst::Load(self.cw(), disp),
+            Inst::LocalSet(V),
+        ]);
+        let overflows = [
+            Inst::LocalGet(V),
+            Inst::Const(ty, bound as i64),
+            Inst::Cmp(ty, cmp),
+        ];
+        let result = [
+            Inst::LocalGet(V),
+            Inst::Const(ty, magnitude as i64),
+            op,
+        ];
+        if self.config.overflow == Overflow::Trap {
+            self.emit_all(&overflows);
+            self.emit(Inst::If);
+            self.report(OVERFLOW_EXIT, format!("Cell overflow {}.\n", loc));
+            self.emit(Inst::End);
+            self.emit_all(&result);
+        } else {
+            self.emit(Inst::Const(ty, limit));
+            self.emit_all(&result);
+            self.emit_all(&overflows);
+            self.emit(Inst::Select);
+        }
+        self.emit(Inst::Store(self.cw(), disp));
+    }
+
+    /// Generates the module for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> Module {
+        self.prologue();
+        walk(&mut self, ir);
+        self.epilogue()
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    type Loop = ();
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of the tape are only detected by checked tapes, and the end of
+    /// the memory traps on cells past the end of a heap tape.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        if self.on_stack() && offset > 0 {
+            self.cell_addr(offset);
+            self.right(A, loc);
+        } else if self.on_stack() && self.config.checked && offset < 0 {
+            self.cell_addr(offset);
+            self.left(A, loc);
+        } else if self.checked() && offset != 0 {
+            self.cell_addr(offset);
+            if offset > 0 {
+                self.right(A, loc);
+            } else {
+                self.left(A, loc);
+            }
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, _loc: &Location) {
+        self.emit_all(&[
+            Inst::LocalGet(P),
+            Inst::Const(Ty::I32, n * self.stride()),
+            Inst::Add(Ty::I32),
+            Inst::LocalSet(P),
+        ]);
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        if n > 0 {
+            self.next(n, loc);
+        } else if n < 0 {
+            self.previous(-n, loc);
+        }
+    }
+
+    /// Moves by `n` cells until a zero cell is found.
+    fn scan(&mut self, n: i64, loc: &Location) {
+        self.emit_all(&[Inst::Block, Inst::Loop]);
+        self.break_if_zero();
+        self.move_by(n, loc);
+        self.emit_all(&[Inst::Br(0), Inst::End, Inst::End]);
+    }
+
+    fn put_char(&mut self, offset: i64, _loc: &Location) {
+        if self.buffered() {
+            self.load(offset);
+            if self.ty() == Ty::I64 {
+                self.emit(Inst::Wrap);
+            }
+            self.call(PUT);
+        } else {
+            self.cell_addr(offset);
+            self.iov(&[Inst::LocalGet(A)], &[Inst::Const(Ty::I32, 1)]);
+            self.io(FD_WRITE, 1);
+            self.nio();
+            self.emit_all(&[
+                Inst::Const(Ty::I32, 1),
+                Inst::Cmp(Ty::I32, Cmp::Ne),
+                Inst::Or(Ty::I32),
+                Inst::If,
+            ]);
+            self.call(WRITE_FAILED);
+            self.emit(Inst::End);
+        }
+        self.puts = true;
+    }
+
+    fn get_char(&mut self, offset: i64, _loc: &Location) {
+        let ty = self.ty();
+        self.gets = true;
+        if self.buffered() {
+            self.call(GET);
+            self.emit(Inst::LocalSet(B));
+            let value = self.byte_cell();
+            match self.config.eof {
+                Eof::MinusOne => self.store(offset, &value),
+                Eof::Zero => {
+                    self.emit_all(&[
+                        Inst::Const(Ty::I32, 0),
+                        Inst::LocalGet(B),
+                        Inst::LocalGet(B),
+                        Inst::Const(Ty::I32, 0),
+                        Inst::Cmp(Ty::I32, Cmp::LtS),
+                        Inst::Select,
+                        Inst::LocalSet(B),
+                    ]);
+                    self.store(offset, &value);
+                },
+                Eof::Unchanged => {
+                    self.emit_all(&[
+                        Inst::LocalGet(B),
+                        Inst::Const(Ty::I32, 0),
+                        Inst::Cmp(Ty::I32, Cmp::GeS),
+                        Inst::If,
+                    ]);
+                    self.store(offset, &value);
+                    self.emit(Inst::End);
+                },
+            }
+            return;
+        }
+        // The byte read only fills the lowest byte of the cell.
+        if self.config.eof != Eof::Unchanged {
+            self.store(offset, &[Inst::Const(ty, 0)]);
+        }
+        self.cell_addr(offset);
+        self.iov(&[Inst::LocalGet(A)], &[Inst::Const(Ty::I32, 1)]);
+        self.io(FD_READ, 0);
+        self.emit(Inst::If);
+        self.call(READ_FAILED);
+        self.emit(Inst::End);
+        match self.config.eof {
+            Eof::MinusOne => {
+                self.nio();
+                self.emit_all(&[Inst::Eqz(Ty::I32), Inst::If]);
+                self.store(offset, &[Inst::Const(ty, -1)]);
+                self.emit(Inst::End);
+            },
+            Eof::Zero => (),
+            Eof::Unchanged => if self.cw() != Width::Byte {
+                self.nio();
+                self.emit(Inst::If);
+                let mut value = vec![Inst::LocalGet(A), Inst::Load(Width::Byte, 0)];
+                if ty == Ty::I64 {
+                    value.push(Inst::Extend);
+                }
+                self.store(offset, &value);
+                self.emit(Inst::End);
+            },
+        }
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let ty = self.ty();
+        let amount = self.config.cell_size.wrap(amount);
+        if amount == 0 {
+            return;
+        }
+        let (local, disp) = self.base(offset);
+        self.emit_all(&[
+            Inst::LocalGet(local),
+            Inst::LocalGet(local),
+            Inst::Load(self.cw(), disp),
+            Inst::Const(ty, amount),
+            Inst::Add(ty),
+            Inst::Store(self.cw(), disp),
+        ]);
+    }
+
+    fn set(&mut self, offset: i64, value: i64, _loc: &Location) {
+        let value = self.config.cell_size.wrap(value);
+        let ty = self.ty();
+        self.store(offset, &[Inst::Const(ty, value)]);
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, _loc: &Location) {
+        let ty = self.ty();
+        let factor = self.config.cell_size.wrap(factor);
+        if factor == 0 {
+            return;
+        }
+        self.load(src);
+        if factor != 1 {
+            self.emit(Inst::Const(ty, factor));
+            self.emit(Inst::Mul(ty));
+        }
+        self.emit(Inst::LocalSet(V));
+        let (local, disp) = self.base(dst);
+        self.emit_all(&[
+            Inst::LocalGet(local),
+            Inst::LocalGet(local),
+            Inst::Load(self.cw(), disp),
+            Inst::LocalGet(V),
+            Inst::Add(ty),
+            Inst::Store(self.cw(), disp),
+        ]);
+    }
+
+    fn open_loop(&mut self, _loc: &Location) {
+        self.emit_all(&[Inst::Block, Inst::Loop]);
+        self.break_if_zero();
+    }
+
+    fn close_loop(&mut self, _: ()) {
+        self.emit_all(&[Inst::Br(0), Inst::End, Inst::End]);
+    }
+
+}
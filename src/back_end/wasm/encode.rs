@@ -0,0 +1,423 @@
+use std::io::{
+    Error,
+    ErrorKind,
+};
+use std::collections::{
+    HashMap,
+};
+use super::inst::{
+    Inst,
+    Ty,
+    Width,
+};
+use super::module::{
+    Module,
+};
+
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn unsigned(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn signed(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn name(buf: &mut Vec<u8>, text: &str) {
+    unsigned(buf, text.len() as u64);
+    buf.extend_from_slice(text.as_bytes());
+}
+
+fn section(buf: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    buf.push(id);
+    unsigned(buf, contents.len() as u64);
+    buf.extend_from_slice(&contents);
+}
+
+/// A vector of items, prefixed by its length.
+fn vector<T, F: FnMut(&mut Vec<u8>, T)>(items: Vec<T>, mut each: F) -> Vec<u8> {
+    let mut buf = Vec::new();
+    unsigned(&mut buf, items.len() as u64);
+    for item in items {
+        each(&mut buf, item);
+    }
+    buf
+}
+
+/// Adds an opcode which differs by a fixed distance between the `i32` and
+/// the `i64` forms.
+fn typed(buf: &mut Vec<u8>, ty: Ty, i32_code: u8, distance: u8) {
+    buf.push(if ty == Ty::I64 {i32_code + distance} else {i32_code});
+}
+
+fn encode(buf: &mut Vec<u8>, inst: &Inst, funcs: &HashMap<&str, u32>) -> Result<(), Error> {
+    match *inst {
+        Inst::Block => buf.extend_from_slice(&[0x02, 0x40]),
+        Inst::Loop => buf.extend_from_slice(&[0x03, 0x40]),
+        Inst::If => buf.extend_from_slice(&[0x04, 0x40]),
+        Inst::End => buf.push(0x0B),
+        Inst::Br(depth) => {
+            buf.push(0x0C);
+            unsigned(buf, depth as u64);
+        },
+        Inst::BrIf(depth) => {
+            buf.push(0x0D);
+            unsigned(buf, depth as u64);
+        },
+        Inst::Return => buf.push(0x0F),
+        Inst::Call(ref func) => {
+            let index = match funcs.get(func.as_str()) {
+                Some(&index) => index,
+                _ => return Err(invalid(format!("Undefined function {}.", func))),
+            };
+            buf.push(0x10);
+            unsigned(buf, index as u64);
+        },
+        Inst::Drop => buf.push(0x1A),
+        Inst::Select => buf.push(0x1B),
+        Inst::LocalGet(index) => {
+            buf.push(0x20);
+            unsigned(buf, index as u64);
+        },
+        Inst::LocalSet(index) => {
+            buf.push(0x21);
+            unsigned(buf, index as u64);
+        },
+        Inst::GlobalGet(global) => {
+            buf.push(0x23);
+            unsigned(buf, global.index() as u64);
+        },
+        Inst::GlobalSet(global) => {
+            buf.push(0x24);
+            unsigned(buf, global.index() as u64);
+        },
+        Inst::Const(Ty::I32, value) => {
+            buf.push(0x41);
+            signed(buf, value as i32 as i64);
+        },
+        Inst::Const(Ty::I64, value) => {
+            buf.push(0x42);
+            signed(buf, value);
+        },
+        Inst::Add(ty) => typed(buf, ty, 0x6A, 0x12),
+        Inst::Sub(ty) => typed(buf, ty, 0x6B, 0x12),
+        Inst::Mul(ty) => typed(buf, ty, 0x6C, 0x12),
+        Inst::And(ty) => typed(buf, ty, 0x71, 0x12),
+        Inst::Or(ty) => typed(buf, ty, 0x72, 0x12),
+        Inst::Shl(ty) => typed(buf, ty, 0x74, 0x12),
+        Inst::ShrU(ty) => typed(buf, ty, 0x76, 0x12),
+        Inst::Eqz(ty) => typed(buf, ty, 0x45, 0x0B),
+        Inst::Cmp(ty, cmp) => typed(buf, ty, cmp.code(), 0x0B),
+        Inst::Wrap => buf.push(0xA7),
+        Inst::Extend => buf.push(0xAC),
+        Inst::Load(width, offset) => {
+            buf.push(match width {
+                Width::Byte => 0x2D,
+                Width::Half => 0x2F,
+                Width::Word => 0x28,
+                Width::Double => 0x29,
+            });
+            unsigned(buf, width.log2() as u64);
+            unsigned(buf, offset as u64);
+        },
+        Inst::Store(width, offset) => {
+            buf.push(match width {
+                Width::Byte => 0x3A,
+                Width::Half => 0x3B,
+                Width::Word => 0x36,
+                Width::Double => 0x37,
+            });
+            unsigned(buf, width.log2() as u64);
+            unsigned(buf, offset as u64);
+        },
+        Inst::MemorySize => buf.extend_from_slice(&[0x3F, 0x00]),
+        Inst::MemoryGrow => buf.extend_from_slice(&[0x40, 0x00]),
+        Inst::MemoryCopy => buf.extend_from_slice(&[0xFC, 10, 0x00, 0x00]),
+        Inst::MemoryFill => buf.extend_from_slice(&[0xFC, 11, 0x00]),
+    }
+    Ok(())
+}
+
+/// The index of a function type, adding it to the list if it is new.
+fn type_index(types: &mut Vec<(Vec<Ty>, Vec<Ty>)>, params: &[Ty], results: &[Ty]) -> u32 {
+    let ty = (params.to_vec(), results.to_vec());
+    match types.iter().position(|t| *t == ty) {
+        Some(index) => index as u32,
+        _ => {
+            types.push(ty);
+            types.len() as u32 - 1
+        },
+    }
+}
+
+impl Module {
+
+    /// The module in the binary format.
+    pub fn to_wasm(&self) -> Result<Vec<u8>, Error> {
+        let mut types = Vec::new();
+        let mut funcs = HashMap::new();
+        let mut imports = Vec::new();
+        for import in &self.imports {
+            funcs.insert(import.name.as_str(), funcs.len() as u32);
+            imports.push(type_index(&mut types, &import.params, &import.results));
+        }
+        let mut defined = Vec::new();
+        for func in &self.funcs {
+            funcs.insert(func.name.as_str(), funcs.len() as u32);
+            defined.push(type_index(&mut types, &func.params, &func.results));
+        }
+
+        let mut bodies = Vec::new();
+        for func in &self.funcs {
+            let mut body = Vec::new();
+            // Locals are declared in runs of the same type.
+            let mut runs: Vec<(u32, Ty)> = Vec::new();
+            for &ty in &func.locals {
+                match runs.last_mut() {
+                    Some(&mut (ref mut count, last)) if last == ty => *count += 1,
+                    _ => runs.push((1, ty)),
+                }
+            }
+            body.extend(vector(runs, |buf, (count, ty)| {
+                unsigned(buf, count as u64);
+                buf.push(ty.code());
+            }));
+            for inst in &func.body {
+                if let Err(e) = encode(&mut body, inst, &funcs) {
+                    return Err(invalid(format!("Cannot encode `{}`: {}", inst, e)));
+                }
+            }
+            body.push(0x0B);
+            bodies.push(body);
+        }
+
+        let mut buf = b"\0asm".to_vec();
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        section(&mut buf, 1, vector(types, |buf, (params, results)| {
+            buf.push(0x60);
+            buf.extend(vector(params, |buf, ty| buf.push(ty.code())));
+            buf.extend(vector(results, |buf, ty| buf.push(ty.code())));
+        }));
+        section(&mut buf, 2, vector(self.imports.iter().zip(imports).collect(), |buf, (import, ty)| {
+            name(buf, &import.module);
+            name(buf, &import.name);
+            buf.push(0x00);
+            unsigned(buf, ty as u64);
+        }));
+        section(&mut buf, 3, vector(defined, |buf, ty| unsigned(buf, ty as u64)));
+        section(&mut buf, 5, vector(vec![self.pages], |buf, (min, max)| {
+            buf.push(0x01);
+            unsigned(buf, min as u64);
+            unsigned(buf, max as u64);
+        }));
+        section(&mut buf, 6, vector(self.globals.clone(), |buf, (_, value)| {
+            buf.extend_from_slice(&[Ty::I32.code(), 0x01, 0x41]);
+            signed(buf, value as i64);
+            buf.push(0x0B);
+        }));
+        let mut exports: Vec<(&str, u8, u32)> = vec![("memory", 0x02, 0)];
+        for func in &self.funcs {
+            if let Some(ref export) = func.export {
+                exports.push((export, 0x00, funcs[func.name.as_str()]));
+            }
+        }
+        section(&mut buf, 7, vector(exports, |buf, (export, kind, index)| {
+            name(buf, export);
+            buf.push(kind);
+            unsigned(buf, index as u64);
+        }));
+        section(&mut buf, 10, vector(bodies, |buf, body| {
+            unsigned(buf, body.len() as u64);
+            buf.extend(body);
+        }));
+        let (addr, ref bytes) = self.data;
+        if !bytes.is_empty() {
+            section(&mut buf, 11, vector(vec![bytes], |buf, bytes| {
+                buf.extend_from_slice(&[0x00, 0x41]);
+                signed(buf, addr as i32 as i64);
+                buf.push(0x0B);
+                unsigned(buf, bytes.len() as u64);
+                buf.extend_from_slice(bytes);
+            }));
+        }
+        Ok(buf)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::{
+        HashMap,
+    };
+    use super::{
+        encode,
+    };
+    use super::super::inst::{
+        Inst,
+        Ty,
+        Width,
+        Cmp,
+        Global,
+    };
+    use super::super::module::{
+        Module,
+        Import,
+        Func,
+    };
+
+    fn code(inst: &Inst) -> Result<Vec<u8>, ::std::io::Error> {
+        let mut funcs = HashMap::new();
+        funcs.insert("fd_write", 1);
+        funcs.insert("flush", 200);
+        let mut buf = Vec::new();
+        encode(&mut buf, inst, &funcs).map(|()| buf)
+    }
+
+    // The expected bytes are the output of `llvm-mc -triple=wasm32`.
+    #[test]
+    fn instructions() {
+        let cases = vec![
+            (Inst::Block, vec![0x02, 0x40]),
+            (Inst::Loop, vec![0x03, 0x40]),
+            (Inst::If, vec![0x04, 0x40]),
+            (Inst::End, vec![0x0B]),
+            (Inst::Br(1), vec![0x0C, 0x01]),
+            (Inst::BrIf(200), vec![0x0D, 0xC8, 0x01]),
+            (Inst::Return, vec![0x0F]),
+            (Inst::Call(String::from("fd_write")), vec![0x10, 0x01]),
+            (Inst::Call(String::from("flush")), vec![0x10, 0xC8, 0x01]),
+            (Inst::Drop, vec![0x1A]),
+            (Inst::Select, vec![0x1B]),
+            (Inst::LocalGet(0), vec![0x20, 0x00]),
+            (Inst::LocalSet(128), vec![0x21, 0x80, 0x01]),
+            (Inst::GlobalGet(Global::Start), vec![0x23, 0x00]),
+            (Inst::GlobalSet(Global::InLen), vec![0x24, 0x04]),
+            (Inst::Const(Ty::I32, -1), vec![0x41, 0x7F]),
+            (Inst::Const(Ty::I32, 64), vec![0x41, 0xC0, 0x00]),
+            (Inst::Const(Ty::I32, -65), vec![0x41, 0xBF, 0x7F]),
+            (Inst::Const(Ty::I32, 0x7FFF_FFFF), vec![0x41, 0xFF, 0xFF, 0xFF, 0xFF, 0x07]),
+            // Unsigned 32-bit masks are the same bits as negative constants.
+            (Inst::Const(Ty::I32, 0xFFFF_FFFF), vec![0x41, 0x7F]),
+            (Inst::Const(Ty::I64, 63), vec![0x42, 0x3F]),
+            (
+                Inst::Const(Ty::I64, i64::MIN),
+                vec![0x42, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F],
+            ),
+            (Inst::Add(Ty::I32), vec![0x6A]),
+            (Inst::Add(Ty::I64), vec![0x7C]),
+            (Inst::Sub(Ty::I64), vec![0x7D]),
+            (Inst::Mul(Ty::I64), vec![0x7E]),
+            (Inst::And(Ty::I64), vec![0x83]),
+            (Inst::Or(Ty::I64), vec![0x84]),
+            (Inst::Shl(Ty::I64), vec![0x86]),
+            (Inst::ShrU(Ty::I32), vec![0x76]),
+            (Inst::ShrU(Ty::I64), vec![0x88]),
+            (Inst::Eqz(Ty::I32), vec![0x45]),
+            (Inst::Eqz(Ty::I64), vec![0x50]),
+            (Inst::Cmp(Ty::I32, Cmp::Eq), vec![0x46]),
+            (Inst::Cmp(Ty::I64, Cmp::Ne), vec![0x52]),
+            (Inst::Cmp(Ty::I32, Cmp::LtS), vec![0x48]),
+            (Inst::Cmp(Ty::I64, Cmp::LtU), vec![0x54]),
+            (Inst::Cmp(Ty::I32, Cmp::GtU), vec![0x4B]),
+            (Inst::Cmp(Ty::I64, Cmp::LeU), vec![0x58]),
+            (Inst::Cmp(Ty::I32, Cmp::GeS), vec![0x4E]),
+            (Inst::Cmp(Ty::I64, Cmp::GeU), vec![0x5A]),
+            (Inst::Wrap, vec![0xA7]),
+            (Inst::Extend, vec![0xAC]),
+            (Inst::Load(Width::Byte, 0), vec![0x2D, 0x00, 0x00]),
+            (Inst::Load(Width::Half, 300), vec![0x2F, 0x01, 0xAC, 0x02]),
+            (Inst::Load(Width::Word, 4), vec![0x28, 0x02, 0x04]),
+            (Inst::Load(Width::Double, 0), vec![0x29, 0x03, 0x00]),
+            (Inst::Store(Width::Byte, 1), vec![0x3A, 0x00, 0x01]),
+            (Inst::Store(Width::Half, 0), vec![0x3B, 0x01, 0x00]),
+            (Inst::Store(Width::Word, 128), vec![0x36, 0x02, 0x80, 0x01]),
+            (Inst::Store(Width::Double, 8), vec![0x37, 0x03, 0x08]),
+            (Inst::MemorySize, vec![0x3F, 0x00]),
+            (Inst::MemoryGrow, vec![0x40, 0x00]),
+            (Inst::MemoryCopy, vec![0xFC, 0x0A, 0x00, 0x00]),
+            (Inst::MemoryFill, vec![0xFC, 0x0B, 0x00]),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(&inst).unwrap(), bytes, "{}", inst);
+        }
+        assert!(code(&Inst::Call(String::from("nowhere"))).is_err());
+    }
+
+    #[test]
+    fn module() {
+        let module = Module {
+            imports: vec![Import {
+                module: String::from("wasi_snapshot_preview1"),
+                name: String::from("proc_exit"),
+                params: vec![Ty::I32],
+                results: vec![],
+            }],
+            funcs: vec![Func {
+                name: String::from("_start"),
+                params: vec![],
+                results: vec![],
+                locals: vec![Ty::I32, Ty::I32, Ty::I64],
+                body: vec![
+                    Inst::LocalGet(0),
+                    Inst::Call(String::from("proc_exit")),
+                ],
+                export: Some(String::from("_start")),
+            }],
+            globals: vec![(Global::Start, 1024)],
+            pages: (1, 2),
+            data: (16, b"hi".to_vec()),
+        };
+        let mut expected = b"\0asm\x01\0\0\0".to_vec();
+        // Types: (i32) -> () for the import, then () -> ().
+        expected.extend_from_slice(&[0x01, 0x08, 0x02, 0x60, 0x01, 0x7F, 0x00, 0x60, 0x00, 0x00]);
+        // Imports.
+        expected.extend_from_slice(&[0x02, 0x24, 0x01, 0x16]);
+        expected.extend_from_slice(b"wasi_snapshot_preview1");
+        expected.extend_from_slice(&[0x09]);
+        expected.extend_from_slice(b"proc_exit");
+        expected.extend_from_slice(&[0x00, 0x00]);
+        // Functions, memory and globals.
+        expected.extend_from_slice(&[0x03, 0x02, 0x01, 0x01]);
+        expected.extend_from_slice(&[0x05, 0x04, 0x01, 0x01, 0x01, 0x02]);
+        expected.extend_from_slice(&[0x06, 0x07, 0x01, 0x7F, 0x01, 0x41, 0x80, 0x08, 0x0B]);
+        // Exports, with `_start` numbered after the import.
+        expected.extend_from_slice(&[0x07, 0x13, 0x02, 0x06]);
+        expected.extend_from_slice(b"memory");
+        expected.extend_from_slice(&[0x02, 0x00, 0x06]);
+        expected.extend_from_slice(b"_start");
+        expected.extend_from_slice(&[0x00, 0x01]);
+        // Code, with the locals in runs of the same type.
+        expected.extend_from_slice(&[
+            0x0A, 0x0C, 0x01, 0x0A, 0x02, 0x02, 0x7F, 0x01, 0x7E,
+            0x20, 0x00, 0x10, 0x00, 0x0B,
+        ]);
+        // Data.
+        expected.extend_from_slice(&[0x0B, 0x08, 0x01, 0x00, 0x41, 0x10, 0x0B, 0x02]);
+        expected.extend_from_slice(b"hi");
+        assert_eq!(module.to_wasm().unwrap(), expected);
+    }
+
+}
@@ -0,0 +1,252 @@
+use std::{
+    fmt,
+};
+
+
+/// A type of values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ty {
+    I32,
+    I64,
+}
+
+impl Ty {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ty::I32 => "i32",
+            Ty::I64 => "i64",
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Ty::I32 => 0x7F,
+            Ty::I64 => 0x7E,
+        }
+    }
+
+}
+
+/// Size of a memory access. Narrow loads zero extend into an `i32`, and
+/// only `Double` accesses use an `i64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+impl Width {
+
+    /// The base 2 logarithm of the size in bytes, which is also the natural
+    /// alignment.
+    pub fn log2(self) -> u32 {
+        match self {
+            Width::Byte => 0,
+            Width::Half => 1,
+            Width::Word => 2,
+            Width::Double => 3,
+        }
+    }
+
+    pub fn load_name(self) -> &'static str {
+        match self {
+            Width::Byte => "i32.load8_u",
+            Width::Half => "i32.load16_u",
+            Width::Word => "i32.load",
+            Width::Double => "i64.load",
+        }
+    }
+
+    pub fn store_name(self) -> &'static str {
+        match self {
+            Width::Byte => "i32.store8",
+            Width::Half => "i32.store16",
+            Width::Word => "i32.store",
+            Width::Double => "i64.store",
+        }
+    }
+
+}
+
+/// A comparison, signed or unsigned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    LtS,
+    LtU,
+    GtU,
+    LeU,
+    GeS,
+    GeU,
+}
+
+impl Cmp {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cmp::Eq => "eq",
+            Cmp::Ne => "ne",
+            Cmp::LtS => "lt_s",
+            Cmp::LtU => "lt_u",
+            Cmp::GtU => "gt_u",
+            Cmp::LeU => "le_u",
+            Cmp::GeS => "ge_s",
+            Cmp::GeU => "ge_u",
+        }
+    }
+
+    /// The opcode of the `i32` comparison. The `i64` one is 11 after it.
+    pub fn code(self) -> u8 {
+        match self {
+            Cmp::Eq => 0x46,
+            Cmp::Ne => 0x47,
+            Cmp::LtS => 0x48,
+            Cmp::LtU => 0x49,
+            Cmp::GtU => 0x4B,
+            Cmp::LeU => 0x4D,
+            Cmp::GeS => 0x4E,
+            Cmp::GeU => 0x4F,
+        }
+    }
+
+}
+
+/// The mutable globals of a module, holding the bounds of the tape and the
+/// state of the I/O buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Global {
+    /// The address of the first byte of the tape.
+    Start,
+    /// The address past the last byte of the tape, which is also the size
+    /// of the memory.
+    End,
+    /// The length of the output buffer.
+    OutLen,
+    /// The position and the length of the input buffer.
+    InPos,
+    InLen,
+}
+
+impl Global {
+
+    pub const ALL: [Global; 5] = [
+        Global::Start,
+        Global::End,
+        Global::OutLen,
+        Global::InPos,
+        Global::InLen,
+    ];
+
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Global::Start => "$start",
+            Global::End => "$end",
+            Global::OutLen => "$out_len",
+            Global::InPos => "$in_pos",
+            Global::InLen => "$in_len",
+        }
+    }
+
+}
+
+/// The subset of WebAssembly instructions the code generator emits, in the
+/// order they are executed. Blocks are closed by `End`, and branches count
+/// the blocks they leave, starting at 0 for the innermost one. Calls name
+/// the function, whose index is resolved when encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inst {
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(String),
+    Drop,
+    /// Takes the first of two values if the condition after them is not
+    /// zero, and the second otherwise.
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(Global),
+    GlobalSet(Global),
+    /// A constant, wrapped to the type.
+    Const(Ty, i64),
+    Add(Ty),
+    Sub(Ty),
+    Mul(Ty),
+    And(Ty),
+    Or(Ty),
+    Shl(Ty),
+    ShrU(Ty),
+    Eqz(Ty),
+    Cmp(Ty, Cmp),
+    /// Truncates an `i64` to an `i32`.
+    Wrap,
+    /// Sign extends an `i32` to an `i64`.
+    Extend,
+    /// Loads from the address on the stack plus a constant offset.
+    Load(Width, u32),
+    Store(Width, u32),
+    /// The size of the memory, in pages.
+    MemorySize,
+    /// Grows the memory by a number of pages, giving the old size or -1.
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+}
+
+impl fmt::Display for Inst {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inst::Block => write!(fmt, "block"),
+            Inst::Loop => write!(fmt, "loop"),
+            Inst::If => write!(fmt, "if"),
+            Inst::End => write!(fmt, "end"),
+            Inst::Br(depth) => write!(fmt, "br {}", depth),
+            Inst::BrIf(depth) => write!(fmt, "br_if {}", depth),
+            Inst::Return => write!(fmt, "return"),
+            Inst::Call(ref func) => write!(fmt, "call ${}", func),
+            Inst::Drop => write!(fmt, "drop"),
+            Inst::Select => write!(fmt, "select"),
+            Inst::LocalGet(index) => write!(fmt, "local.get {}", index),
+            Inst::LocalSet(index) => write!(fmt, "local.set {}", index),
+            Inst::GlobalGet(global) => write!(fmt, "global.get {}", global.name()),
+            Inst::GlobalSet(global) => write!(fmt, "global.set {}", global.name()),
+            Inst::Const(Ty::I32, value) => write!(fmt, "i32.const {}", value as i32),
+            Inst::Const(Ty::I64, value) => write!(fmt, "i64.const {}", value),
+            Inst::Add(ty) => write!(fmt, "{}.add", ty.name()),
+            Inst::Sub(ty) => write!(fmt, "{}.sub", ty.name()),
+            Inst::Mul(ty) => write!(fmt, "{}.mul", ty.name()),
+            Inst::And(ty) => write!(fmt, "{}.and", ty.name()),
+            Inst::Or(ty) => write!(fmt, "{}.or", ty.name()),
+            Inst::Shl(ty) => write!(fmt, "{}.shl", ty.name()),
+            Inst::ShrU(ty) => write!(fmt, "{}.shr_u", ty.name()),
+            Inst::Eqz(ty) => write!(fmt, "{}.eqz", ty.name()),
+            Inst::Cmp(ty, cmp) => write!(fmt, "{}.{}", ty.name(), cmp.name()),
+            Inst::Wrap => write!(fmt, "i32.wrap_i64"),
+            Inst::Extend => write!(fmt, "i64.extend_i32_s"),
+            Inst::Load(width, 0) => write!(fmt, "{}", width.load_name()),
+            Inst::Load(width, offset) =>
+                write!(fmt, "{} offset={}", width.load_name(), offset),
+            Inst::Store(width, 0) => write!(fmt, "{}", width.store_name()),
+            Inst::Store(width, offset) =>
+                write!(fmt, "{} offset={}", width.store_name(), offset),
+            Inst::MemorySize => write!(fmt, "memory.size"),
+            Inst::MemoryGrow => write!(fmt, "memory.grow"),
+            Inst::MemoryCopy => write!(fmt, "memory.copy"),
+            Inst::MemoryFill => write!(fmt, "memory.fill"),
+        }
+    }
+
+}
@@ -0,0 +1,145 @@
+pub mod inst;
+pub mod module;
+pub mod encode;
+pub mod codegen;
+
+pub use self::inst::{
+    Inst,
+    Ty,
+    Width,
+    Cmp,
+    Global,
+};
+pub use self::module::{
+    Module,
+    Func,
+    Import,
+};
+pub use self::codegen::{
+    Codegen,
+};
+
+use std::io::{
+    Write,
+    Error,
+    ErrorKind,
+};
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Tape,
+};
+use super::{
+    Arch,
+    Format,
+    create_output,
+};
+
+
+/// Size of a page of memory, the unit the memory grows by, and the size
+/// heap tapes are rounded to.
+pub const PAGE_SIZE: u64 = 0x10000;
+
+/// The most pages the memory may have, keeping addresses below 2 GiB.
+pub const MAX_PAGES: u32 = 0x8000;
+
+/// WebAssembly with 32-bit memory, running on a WASI host, which provides
+/// `fd_read`, `fd_write` and `proc_exit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Wasm;
+
+impl Wasm {
+
+    /// The memory access width of cells of the given size.
+    pub fn cell_width(size: CellSize) -> Width {
+        match size {
+            CellSize::Bits8 => Width::Byte,
+            CellSize::Bits16 => Width::Half,
+            CellSize::Bits32 => Width::Word,
+            CellSize::Bits64 => Width::Double,
+        }
+    }
+
+    /// Fails if the configuration cannot be generated.
+    pub fn check(&self, config: &Config) -> Result<(), Error> {
+        if let Tape::Heap(cells) | Tape::Bidirectional(cells) = config.tape {
+            let fits = cells.checked_mul(config.cell_size.bytes() as u64)
+                .is_some_and(|bytes| bytes <= 1 << 30);
+            if !fits {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The tape is too big for the architecture."
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The size in bytes of a heap tape, rounded up to whole pages. The size
+    /// must have been checked.
+    pub fn heap_bytes(cells: u64, size: CellSize) -> u64 {
+        let bytes = cells * size.bytes() as u64;
+        (bytes + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+    }
+
+    /// Generates the module of a WASI command, exporting `_start`.
+    pub fn gen_module(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Module, Error> {
+        self.check(config)?;
+        Ok(Codegen::new(config).generate(ir))
+    }
+
+    pub fn gen_wat<T: Write>(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        out: &mut T
+    ) -> Result<usize, Error> {
+        let text = self.gen_module(ir, config)?.to_wat();
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+}
+
+impl Arch for Wasm {
+
+    fn generate(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        format: Format,
+        out: String
+    ) -> Result<usize, Error> {
+        match format {
+            Format::Asm => self.gen_wat(ir, config, &mut create_output(&out)?),
+            Format::Bin => {
+                let code = self.gen_module(ir, config)?.to_wasm()?;
+                create_output(&out)?.write_all(&code)?;
+                Ok(code.len())
+            },
+            Format::Elf => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The `elf` format is not supported by the wasm back end."
+            )),
+            Format::Object(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The `obj` format is not supported by the wasm back end."
+            )),
+        }
+    }
+
+    fn default_format(&self) -> Format {
+        Format::Bin
+    }
+
+}
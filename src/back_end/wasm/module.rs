@@ -0,0 +1,124 @@
+use super::inst::{
+    Inst,
+    Ty,
+    Global,
+};
+
+
+/// A function imported from the host.
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub params: Vec<Ty>,
+    pub results: Vec<Ty>,
+}
+
+/// A function defined by the module. Its locals are numbered after its
+/// parameters.
+#[derive(Clone, Debug)]
+pub struct Func {
+    pub name: String,
+    pub params: Vec<Ty>,
+    pub results: Vec<Ty>,
+    pub locals: Vec<Ty>,
+    pub body: Vec<Inst>,
+    /// The name the function is exported with, if it is.
+    pub export: Option<String>,
+}
+
+/// A complete module, with a single memory exported as `memory`, whose
+/// initial contents are the data at the given address. Functions are
+/// numbered with the imports first. The globals are the ones of `Global`,
+/// with the given initial values.
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub funcs: Vec<Func>,
+    pub globals: Vec<(Global, i32)>,
+    /// The initial and the maximum size of the memory, in pages.
+    pub pages: (u32, u32),
+    pub data: (u32, Vec<u8>),
+}
+
+fn signature(params: &[Ty], results: &[Ty]) -> String {
+    let mut text = String::new();
+    if !params.is_empty() {
+        let names: Vec<_> = params.iter().map(|ty| ty.name()).collect();
+        text += &format!(" (param {})", names.join(" "));
+    }
+    if !results.is_empty() {
+        let names: Vec<_> = results.iter().map(|ty| ty.name()).collect();
+        text += &format!(" (result {})", names.join(" "));
+    }
+    text
+}
+
+/// Escapes bytes for a string of the text format.
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in bytes {
+        let plain = byte.is_ascii_graphic() && byte != b'"' && byte != b'\\';
+        if plain || byte == b' ' {
+            text.push(byte as char);
+        } else {
+            text += &format!("\\{:02x}", byte);
+        }
+    }
+    text
+}
+
+impl Module {
+
+    /// The module in the text format, with instructions indented by the
+    /// blocks they are in.
+    pub fn to_wat(&self) -> String {
+        let mut text = String::from("(module\n");
+        for import in &self.imports {
+            text += &format!(
+                "  (import \"{}\" \"{}\" (func ${}{}))\n",
+                import.module,
+                import.name,
+                import.name,
+                signature(&import.params, &import.results)
+            );
+        }
+        let (min, max) = self.pages;
+        text += &format!("  (memory (export \"memory\") {} {})\n", min, max);
+        for &(global, value) in &self.globals {
+            text += &format!("  (global {} (mut i32) (i32.const {}))\n", global.name(), value);
+        }
+        let (addr, ref bytes) = self.data;
+        if !bytes.is_empty() {
+            text += &format!("  (data (i32.const {}) \"{}\")\n", addr, escape(bytes));
+        }
+        for func in &self.funcs {
+            text += &format!("  (func ${}", func.name);
+            if let Some(ref name) = func.export {
+                text += &format!(" (export \"{}\")", name);
+            }
+            text += &signature(&func.params, &func.results);
+            text.push('\n');
+            if !func.locals.is_empty() {
+                let names: Vec<_> = func.locals.iter().map(|ty| ty.name()).collect();
+                text += &format!("    (local {})\n", names.join(" "));
+            }
+            let mut depth = 2;
+            for inst in &func.body {
+                if *inst == Inst::End {
+                    depth -= 1;
+                }
+                text += &"  ".repeat(depth);
+                text += &inst.to_string();
+                text.push('\n');
+                if let Inst::Block | Inst::Loop | Inst::If = *inst {
+                    depth += 1;
+                }
+            }
+            text += "  )\n";
+        }
+        text += ")\n";
+        text
+    }
+
+}
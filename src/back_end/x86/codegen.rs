@@ -0,0 +1,1606 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Eof,
+    Overflow,
+    Tape,
+};
+use back_end::{
+    Emitter,
+    Labels,
+    walk,
+    label_for,
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+    IO_EXIT,
+};
+use back_end::linux::{
+    PROT_READ_WRITE,
+    MAP_FLAGS,
+    RLIMIT_STACK,
+    STACK_CAP,
+};
+use super::{
+    X86Mode,
+};
+use super::inst::{
+    Inst,
+    Operand,
+    Mem,
+    Reg,
+    Width,
+    Cond,
+};
+
+
+/// How the generated code is entered and left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Linkage {
+    /// A standalone program starting at `_start`, with the tape on the stack
+    /// or in a region given by `mmap`, see `Tape`, and I/O done through
+    /// system calls.
+    Start,
+    /// A function following the System V calling convention, with the
+    /// signature `cell *f(cell *tape, int (*get)(void), int (*put)(int))`.
+    /// The tape grows upwards from the given pointer, and the pointer to the
+    /// current cell is returned.
+    Function,
+    /// A function for the JIT, amd64 only, with the signature
+    /// `cell *f(cell *tape, struct context *ctx)`. The context starts with
+    /// the following fields, which the generated code reads:
+    ///
+    /// * offset 0: pointer to the first cell of the tape;
+    /// * offset 8: pointer past the last cell of the tape;
+    /// * offset 16: `int get(struct context *)`, where less than -1 means an
+    ///   error;
+    /// * offset 24: `int put(struct context *, int)`, where a negative return
+    ///   means an error;
+    /// * offset 32: `cell *bounds(struct context *, cell *, const char *)`,
+    ///   called when the pointer leaves the tape, which returns the new
+    ///   pointer after growing the tape, or null on error. The message, laid
+    ///   out as for `_fail`, names the `<` or `>` which left the tape.
+    /// * offset 40: `void fail(struct context *, const char *)`, called with
+    ///   a message, laid out as for `_fail`, when the program must stop.
+    ///
+    /// On error, the function returns immediately.
+    Jit,
+}
+
+const JIT_START: i32 = 0;
+const JIT_END: i32 = 8;
+const JIT_GET: i32 = 16;
+const JIT_PUT: i32 = 24;
+const JIT_BOUNDS: i32 = 32;
+const JIT_FAIL: i32 = 40;
+const JIT_EXIT: &str = "_jit_exit";
+const TAPE_GROW: &str = "_tape_grow";
+const FAIL: &str = "_fail";
+/// Enters `_fail` past its flush, before the output buffer is set up.
+const FAIL_EARLY: &str = "_fail_early";
+const PUT: &str = "_put";
+const GET: &str = "_get";
+const FLUSH: &str = "_flush";
+const WRITE_FAILED: &str = "_write_failed";
+const READ_FAILED: &str = "_read_failed";
+
+/// Size of each I/O buffer of a standalone program.
+const IO_BUFFER: i32 = 4096;
+/// Offsets from `%bp` of the I/O state of a standalone program: the length
+/// of the output buffer, the position and the length of the input buffer,
+/// and the buffers themselves.
+const IO_OUT_LEN: i32 = 8;
+const IO_IN_POS: i32 = 16;
+const IO_IN_LEN: i32 = 24;
+const IO_OUT: i32 = 32;
+const IO_IN: i32 = IO_OUT + IO_BUFFER;
+const IO_END: i32 = IO_IN + IO_BUFFER;
+
+const PAGE_SIZE: i64 = 4096;
+
+/// Lays a message out as `_fail` reads it: the exit status in one byte, the
+/// length of the text in two bytes, and the text.
+fn fail_message(status: i64, message: &str) -> Vec<u8> {
+    let mut data = vec![status as u8];
+    data.extend_from_slice(&(message.len() as u16).to_le_bytes());
+    data.extend_from_slice(message.as_bytes());
+    data
+}
+
+/// The high word of a 64-bit cell on x86, whose low word is `cell`.
+fn high(cell: Operand) -> Operand {
+    match cell {
+        Operand::Mem(Mem {base, disp}) => Operand::mem(base, disp + 4),
+        _ => unreachable!(),
+    }
+}
+
+/// The message reporting that the pointer left the tape at `loc`.
+fn bounds_message(loc: &Location) -> String {
+    format!("Tape pointer out of bounds {}.\n", loc)
+}
+
+pub struct Codegen {
+    mode: X86Mode,
+    linkage: Linkage,
+    config: Config,
+    insts: Vec<Inst>,
+    labels: Labels,
+    /// Whether `_fail` is called.
+    fails: bool,
+    /// Whether a standalone program writes, through `_put` if buffered.
+    puts: bool,
+    /// Whether a standalone program reads, through `_get` if buffered.
+    gets: bool,
+}
+
+impl Codegen {
+
+    /// Creates a code generator. The configuration must be supported by the
+    /// mode, see `X86Mode::check`.
+    pub fn new(mode: X86Mode, linkage: Linkage, config: &Config) -> Self {
+        Self {
+            mode,
+            linkage,
+            config: config.clone(),
+            insts: Vec::new(),
+            labels: Labels::default(),
+            fails: false,
+            puts: false,
+            gets: false,
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    fn word(&self) -> Width {
+        self.mode.addr_width()
+    }
+
+    fn tape(&self) -> Operand {
+        Operand::Reg(self.mode.tape_reg())
+    }
+
+    /// Width of the cells.
+    fn cw(&self) -> Width {
+        X86Mode::cell_width(self.config.cell_size)
+    }
+
+    /// Size of a cell in bytes.
+    fn stride(&self) -> i64 {
+        self.config.cell_size.bytes() as i64
+    }
+
+    /// Whether cells are 64 bits wide on x86, where each one is a pair of
+    /// words, the low one first.
+    fn pair(&self) -> bool {
+        self.mode == X86Mode::X86 && self.cw() == Width::Quad
+    }
+
+    /// Size of the first cell of a stack tape in bytes, which is pushed
+    /// a word at a time.
+    fn first_cell(&self) -> i32 {
+        (self.stride() as i32).max(self.word().bytes() as i32)
+    }
+
+    /// Whether the tape is on the stack, which is grown by pushing.
+    fn on_stack(&self) -> bool {
+        self.linkage == Linkage::Start && self.config.tape == Tape::Stack
+    }
+
+    /// Whether a standalone program grows its tape at both ends. The bounds
+    /// of the tape are kept at the top of the stack.
+    fn growable(&self) -> bool {
+        self.linkage == Linkage::Start && self.config.tape.bidirectional()
+    }
+
+    /// Whether a standalone program goes through the I/O buffers, whose state
+    /// is pointed by `%bp`. The first cell of a stack tape is kept in `%bp`
+    /// too, and the state follows it.
+    fn buffered(&self) -> bool {
+        self.linkage == Linkage::Start && !self.config.unbuffered
+    }
+
+    /// Whether the pointer is checked against both ends of the tape, which
+    /// are kept in memory. The first cell of a stack tape is kept in `%bp`
+    /// instead, when checked.
+    fn checked(&self) -> bool {
+        match self.linkage {
+            Linkage::Start =>
+                !self.on_stack() && (self.growable() || self.config.checked),
+            Linkage::Function => false,
+            Linkage::Jit => true,
+        }
+    }
+
+    /// Where the start, or the end, of a checked tape is kept.
+    fn bound(&self, end: bool) -> Operand {
+        match self.linkage {
+            Linkage::Jit =>
+                Operand::mem(Reg::R12, if end {JIT_END} else {JIT_START}),
+            _ => {
+                let disp = if end {self.word().bytes() as i32} else {0};
+                Operand::mem(Reg::Sp, disp)
+            },
+        }
+    }
+
+    /// Where the lowest address a checked stack tape may use is kept, right
+    /// above the I/O state, if any.
+    fn stack_limit(&self) -> Operand {
+        let above = if self.buffered() {IO_END} else {self.first_cell()};
+        Operand::mem(Reg::Bp, above)
+    }
+
+    /// Cells follow the stack downwards when the tape is on the stack, and
+    /// go upwards from the start of the tape otherwise.
+    fn cell(&self, offset: i64) -> Operand {
+        let dir = if self.on_stack() {-self.stride()} else {self.stride()};
+        Operand::mem(self.mode.tape_reg(), (offset * dir) as i32)
+    }
+
+    /// An immediate operand for a value wrapped around the cell size. Values
+    /// that only fit in 64 bits are loaded into `%rcx` first.
+    fn imm(&mut self, value: i64) -> Operand {
+        let value = self.config.cell_size.wrap(value);
+        if value == value as i32 as i64 {
+            Operand::Imm(value)
+        } else {
+            let cx = Operand::Reg(Reg::Cx);
+            self.emit(Inst::Mov(Width::Quad, Operand::Imm(value), cx));
+            cx
+        }
+    }
+
+    /// Stores a value wrapped around the cell size into a cell, a word at a
+    /// time for 64-bit cells on x86.
+    fn store_imm(&mut self, cell: Operand, value: i64) {
+        if self.pair() {
+            let value = self.config.cell_size.wrap(value);
+            let low = Operand::Imm(value as i32 as i64);
+            self.emit(Inst::Mov(Width::Long, low, cell));
+            self.emit(Inst::Mov(Width::Long, Operand::Imm(value >> 32), high(cell)));
+        } else {
+            let cw = self.cw();
+            let imm = self.imm(value);
+            self.emit(Inst::Mov(cw, imm, cell));
+        }
+    }
+
+    /// Adds a value to a 64-bit cell on x86, or subtracts it, carrying from
+    /// the low word into the high one.
+    fn add_pair(&mut self, cell: Operand, value: u64, sub: bool) {
+        let low = Operand::Imm(value as i32 as i64);
+        let high_part = Operand::Imm((value >> 32) as i32 as i64);
+        if sub {
+            self.emit(Inst::Sub(Width::Long, low, cell));
+            self.emit(Inst::Sbb(Width::Long, high_part, high(cell)));
+        } else {
+            self.emit(Inst::Add(Width::Long, low, cell));
+            self.emit(Inst::Adc(Width::Long, high_part, high(cell)));
+        }
+    }
+
+    /// Adds a 64-bit cell times a factor to another on x86. The low words
+    /// multiply into `%edx:%eax`, and the products with a high word only
+    /// add to the high word of the destination.
+    fn mul_pair(&mut self, src: Operand, dst: Operand, factor: i64) {
+        let l = Width::Long;
+        let ax = Operand::Reg(Reg::Ax);
+        let cx = Operand::Reg(Reg::Cx);
+        let dx = Operand::Reg(Reg::Dx);
+        let low = factor as i32 as i64;
+        if factor == 1 || factor == -1 {
+            self.emit(Inst::Mov(l, src, ax));
+            self.emit(Inst::Mov(l, high(src), dx));
+        } else {
+            if factor >> 32 != 0 {
+                self.emit(Inst::Imul(l, factor >> 32, src, cx));
+                self.emit(Inst::Add(l, cx, high(dst)));
+            }
+            if low == 0 {
+                return;
+            }
+            self.emit(Inst::Imul(l, low, high(src), cx));
+            self.emit(Inst::Add(l, cx, high(dst)));
+            self.emit(Inst::Mov(l, src, ax));
+            self.emit(Inst::Mov(l, Operand::Imm(low), cx));
+            self.emit(Inst::Mul(l, cx));
+        }
+        if factor == -1 {
+            self.emit(Inst::Sub(l, ax, dst));
+            self.emit(Inst::Sbb(l, dx, high(dst)));
+        } else {
+            self.emit(Inst::Add(l, ax, dst));
+            self.emit(Inst::Adc(l, dx, high(dst)));
+        }
+    }
+
+    /// Compares the current cell with zero, setting the zero flag. The words
+    /// of a 64-bit cell on x86 are or'ed together instead.
+    fn test_zero(&mut self) {
+        let cell = self.cell(0);
+        if self.pair() {
+            let ax = Operand::Reg(Reg::Ax);
+            self.emit(Inst::Mov(Width::Long, cell, ax));
+            self.emit(Inst::Or(Width::Long, high(cell), ax));
+        } else {
+            self.emit(Inst::Cmp(self.cw(), Operand::Imm(0), cell));
+        }
+    }
+
+    /// Loads the low 32 bits of a cell, zero extended, into a register.
+    fn load_low(&mut self, cell: Operand, reg: Reg) {
+        match self.cw() {
+            w @ Width::Byte | w @ Width::Word =>
+                self.emit(Inst::Movzx(w, Width::Long, cell, Operand::Reg(reg))),
+            _ => self.emit(Inst::Mov(Width::Long, cell, Operand::Reg(reg))),
+        }
+    }
+
+    /// Stores the `int` in `%eax` into a cell, sign extended.
+    fn store_int(&mut self, cell: Operand) {
+        let cw = self.cw();
+        let ax = Operand::Reg(Reg::Ax);
+        if self.pair() {
+            self.emit(Inst::Mov(Width::Long, ax, cell));
+            self.emit(Inst::Cltd);
+            self.emit(Inst::Mov(Width::Long, Operand::Reg(Reg::Dx), high(cell)));
+            return;
+        }
+        if cw == Width::Quad {
+            self.emit(Inst::Movsx(Width::Long, Width::Quad, ax, ax));
+        }
+        self.emit(Inst::Mov(cw, ax, cell));
+    }
+
+    /// Registers holding the `get` and `put` callbacks of a function.
+    fn callbacks(&self) -> (Operand, Operand) {
+        match self.mode {
+            X86Mode::Amd64 => (Operand::Reg(Reg::R12), Operand::Reg(Reg::R13)),
+            X86Mode::X86 => (Operand::Reg(Reg::Di), Operand::Reg(Reg::Bx)),
+        }
+    }
+
+    /// Callee saved registers used by a function, in the order they are
+    /// pushed.
+    fn saved_regs(&self) -> [Reg; 3] {
+        match self.mode {
+            X86Mode::Amd64 => [Reg::Bx, Reg::R12, Reg::R13],
+            X86Mode::X86 => [Reg::Bx, Reg::Si, Reg::Di],
+        }
+    }
+
+    /// Emits a system call whose number and arguments are given in Linux's
+    /// order: number, then the arguments from the first one. Memory operands
+    /// are passed by address.
+    fn syscall(&mut self, args: &[Operand]) {
+        let regs = match self.mode {
+            X86Mode::Amd64 =>
+                [Reg::Ax, Reg::Di, Reg::Si, Reg::Dx, Reg::R10, Reg::R8, Reg::R9],
+            X86Mode::X86 =>
+                [Reg::Ax, Reg::Bx, Reg::Cx, Reg::Dx, Reg::Si, Reg::Di, Reg::Bp],
+        };
+        for (&arg, &reg) in args.iter().zip(regs.iter()) {
+            self.syscall_arg(arg, reg);
+        }
+        self.emit(match self.mode {
+            X86Mode::Amd64 => Inst::Syscall,
+            X86Mode::X86 => Inst::Int(0x80),
+        });
+    }
+
+    /// Loads an argument into a register, taking the address of memory
+    /// operands.
+    fn syscall_arg(&mut self, arg: Operand, reg: Reg) {
+        let w = self.word();
+        match arg {
+            Operand::Mem(_) => self.emit(Inst::Lea(w, arg, Operand::Reg(reg))),
+            Operand::Reg(r) if r == reg => (),
+            _ => self.emit(Inst::Mov(w, arg, Operand::Reg(reg))),
+        }
+    }
+
+    /// Reports `message` and exits with status 1 if the last system call
+    /// failed, that is, if it returned a value from -4095 to -1. Calls made
+    /// while the program starts fail through `_fail_early`.
+    fn check_syscall(&mut self, label: &str, message: &str, early: bool) {
+        let w = self.word();
+        self.emit(Inst::Cmp(w, Operand::Imm(-4095), Operand::Reg(Reg::Ax)));
+        self.emit(Inst::Jcc(Cond::B, String::from(label)));
+        let entry = if early {FAIL_EARLY} else {FAIL};
+        self.report_at(entry, IO_EXIT, String::from(message));
+        self.emit(Inst::Label(String::from(label)));
+    }
+
+    /// Pushes the lowest address a checked stack tape may use. The arguments
+    /// and the environment take at most a quarter of the stack size limit,
+    /// so half of it is left for the tape.
+    fn limit_stack(&mut self) {
+        let w = self.word();
+        let ax = Operand::Reg(Reg::Ax);
+        let cx = Operand::Reg(Reg::Cx);
+        let getrlimit = self.mode.sys_getrlimit();
+        // The current and the maximum limit are written on the stack.
+        self.emit(Inst::Push(w, Operand::Imm(0)));
+        self.emit(Inst::Push(w, Operand::Imm(0)));
+        self.syscall(&[
+            Operand::Imm(getrlimit),
+            Operand::Imm(RLIMIT_STACK),
+            Operand::mem(Reg::Sp, 0),
+        ]);
+        self.check_syscall("_getrlimit_ok", "Could not read the stack size limit.\n", true);
+        self.emit(Inst::Pop(w, ax));
+        self.emit(Inst::Pop(w, cx));
+        let capped = String::from("_stack_capped");
+        self.emit(Inst::Cmp(w, Operand::Imm(STACK_CAP), ax));
+        self.emit(Inst::Jcc(Cond::Be, capped.clone()));
+        self.emit(Inst::Mov(w, Operand::Imm(STACK_CAP), ax));
+        self.emit(Inst::Label(capped));
+        self.emit(Inst::Shr(w, 1, ax));
+        self.emit(Inst::Mov(w, Operand::Reg(Reg::Sp), cx));
+        self.emit(Inst::Sub(w, ax, cx));
+        self.emit(Inst::Push(w, cx));
+    }
+
+    /// Maps the heap tape with a guard page at each end, which are left
+    /// inaccessible, and points to its first cell. When checked, its end and
+    /// then its start are pushed.
+    fn map_tape(&mut self, cells: u64) {
+        let w = self.word();
+        let tape = self.tape();
+        let bytes = X86Mode::heap_bytes(cells, self.config.cell_size) as i64;
+        let mmap = self.mode.sys_mmap();
+        self.syscall(&[
+            Operand::Imm(mmap),
+            Operand::Imm(0),
+            Operand::Imm(bytes + 2 * PAGE_SIZE),
+            Operand::Imm(0),
+            Operand::Imm(MAP_FLAGS),
+            Operand::Imm(-1),
+            Operand::Imm(0),
+        ]);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.emit(Inst::Lea(w, Operand::mem(Reg::Ax, PAGE_SIZE as i32), tape));
+        let mprotect = self.mode.sys_mprotect();
+        self.syscall(&[
+            Operand::Imm(mprotect),
+            tape,
+            Operand::Imm(bytes),
+            Operand::Imm(PROT_READ_WRITE),
+        ]);
+        self.check_syscall("_mprotect_ok", "Could not map the tape.\n", true);
+        if self.config.checked {
+            // The end of the last cell, not of its page.
+            let end = cells as i64 * self.stride();
+            let cx = Operand::Reg(Reg::Cx);
+            self.emit(Inst::Mov(w, Operand::Imm(end), cx));
+            self.emit(Inst::Add(w, tape, cx));
+            self.emit(Inst::Push(w, cx));
+            self.emit(Inst::Push(w, tape));
+        }
+    }
+
+    /// Emits the `_fail` routine, called right before a message laid out as
+    /// the exit status in one byte, the length of the text in two bytes, and
+    /// the text. A standalone program writes the text to stderr and exits,
+    /// and the JIT hands the message to its `fail` hook and returns.
+    fn fail(&mut self) {
+        let w = self.word();
+        self.emit(Inst::Label(String::from(FAIL)));
+        if self.linkage == Linkage::Jit {
+            self.emit(Inst::Pop(w, Operand::Reg(Reg::Si)));
+            self.emit(Inst::Mov(w, Operand::Reg(Reg::R12), Operand::Reg(Reg::Di)));
+            self.emit(Inst::Call(Operand::mem(Reg::R12, JIT_FAIL)));
+            self.emit(Inst::Jmp(String::from(JIT_EXIT)));
+            return;
+        }
+        let (status, msg, len) = match self.mode {
+            X86Mode::Amd64 => (Reg::Di, Reg::Si, Reg::Dx),
+            X86Mode::X86 => (Reg::Bx, Reg::Cx, Reg::Dx),
+        };
+        let write = self.mode.sys_write();
+        let exit = self.mode.sys_exit();
+        // What was written before is shown first.
+        if self.buffered() && self.puts {
+            self.emit(Inst::CallLabel(String::from(FLUSH)));
+        }
+        self.emit(Inst::Label(String::from(FAIL_EARLY)));
+        self.emit(Inst::Pop(w, Operand::Reg(msg)));
+        self.emit(Inst::Movzx(
+            Width::Word,
+            Width::Long,
+            Operand::mem(msg, 1),
+            Operand::Reg(len)
+        ));
+        self.emit(Inst::Add(w, Operand::Imm(3), Operand::Reg(msg)));
+        self.syscall(&[
+            Operand::Imm(write),
+            Operand::Imm(2),
+            Operand::Reg(msg),
+            Operand::Reg(len),
+        ]);
+        self.emit(Inst::Movzx(
+            Width::Byte,
+            Width::Long,
+            Operand::mem(msg, -3),
+            Operand::Reg(status)
+        ));
+        self.syscall(&[Operand::Imm(exit), Operand::Reg(status)]);
+    }
+
+    /// Stops the program with the given exit status and message, through
+    /// `_fail`.
+    fn report(&mut self, status: i64, message: String) {
+        self.report_at(FAIL, status, message);
+    }
+
+    /// Stops the program through the given entry of `_fail`.
+    fn report_at(&mut self, entry: &str, status: i64, message: String) {
+        self.emit(Inst::CallLabel(String::from(entry)));
+        self.emit(Inst::Data(fail_message(status, &message)));
+        self.fails = true;
+    }
+
+    /// Reports that the pointer left the tape at `loc` and exits.
+    fn report_bounds(&mut self, loc: &Location) {
+        self.report(BOUNDS_EXIT, bounds_message(loc));
+    }
+
+    /// Reports that the pointer left the tape, unless `ok` holds after the
+    /// comparison emitted before.
+    fn out_of_bounds(&mut self, ok: Cond, loc: &Location) {
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.emit(Inst::Jcc(ok, label.clone()));
+        self.report_bounds(loc);
+        self.emit(Inst::Label(label));
+    }
+
+    /// Maps the first region of a bidirectional tape, points to its middle,
+    /// and pushes its end and then its start.
+    fn map_growable(&mut self, cells: u64) {
+        let w = self.word();
+        let tape = self.tape();
+        let ax = Operand::Reg(Reg::Ax);
+        let cx = Operand::Reg(Reg::Cx);
+        let bytes = X86Mode::heap_bytes(cells, self.config.cell_size) as i64;
+        let mmap = self.mode.sys_mmap();
+        self.syscall(&[
+            Operand::Imm(mmap),
+            Operand::Imm(0),
+            Operand::Imm(bytes),
+            Operand::Imm(PROT_READ_WRITE),
+            Operand::Imm(MAP_FLAGS),
+            Operand::Imm(-1),
+            Operand::Imm(0),
+        ]);
+        self.check_syscall("_mmap_ok", "Could not map the tape.\n", true);
+        self.emit(Inst::Mov(w, Operand::Imm(bytes), cx));
+        self.emit(Inst::Add(w, ax, cx));
+        self.emit(Inst::Push(w, cx));
+        self.emit(Inst::Push(w, ax));
+        self.emit(Inst::Mov(w, Operand::Imm(bytes / 2), tape));
+        self.emit(Inst::Add(w, ax, tape));
+    }
+
+    /// Emits the `_flush` routine, which writes the whole output buffer.
+    fn flush(&mut self) {
+        let w = self.word();
+        let ax = Operand::Reg(Reg::Ax);
+        let dx = Operand::Reg(Reg::Dx);
+        let buf = Operand::Reg(match self.mode {
+            X86Mode::Amd64 => Reg::Si,
+            X86Mode::X86 => Reg::Cx,
+        });
+        let len = Operand::mem(Reg::Bp, IO_OUT_LEN);
+        let again = String::from("_flush_again");
+        let done = String::from("_flush_done");
+        let write = self.mode.sys_write();
+        self.emit(Inst::Label(String::from(FLUSH)));
+        self.emit(Inst::Lea(w, Operand::mem(Reg::Bp, IO_OUT), buf));
+        self.emit(Inst::Label(again.clone()));
+        self.emit(Inst::Mov(w, len, dx));
+        self.emit(Inst::Cmp(w, Operand::Imm(0), dx));
+        self.emit(Inst::Jcc(Cond::E, done.clone()));
+        self.syscall(&[Operand::Imm(write), Operand::Imm(1), buf, dx]);
+        self.emit(Inst::Cmp(w, Operand::Imm(0), ax));
+        self.emit(Inst::Jcc(Cond::Le, String::from(WRITE_FAILED)));
+        self.emit(Inst::Add(w, ax, buf));
+        self.emit(Inst::Sub(w, ax, len));
+        self.emit(Inst::Jmp(again));
+        self.emit(Inst::Label(done));
+        self.emit(Inst::Mov(w, Operand::Imm(0), len));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_put` routine, which appends the byte in `%al` to the
+    /// output buffer, flushing it on newlines and when full.
+    fn put(&mut self) {
+        let w = self.word();
+        let ax = Operand::Reg(Reg::Ax);
+        let cx = Operand::Reg(Reg::Cx);
+        let dx = Operand::Reg(Reg::Dx);
+        let len = Operand::mem(Reg::Bp, IO_OUT_LEN);
+        self.emit(Inst::Label(String::from(PUT)));
+        self.emit(Inst::Mov(w, len, cx));
+        self.emit(Inst::Lea(w, Operand::mem(Reg::Bp, IO_OUT), dx));
+        self.emit(Inst::Add(w, cx, dx));
+        self.emit(Inst::Mov(Width::Byte, ax, Operand::mem(Reg::Dx, 0)));
+        self.emit(Inst::Add(w, Operand::Imm(1), cx));
+        self.emit(Inst::Mov(w, cx, len));
+        self.emit(Inst::Cmp(Width::Byte, Operand::Imm(b'\n' as i64), ax));
+        self.emit(Inst::Jcc(Cond::E, String::from(FLUSH)));
+        self.emit(Inst::Cmp(w, Operand::Imm(IO_BUFFER as i64), cx));
+        self.emit(Inst::Jcc(Cond::E, String::from(FLUSH)));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits the `_get` routine, which takes the next byte of the input
+    /// buffer into `%eax`, or -1 at the end of the input. The output is
+    /// flushed before the buffer is filled again.
+    fn get(&mut self) {
+        let w = self.word();
+        let ax = Operand::Reg(Reg::Ax);
+        let cx = Operand::Reg(Reg::Cx);
+        let dx = Operand::Reg(Reg::Dx);
+        let pos = Operand::mem(Reg::Bp, IO_IN_POS);
+        let len = Operand::mem(Reg::Bp, IO_IN_LEN);
+        let byte = String::from("_get_byte");
+        let eof = String::from("_get_eof");
+        let read = self.mode.sys_read();
+        self.emit(Inst::Label(String::from(GET)));
+        self.emit(Inst::Mov(w, pos, cx));
+        self.emit(Inst::Cmp(w, len, cx));
+        self.emit(Inst::Jcc(Cond::B, byte.clone()));
+        self.emit(Inst::CallLabel(String::from(FLUSH)));
+        self.syscall(&[
+            Operand::Imm(read),
+            Operand::Imm(0),
+            Operand::mem(Reg::Bp, IO_IN),
+            Operand::Imm(IO_BUFFER as i64),
+        ]);
+        self.emit(Inst::Cmp(w, Operand::Imm(0), ax));
+        self.emit(Inst::Jcc(Cond::L, String::from(READ_FAILED)));
+        self.emit(Inst::Jcc(Cond::E, eof.clone()));
+        self.emit(Inst::Mov(w, ax, len));
+        self.emit(Inst::Mov(w, Operand::Imm(0), cx));
+        self.emit(Inst::Label(byte));
+        self.emit(Inst::Lea(w, Operand::mem(Reg::Bp, IO_IN), dx));
+        self.emit(Inst::Add(w, cx, dx));
+        self.emit(Inst::Movzx(Width::Byte, Width::Long, Operand::mem(Reg::Dx, 0), ax));
+        self.emit(Inst::Add(w, Operand::Imm(1), cx));
+        self.emit(Inst::Mov(w, cx, pos));
+        self.emit(Inst::Ret);
+        self.emit(Inst::Label(eof));
+        self.emit(Inst::Mov(Width::Long, Operand::Imm(-1), ax));
+        self.emit(Inst::Ret);
+    }
+
+    /// Emits a routine reporting that I/O failed. The output buffer is
+    /// dropped, so that `_fail` does not write it again.
+    fn io_failed(&mut self, label: &str, message: &str) {
+        let w = self.word();
+        self.emit(Inst::Label(String::from(label)));
+        if self.buffered() {
+            self.emit(Inst::Mov(w, Operand::Imm(0), Operand::mem(Reg::Bp, IO_OUT_LEN)));
+        }
+        self.report(IO_EXIT, String::from(message));
+    }
+
+    /// Emits the `_tape_grow` routine, called with the address of a cell out
+    /// of a bidirectional tape in `%di`. It maps a region three times as big
+    /// as the tape, moves the tape to its middle and frees the old one,
+    /// until the cell is in the tape. The tape pointer is moved along.
+    fn tape_grow(&mut self) {
+        let w = self.word();
+        let word = w.bytes() as i32;
+        let tape = self.tape();
+        let ax = Operand::Reg(Reg::Ax);
+        let cx = Operand::Reg(Reg::Cx);
+        let dx = Operand::Reg(Reg::Dx);
+        let si = Operand::Reg(Reg::Si);
+        let di = Operand::Reg(Reg::Di);
+        let cell = Operand::mem(Reg::Sp, 0);
+        let saved_tape = Operand::mem(Reg::Sp, word);
+        // Above the saved registers and the return address.
+        let start = Operand::mem(Reg::Sp, 3 * word);
+        let end = Operand::mem(Reg::Sp, 4 * word);
+        let again = String::from("_tape_grow_again");
+        self.emit(Inst::Label(String::from(TAPE_GROW)));
+        self.emit(Inst::Push(w, tape));
+        self.emit(Inst::Push(w, di));
+        self.emit(Inst::Label(again.clone()));
+        self.emit(Inst::Mov(w, end, cx));
+        self.emit(Inst::Sub(w, start, cx));
+        self.emit(Inst::Push(w, cx));
+        self.emit(Inst::Mov(w, cx, ax));
+        self.emit(Inst::Add(w, cx, cx));
+        self.emit(Inst::Add(w, ax, cx));
+        let mmap = self.mode.sys_mmap();
+        // The last argument goes in `%ebp` on x86.
+        let bp = Operand::Reg(Reg::Bp);
+        if self.mode == X86Mode::X86 {
+            self.emit(Inst::Push(w, bp));
+        }
+        self.syscall(&[
+            Operand::Imm(mmap),
+            Operand::Imm(0),
+            cx,
+            Operand::Imm(PROT_READ_WRITE),
+            Operand::Imm(MAP_FLAGS),
+            Operand::Imm(-1),
+            Operand::Imm(0),
+        ]);
+        // `_fail` needs `%bp` back, and popping it keeps the flags.
+        if self.mode == X86Mode::X86 {
+            self.emit(Inst::Pop(w, bp));
+        }
+        self.check_syscall("_tape_grow_ok", "Could not grow the tape.\n", false);
+        self.emit(Inst::Pop(w, cx));
+        self.emit(Inst::Mov(w, start, si));
+        self.emit(Inst::Mov(w, ax, start));
+        self.emit(Inst::Mov(w, ax, di));
+        self.emit(Inst::Add(w, cx, di));
+        for _ in 0 .. 3 {
+            self.emit(Inst::Add(w, cx, ax));
+        }
+        self.emit(Inst::Mov(w, ax, end));
+        self.emit(Inst::Mov(w, di, dx));
+        self.emit(Inst::Sub(w, si, dx));
+        self.emit(Inst::Add(w, dx, cell));
+        self.emit(Inst::Add(w, dx, saved_tape));
+        self.emit(Inst::Push(w, si));
+        self.emit(Inst::Push(w, cx));
+        self.emit(Inst::RepMovsb);
+        self.emit(Inst::Pop(w, cx));
+        self.emit(Inst::Pop(w, si));
+        let munmap = self.mode.sys_munmap();
+        self.syscall(&[Operand::Imm(munmap), si, cx]);
+        self.emit(Inst::Mov(w, cell, ax));
+        self.emit(Inst::Cmp(w, start, ax));
+        self.emit(Inst::Jcc(Cond::B, again.clone()));
+        self.emit(Inst::Cmp(w, end, ax));
+        self.emit(Inst::Jcc(Cond::Ae, again));
+        self.emit(Inst::Pop(w, di));
+        self.emit(Inst::Pop(w, tape));
+        self.emit(Inst::Ret);
+    }
+
+    fn prologue(&mut self) {
+        let w = self.word();
+        let tape = self.tape();
+        match self.linkage {
+            Linkage::Start => {
+                let buffered = self.buffered();
+                let sp = Operand::Reg(Reg::Sp);
+                let bp = Operand::Reg(Reg::Bp);
+                if self.on_stack() && self.config.checked {
+                    self.limit_stack();
+                }
+                if buffered {
+                    let size = IO_END - if self.on_stack() {self.first_cell()} else {0};
+                    self.emit(Inst::Sub(w, Operand::Imm(size as i64), sp));
+                }
+                match self.config.tape {
+                    Tape::Stack => {
+                        for _ in 0..self.first_cell() / w.bytes() as i32 {
+                            self.emit(Inst::Push(w, Operand::Imm(0)));
+                        }
+                        self.emit(Inst::Mov(w, sp, tape));
+                        if self.config.checked || buffered {
+                            self.emit(Inst::Mov(w, tape, bp));
+                        }
+                    },
+                    Tape::Heap(cells) => self.map_tape(cells),
+                    Tape::Bidirectional(cells) => self.map_growable(cells),
+                }
+                if buffered {
+                    if !self.on_stack() {
+                        // Above the bounds of the tape, if pushed.
+                        let pushed = if self.checked() {2 * w.bytes() as i32} else {0};
+                        self.emit(Inst::Lea(w, Operand::mem(Reg::Sp, pushed), bp));
+                    }
+                    for &field in [IO_OUT_LEN, IO_IN_POS, IO_IN_LEN].iter() {
+                        self.emit(Inst::Mov(w, Operand::Imm(0), Operand::mem(Reg::Bp, field)));
+                    }
+                }
+            },
+            Linkage::Function => {
+                for &reg in self.saved_regs().iter() {
+                    self.emit(Inst::Push(w, Operand::Reg(reg)));
+                }
+                let (get, put) = self.callbacks();
+                match self.mode {
+                    X86Mode::Amd64 => {
+                        self.emit(Inst::Mov(w, Operand::Reg(Reg::Di), tape));
+                        self.emit(Inst::Mov(w, Operand::Reg(Reg::Si), get));
+                        self.emit(Inst::Mov(w, Operand::Reg(Reg::Dx), put));
+                    },
+                    X86Mode::X86 => {
+                        let args = 4 * (self.saved_regs().len() as i32 + 1);
+                        self.emit(Inst::Mov(w, Operand::mem(Reg::Sp, args), tape));
+                        self.emit(Inst::Mov(w, Operand::mem(Reg::Sp, args + 4), get));
+                        self.emit(Inst::Mov(w, Operand::mem(Reg::Sp, args + 8), put));
+                    },
+                }
+            },
+            Linkage::Jit => {
+                // Two pushes plus the return address: 8 more bytes keep the
+                // stack aligned to 16 bytes.
+                self.emit(Inst::Push(w, Operand::Reg(Reg::Bx)));
+                self.emit(Inst::Push(w, Operand::Reg(Reg::R12)));
+                self.emit(Inst::Sub(w, Operand::Imm(8), Operand::Reg(Reg::Sp)));
+                self.emit(Inst::Mov(w, Operand::Reg(Reg::Di), tape));
+                self.emit(Inst::Mov(w, Operand::Reg(Reg::Si), Operand::Reg(Reg::R12)));
+            },
+        }
+    }
+
+    fn epilogue(&mut self) {
+        self.exit();
+        let buffered = self.buffered();
+        if buffered && (self.puts || self.gets) {
+            self.flush();
+        }
+        if buffered && self.puts {
+            self.put();
+        }
+        if buffered && self.gets {
+            self.get();
+        }
+        // `_flush` is also called before reading.
+        if self.puts || (buffered && self.gets) {
+            self.io_failed(WRITE_FAILED, "Could not write the output.\n");
+        }
+        if self.gets {
+            self.io_failed(READ_FAILED, "Could not read the input.\n");
+        }
+        if self.fails {
+            self.fail();
+        }
+    }
+
+    fn exit(&mut self) {
+        let w = self.word();
+        match self.linkage {
+            Linkage::Start => {
+                if self.buffered() && self.puts {
+                    self.emit(Inst::CallLabel(String::from(FLUSH)));
+                }
+                let exit = self.mode.sys_exit();
+                if self.config.exit_cell {
+                    let status = match self.mode {
+                        X86Mode::Amd64 => Reg::Di,
+                        X86Mode::X86 => Reg::Bx,
+                    };
+                    let cell = self.cell(0);
+                    self.load_low(cell, status);
+                    self.syscall(&[Operand::Imm(exit), Operand::Reg(status)]);
+                } else {
+                    self.syscall(&[Operand::Imm(exit), Operand::Imm(0)]);
+                }
+                if self.growable() {
+                    self.tape_grow();
+                }
+            },
+            Linkage::Function => {
+                let tape = self.tape();
+                self.emit(Inst::Mov(w, tape, Operand::Reg(Reg::Ax)));
+                for &reg in self.saved_regs().iter().rev() {
+                    self.emit(Inst::Pop(w, Operand::Reg(reg)));
+                }
+                self.emit(Inst::Ret);
+            },
+            Linkage::Jit => {
+                let tape = self.tape();
+                self.emit(Inst::Label(String::from(JIT_EXIT)));
+                self.emit(Inst::Mov(w, tape, Operand::Reg(Reg::Ax)));
+                self.emit(Inst::Add(w, Operand::Imm(8), Operand::Reg(Reg::Sp)));
+                self.emit(Inst::Pop(w, Operand::Reg(Reg::R12)));
+                self.emit(Inst::Pop(w, Operand::Reg(Reg::Bx)));
+                self.emit(Inst::Ret);
+            },
+        }
+    }
+
+    /// Grows a checked tape if the cell at `offset` is out of it, through
+    /// the JIT's `bounds` hook or `_tape_grow`, or reports the error if the
+    /// tape cannot grow. The comparison of its address against the bound
+    /// must have been emitted already, and `ok` is the condition under which
+    /// the cell is fine.
+    fn bounds(&mut self, ok: Cond, offset: i64, loc: &Location) {
+        if self.linkage == Linkage::Start && !self.growable() {
+            self.out_of_bounds(ok, loc);
+            return;
+        }
+        let w = self.word();
+        let tape = self.tape();
+        let ctx = Operand::Reg(Reg::R12);
+        let label = self.labels.fresh(loc, "bounds_ok");
+        self.emit(Inst::Jcc(ok, label.clone()));
+        if self.linkage != Linkage::Jit {
+            let addr = self.cell_addr(offset);
+            self.syscall_arg(addr, Reg::Di);
+            self.emit(Inst::CallLabel(String::from(TAPE_GROW)));
+            self.emit(Inst::Label(label));
+            return;
+        }
+        self.emit(Inst::Mov(w, ctx, Operand::Reg(Reg::Di)));
+        let addr = self.cell_addr(offset);
+        self.syscall_arg(addr, Reg::Si);
+        // The message follows a call to the code after it, which pushes its
+        // address.
+        let message = fail_message(BOUNDS_EXIT, &bounds_message(loc));
+        let after = self.labels.fresh(loc, "bounds_message");
+        self.emit(Inst::CallLabel(after.clone()));
+        self.emit(Inst::Data(message));
+        self.emit(Inst::Label(after));
+        self.emit(Inst::Pop(w, Operand::Reg(Reg::Dx)));
+        self.emit(Inst::Call(Operand::mem(Reg::R12, JIT_BOUNDS)));
+        self.emit(Inst::Mov(w, Operand::Reg(Reg::Ax), tape));
+        self.emit(Inst::Cmp(w, Operand::Imm(0), tape));
+        self.emit(Inst::Jcc(Cond::E, String::from(JIT_EXIT)));
+        if offset != 0 {
+            self.emit(Inst::Sub(w, Operand::Imm(offset * self.stride()), tape));
+        }
+        self.emit(Inst::Label(label));
+    }
+
+    fn next(&mut self, n: i64, loc: &Location) {
+        let w = self.word();
+        let tape = self.tape();
+        let amount = Operand::Imm(n * self.stride());
+        match self.linkage {
+            Linkage::Start if self.on_stack() => {
+                let start = self.labels.fresh(loc, "check_esp_start");
+                let end = self.labels.fresh(loc, "check_esp_end");
+                self.emit(Inst::Sub(w, amount, tape));
+                if self.config.checked {
+                    self.emit(Inst::Cmp(w, self.stack_limit(), tape));
+                    self.out_of_bounds(Cond::Ae, loc);
+                }
+                self.emit(Inst::Jmp(end.clone()));
+                self.emit(Inst::Label(start.clone()));
+                self.emit(Inst::Push(w, Operand::Imm(0)));
+                self.emit(Inst::Label(end));
+                self.emit(Inst::Cmp(w, tape, Operand::Reg(Reg::Sp)));
+                self.emit(Inst::Jcc(Cond::Ae, start));
+            },
+            _ => {
+                self.emit(Inst::Add(w, amount, tape));
+                if self.checked() {
+                    self.emit(Inst::Cmp(w, self.bound(true), tape));
+                    self.bounds(Cond::B, 0, loc);
+                }
+            },
+        }
+    }
+
+    fn previous(&mut self, n: i64, loc: &Location) {
+        let w = self.word();
+        let tape = self.tape();
+        let amount = Operand::Imm(n * self.stride());
+        match self.linkage {
+            Linkage::Start if self.on_stack() => {
+                self.emit(Inst::Add(w, amount, tape));
+                if self.config.checked {
+                    self.emit(Inst::Cmp(w, Operand::Reg(Reg::Bp), tape));
+                    self.out_of_bounds(Cond::Be, loc);
+                }
+            },
+            _ => {
+                self.emit(Inst::Sub(w, amount, tape));
+                if self.checked() {
+                    self.emit(Inst::Cmp(w, self.bound(false), tape));
+                    self.bounds(Cond::Ae, 0, loc);
+                }
+            },
+        }
+    }
+
+    /// The address of a cell, as passed to system calls.
+    fn cell_addr(&self, offset: i64) -> Operand {
+        if offset == 0 {self.tape()} else {self.cell(offset)}
+    }
+
+    /// Adds to an unsigned cell, saturating or trapping on overflow, which
+    /// the carry flag tells in both directions.
+    fn add_checked(&mut self, offset: i64, amount: i64, loc: &Location) {
+        let cw = self.cw();
+        let cell = self.cell(offset);
+        let mask = self.config.cell_size.mask();
+        let magnitude = amount.unsigned_abs();
+        let limit = if amount < 0 {0} else {mask as i64};
+        if magnitude == 0 {
+            return;
+        }
+        if self.pair() {
+            self.add_pair(cell, magnitude, amount < 0);
+            let ok = self.labels.fresh(loc, "add_ok");
+            self.emit(Inst::Jcc(Cond::Ae, ok.clone()));
+            self.overflow(cell, limit, loc);
+            self.emit(Inst::Label(ok));
+        } else if magnitude <= mask {
+            let imm = self.imm(magnitude as i64);
+            if amount < 0 {
+                self.emit(Inst::Sub(cw, imm, cell));
+            } else {
+                self.emit(Inst::Add(cw, imm, cell));
+            }
+            let ok = self.labels.fresh(loc, "add_ok");
+            self.emit(Inst::Jcc(Cond::Ae, ok.clone()));
+            self.overflow(cell, limit, loc);
+            self.emit(Inst::Label(ok));
+        } else {
+            self.overflow(cell, limit, loc);
+        }
+    }
+
+    /// Handles an overflowing cell, which saturates to `limit` or traps.
+    fn overflow(&mut self, cell: Operand, limit: i64, loc: &Location) {
+        if self.config.overflow == Overflow::Saturate {
+            self.store_imm(cell, limit);
+        } else {
+            let message = format!("Cell overflow {}.\n", loc);
+            self.report(OVERFLOW_EXIT, message);
+        }
+    }
+
+    /// Generates the instructions for the whole program.
+    pub fn generate(mut self, ir: Vec<Node<Ir>>) -> Vec<Inst> {
+        self.prologue();
+        walk(&mut self, ir);
+        self.epilogue();
+        self.insts
+    }
+
+}
+
+impl Emitter for Codegen {
+
+    /// The labels of the start and of the end of the loop.
+    type Loop = (String, String);
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Makes sure the cell at `offset` from the pointer is part of the tape
+    /// before it is accessed, growing the tape if needed. Cells before the
+    /// start of the tape are only detected by checked tapes, and the guard
+    /// pages of a heap tape fault on cells past either end.
+    fn reach(&mut self, offset: i64, loc: &Location) {
+        let w = self.word();
+        let addr = self.cell_addr(offset);
+        let ax = Operand::Reg(Reg::Ax);
+        if self.on_stack() && offset > 0 {
+            let start = self.labels.fresh(loc, "reach_start");
+            let end = self.labels.fresh(loc, "reach_end");
+            self.emit(Inst::Lea(w, addr, ax));
+            if self.config.checked {
+                self.emit(Inst::Cmp(w, self.stack_limit(), ax));
+                self.out_of_bounds(Cond::Ae, loc);
+            }
+            self.emit(Inst::Jmp(end.clone()));
+            self.emit(Inst::Label(start.clone()));
+            self.emit(Inst::Push(w, Operand::Imm(0)));
+            self.emit(Inst::Label(end));
+            self.emit(Inst::Cmp(w, ax, Operand::Reg(Reg::Sp)));
+            self.emit(Inst::Jcc(Cond::Ae, start));
+        } else if self.on_stack() && self.config.checked && offset < 0 {
+            self.emit(Inst::Lea(w, addr, ax));
+            self.emit(Inst::Cmp(w, Operand::Reg(Reg::Bp), ax));
+            self.out_of_bounds(Cond::Be, loc);
+        } else if self.checked() && offset != 0 {
+            self.emit(Inst::Lea(w, addr, ax));
+            if offset > 0 {
+                self.emit(Inst::Cmp(w, self.bound(true), ax));
+                self.bounds(Cond::B, offset, loc);
+            } else {
+                self.emit(Inst::Cmp(w, self.bound(false), ax));
+                self.bounds(Cond::Ae, offset, loc);
+            }
+        }
+    }
+
+    /// Moves the pointer without checking the tape.
+    fn shift(&mut self, n: i64, _loc: &Location) {
+        let w = self.word();
+        let tape = self.tape();
+        let delta = if self.on_stack() {
+            -self.stride() * n
+        } else {
+            self.stride() * n
+        };
+        if delta > 0 {
+            self.emit(Inst::Add(w, Operand::Imm(delta), tape));
+        } else if delta < 0 {
+            self.emit(Inst::Sub(w, Operand::Imm(-delta), tape));
+        }
+    }
+
+    fn move_by(&mut self, n: i64, loc: &Location) {
+        if n > 0 {
+            self.next(n, loc);
+        } else if n < 0 {
+            self.previous(-n, loc);
+        }
+    }
+
+    /// Moves by `n` cells until a zero cell is found. Single steps use
+    /// `repne scas`, bounded by the end of the tape when the tape is known,
+    /// and other strides, or cells made of two words, a loop around a move.
+    fn scan(&mut self, n: i64, loc: &Location) {
+        if n != 1 && n != -1 || self.pair() {
+            let body = self.labels.fresh(loc, "scan_body");
+            let test = self.labels.fresh(loc, "scan_test");
+            self.emit(Inst::Jmp(test.clone()));
+            self.emit(Inst::Label(body.clone()));
+            self.move_by(n, loc);
+            self.emit(Inst::Label(test));
+            self.test_zero();
+            self.emit(Inst::Jcc(Cond::Ne, body));
+            return;
+        }
+        let w = self.word();
+        let tape = self.tape();
+        let di = Operand::Reg(Reg::Di);
+        let cx = Operand::Reg(Reg::Cx);
+        // Whether the scan goes towards lower addresses.
+        let down = (n == 1) == self.on_stack();
+        let bounded = self.checked()
+            || (self.on_stack() && (n == 1 || self.config.checked));
+        let saves_di = self.linkage == Linkage::Function && self.mode == X86Mode::X86;
+        if saves_di {
+            self.emit(Inst::Push(w, di));
+        }
+        self.emit(Inst::Mov(w, tape, di));
+        // The addresses the scan goes from and to.
+        let (from, to) = match (self.on_stack(), down) {
+            (true, true) => (tape, Operand::Reg(Reg::Sp)),
+            (true, false) => (Operand::Reg(Reg::Bp), tape),
+            (false, true) => (tape, self.bound(false)),
+            (false, false) => (self.bound(true), tape),
+        };
+        if bounded {
+            // The number of cells from the current one to the bound, which
+            // is included unless it is the end of a tape in memory.
+            self.emit(Inst::Mov(w, from, cx));
+            self.emit(Inst::Sub(w, to, cx));
+            let shift = self.stride().trailing_zeros() as u8;
+            if shift > 0 {
+                self.emit(Inst::Shr(w, shift, cx));
+            }
+            if down || self.on_stack() {
+                self.emit(Inst::Add(w, Operand::Imm(1), cx));
+            }
+        } else {
+            self.emit(Inst::Mov(w, Operand::Imm(-1), cx));
+        }
+        self.emit(Inst::Mov(Width::Long, Operand::Imm(0), Operand::Reg(Reg::Ax)));
+        if down {
+            self.emit(Inst::Std);
+        }
+        self.emit(Inst::RepneScas(self.cw()));
+        if down {
+            self.emit(Inst::Cld);
+        }
+        let not_found = self.labels.fresh(loc, "scan_not_found");
+        let done = self.labels.fresh(loc, "scan_done");
+        if bounded {
+            self.emit(Inst::Jcc(Cond::Ne, not_found.clone()));
+        }
+        // The string instruction steps past the zero cell.
+        let back = if down {self.stride()} else {-self.stride()};
+        self.emit(Inst::Lea(w, Operand::mem(Reg::Di, back as i32), tape));
+        if saves_di {
+            self.emit(Inst::Pop(w, di));
+        }
+        if !bounded {
+            return;
+        }
+        self.emit(Inst::Jmp(done.clone()));
+        self.emit(Inst::Label(not_found));
+        if self.on_stack() && down {
+            // The scan went through the whole tape, so the next cell is a
+            // new one, which is zero.
+            self.emit(Inst::Push(w, Operand::Imm(0)));
+            self.emit(Inst::Mov(w, di, tape));
+            if self.config.checked {
+                self.emit(Inst::Cmp(w, self.stack_limit(), tape));
+                self.out_of_bounds(Cond::Ae, loc);
+            }
+        } else if self.on_stack() {
+            // The scan went past the first cell.
+            self.report_bounds(loc);
+        } else {
+            // The pointer is just out of the tape, which is grown, or the
+            // error is reported by the hook.
+            self.emit(Inst::Mov(w, di, tape));
+            if down {
+                self.emit(Inst::Cmp(w, self.bound(false), tape));
+                self.bounds(Cond::Ae, 0, loc);
+            } else {
+                self.emit(Inst::Cmp(w, self.bound(true), tape));
+                self.bounds(Cond::B, 0, loc);
+            }
+        }
+        self.emit(Inst::Label(done));
+    }
+
+    fn put_char(&mut self, offset: i64, _loc: &Location) {
+        let w = self.word();
+        let cell = self.cell(offset);
+        match (self.linkage, self.mode) {
+            (Linkage::Start, _) if self.buffered() => {
+                self.load_low(cell, Reg::Ax);
+                self.emit(Inst::CallLabel(String::from(PUT)));
+                self.puts = true;
+            },
+            (Linkage::Start, _) => {
+                let write = self.mode.sys_write();
+                let addr = self.cell_addr(offset);
+                self.syscall(&[
+                    Operand::Imm(write),
+                    Operand::Imm(1),
+                    addr,
+                    Operand::Imm(1),
+                ]);
+                self.emit(Inst::Cmp(w, Operand::Imm(1), Operand::Reg(Reg::Ax)));
+                self.emit(Inst::Jcc(Cond::Ne, String::from(WRITE_FAILED)));
+                self.puts = true;
+            },
+            (Linkage::Function, X86Mode::Amd64) => {
+                let (_, put) = self.callbacks();
+                self.load_low(cell, Reg::Di);
+                self.emit(Inst::Call(put));
+            },
+            (Linkage::Function, X86Mode::X86) => {
+                // Keeps the stack aligned to 16 bytes at the call.
+                let (_, put) = self.callbacks();
+                let sp = Operand::Reg(Reg::Sp);
+                self.load_low(cell, Reg::Ax);
+                self.emit(Inst::Sub(w, Operand::Imm(12), sp));
+                self.emit(Inst::Push(w, Operand::Reg(Reg::Ax)));
+                self.emit(Inst::Call(put));
+                self.emit(Inst::Add(w, Operand::Imm(16), sp));
+            },
+            (Linkage::Jit, _) => {
+                self.load_low(cell, Reg::Si);
+                self.emit(Inst::Mov(w, Operand::Reg(Reg::R12), Operand::Reg(Reg::Di)));
+                self.emit(Inst::Call(Operand::mem(Reg::R12, JIT_PUT)));
+                self.emit(Inst::Cmp(Width::Long, Operand::Imm(0), Operand::Reg(Reg::Ax)));
+                self.emit(Inst::Jcc(Cond::L, String::from(JIT_EXIT)));
+            },
+        }
+    }
+
+    fn get_char(&mut self, offset: i64, loc: &Location) {
+        let w = self.word();
+        let cw = self.cw();
+        let cell = self.cell(offset);
+        let ax = Operand::Reg(Reg::Ax);
+        match self.linkage {
+            Linkage::Start if !self.buffered() => {
+                let end = self.labels.fresh(loc, "getc_end");
+                let read = self.mode.sys_read();
+                let addr = self.cell_addr(offset);
+                // The byte read only fills the lowest byte of the cell.
+                if self.config.eof != Eof::Unchanged {
+                    self.store_imm(cell, 0);
+                }
+                self.syscall(&[
+                    Operand::Imm(read),
+                    Operand::Imm(0),
+                    addr,
+                    Operand::Imm(1),
+                ]);
+                self.emit(Inst::Cmp(w, Operand::Imm(0), ax));
+                self.emit(Inst::Jcc(Cond::L, String::from(READ_FAILED)));
+                self.gets = true;
+                match self.config.eof {
+                    Eof::MinusOne => {
+                        self.emit(Inst::Cmp(w, Operand::Imm(1), ax));
+                        self.emit(Inst::Jcc(Cond::E, end.clone()));
+                        self.store_imm(cell, -1);
+                    },
+                    Eof::Zero => (),
+                    Eof::Unchanged => if cw != Width::Byte {
+                        self.emit(Inst::Cmp(w, Operand::Imm(1), ax));
+                        self.emit(Inst::Jcc(Cond::Ne, end.clone()));
+                        self.emit(Inst::Movzx(Width::Byte, Width::Long, cell, ax));
+                        if self.pair() {
+                            self.emit(Inst::Mov(Width::Long, ax, cell));
+                            self.emit(Inst::Mov(Width::Long, Operand::Imm(0), high(cell)));
+                        } else {
+                            self.emit(Inst::Mov(cw, ax, cell));
+                        }
+                    },
+                }
+                self.emit(Inst::Label(end));
+            },
+            _ => {
+                match self.linkage {
+                    Linkage::Start => {
+                        self.emit(Inst::CallLabel(String::from(GET)));
+                        self.gets = true;
+                    },
+                    Linkage::Function => {
+                        let (get, _) = self.callbacks();
+                        self.emit(Inst::Call(get));
+                    },
+                    Linkage::Jit => {
+                        self.emit(Inst::Mov(w, Operand::Reg(Reg::R12), Operand::Reg(Reg::Di)));
+                        self.emit(Inst::Call(Operand::mem(Reg::R12, JIT_GET)));
+                        self.emit(Inst::Cmp(Width::Long, Operand::Imm(-1), ax));
+                        self.emit(Inst::Jcc(Cond::L, String::from(JIT_EXIT)));
+                    },
+                }
+                let end = self.labels.fresh(loc, "getc_end");
+                match self.config.eof {
+                    Eof::MinusOne => (),
+                    Eof::Zero => {
+                        self.emit(Inst::Cmp(Width::Long, Operand::Imm(0), ax));
+                        self.emit(Inst::Jcc(Cond::Ge, end.clone()));
+                        self.emit(Inst::Mov(Width::Long, Operand::Imm(0), ax));
+                        self.emit(Inst::Label(end.clone()));
+                    },
+                    Eof::Unchanged => {
+                        self.emit(Inst::Cmp(Width::Long, Operand::Imm(0), ax));
+                        self.emit(Inst::Jcc(Cond::L, end.clone()));
+                    },
+                }
+                self.store_int(cell);
+                if self.config.eof == Eof::Unchanged {
+                    self.emit(Inst::Label(end));
+                }
+            },
+        }
+    }
+
+    fn add(&mut self, offset: i64, amount: i64, loc: &Location) {
+        if self.config.overflow != Overflow::Wrap {
+            self.add_checked(offset, amount, loc);
+            return;
+        }
+        let cw = self.cw();
+        let cell = self.cell(offset);
+        let amount = self.config.cell_size.wrap(amount);
+        if self.pair() {
+            if amount < 0 && amount != i64::MIN {
+                self.add_pair(cell, amount.unsigned_abs(), true);
+            } else if amount != 0 {
+                self.add_pair(cell, amount as u64, false);
+            }
+        } else if amount < 0 && amount != i64::MIN {
+            let imm = self.imm(-amount);
+            self.emit(Inst::Sub(cw, imm, cell));
+        } else if amount != 0 {
+            let imm = self.imm(amount);
+            self.emit(Inst::Add(cw, imm, cell));
+        }
+    }
+
+    fn set(&mut self, offset: i64, value: i64, _loc: &Location) {
+        let cell = self.cell(offset);
+        self.store_imm(cell, value);
+    }
+
+    fn mul(&mut self, src: i64, dst: i64, factor: i64, _loc: &Location) {
+        let cw = self.cw();
+        let src = self.cell(src);
+        let dst = self.cell(dst);
+        let ax = Operand::Reg(Reg::Ax);
+        let factor = self.config.cell_size.wrap(factor);
+        if factor == 0 {
+            return;
+        }
+        if self.pair() {
+            self.mul_pair(src, dst, factor);
+            return;
+        }
+        if cw == Width::Quad {
+            self.emit(Inst::Mov(cw, src, ax));
+        } else {
+            self.load_low(src, Reg::Ax);
+        }
+        match factor {
+            1 => self.emit(Inst::Add(cw, ax, dst)),
+            -1 => self.emit(Inst::Sub(cw, ax, dst)),
+            _ => {
+                let mw = if cw == Width::Quad {Width::Quad} else {Width::Long};
+                match self.imm(factor) {
+                    Operand::Imm(factor) =>
+                        self.emit(Inst::Imul(mw, factor, ax, ax)),
+                    factor => self.emit(Inst::ImulReg(mw, factor, ax)),
+                }
+                self.emit(Inst::Add(cw, ax, dst));
+            },
+        }
+    }
+
+    fn open_loop(&mut self, loc: &Location) -> (String, String) {
+        let label = label_for(loc);
+        let start = label.clone() + "_loop_start";
+        let end = label + "_loop_end";
+        self.emit(Inst::Jmp(end.clone()));
+        self.emit(Inst::Label(start.clone()));
+        (start, end)
+    }
+
+    fn close_loop(&mut self, (start, end): (String, String)) {
+        self.emit(Inst::Label(end));
+        self.test_zero();
+        self.emit(Inst::Jcc(Cond::Ne, start));
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        Codegen,
+        Linkage,
+    };
+    use super::super::{
+        X86Mode,
+    };
+    use super::super::inst::{
+        Inst,
+        Operand,
+        Mem,
+        Reg,
+        Width,
+    };
+    use back_end::{
+        Emitter,
+    };
+    use front_end::{
+        Location,
+    };
+    use config::{
+        CellSize,
+        Config,
+    };
+
+    /// Runs the arithmetic emitted for 64-bit cells on x86, with the tape
+    /// pointer at the first of `words`.
+    struct Machine {
+        regs: [u32; 3],
+        carry: bool,
+        words: Vec<u32>,
+    }
+
+    impl Machine {
+
+        fn slot(&mut self, op: Operand) -> &mut u32 {
+            match op {
+                Operand::Reg(Reg::Ax) => &mut self.regs[0],
+                Operand::Reg(Reg::Cx) => &mut self.regs[1],
+                Operand::Reg(Reg::Dx) => &mut self.regs[2],
+                Operand::Mem(Mem {base: Reg::Si, disp}) => &mut self.words[disp as usize / 4],
+                _ => panic!("{:?}", op),
+            }
+        }
+
+        fn get(&mut self, op: Operand) -> u32 {
+            match op {
+                Operand::Imm(n) => n as u32,
+                _ => *self.slot(op),
+            }
+        }
+
+        fn run(&mut self, insts: &[Inst]) {
+            for inst in insts {
+                match *inst {
+                    Inst::Mov(Width::Long, src, dst) => *self.slot(dst) = self.get(src),
+                    Inst::Or(Width::Long, src, dst) => *self.slot(dst) |= self.get(src),
+                    Inst::Add(Width::Long, src, dst) | Inst::Adc(Width::Long, src, dst) => {
+                        let carry = matches!(*inst, Inst::Adc(..)) && self.carry;
+                        let sum = self.get(dst) as u64 + self.get(src) as u64 + carry as u64;
+                        *self.slot(dst) = sum as u32;
+                        self.carry = sum >> 32 != 0;
+                    },
+                    Inst::Sub(Width::Long, src, dst) | Inst::Sbb(Width::Long, src, dst) => {
+                        let borrow = matches!(*inst, Inst::Sbb(..)) && self.carry;
+                        let (a, b) = (self.get(dst) as u64, self.get(src) as u64);
+                        *self.slot(dst) = a.wrapping_sub(b + borrow as u64) as u32;
+                        self.carry = a < b + borrow as u64;
+                    },
+                    Inst::Imul(Width::Long, factor, src, dst) =>
+                        *self.slot(dst) = self.get(src).wrapping_mul(factor as u32),
+                    Inst::Mul(Width::Long, src) => {
+                        let product = self.regs[0] as u64 * self.get(src) as u64;
+                        self.regs[0] = product as u32;
+                        self.regs[2] = (product >> 32) as u32;
+                    },
+                    _ => panic!("{:?}", inst),
+                }
+            }
+        }
+
+        fn cell(&self, index: usize) -> u64 {
+            self.words[2 * index] as u64 | (self.words[2 * index + 1] as u64) << 32
+        }
+
+    }
+
+    /// Emits an operation on a tape whose first two cells are `cells`,
+    /// runs it and gives the cells after it.
+    fn run<F: Fn(&mut Codegen, &Location)>(cells: [u64; 2], emit: F) -> [u64; 2] {
+        let config = Config {cell_size: CellSize::Bits64, ..Config::default()};
+        let mut codegen = Codegen::new(X86Mode::X86, Linkage::Function, &config);
+        let loc = Location {file: String::from("test.bf"), line: 1, column: 1};
+        emit(&mut codegen, &loc);
+        let mut words = Vec::new();
+        for &cell in &cells {
+            words.push(cell as u32);
+            words.push((cell >> 32) as u32);
+        }
+        let mut machine = Machine {regs: [0xAAAA_AAAA; 3], carry: true, words};
+        machine.run(&codegen.insts);
+        [machine.cell(0), machine.cell(1)]
+    }
+
+    const VALUES: [u64; 6] = [
+        0,
+        1,
+        0xFFFF_FFFF,
+        0x1_0000_0000,
+        0xDEAD_BEEF_0123_4567,
+        u64::MAX,
+    ];
+
+    #[test]
+    fn pair_addition() {
+        let amounts = [
+            1,
+            -1,
+            0xFFFF_FFFF,
+            -0x1_0000_0000,
+            0x1234_5678_9ABC,
+            i64::MIN,
+            i64::MAX,
+        ];
+        for &value in &VALUES {
+            for &amount in &amounts {
+                let cells = run([value, 7], |codegen, loc| codegen.add(0, amount, loc));
+                assert_eq!(cells, [value.wrapping_add(amount as u64), 7], "{} {}", value, amount);
+            }
+        }
+    }
+
+    #[test]
+    fn pair_multiplication() {
+        let factors = [
+            1,
+            -1,
+            2,
+            -3,
+            0x8000_0000,
+            0x1_0000_0000,
+            0x1_0000_0003,
+            -0x1_0000_0001,
+            i64::MIN,
+            i64::MAX,
+        ];
+        for &src in &VALUES {
+            for &dst in &VALUES {
+                for &factor in &factors {
+                    let cells = run([src, dst], |codegen, loc| codegen.mul(0, 1, factor, loc));
+                    let product = dst.wrapping_add(src.wrapping_mul(factor as u64));
+                    assert_eq!(cells, [src, product], "{} {} {}", src, dst, factor);
+                }
+            }
+        }
+    }
+
+}
@@ -0,0 +1,834 @@
+use std::io::{
+    Error,
+    ErrorKind,
+};
+use std::collections::{
+    HashMap,
+};
+use std::{
+    mem,
+};
+use super::{
+    X86Mode,
+};
+use super::inst::{
+    Inst,
+    Operand,
+    Mem,
+    Reg,
+    Width,
+    Cond,
+};
+
+
+/// Machine code produced from a list of instructions, together with the
+/// offset of every label defined by it.
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+}
+
+/// A piece of encoded code. Jumps are kept apart from the rest, since their
+/// size depends on how far their target ends up.
+#[derive(Clone, Debug)]
+enum Fragment {
+    Code(Vec<u8>),
+    Label(String),
+    Jump {
+        cond: Option<Cond>,
+        label: String,
+        near: bool,
+    },
+    /// A direct call, which always takes a 32-bit displacement.
+    Call(String),
+}
+
+impl Fragment {
+
+    fn size(&self) -> usize {
+        match *self {
+            Fragment::Code(ref code) => code.len(),
+            Fragment::Label(_) => 0,
+            Fragment::Jump {cond, near, ..} => jump_size(cond, near),
+            Fragment::Call(_) => 5,
+        }
+    }
+
+}
+
+fn jump_size(cond: Option<Cond>, near: bool) -> usize {
+    match (cond, near) {
+        (_, false) => 2,
+        (None, true) => 5,
+        (Some(_), true) => 6,
+    }
+}
+
+struct Encoder {
+    mode: X86Mode,
+    code: Vec<u8>,
+    frags: Vec<Fragment>,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn fits_i8(n: i64) -> bool {
+    n >= i8::MIN as i64 && n <= i8::MAX as i64
+}
+
+fn fits_i32(n: i64) -> bool {
+    n >= i32::MIN as i64 && n <= i32::MAX as i64
+}
+
+impl Encoder {
+
+    fn byte(&mut self, b: u8) {
+        self.code.push(b);
+    }
+
+    fn imm(&mut self, width: Width, n: i64) -> Result<(), Error> {
+        let (min, max) = match width {
+            Width::Byte => (i8::MIN as i64, u8::MAX as i64),
+            Width::Word => (i16::MIN as i64, u16::MAX as i64),
+            Width::Long => (i32::MIN as i64, u32::MAX as i64),
+            Width::Quad => (i32::MIN as i64, i32::MAX as i64),
+        };
+        if n < min || n > max {
+            return Err(invalid(format!(
+                "Immediate {} does not fit in {} bytes.",
+                n,
+                width.bytes()
+            )));
+        }
+        let size = if width == Width::Quad {4} else {width.bytes() as usize};
+        let bytes = (n as u64).to_le_bytes();
+        self.code.extend_from_slice(&bytes[.. size]);
+        Ok(())
+    }
+
+    /// Emits the operand size prefix, the REX prefix if needed, the opcode,
+    /// and the ModRM (plus SIB and displacement) for a register or memory
+    /// operand `rm`. `reg` is either a register code or an opcode extension.
+    fn modrm(
+        &mut self,
+        width: Width,
+        opcode: &[u8],
+        reg: u8,
+        reg_is_byte_reg: bool,
+        rm: &Operand
+    ) -> Result<(), Error> {
+        if width == Width::Word {
+            self.byte(0x66);
+        }
+        let rm_code = match *rm {
+            Operand::Reg(r) => r.code(),
+            Operand::Mem(Mem {base, ..}) => base.code(),
+            Operand::Imm(_) => return Err(invalid(String::from(
+                "Immediate used where a register or memory operand is required."
+            ))),
+        };
+        let mut rex = 0;
+        if width == Width::Quad {
+            rex |= 0x48;
+        }
+        if reg >= 8 {
+            rex |= 0x44;
+        }
+        if rm_code >= 8 {
+            rex |= 0x41;
+        }
+        if width == Width::Byte {
+            let rm_byte_reg = match *rm {
+                Operand::Reg(r) => r.code() >= 4,
+                _ => false,
+            };
+            if rm_byte_reg || (reg_is_byte_reg && reg >= 4) {
+                rex |= 0x40;
+            }
+        }
+        if rex != 0 {
+            if self.mode == X86Mode::X86 {
+                return Err(invalid(String::from(
+                    "Operand not encodable in 32-bit mode."
+                )));
+            }
+            self.byte(rex);
+        }
+        self.code.extend_from_slice(opcode);
+        let reg = (reg & 7) << 3;
+        match *rm {
+            Operand::Reg(r) => self.byte(0xC0 | reg | (r.code() & 7)),
+            Operand::Mem(Mem {base, disp}) => {
+                let base_code = base.code() & 7;
+                let (md, disp_size) = if disp == 0 && base_code != 5 {
+                    (0x00, 0)
+                } else if fits_i8(disp as i64) {
+                    (0x40, 1)
+                } else {
+                    (0x80, 4)
+                };
+                self.byte(md | reg | base_code);
+                if base_code == 4 {
+                    self.byte(0x24);
+                }
+                let bytes = disp.to_le_bytes();
+                self.code.extend_from_slice(&bytes[.. disp_size]);
+            },
+            Operand::Imm(_) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Encodes `add`, `sub`, `cmp` and the other classic ALU operations,
+    /// given the operation number used in their opcodes.
+    fn alu(
+        &mut self,
+        op: u8,
+        width: Width,
+        src: &Operand,
+        dst: &Operand
+    ) -> Result<(), Error> {
+        let byte = width == Width::Byte;
+        match (*src, *dst) {
+            (Operand::Imm(n), _) => if byte && *dst == Operand::Reg(Reg::Ax) {
+                self.byte(op << 3 | 0x04);
+                self.imm(width, n)
+            } else if byte {
+                self.modrm(width, &[0x80], op, false, dst)?;
+                self.imm(width, n)
+            } else if fits_i8(n) {
+                self.modrm(width, &[0x83], op, false, dst)?;
+                self.imm(Width::Byte, n)
+            } else if *dst == Operand::Reg(Reg::Ax) {
+                // The short form for the accumulator, as assemblers prefer.
+                match width {
+                    Width::Word => self.byte(0x66),
+                    Width::Quad => self.byte(0x48),
+                    _ => (),
+                }
+                self.byte(op << 3 | 0x05);
+                self.imm(width, n)
+            } else {
+                self.modrm(width, &[0x81], op, false, dst)?;
+                self.imm(width, n)
+            },
+            (Operand::Reg(r), _) => {
+                let opcode = op << 3 | if byte {0x00} else {0x01};
+                self.modrm(width, &[opcode], r.code(), true, dst)
+            },
+            (Operand::Mem(_), Operand::Reg(r)) => {
+                let opcode = op << 3 | if byte {0x02} else {0x03};
+                self.modrm(width, &[opcode], r.code(), true, src)
+            },
+            _ => Err(invalid(String::from(
+                "Instruction cannot have two memory operands."
+            ))),
+        }
+    }
+
+    fn mov(
+        &mut self,
+        width: Width,
+        src: &Operand,
+        dst: &Operand
+    ) -> Result<(), Error> {
+        let byte = width == Width::Byte;
+        match (*src, *dst) {
+            (Operand::Imm(n), Operand::Reg(r)) => if width == Width::Quad {
+                if fits_i32(n) {
+                    self.modrm(width, &[0xC7], 0, false, dst)?;
+                    self.imm(Width::Long, n)
+                } else {
+                    self.byte(if r.code() >= 8 {0x49} else {0x48});
+                    self.byte(0xB8 | (r.code() & 7));
+                    self.code.extend_from_slice(&n.to_le_bytes());
+                    Ok(())
+                }
+            } else {
+                let opcode = if byte {0xB0} else {0xB8};
+                if width == Width::Word {
+                    self.byte(0x66);
+                }
+                if r.code() >= 8 || (byte && r.code() >= 4) {
+                    if self.mode == X86Mode::X86 {
+                        return Err(invalid(String::from(
+                            "Operand not encodable in 32-bit mode."
+                        )));
+                    }
+                    self.byte(if r.code() >= 8 {0x41} else {0x40});
+                }
+                self.byte(opcode | (r.code() & 7));
+                self.imm(width, n)
+            },
+            (Operand::Imm(n), _) => {
+                self.modrm(width, &[if byte {0xC6} else {0xC7}], 0, false, dst)?;
+                self.imm(width, n)
+            },
+            (Operand::Reg(r), _) =>
+                self.modrm(width, &[if byte {0x88} else {0x89}], r.code(), true, dst),
+            (Operand::Mem(_), Operand::Reg(r)) =>
+                self.modrm(width, &[if byte {0x8A} else {0x8B}], r.code(), true, src),
+            _ => Err(invalid(String::from(
+                "Instruction cannot have two memory operands."
+            ))),
+        }
+    }
+
+    fn movzx(
+        &mut self,
+        from: Width,
+        to: Width,
+        src: &Operand,
+        dst: &Operand
+    ) -> Result<(), Error> {
+        let opcode = match from {
+            Width::Byte => 0xB6,
+            Width::Word => 0xB7,
+            _ => return Err(invalid(String::from(
+                "Zero extension only works from bytes and words."
+            ))),
+        };
+        match *dst {
+            Operand::Reg(r) => self.modrm(to, &[0x0F, opcode], r.code(), false, src),
+            _ => Err(invalid(String::from(
+                "Zero extension needs a register destination."
+            ))),
+        }
+    }
+
+    /// Emits an opcode which has the register number in its low bits.
+    fn opcode_reg(&mut self, opcode: u8, r: Reg) -> Result<(), Error> {
+        if r.code() >= 8 {
+            if self.mode == X86Mode::X86 {
+                return Err(invalid(String::from(
+                    "Operand not encodable in 32-bit mode."
+                )));
+            }
+            self.byte(0x41);
+        }
+        self.byte(opcode | (r.code() & 7));
+        Ok(())
+    }
+
+    fn push(&mut self, op: &Operand) -> Result<(), Error> {
+        match *op {
+            Operand::Imm(n) => if fits_i8(n) {
+                self.byte(0x6A);
+                self.imm(Width::Byte, n)
+            } else {
+                self.byte(0x68);
+                self.imm(Width::Long, n)
+            },
+            Operand::Reg(r) => self.opcode_reg(0x50, r),
+            Operand::Mem(_) => self.modrm(Width::Long, &[0xFF], 6, false, op),
+        }
+    }
+
+    fn pop(&mut self, op: &Operand) -> Result<(), Error> {
+        match *op {
+            Operand::Reg(r) => self.opcode_reg(0x58, r),
+            _ => self.modrm(Width::Long, &[0x8F], 0, false, op),
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.code.is_empty() {
+            let code = mem::take(&mut self.code);
+            self.frags.push(Fragment::Code(code));
+        }
+    }
+
+    fn jump(&mut self, cond: Option<Cond>, label: &str) {
+        self.flush();
+        self.frags.push(Fragment::Jump {
+            cond,
+            label: label.to_string(),
+            near: false,
+        });
+    }
+
+    fn call(&mut self, label: &str) {
+        self.flush();
+        self.frags.push(Fragment::Call(label.to_string()));
+    }
+
+    fn inst(&mut self, inst: &Inst) -> Result<(), Error> {
+        match *inst {
+            Inst::Label(ref label) => {
+                self.flush();
+                self.frags.push(Fragment::Label(label.clone()));
+                Ok(())
+            },
+            Inst::Mov(w, ref src, ref dst) => self.mov(w, src, dst),
+            Inst::Add(w, ref src, ref dst) => self.alu(0, w, src, dst),
+            Inst::Sub(w, ref src, ref dst) => self.alu(5, w, src, dst),
+            Inst::Adc(w, ref src, ref dst) => self.alu(2, w, src, dst),
+            Inst::Sbb(w, ref src, ref dst) => self.alu(3, w, src, dst),
+            Inst::Or(w, ref src, ref dst) => self.alu(1, w, src, dst),
+            Inst::Cmp(w, ref src, ref dst) => self.alu(7, w, src, dst),
+            Inst::Movzx(from, to, ref src, ref dst) => self.movzx(from, to, src, dst),
+            Inst::Lea(w, ref src, Operand::Reg(r)) =>
+                self.modrm(w, &[0x8D], r.code(), false, src),
+            Inst::Imul(w, factor, ref src, Operand::Reg(r)) => if fits_i8(factor) {
+                self.modrm(w, &[0x6B], r.code(), false, src)?;
+                self.imm(Width::Byte, factor)
+            } else {
+                self.modrm(w, &[0x69], r.code(), false, src)?;
+                self.imm(w, factor)
+            },
+            Inst::ImulReg(w, ref src, Operand::Reg(r)) =>
+                self.modrm(w, &[0x0F, 0xAF], r.code(), false, src),
+            Inst::Mul(w, ref op) => {
+                let opcode = if w == Width::Byte {0xF6} else {0xF7};
+                self.modrm(w, &[opcode], 4, false, op)
+            },
+            Inst::Cltd => {
+                self.byte(0x99);
+                Ok(())
+            },
+            Inst::Movsx(from, to, ref src, Operand::Reg(r)) => match from {
+                Width::Byte => self.modrm(to, &[0x0F, 0xBE], r.code(), false, src),
+                Width::Word => self.modrm(to, &[0x0F, 0xBF], r.code(), false, src),
+                _ => self.modrm(to, &[0x63], r.code(), false, src),
+            },
+            Inst::Lea(..)
+            | Inst::Imul(..)
+            | Inst::ImulReg(..)
+            | Inst::Movsx(..) => Err(invalid(String::from(
+                "Instruction needs a register destination."
+            ))),
+            Inst::Shr(w, n, ref op) => {
+                let byte = if w == Width::Byte {0} else {1};
+                if n == 1 {
+                    self.modrm(w, &[0xD0 | byte], 5, false, op)
+                } else {
+                    self.modrm(w, &[0xC0 | byte], 5, false, op)?;
+                    self.imm(Width::Byte, n as i64)
+                }
+            },
+            Inst::RepneScas(w) => {
+                match w {
+                    Width::Byte => self.code.extend_from_slice(&[0xF2, 0xAE]),
+                    Width::Word => self.code.extend_from_slice(&[0x66, 0xF2, 0xAF]),
+                    Width::Long => self.code.extend_from_slice(&[0xF2, 0xAF]),
+                    Width::Quad => self.code.extend_from_slice(&[0xF2, 0x48, 0xAF]),
+                }
+                Ok(())
+            },
+            Inst::Cld => {
+                self.byte(0xFC);
+                Ok(())
+            },
+            Inst::Std => {
+                self.byte(0xFD);
+                Ok(())
+            },
+            Inst::Push(_, ref op) => self.push(op),
+            Inst::Pop(_, ref op) => self.pop(op),
+            Inst::Jmp(ref label) => {
+                self.jump(None, label);
+                Ok(())
+            },
+            Inst::Jcc(cond, ref label) => {
+                self.jump(Some(cond), label);
+                Ok(())
+            },
+            Inst::Call(ref op) => self.modrm(Width::Long, &[0xFF], 2, false, op),
+            Inst::CallLabel(ref label) => {
+                self.call(label);
+                Ok(())
+            },
+            Inst::Data(ref bytes) => {
+                self.code.extend_from_slice(bytes);
+                Ok(())
+            },
+            Inst::RepMovsb => {
+                self.code.extend_from_slice(&[0xF3, 0xA4]);
+                Ok(())
+            },
+            Inst::Ret => {
+                self.byte(0xC3);
+                Ok(())
+            },
+            Inst::Syscall => {
+                self.code.extend_from_slice(&[0x0F, 0x05]);
+                Ok(())
+            },
+            Inst::Int(n) => {
+                self.code.extend_from_slice(&[0xCD, n]);
+                Ok(())
+            },
+        }
+    }
+
+    /// Computes the offset of every label with the current jump sizes.
+    fn layout(&self) -> Result<HashMap<String, usize>, Error> {
+        let mut labels = HashMap::new();
+        let mut pos = 0;
+        for frag in &self.frags {
+            if let Fragment::Label(ref label) = *frag {
+                if labels.insert(label.clone(), pos).is_some() {
+                    return Err(invalid(format!("Label {} defined twice.", label)));
+                }
+            }
+            pos += frag.size();
+        }
+        Ok(labels)
+    }
+
+    /// Starts with every jump in its short form and widens the ones whose
+    /// target is out of reach, until nothing changes. Widening a jump only
+    /// makes the others longer, so this always terminates.
+    fn relax(&mut self) -> Result<HashMap<String, usize>, Error> {
+        loop {
+            let labels = self.layout()?;
+            let mut changed = false;
+            let mut pos = 0;
+            for frag in &mut self.frags {
+                let size = frag.size();
+                if let Fragment::Jump {ref label, ref mut near, ..} = *frag {
+                    let target = match labels.get(label) {
+                        Some(&t) => t,
+                        _ => return Err(invalid(format!("Undefined label {}.", label))),
+                    };
+                    let rel = target as i64 - (pos + size) as i64;
+                    if !*near && !fits_i8(rel) {
+                        *near = true;
+                        changed = true;
+                    }
+                }
+                pos += size;
+            }
+            if !changed {
+                return Ok(labels);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, Error> {
+        self.flush();
+        let labels = self.relax()?;
+        let mut code = Vec::new();
+        for frag in self.frags {
+            match frag {
+                Fragment::Code(bytes) => code.extend_from_slice(&bytes),
+                Fragment::Label(_) => (),
+                Fragment::Call(label) => {
+                    let target = match labels.get(&label) {
+                        Some(&t) => t,
+                        _ => return Err(invalid(format!("Undefined label {}.", label))),
+                    };
+                    let rel = target as i64 - (code.len() + 5) as i64;
+                    code.push(0xE8);
+                    code.extend_from_slice(&(rel as i32).to_le_bytes());
+                },
+                Fragment::Jump {cond, label, near} => {
+                    let end = code.len() + jump_size(cond, near);
+                    let rel = labels[&label] as i64 - end as i64;
+                    match (cond, near) {
+                        (None, false) => code.push(0xEB),
+                        (Some(c), false) => code.push(0x70 | c.code()),
+                        (None, true) => code.push(0xE9),
+                        (Some(c), true) => {
+                            code.push(0x0F);
+                            code.push(0x80 | c.code());
+                        },
+                    }
+                    if near {
+                        code.extend_from_slice(&(rel as i32).to_le_bytes());
+                    } else {
+                        code.push(rel as i8 as u8);
+                    }
+                },
+            }
+        }
+        Ok(Program {code, labels})
+    }
+
+}
+
+/// Encodes the given instructions into machine code for the given mode.
+/// Labels are resolved here, and jumps use the short form whenever their
+/// target is within reach.
+pub fn assemble(mode: X86Mode, insts: &[Inst]) -> Result<Program, Error> {
+    let mut encoder = Encoder {
+        mode,
+        code: Vec::new(),
+        frags: Vec::new(),
+    };
+    for inst in insts {
+        if let Err(e) = encoder.inst(inst) {
+            return Err(invalid(format!(
+                "Cannot encode `{}`: {}",
+                inst.att(mode).to_string().trim(),
+                e
+            )));
+        }
+    }
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        assemble,
+    };
+    use super::super::{
+        X86Mode,
+    };
+    use super::super::inst::{
+        Inst,
+        Operand,
+        Reg,
+        Width,
+        Cond,
+    };
+
+    fn reg(r: Reg) -> Operand {
+        Operand::Reg(r)
+    }
+
+    fn imm(n: i64) -> Operand {
+        Operand::Imm(n)
+    }
+
+    fn label(name: &str) -> Inst {
+        Inst::Label(name.to_string())
+    }
+
+    fn code(mode: X86Mode, insts: &[Inst]) -> Vec<u8> {
+        assemble(mode, insts).unwrap().code
+    }
+
+    /// `len` bytes of one-byte `push %rbp` instructions.
+    fn padding(len: usize) -> Vec<Inst> {
+        vec![Inst::Push(Width::Quad, reg(Reg::Bp)); len]
+    }
+
+    /// A jump over `gap` bytes, in either direction.
+    fn jump_over(gap: usize, backward: bool) -> Vec<u8> {
+        let jmp = Inst::Jmp(String::from("target"));
+        let mut insts = padding(gap);
+        if backward {
+            insts.insert(0, label("target"));
+            insts.push(jmp);
+        } else {
+            insts.insert(0, jmp);
+            insts.push(label("target"));
+        }
+        let mut code = code(X86Mode::Amd64, &insts);
+        if backward {
+            code.drain(.. gap);
+        } else {
+            code.truncate(code.len() - gap);
+        }
+        code
+    }
+
+    #[test]
+    fn mov_widths() {
+        let bx = Operand::mem(Reg::Bx, 0);
+        let cases = vec![
+            (Inst::Mov(Width::Byte, imm(5), bx), vec![0xC6, 0x03, 0x05]),
+            (
+                Inst::Mov(Width::Word, imm(5), bx),
+                vec![0x66, 0xC7, 0x03, 0x05, 0x00],
+            ),
+            (
+                Inst::Mov(Width::Long, imm(5), bx),
+                vec![0xC7, 0x03, 0x05, 0x00, 0x00, 0x00],
+            ),
+            (
+                Inst::Mov(Width::Quad, imm(-1), bx),
+                vec![0x48, 0xC7, 0x03, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+            (
+                Inst::Mov(Width::Word, reg(Reg::Ax), reg(Reg::Cx)),
+                vec![0x66, 0x89, 0xC1],
+            ),
+            (
+                Inst::Mov(Width::Quad, imm(0x1_2345_6789), reg(Reg::Ax)),
+                vec![0x48, 0xB8, 0x89, 0x67, 0x45, 0x23, 0x01, 0, 0, 0],
+            ),
+            (
+                Inst::Mov(Width::Quad, imm(-2), reg(Reg::Ax)),
+                vec![0x48, 0xC7, 0xC0, 0xFE, 0xFF, 0xFF, 0xFF],
+            ),
+            (
+                Inst::Mov(Width::Long, imm(1), reg(Reg::R8)),
+                vec![0x41, 0xB8, 0x01, 0x00, 0x00, 0x00],
+            ),
+            (
+                Inst::Mov(Width::Byte, imm(1), reg(Reg::Si)),
+                vec![0x40, 0xB6, 0x01],
+            ),
+            (Inst::Mov(Width::Byte, imm(7), reg(Reg::Ax)), vec![0xB0, 0x07]),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(X86Mode::Amd64, std::slice::from_ref(&inst)), bytes, "{:?}", inst);
+        }
+    }
+
+    #[test]
+    fn alu_widths() {
+        let bx = Operand::mem(Reg::Bx, 0);
+        let cases = vec![
+            (Inst::Add(Width::Word, imm(1), bx), vec![0x66, 0x83, 0x03, 0x01]),
+            (
+                Inst::Add(Width::Word, imm(1000), bx),
+                vec![0x66, 0x81, 0x03, 0xE8, 0x03],
+            ),
+            (Inst::Add(Width::Byte, imm(3), bx), vec![0x80, 0x03, 0x03]),
+            (Inst::Cmp(Width::Byte, imm(-1), reg(Reg::Ax)), vec![0x3C, 0xFF]),
+            (
+                Inst::Add(Width::Long, imm(1000), reg(Reg::Ax)),
+                vec![0x05, 0xE8, 0x03, 0x00, 0x00],
+            ),
+            (Inst::Sub(Width::Word, reg(Reg::Ax), bx), vec![0x66, 0x29, 0x03]),
+            (Inst::Add(Width::Word, bx, reg(Reg::Cx)), vec![0x66, 0x03, 0x0B]),
+            (
+                Inst::Add(Width::Quad, imm(1), reg(Reg::R12)),
+                vec![0x49, 0x83, 0xC4, 0x01],
+            ),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(X86Mode::Amd64, std::slice::from_ref(&inst)), bytes, "{:?}", inst);
+        }
+    }
+
+    #[test]
+    fn memory_operands() {
+        let load = |base, disp| Inst::Mov(
+            Width::Word,
+            Operand::mem(base, disp),
+            reg(Reg::Ax)
+        );
+        let cases = vec![
+            (load(Reg::Sp, 0), vec![0x66, 0x8B, 0x04, 0x24]),
+            (load(Reg::Sp, 8), vec![0x66, 0x8B, 0x44, 0x24, 0x08]),
+            (load(Reg::Bp, 0), vec![0x66, 0x8B, 0x45, 0x00]),
+            (load(Reg::Bx, 200), vec![0x66, 0x8B, 0x83, 0xC8, 0x00, 0x00, 0x00]),
+            (load(Reg::R12, -8), vec![0x66, 0x41, 0x8B, 0x44, 0x24, 0xF8]),
+            (load(Reg::R13, 0), vec![0x66, 0x41, 0x8B, 0x45, 0x00]),
+            (
+                Inst::Mov(Width::Word, reg(Reg::R9), Operand::mem(Reg::R13, -129)),
+                vec![0x66, 0x45, 0x89, 0x8D, 0x7F, 0xFF, 0xFF, 0xFF],
+            ),
+        ];
+        for (inst, bytes) in cases {
+            assert_eq!(code(X86Mode::Amd64, std::slice::from_ref(&inst)), bytes, "{:?}", inst);
+        }
+    }
+
+    #[test]
+    fn x86_mode() {
+        let insts = [
+            Inst::Mov(Width::Word, imm(1), Operand::mem(Reg::Si, 0)),
+            Inst::Add(Width::Long, imm(-1), Operand::mem(Reg::Si, 4)),
+            Inst::Push(Width::Long, reg(Reg::Bp)),
+            Inst::Push(Width::Long, imm(300)),
+        ];
+        assert_eq!(code(X86Mode::X86, &insts), vec![
+            0x66, 0xC7, 0x06, 0x01, 0x00,
+            0x83, 0x46, 0x04, 0xFF,
+            0x55,
+            0x68, 0x2C, 0x01, 0x00, 0x00,
+        ]);
+        let rex = Inst::Mov(Width::Long, imm(1), reg(Reg::R8));
+        assert!(assemble(X86Mode::X86, &[rex]).is_err());
+        let byte_reg = Inst::Mov(Width::Byte, imm(1), reg(Reg::Si));
+        assert!(assemble(X86Mode::X86, &[byte_reg]).is_err());
+    }
+
+    #[test]
+    fn word_pairs() {
+        let high = Operand::mem(Reg::Si, 4);
+        let insts = [
+            Inst::Adc(Width::Long, imm(-1), high),
+            Inst::Sbb(Width::Long, reg(Reg::Dx), Operand::mem(Reg::Si, 12)),
+            Inst::Or(Width::Long, high, reg(Reg::Ax)),
+            Inst::Adc(Width::Long, imm(300), reg(Reg::Ax)),
+            Inst::Mul(Width::Long, reg(Reg::Cx)),
+            Inst::Cltd,
+            Inst::Imul(Width::Long, -3, high, reg(Reg::Cx)),
+        ];
+        assert_eq!(code(X86Mode::X86, &insts), vec![
+            0x83, 0x56, 0x04, 0xFF,
+            0x19, 0x56, 0x0C,
+            0x0B, 0x46, 0x04,
+            0x15, 0x2C, 0x01, 0x00, 0x00,
+            0xF7, 0xE1,
+            0x99,
+            0x6B, 0x4E, 0x04, 0xFD,
+        ]);
+    }
+
+    #[test]
+    fn short_jumps() {
+        let insts = [
+            label("start"),
+            Inst::Jcc(Cond::Ne, String::from("start")),
+            Inst::Jcc(Cond::E, String::from("end")),
+            Inst::Jmp(String::from("start")),
+            label("end"),
+        ];
+        let program = assemble(X86Mode::Amd64, &insts).unwrap();
+        assert_eq!(program.code, vec![0x75, 0xFE, 0x74, 0x02, 0xEB, 0xFA]);
+        assert_eq!(program.labels["start"], 0);
+        assert_eq!(program.labels["end"], 6);
+    }
+
+    #[test]
+    fn near_jumps() {
+        let mut insts = vec![label("top"), Inst::Jcc(Cond::E, String::from("end"))];
+        insts.extend(padding(200));
+        insts.push(Inst::Jmp(String::from("top")));
+        insts.push(label("end"));
+        let code = code(X86Mode::Amd64, &insts);
+        assert_eq!(code[.. 6], [0x0F, 0x84, 0xCD, 0x00, 0x00, 0x00]);
+        assert_eq!(code[206 ..], [0xE9, 0x2D, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn jump_reach() {
+        assert_eq!(jump_over(127, false), vec![0xEB, 0x7F]);
+        assert_eq!(jump_over(128, false), vec![0xE9, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(jump_over(126, true), vec![0xEB, 0x80]);
+        assert_eq!(jump_over(127, true), vec![0xE9, 0x7C, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn widening_cascades() {
+        // Widening the `je` pushes the target of the `jmp` out of reach.
+        let mut insts = vec![Inst::Jmp(String::from("x"))];
+        insts.extend(padding(124));
+        insts.push(Inst::Jcc(Cond::E, String::from("y")));
+        insts.push(label("x"));
+        insts.extend(padding(128));
+        insts.push(label("y"));
+        let program = assemble(X86Mode::Amd64, &insts).unwrap();
+        assert_eq!(program.code[.. 5], [0xE9, 0x82, 0x00, 0x00, 0x00]);
+        assert_eq!(program.code[129 .. 135], [0x0F, 0x84, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(program.labels["x"], 135);
+        assert_eq!(program.labels["y"], 263);
+    }
+
+    #[test]
+    fn calls_and_label_errors() {
+        let insts = [
+            Inst::CallLabel(String::from("f")),
+            label("f"),
+            Inst::Ret,
+        ];
+        assert_eq!(code(X86Mode::Amd64, &insts), vec![0xE8, 0, 0, 0, 0, 0xC3]);
+        let undefined = [Inst::Jmp(String::from("nowhere"))];
+        assert!(assemble(X86Mode::Amd64, &undefined).is_err());
+        let twice = [label("a"), label("a")];
+        assert!(assemble(X86Mode::Amd64, &twice).is_err());
+    }
+
+}
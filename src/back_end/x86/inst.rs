@@ -0,0 +1,330 @@
+use std::{
+    fmt,
+};
+use super::{
+    X86Mode,
+};
+
+
+/// General purpose registers, independent of the width they are used with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reg {
+    Ax,
+    Cx,
+    Dx,
+    Bx,
+    Sp,
+    Bp,
+    Si,
+    Di,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+
+    /// The 4-bit register number used by the encoding.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self, width: Width) -> &'static str {
+        const BYTE: [&str; 16] = [
+            "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil",
+            "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b",
+        ];
+        const WORD: [&str; 16] = [
+            "ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+            "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w",
+        ];
+        const LONG: [&str; 16] = [
+            "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+            "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d",
+        ];
+        const QUAD: [&str; 16] = [
+            "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+            "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+        ];
+        match width {
+            Width::Byte => BYTE[self.code() as usize],
+            Width::Word => WORD[self.code() as usize],
+            Width::Long => LONG[self.code() as usize],
+            Width::Quad => QUAD[self.code() as usize],
+        }
+    }
+
+}
+
+/// Operand sizes, named after their AT&T suffixes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+    Long,
+    Quad,
+}
+
+impl Width {
+
+    pub fn suffix(self) -> char {
+        match self {
+            Width::Byte => 'b',
+            Width::Word => 'w',
+            Width::Long => 'l',
+            Width::Quad => 'q',
+        }
+    }
+
+    pub fn bytes(self) -> u64 {
+        match self {
+            Width::Byte => 1,
+            Width::Word => 2,
+            Width::Long => 4,
+            Width::Quad => 8,
+        }
+    }
+
+}
+
+/// A memory operand of the form `disp(%base)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mem {
+    pub base: Reg,
+    pub disp: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Imm(i64),
+    Reg(Reg),
+    Mem(Mem),
+}
+
+impl Operand {
+
+    pub fn mem(base: Reg, disp: i32) -> Self {
+        Operand::Mem(Mem {base, disp})
+    }
+
+    fn is_reg(&self) -> bool {
+        matches!(*self, Operand::Reg(_))
+    }
+
+}
+
+/// Condition codes, with their encoding as the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cond {
+    B = 0x2,
+    Ae = 0x3,
+    E = 0x4,
+    Ne = 0x5,
+    Be = 0x6,
+    A = 0x7,
+    L = 0xC,
+    Ge = 0xD,
+    Le = 0xE,
+    G = 0xF,
+}
+
+impl Cond {
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cond::B => "b",
+            Cond::Ae => "ae",
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::Be => "be",
+            Cond::A => "a",
+            Cond::L => "l",
+            Cond::Ge => "ge",
+            Cond::Le => "le",
+            Cond::G => "g",
+        }
+    }
+
+}
+
+/// The subset of x86 instructions the code generator emits. Two operand
+/// instructions take their operands in AT&T order: source, then destination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inst {
+    Label(String),
+    Mov(Width, Operand, Operand),
+    Add(Width, Operand, Operand),
+    Sub(Width, Operand, Operand),
+    /// Adds the source and the carry flag to the destination.
+    Adc(Width, Operand, Operand),
+    /// Subtracts the source and the carry flag from the destination.
+    Sbb(Width, Operand, Operand),
+    Or(Width, Operand, Operand),
+    Cmp(Width, Operand, Operand),
+    /// Zero extending move, from the first width to the second.
+    Movzx(Width, Width, Operand, Operand),
+    /// Sign extending move, from the first width to the second.
+    Movsx(Width, Width, Operand, Operand),
+    Lea(Width, Operand, Operand),
+    /// Multiplies the source by an immediate into the destination register.
+    Imul(Width, i64, Operand, Operand),
+    /// Multiplies the destination register by the source.
+    ImulReg(Width, Operand, Operand),
+    /// Multiplies `%ax` by the operand, unsigned, into `%dx:%ax`.
+    Mul(Width, Operand),
+    /// Sign extends `%eax` into `%edx`.
+    Cltd,
+    /// Logical right shift by a constant.
+    Shr(Width, u8, Operand),
+    /// Compares `%ax` (of the given width) against the string at `%di`,
+    /// stepping `%di` and decrementing `%cx` until they are equal or `%cx`
+    /// is zero.
+    RepneScas(Width),
+    /// Clears the direction flag, so string instructions step upwards.
+    Cld,
+    /// Sets the direction flag, so string instructions step downwards.
+    Std,
+    /// Copies `%cx` bytes from `%si` to `%di`.
+    RepMovsb,
+    Push(Width, Operand),
+    Pop(Width, Operand),
+    Jmp(String),
+    Jcc(Cond, String),
+    /// Indirect call through a register.
+    Call(Operand),
+    /// Direct call to a label.
+    CallLabel(String),
+    /// Raw bytes placed among the instructions.
+    Data(Vec<u8>),
+    Ret,
+    Syscall,
+    Int(u8),
+}
+
+impl Inst {
+
+    /// Displays the instruction in AT&T syntax for the given mode.
+    pub fn att(&self, mode: X86Mode) -> Att<'_> {
+        Att {inst: self, mode}
+    }
+
+}
+
+pub struct Att<'a> {
+    inst: &'a Inst,
+    mode: X86Mode,
+}
+
+impl<'a> Att<'a> {
+
+    fn operand(&self, width: Width, op: &Operand) -> String {
+        match *op {
+            Operand::Imm(n) => format!("${}", n),
+            Operand::Reg(r) => format!("%{}", r.name(width)),
+            Operand::Mem(Mem {base, disp: 0}) =>
+                format!("(%{})", base.name(self.mode.addr_width())),
+            Operand::Mem(Mem {base, disp}) =>
+                format!("{}(%{})", disp, base.name(self.mode.addr_width())),
+        }
+    }
+
+    fn binary(
+        &self,
+        name: &str,
+        width: Width,
+        src: &Operand,
+        dst: &Operand
+    ) -> String {
+        let name = if src.is_reg() || dst.is_reg() {
+            name.to_string()
+        } else {
+            format!("{}{}", name, width.suffix())
+        };
+        format!(
+            "  {} {}, {}",
+            name,
+            self.operand(width, src),
+            self.operand(width, dst)
+        )
+    }
+
+}
+
+impl<'a> fmt::Display for Att<'a> {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", match *self.inst {
+            Inst::Label(ref label) => format!("  {}:", label),
+            Inst::Mov(w, ref src, ref dst) => self.binary("mov", w, src, dst),
+            Inst::Add(w, ref src, ref dst) => self.binary("add", w, src, dst),
+            Inst::Sub(w, ref src, ref dst) => self.binary("sub", w, src, dst),
+            Inst::Adc(w, ref src, ref dst) => self.binary("adc", w, src, dst),
+            Inst::Sbb(w, ref src, ref dst) => self.binary("sbb", w, src, dst),
+            Inst::Or(w, ref src, ref dst) => self.binary("or", w, src, dst),
+            Inst::Cmp(w, ref src, ref dst) => self.binary("cmp", w, src, dst),
+            Inst::Movzx(from, to, ref src, ref dst) => format!(
+                "  movz{}{} {}, {}",
+                from.suffix(),
+                to.suffix(),
+                self.operand(from, src),
+                self.operand(to, dst)
+            ),
+            Inst::Movsx(from, to, ref src, ref dst) => format!(
+                "  movs{}{} {}, {}",
+                from.suffix(),
+                to.suffix(),
+                self.operand(from, src),
+                self.operand(to, dst)
+            ),
+            Inst::Lea(w, ref src, ref dst) => self.binary("lea", w, src, dst),
+            Inst::Imul(w, factor, ref src, ref dst) => format!(
+                "  imul ${}, {}, {}",
+                factor,
+                self.operand(w, src),
+                self.operand(w, dst)
+            ),
+            Inst::ImulReg(w, ref src, ref dst) => self.binary("imul", w, src, dst),
+            Inst::Mul(w, ref op) =>
+                format!("  mul{} {}", w.suffix(), self.operand(w, op)),
+            Inst::Cltd => String::from("  cltd"),
+            Inst::Shr(w, n, ref op) =>
+                self.binary("shr", w, &Operand::Imm(n as i64), op),
+            Inst::RepneScas(w) => format!("  repne scas{}", w.suffix()),
+            Inst::Cld => String::from("  cld"),
+            Inst::Std => String::from("  std"),
+            Inst::RepMovsb => String::from("  rep movsb"),
+            Inst::Push(w, ref op) =>
+                format!("  push{} {}", w.suffix(), self.operand(w, op)),
+            Inst::Pop(w, ref op) =>
+                format!("  pop{} {}", w.suffix(), self.operand(w, op)),
+            Inst::Jmp(ref label) => format!("  jmp {}", label),
+            Inst::Jcc(cond, ref label) =>
+                format!("  j{} {}", cond.name(), label),
+            Inst::Call(ref op) => format!(
+                "  call *{}",
+                self.operand(self.mode.addr_width(), op)
+            ),
+            Inst::CallLabel(ref label) => format!("  call {}", label),
+            Inst::Data(ref bytes) => format!(
+                "  .byte {}",
+                bytes.iter()
+                    .map(|b| b.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Inst::Ret => String::from("  ret"),
+            Inst::Syscall => String::from("  syscall"),
+            Inst::Int(n) => format!("  int $0x{:x}", n),
+        })
+    }
+
+}
@@ -0,0 +1,272 @@
+pub mod inst;
+pub mod encode;
+pub mod codegen;
+
+pub use self::inst::{
+    Inst,
+    Operand,
+    Reg,
+    Width,
+    Cond,
+};
+pub use self::encode::{
+    assemble,
+    Program,
+};
+pub use self::codegen::{
+    Codegen,
+    Linkage,
+};
+
+use std::io::{
+    Write,
+    Error,
+    ErrorKind,
+};
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    CellSize,
+    Tape,
+};
+use super::{
+    Arch,
+    Format,
+    create_output,
+    check_function,
+};
+use super::elf::{
+    self,
+    Executable,
+    Object,
+};
+use std::os::unix::fs::{
+    PermissionsExt,
+};
+use std::{
+    fs,
+};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum X86Mode {
+    Amd64,
+    X86,
+}
+
+impl X86Mode {
+
+    /// Width of pointers and of the registers holding them.
+    pub fn addr_width(&self) -> Width {
+        match *self {
+            X86Mode::Amd64 => Width::Quad,
+            X86Mode::X86 => Width::Long,
+        }
+    }
+
+    /// The instruction width of cells of the given size.
+    pub fn cell_width(size: CellSize) -> Width {
+        match size {
+            CellSize::Bits8 => Width::Byte,
+            CellSize::Bits16 => Width::Word,
+            CellSize::Bits32 => Width::Long,
+            CellSize::Bits64 => Width::Quad,
+        }
+    }
+
+    /// Fails if the mode cannot generate code for the configuration.
+    pub fn check(&self, config: &Config) -> Result<(), Error> {
+        if let Tape::Heap(cells) | Tape::Bidirectional(cells) = config.tape {
+            let max = match *self {
+                X86Mode::Amd64 => 1 << 46,
+                X86Mode::X86 => 1 << 30,
+            };
+            let fits = cells.checked_mul(config.cell_size.bytes() as u64)
+                .is_some_and(|bytes| bytes <= max);
+            if !fits {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The tape is too big for the architecture."
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The size in bytes of a heap tape, rounded up to whole pages. The size
+    /// must have been checked.
+    pub fn heap_bytes(cells: u64, size: CellSize) -> u64 {
+        let bytes = cells * size.bytes() as u64;
+        (bytes + 4095) & !4095
+    }
+
+    /// The register holding the tape pointer.
+    pub fn tape_reg(&self) -> Reg {
+        match *self {
+            X86Mode::Amd64 => Reg::Bx,
+            X86Mode::X86 => Reg::Si,
+        }
+    }
+
+    fn sys_read(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 0,
+            X86Mode::X86 => 3,
+        }
+    }
+
+    fn sys_write(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 1,
+            X86Mode::X86 => 4,
+        }
+    }
+
+    /// `mmap` on x86-64, and `mmap2`, which takes the offset in pages, on
+    /// x86.
+    fn sys_mmap(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 9,
+            X86Mode::X86 => 192,
+        }
+    }
+
+    fn sys_mprotect(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 10,
+            X86Mode::X86 => 125,
+        }
+    }
+
+    /// `getrlimit` on x86-64, and `ugetrlimit`, which gives unsigned limits,
+    /// on x86.
+    fn sys_getrlimit(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 97,
+            X86Mode::X86 => 191,
+        }
+    }
+
+    fn sys_munmap(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 11,
+            X86Mode::X86 => 91,
+        }
+    }
+
+    fn sys_exit(&self) -> i64 {
+        match *self {
+            X86Mode::Amd64 => 60,
+            X86Mode::X86 => 1,
+        }
+    }
+
+    /// Generates the instructions for a standalone program, starting at the
+    /// `_start` entry point.
+    pub fn gen_insts(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Vec<Inst>, Error> {
+        self.check(config)?;
+        Ok(Codegen::new(*self, Linkage::Start, config).generate(ir))
+    }
+
+    pub fn gen_asm<T: Write>(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        out: &mut T
+    ) -> Result<usize, Error> {
+        let mut text = String::from(".text\n.globl _start\n_start:\n");
+        for inst in self.gen_insts(ir, config)? {
+            text += &inst.att(*self).to_string();
+            text.push('\n');
+        }
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    fn elf_class(&self) -> (elf::Class, u16) {
+        match *self {
+            X86Mode::Amd64 => (elf::Class::Elf64, elf::EM_X86_64),
+            X86Mode::X86 => (elf::Class::Elf32, elf::EM_386),
+        }
+    }
+
+    /// Generates a complete, statically linked executable for the program.
+    pub fn gen_elf(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config
+    ) -> Result<Executable, Error> {
+        let program = assemble(*self, &self.gen_insts(ir, config)?)?;
+        let (class, machine) = self.elf_class();
+        Ok(Executable {
+            class,
+            machine,
+            flags: 0,
+            text: program.code,
+            entry: 0,
+        })
+    }
+
+    /// Generates a relocatable object exporting the program as a function
+    /// named `symbol`. See `Linkage::Function` for its signature.
+    pub fn gen_object(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        symbol: String
+    ) -> Result<Object, Error> {
+        self.check(config)?;
+        check_function(config)?;
+        let insts = Codegen::new(*self, Linkage::Function, config).generate(ir);
+        let program = assemble(*self, &insts)?;
+        let (class, machine) = self.elf_class();
+        Ok(Object {
+            class,
+            machine,
+            flags: 0,
+            text: program.code,
+            symbol,
+        })
+    }
+
+}
+
+impl Arch for X86Mode {
+
+    fn generate(
+        &self,
+        ir: Vec<Node<Ir>>,
+        config: &Config,
+        format: Format,
+        out: String
+    ) -> Result<usize, Error> {
+        match format {
+            Format::Asm => self.gen_asm(ir, config, &mut create_output(&out)?),
+            Format::Elf => {
+                let exe = self.gen_elf(ir, config)?;
+                let amount = exe.write(&mut create_output(&out)?)?;
+                fs::set_permissions(&out, fs::Permissions::from_mode(0o755))?;
+                Ok(amount)
+            },
+            Format::Object(symbol) => {
+                let obj = self.gen_object(ir, config, symbol)?;
+                obj.write(&mut create_output(&out)?)
+            },
+            Format::Bin => {
+                let program = assemble(*self, &self.gen_insts(ir, config)?)?;
+                create_output(&out)?.write_all(&program.code)?;
+                Ok(program.code.len())
+            },
+        }
+    }
+
+}
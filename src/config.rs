@@ -0,0 +1,256 @@
+/// The size of each cell of the tape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellSize {
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl CellSize {
+
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(CellSize::Bits8),
+            16 => Some(CellSize::Bits16),
+            32 => Some(CellSize::Bits32),
+            64 => Some(CellSize::Bits64),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match *self {
+            CellSize::Bits8 => 8,
+            CellSize::Bits16 => 16,
+            CellSize::Bits32 => 32,
+            CellSize::Bits64 => 64,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    /// The mask of the bits a cell holds.
+    pub fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+
+    /// Wraps a value around the cell size, giving it sign extended.
+    pub fn wrap(&self, value: i64) -> i64 {
+        let shift = 64 - self.bits();
+        (value << shift) >> shift
+    }
+
+}
+
+/// What `,` stores in the cell at the end of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eof {
+    MinusOne,
+    Zero,
+    /// Leaves the cell unchanged.
+    Unchanged,
+}
+
+impl Eof {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "minus-one" => Some(Eof::MinusOne),
+            "zero" => Some(Eof::Zero),
+            "unchanged" => Some(Eof::Unchanged),
+            _ => None,
+        }
+    }
+
+}
+
+/// What happens when `+` or `-` takes a cell out of its range. Cells are
+/// unsigned when they do not wrap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    Wrap,
+    /// Stops the program, reporting the location of the operation.
+    Trap,
+    /// Keeps the cell at its highest or lowest value.
+    Saturate,
+}
+
+impl Overflow {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wrap" => Some(Overflow::Wrap),
+            "trap" => Some(Overflow::Trap),
+            "saturate" => Some(Overflow::Saturate),
+            _ => None,
+        }
+    }
+
+}
+
+/// Where a standalone program keeps its tape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tape {
+    /// On the process stack, growing downwards as the pointer moves right.
+    /// When checked, it may take half of the stack size limit, and at most
+    /// 512 MiB.
+    Stack,
+    /// In a region of the given number of cells reserved with `mmap` at
+    /// startup, between guard pages. Pages are only allocated when they are
+    /// first touched, and going past either end of the region faults.
+    Heap(u64),
+    /// In a region of the given number of cells given by `mmap`, starting
+    /// in its middle. The region is grown when the pointer goes past either
+    /// end, so cells left of the first one can be used.
+    Bidirectional(u64),
+}
+
+impl Tape {
+
+    /// Number of cells of a heap tape when no size is given.
+    pub const DEFAULT_HEAP: u64 = 1 << 24;
+
+    /// Number of cells a bidirectional tape starts with when no size is
+    /// given.
+    pub const DEFAULT_BIDIRECTIONAL: u64 = 1 << 16;
+
+    /// Parses `stack`, `heap` or `bidirectional`, the last two optionally
+    /// followed by `:<cells>`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut parts = name.splitn(2, ':');
+        let (kind, size) = (parts.next()?, parts.next());
+        let size = match size.map(str::parse) {
+            Some(Ok(0)) | Some(Err(_)) => return None,
+            Some(Ok(size)) => Some(size),
+            None => None,
+        };
+        match (kind, size) {
+            ("stack", None) => Some(Tape::Stack),
+            ("heap", size) =>
+                Some(Tape::Heap(size.unwrap_or(Tape::DEFAULT_HEAP))),
+            ("bidirectional", size) => Some(Tape::Bidirectional(
+                size.unwrap_or(Tape::DEFAULT_BIDIRECTIONAL)
+            )),
+            _ => None,
+        }
+    }
+
+    /// Whether cells left of the first one can be used.
+    pub fn bidirectional(&self) -> bool {
+        matches!(*self, Tape::Bidirectional(_))
+    }
+
+}
+
+/// Settings of the generated code which change the behavior of programs,
+/// shared by every back end and the interpreter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub cell_size: CellSize,
+    pub eof: Eof,
+    pub overflow: Overflow,
+    pub tape: Tape,
+    /// Whether executables check that the pointer stays in the tape,
+    /// reporting where it left instead of faulting. The JIT and the
+    /// interpreter always check.
+    pub checked: bool,
+    /// Whether executables write every `.` and read every `,` with a system
+    /// call of their own, instead of going through buffers, for interactive
+    /// programs.
+    pub unbuffered: bool,
+    /// Whether programs exit with the low byte of the current cell as their
+    /// status, instead of 0.
+    pub exit_cell: bool,
+}
+
+impl Default for Config {
+
+    fn default() -> Self {
+        Self {
+            cell_size: CellSize::Bits16,
+            eof: Eof::MinusOne,
+            overflow: Overflow::Wrap,
+            tape: Tape::Stack,
+            checked: false,
+            unbuffered: false,
+            exit_cell: false,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        CellSize,
+        Eof,
+        Overflow,
+        Tape,
+    };
+
+    #[test]
+    fn cell_sizes() {
+        for &bits in &[8, 16, 32, 64] {
+            assert_eq!(CellSize::from_bits(bits).unwrap().bits(), bits);
+        }
+        for &bits in &[0, 1, 7, 24, 128] {
+            assert_eq!(CellSize::from_bits(bits), None);
+        }
+        assert_eq!(CellSize::Bits8.mask(), 0xFF);
+        assert_eq!(CellSize::Bits16.mask(), 0xFFFF);
+        assert_eq!(CellSize::Bits32.mask(), 0xFFFF_FFFF);
+        assert_eq!(CellSize::Bits64.mask(), u64::MAX);
+        assert_eq!(CellSize::Bits8.wrap(255), -1);
+        assert_eq!(CellSize::Bits8.wrap(256), 0);
+        assert_eq!(CellSize::Bits8.wrap(-129), 127);
+        assert_eq!(CellSize::Bits16.wrap(0x8000), -0x8000);
+        assert_eq!(CellSize::Bits32.wrap(0x1_0000_0005), 5);
+        assert_eq!(CellSize::Bits64.wrap(i64::MIN), i64::MIN);
+        assert_eq!(CellSize::Bits64.wrap(-1), -1);
+    }
+
+    #[test]
+    fn eof_names() {
+        assert_eq!(Eof::from_name("minus-one"), Some(Eof::MinusOne));
+        assert_eq!(Eof::from_name("zero"), Some(Eof::Zero));
+        assert_eq!(Eof::from_name("unchanged"), Some(Eof::Unchanged));
+        for name in &["", "-1", "0", "minus_one", "Zero", "unchanged "] {
+            assert_eq!(Eof::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn tape_names() {
+        assert_eq!(Tape::from_name("stack"), Some(Tape::Stack));
+        assert_eq!(Tape::from_name("heap"), Some(Tape::Heap(Tape::DEFAULT_HEAP)));
+        assert_eq!(Tape::from_name("heap:1"), Some(Tape::Heap(1)));
+        assert_eq!(Tape::from_name("heap:65536"), Some(Tape::Heap(65536)));
+        let bidirectional = Tape::Bidirectional(Tape::DEFAULT_BIDIRECTIONAL);
+        assert_eq!(Tape::from_name("bidirectional"), Some(bidirectional));
+        assert_eq!(Tape::from_name("bidirectional:3"), Some(Tape::Bidirectional(3)));
+        assert!(bidirectional.bidirectional());
+        assert!(!Tape::Heap(1).bidirectional());
+        let rejected = [
+            "", "stack:1", "heap:", "heap:0", "heap:-1", "heap:1k", "heap:1:2",
+            "heap 1", "Heap", "bidirectional:", "bidirectional:0", "bidi",
+        ];
+        for name in &rejected {
+            assert_eq!(Tape::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn overflow_names() {
+        assert_eq!(Overflow::from_name("wrap"), Some(Overflow::Wrap));
+        assert_eq!(Overflow::from_name("trap"), Some(Overflow::Trap));
+        assert_eq!(Overflow::from_name("saturate"), Some(Overflow::Saturate));
+        for name in &["", "wraps", "Trap", "saturating", "trap:1"] {
+            assert_eq!(Overflow::from_name(name), None, "{}", name);
+        }
+    }
+
+}
@@ -24,6 +24,7 @@ pub struct ByteStream {
     cols: HeadedList<u64>,
 }
 
+#[allow(clippy::should_implement_trait)]
 impl ByteStream {
 
     pub fn from_str(file: String, bytes: &str) -> Self {
@@ -41,10 +42,7 @@ impl ByteStream {
     }
 
     pub fn from_file(file: String) -> Result<Self, io::Error> {
-        let mut stream = match fs::File::open(&file) {
-            Err(e) => return Err(e),
-            Ok(s) => s,
-        };
+        let mut stream = fs::File::open(&file)?;
         let mut bytes = Vec::new();
         match stream.read_to_end(&mut bytes) {
             Err(e) => Err(e),
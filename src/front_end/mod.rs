@@ -138,7 +138,7 @@ pub fn parse(mut stream: ByteStream) -> Result<Vec<Node<AstNode>>, Vec<ParseErro
             loc: lp.loc,
         });
     }
-    if errs.len() > 0 {Err(errs)} else {Ok(loops.reclaim_val().ops)}
+    if !errs.is_empty() {Err(errs)} else {Ok(loops.reclaim_val().ops)}
 }
 
 
@@ -56,20 +56,16 @@ impl fmt::Display for AstNode {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "{}", match *self {
             AstNode::Increment(n) =>
-                iter::repeat(INC_CHAR as char)
-                .take(n as usize)
+                iter::repeat_n(INC_CHAR as char, n as usize)
                 .collect::<String>(),
             AstNode::Decrement(n) =>
-                iter::repeat(DEC_CHAR as char)
-                .take(n as usize)
+                iter::repeat_n(DEC_CHAR as char, n as usize)
                 .collect::<String>(),
             AstNode::Next(n) =>
-                iter::repeat(NEXT_CHAR as char)
-                .take(n as usize)
+                iter::repeat_n(NEXT_CHAR as char, n as usize)
                 .collect::<String>(),
             AstNode::Previous(n) =>
-                iter::repeat(PREV_CHAR as char)
-                .take(n as usize)
+                iter::repeat_n(PREV_CHAR as char, n as usize)
                 .collect::<String>(),
             AstNode::PutChar() => (PUTC_CHAR as char).to_string(),
             AstNode::GetChar() => (GETC_CHAR as char).to_string(),
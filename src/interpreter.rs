@@ -0,0 +1,580 @@
+use std::io::{
+    Read,
+    Write,
+    Error,
+    ErrorKind,
+};
+use std::vec::{
+    IntoIter,
+};
+use std::{
+    fmt,
+    iter,
+};
+use std::error;
+use front_end::{
+    AstNode,
+    Node,
+    Location,
+};
+use ir::{
+    self,
+    Ir,
+};
+use opt::{
+    self,
+};
+use config::{
+    Config,
+    Eof,
+    Overflow,
+};
+use back_end::{
+    BOUNDS_EXIT,
+    OVERFLOW_EXIT,
+};
+use utils::{
+    HeadedList,
+};
+
+
+/// Number of cells the tape starts with. It grows as needed.
+pub const INITIAL_TAPE_LEN: usize = 0x1000;
+
+/// A failure of the program itself, rather than of its I/O, carried by the
+/// errors of `Interpreter::run`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The pointer left the tape at the given location.
+    Bounds(Location),
+    /// A cell overflowed at the given location, when overflow traps.
+    Overflow(Location),
+}
+
+impl Fault {
+
+    /// The fault carried by an error, if any.
+    pub fn of(error: &Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// The status compiled programs exit with on this fault.
+    pub fn status(&self) -> i64 {
+        match *self {
+            Fault::Bounds(_) => BOUNDS_EXIT,
+            Fault::Overflow(_) => OVERFLOW_EXIT,
+        }
+    }
+
+}
+
+impl fmt::Display for Fault {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::Bounds(ref loc) =>
+                write!(fmt, "Tape pointer out of bounds {}.", loc),
+            Fault::Overflow(ref loc) => write!(fmt, "Cell overflow {}.", loc),
+        }
+    }
+
+}
+
+impl error::Error for Fault {}
+
+/// Bytecode executed by the interpreter. Offsets are relative to the tape
+/// pointer, and arithmetic wraps around, except for additions, whose amount
+/// is kept whole so that overflow can be detected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add {
+        offset: isize,
+        amount: i64,
+    },
+    Set {
+        offset: isize,
+        value: u64,
+    },
+    Mul {
+        src: isize,
+        dst: isize,
+        factor: u64,
+    },
+    Move(isize),
+    Scan(isize),
+    PutChar {
+        offset: isize,
+    },
+    GetChar {
+        offset: isize,
+    },
+    /// Jumps to the given index if the current cell is zero.
+    JumpZero(usize),
+    /// Jumps to the given index if the current cell is not zero.
+    JumpNonZero(usize),
+}
+
+#[derive(Clone, Debug)]
+struct Loop {
+    ops: IntoIter<Node<Ir>>,
+    start: usize,
+}
+
+/// A program lowered to bytecode, ready to be run any number of times. It
+/// follows the semantics of the native back ends: wrapping cells of the
+/// configured size, EOF and overflow behavior, and a tape that grows to the right.
+/// Moving before the first cell is an error, unless the tape is
+/// bidirectional, in which case the pointer starts in the middle of the tape
+/// and the tape grows to the left too.
+#[derive(Clone, Debug)]
+pub struct Interpreter {
+    code: Vec<Op>,
+    /// The location of each operation.
+    locs: Vec<Location>,
+    config: Config,
+}
+
+impl Interpreter {
+
+    /// Optimizes and lowers the program, with the default configuration.
+    pub fn new(ast: Vec<Node<AstNode>>) -> Self {
+        Self::from_ir(opt::optimize(ir::lower(ast)), &Config::default())
+    }
+
+    pub fn from_ir(ir: Vec<Node<Ir>>, config: &Config) -> Self {
+        let mask = config.cell_size.mask();
+        let mut code = Vec::new();
+        let mut locs: Vec<Location> = Vec::new();
+        let mut loops = HeadedList::new(Loop {
+            ops: ir.into_iter(),
+            start: 0,
+        }, None);
+        loop {
+            let node = match loops.val_mut().ops.next() {
+                Some(node) => node,
+                _ => match loops.take() {
+                    Some(lp) => {
+                        code.push(Op::JumpNonZero(lp.start + 1));
+                        locs.push(locs[lp.start].clone());
+                        code[lp.start] = Op::JumpZero(code.len());
+                        continue;
+                    },
+                    _ => break,
+                },
+            };
+            locs.push(node.loc);
+            code.push(match node.val {
+                Ir::Add {offset, amount} => Op::Add {
+                    offset: offset as isize,
+                    amount,
+                },
+                Ir::Set {offset, value} => Op::Set {
+                    offset: offset as isize,
+                    value: value as u64 & mask,
+                },
+                Ir::Mul {src, dst, factor} => Op::Mul {
+                    src: src as isize,
+                    dst: dst as isize,
+                    factor: factor as u64 & mask,
+                },
+                Ir::Move(n) => Op::Move(n as isize),
+                Ir::Scan(n) => Op::Scan(n as isize),
+                Ir::PutChar {offset} => Op::PutChar {offset: offset as isize},
+                Ir::GetChar {offset} => Op::GetChar {offset: offset as isize},
+                Ir::Loop(body) => {
+                    loops.receive(Loop {
+                        ops: body.into_iter(),
+                        start: code.len(),
+                    });
+                    Op::JumpZero(0)
+                },
+            });
+        }
+        Self {
+            code,
+            locs,
+            config: config.clone(),
+        }
+    }
+
+    pub fn code(&self) -> &[Op] {
+        &self.code
+    }
+
+    /// Runs the program, reading `,` from `input` and writing `.` to
+    /// `output`. Returns the tape and the index of the current cell when the
+    /// program ends. Errors which stop the program carry a `Fault`.
+    pub fn run<R: Read, W: Write>(
+        &self,
+        mut input: R,
+        mut output: W
+    ) -> Result<(Vec<u64>, usize), Error> {
+        let mut tape = vec![0u64; INITIAL_TAPE_LEN];
+        let mut cell = if self.config.tape.bidirectional() {
+            INITIAL_TAPE_LEN / 2
+        } else {
+            0
+        };
+        let result = self.exec(&mut input, &mut output, &mut tape, &mut cell);
+        // What was written before an error is still flushed.
+        let flushed = output.flush();
+        result?;
+        flushed?;
+        Ok((tape, cell))
+    }
+
+    fn exec<R: Read, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+        tape: &mut Vec<u64>,
+        cell: &mut usize
+    ) -> Result<(), Error> {
+        let mask = self.config.cell_size.mask();
+        let mut pc = 0;
+        while let Some(&op) = self.code.get(pc) {
+            let at = pc;
+            pc += 1;
+            match op {
+                Op::Add {offset, amount} => {
+                    let i = self.index(tape, cell, offset, at)?;
+                    tape[i] = self.add(tape[i], amount, at)?;
+                },
+                Op::Set {offset, value} => {
+                    let i = self.index(tape, cell, offset, at)?;
+                    tape[i] = value;
+                },
+                Op::Mul {src, dst, factor} => {
+                    // Growing the tape for `dst` may move the cells.
+                    self.index(tape, cell, src, at)?;
+                    let dst = self.index(tape, cell, dst, at)?;
+                    let src = self.index(tape, cell, src, at)?;
+                    let product = tape[src].wrapping_mul(factor);
+                    tape[dst] = tape[dst].wrapping_add(product) & mask;
+                },
+                Op::Move(n) => *cell = self.index(tape, cell, n, at)?,
+                Op::Scan(n) => while tape[*cell] != 0 {
+                    *cell = self.index(tape, cell, n, at)?;
+                },
+                Op::PutChar {offset} => {
+                    let i = self.index(tape, cell, offset, at)?;
+                    output.write_all(&[tape[i] as u8])?;
+                    if self.config.unbuffered {
+                        output.flush()?;
+                    }
+                },
+                Op::GetChar {offset} => {
+                    let i = self.index(tape, cell, offset, at)?;
+                    // Prompts are shown before waiting for input.
+                    output.flush()?;
+                    tape[i] = match (read_byte(input)?, self.config.eof) {
+                        (Some(byte), _) => byte as u64,
+                        (_, Eof::MinusOne) => mask,
+                        (_, Eof::Zero) => 0,
+                        (_, Eof::Unchanged) => tape[i],
+                    };
+                },
+                Op::JumpZero(target) => if tape[*cell] == 0 {
+                    pc = target;
+                },
+                Op::JumpNonZero(target) => if tape[*cell] != 0 {
+                    pc = target;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `amount` to a cell value as the operation at `pc` does, under
+    /// the configured overflow behavior.
+    fn add(&self, value: u64, amount: i64, pc: usize) -> Result<u64, Error> {
+        let mask = self.config.cell_size.mask();
+        if self.config.overflow == Overflow::Wrap {
+            return Ok(value.wrapping_add(amount as u64) & mask);
+        }
+        let sum = value as i128 + amount as i128;
+        if 0 <= sum && sum <= mask as i128 {
+            return Ok(sum as u64);
+        }
+        match self.config.overflow {
+            Overflow::Saturate if sum < 0 => Ok(0),
+            Overflow::Saturate => Ok(mask),
+            _ => Err(Error::other(Fault::Overflow(self.locs[pc].clone()))),
+        }
+    }
+
+    /// Finds the index of the cell at `offset` from `cell` for the operation
+    /// at `pc`, growing the tape if it is past the end, or past the start of
+    /// a bidirectional tape, which moves `cell` along.
+    fn index(
+        &self,
+        tape: &mut Vec<u64>,
+        cell: &mut usize,
+        offset: isize,
+        pc: usize
+    ) -> Result<usize, Error> {
+        let i = match cell.checked_add_signed(offset) {
+            Some(i) => i,
+            _ if self.config.tape.bidirectional() => {
+                let extra = tape.len().max(offset.unsigned_abs() - *cell);
+                tape.splice(0 .. 0, iter::repeat_n(0, extra));
+                *cell += extra;
+                *cell - offset.unsigned_abs()
+            },
+            _ => return Err(Error::other(Fault::Bounds(self.locs[pc].clone()))),
+        };
+        if i >= tape.len() {
+            let len = (tape.len() * 2).max(i + 1);
+            tape.resize(len, 0);
+        }
+        Ok(i)
+    }
+
+}
+
+/// Reads one byte, giving `None` on EOF.
+fn read_byte<R: Read>(input: &mut R) -> Result<Option<u8>, Error> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Lowers and runs the program once. See `Interpreter::run`.
+pub fn run<R: Read, W: Write>(
+    ast: Vec<Node<AstNode>>,
+    input: R,
+    output: W
+) -> Result<(Vec<u64>, usize), Error> {
+    Interpreter::new(ast).run(input, output)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        Interpreter,
+        Fault,
+        Op,
+        INITIAL_TAPE_LEN,
+    };
+    use front_end::{
+        self,
+        ByteStream,
+        Location,
+        Node,
+    };
+    use ir::{
+        self,
+        Ir,
+    };
+    use config::{
+        CellSize,
+        Config,
+        Eof,
+        Overflow,
+        Tape,
+    };
+
+    const SIZES: [CellSize; 4] = [
+        CellSize::Bits8,
+        CellSize::Bits16,
+        CellSize::Bits32,
+        CellSize::Bits64,
+    ];
+
+    fn loc(column: u64) -> Location {
+        Location {
+            file: String::from("test.bf"),
+            line: 1,
+            column,
+        }
+    }
+
+    /// Lowers the source without optimizing it.
+    fn compile(src: &str, config: &Config) -> Interpreter {
+        let stream = ByteStream::from_str(String::from("test.bf"), src);
+        let ast = front_end::parse(stream).unwrap();
+        Interpreter::from_ir(ir::lower(ast), config)
+    }
+
+    /// Gives every operation the same location.
+    fn from_ops(ops: Vec<Ir>, config: &Config) -> Interpreter {
+        let ir = ops.into_iter().map(|val| Node {val, loc: loc(1)}).collect();
+        Interpreter::from_ir(ir, config)
+    }
+
+    /// The current cell at the end of the program, or the error.
+    fn current(prog: &Interpreter, input: &[u8]) -> Result<u64, String> {
+        match prog.run(input, Vec::new()) {
+            Ok((tape, cell)) => Ok(tape[cell]),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// The tape and the output at the end of the program.
+    fn run_output(src: &str, input: &[u8]) -> (Vec<u64>, Vec<u8>) {
+        let mut output = Vec::new();
+        let prog = compile(src, &Config::default());
+        let (tape, _) = prog.run(input, &mut output).unwrap();
+        (tape, output)
+    }
+
+    fn config(cell_size: CellSize, overflow: Overflow) -> Config {
+        Config {
+            cell_size,
+            overflow,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn loop_patching() {
+        let prog = compile("+[>[-]<-]", &Config::default());
+        assert_eq!(prog.code(), [
+            Op::Add {offset: 0, amount: 1},
+            Op::JumpZero(9),
+            Op::Move(1),
+            Op::JumpZero(6),
+            Op::Add {offset: 0, amount: -1},
+            Op::JumpNonZero(4),
+            Op::Move(-1),
+            Op::Add {offset: 0, amount: -1},
+            Op::JumpNonZero(2),
+        ]);
+        assert_eq!(prog.locs[5], loc(4));
+        assert_eq!(prog.locs[8], loc(2));
+        // The loop is skipped when the cell is zero on entry.
+        let skipped = compile("[<]+", &Config::default());
+        assert_eq!(current(&skipped, b""), Ok(1));
+        let (_, output) = run_output("++++++++[>++++++++<-]>+.", b"");
+        assert_eq!(output, b"A");
+    }
+
+    #[test]
+    fn eof_modes() {
+        let cases = [
+            (Eof::MinusOne, "+,", 0xFFFF),
+            (Eof::Zero, "+,", 0),
+            (Eof::Unchanged, "+,", 1),
+        ];
+        for &(eof, src, value) in &cases {
+            let config = Config {eof, ..Config::default()};
+            let prog = compile(src, &config);
+            assert_eq!(current(&prog, b""), Ok(value), "{:?}", eof);
+            assert_eq!(current(&prog, b"x"), Ok(b'x' as u64), "{:?}", eof);
+        }
+        let (_, output) = run_output(",.,.,.", b"ab");
+        assert_eq!(output, [b'a', b'b', 0xFF]);
+    }
+
+    #[test]
+    fn wrap() {
+        for &size in &SIZES {
+            let config = config(size, Overflow::Wrap);
+            let mask = size.mask();
+            assert_eq!(current(&compile("-", &config), b""), Ok(mask));
+            let ops = vec![
+                Ir::Set {offset: 0, value: -1},
+                Ir::Add {offset: 0, amount: 3},
+            ];
+            assert_eq!(current(&from_ops(ops, &config), b""), Ok(2));
+        }
+    }
+
+    #[test]
+    fn saturate() {
+        for &size in &SIZES {
+            let config = config(size, Overflow::Saturate);
+            let mask = size.mask();
+            assert_eq!(current(&compile("+--", &config), b""), Ok(0));
+            let ops = vec![
+                Ir::Set {offset: 0, value: -2},
+                Ir::Add {offset: 0, amount: 5},
+            ];
+            assert_eq!(current(&from_ops(ops, &config), b""), Ok(mask));
+            let ops = vec![
+                Ir::Set {offset: 0, value: -2},
+                Ir::Add {offset: 0, amount: 1},
+            ];
+            assert_eq!(current(&from_ops(ops, &config), b""), Ok(mask));
+        }
+    }
+
+    #[test]
+    fn trap() {
+        for &size in &SIZES {
+            let config = config(size, Overflow::Trap);
+            let prog = compile("+--", &config);
+            let err = prog.run(&b""[..], Vec::new()).unwrap_err();
+            assert_eq!(Fault::of(&err), Some(&Fault::Overflow(loc(2))));
+            assert_eq!(err.to_string(), "Cell overflow in test.bf (1:2).");
+            let ops = vec![
+                Ir::Set {offset: 0, value: -2},
+                Ir::Add {offset: 0, amount: 1},
+            ];
+            assert_eq!(current(&from_ops(ops, &config), b""), Ok(size.mask()));
+            let ops = vec![
+                Ir::Set {offset: 0, value: -2},
+                Ir::Add {offset: 0, amount: 2},
+            ];
+            let prog = from_ops(ops, &config);
+            let err = prog.run(&b""[..], Vec::new()).unwrap_err();
+            assert_eq!(Fault::of(&err), Some(&Fault::Overflow(loc(1))));
+        }
+    }
+
+    #[test]
+    fn bidirectional_growth() {
+        let config = Config {
+            tape: Tape::Bidirectional(INITIAL_TAPE_LEN as u64),
+            ..Config::default()
+        };
+        let far = INITIAL_TAPE_LEN as i64 * 3;
+        let ops = vec![
+            Ir::Add {offset: 0, amount: 7},
+            Ir::Move(-far),
+            Ir::Add {offset: 0, amount: 1},
+            Ir::Add {offset: -1, amount: 2},
+        ];
+        let prog = from_ops(ops, &config);
+        let (tape, cell) = prog.run(&b""[..], Vec::new()).unwrap();
+        assert_eq!((tape[cell - 1], tape[cell]), (2, 1));
+        assert_eq!(tape[cell + far as usize], 7);
+        assert!(tape.len() > INITIAL_TAPE_LEN * 3);
+        // Cells left of the first one are reached by offset too.
+        let ops = vec![
+            Ir::Add {offset: -(INITIAL_TAPE_LEN as i64), amount: 3},
+            Ir::Move(-(INITIAL_TAPE_LEN as i64)),
+        ];
+        assert_eq!(current(&from_ops(ops, &config), b""), Ok(3));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let prog = compile("+>\n<<+", &Config::default());
+        let mut output = Vec::new();
+        let err = prog.run(&b""[..], &mut output).unwrap_err();
+        let at = Location {line: 2, ..loc(1)};
+        assert_eq!(Fault::of(&err), Some(&Fault::Bounds(at)));
+        assert_eq!(Fault::of(&err).unwrap().status(), 3);
+        assert_eq!(
+            err.to_string(),
+            "Tape pointer out of bounds in test.bf (2:1)."
+        );
+        // Growing to the right is fine.
+        let ops = vec![
+            Ir::Move(INITIAL_TAPE_LEN as i64 * 5),
+            Ir::Add {offset: 1, amount: 1},
+        ];
+        let prog = from_ops(ops, &Config::default());
+        let (tape, cell) = prog.run(&b""[..], Vec::new()).unwrap();
+        assert_eq!(tape[cell + 1], 1);
+    }
+
+}
@@ -0,0 +1,132 @@
+use front_end::{
+    AstNode,
+    Node,
+    Location,
+};
+use utils::{
+    HeadedList,
+};
+use std::vec::{
+    IntoIter,
+};
+use super::{
+    Ir,
+};
+
+
+#[derive(Clone, Debug)]
+struct Loop {
+    ops: IntoIter<Node<AstNode>>,
+    lowered: Vec<Node<Ir>>,
+    loc: Location,
+}
+
+/// Lowers the syntax tree into the intermediate representation, one
+/// operation per node, keeping every location.
+pub fn lower(ast: Vec<Node<AstNode>>) -> Vec<Node<Ir>> {
+    let mut loops = HeadedList::new(Loop {
+        ops: ast.into_iter(),
+        lowered: Vec::new(),
+        loc: Location {
+            file: String::new(),
+            line: 0,
+            column: 0,
+        },
+    }, None);
+    loop {
+        let Node {val, loc} = match loops.val_mut().ops.next() {
+            Some(node) => node,
+            _ => match loops.take() {
+                Some(lp) => {
+                    loops.val_mut().lowered.push(Node {
+                        val: Ir::Loop(lp.lowered),
+                        loc: lp.loc,
+                    });
+                    continue;
+                },
+                _ => break,
+            },
+        };
+        let val = match val {
+            AstNode::Increment(n) => Ir::Add {offset: 0, amount: n as i64},
+            AstNode::Decrement(n) => Ir::Add {offset: 0, amount: -(n as i64)},
+            AstNode::Next(n) => Ir::Move(n as i64),
+            AstNode::Previous(n) => Ir::Move(-(n as i64)),
+            AstNode::PutChar() => Ir::PutChar {offset: 0},
+            AstNode::GetChar() => Ir::GetChar {offset: 0},
+            AstNode::Loop(body) => {
+                loops.receive(Loop {
+                    ops: body.into_iter(),
+                    lowered: Vec::new(),
+                    loc,
+                });
+                continue;
+            },
+        };
+        loops.val_mut().lowered.push(Node {val, loc});
+    }
+    loops.reclaim_val().lowered
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        lower,
+    };
+    use super::super::{
+        Ir,
+    };
+    use front_end::{
+        self,
+        ByteStream,
+        Node,
+    };
+
+    fn lowered(src: &str) -> Vec<Node<Ir>> {
+        let stream = ByteStream::from_str(String::from("test.bf"), src);
+        lower(front_end::parse(stream).unwrap())
+    }
+
+    /// The line and column of every operation, in order.
+    fn locations(ops: &[Node<Ir>]) -> Vec<(u64, u64)> {
+        ops.iter().map(|op| (op.loc.line, op.loc.column)).collect()
+    }
+
+    #[test]
+    fn runs() {
+        // Runs of the same character are one operation, at their first
+        // character.
+        let ops = lowered("+++--\n>> <.,");
+        let vals: Vec<_> = ops.iter().map(|op| op.val.clone()).collect();
+        assert_eq!(vals, [
+            Ir::Add {offset: 0, amount: 3},
+            Ir::Add {offset: 0, amount: -2},
+            Ir::Move(2),
+            Ir::Move(-1),
+            Ir::PutChar {offset: 0},
+            Ir::GetChar {offset: 0},
+        ]);
+        assert_eq!(locations(&ops), [(1, 1), (1, 4), (2, 1), (2, 4), (2, 5), (2, 6)]);
+        assert!(lowered("").is_empty());
+    }
+
+    #[test]
+    fn nested_loops() {
+        let ops = lowered("+[>[-]\n<-]");
+        assert_eq!(locations(&ops), [(1, 1), (1, 2)]);
+        let body = match ops[1].val {
+            Ir::Loop(ref body) => body,
+            ref val => panic!("{:?}", val),
+        };
+        assert_eq!(locations(body), [(1, 3), (1, 4), (2, 1), (2, 2)]);
+        let inner = match body[1].val {
+            Ir::Loop(ref inner) => inner,
+            ref val => panic!("{:?}", val),
+        };
+        assert_eq!(inner[0].val, Ir::Add {offset: 0, amount: -1});
+        assert_eq!(locations(inner), [(1, 5)]);
+        assert_eq!(body[3].val, Ir::Add {offset: 0, amount: -1});
+    }
+
+}
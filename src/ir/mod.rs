@@ -0,0 +1,125 @@
+pub mod lower;
+
+pub use self::lower::lower;
+
+use front_end::{
+    Node,
+};
+use std::{
+    fmt,
+};
+
+
+/// Operations of the intermediate representation. Cells are addressed by
+/// their offset from the tape pointer, which only moves through `Move`.
+/// Arithmetic wraps around the cell size.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ir {
+    /// Adds `amount`, possibly negative, to a cell.
+    Add {
+        offset: i64,
+        amount: i64,
+    },
+    /// Stores `value` in a cell.
+    Set {
+        offset: i64,
+        value: i64,
+    },
+    /// Adds the cell at `src` multiplied by `factor` to the cell at `dst`.
+    Mul {
+        src: i64,
+        dst: i64,
+        factor: i64,
+    },
+    /// Moves the tape pointer by the given number of cells.
+    Move(i64),
+    /// Moves the tape pointer by the given number of cells until it reaches
+    /// a zero cell, which may be the current one.
+    Scan(i64),
+    PutChar {
+        offset: i64,
+    },
+    GetChar {
+        offset: i64,
+    },
+    /// Runs the body while the current cell is not zero.
+    Loop(Vec<Node<Ir>>),
+}
+
+fn cell(offset: i64) -> String {
+    format!("[{}]", offset)
+}
+
+impl fmt::Display for Ir {
+
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ir::Add {offset, amount} =>
+                write!(fmt, "add {} {}", cell(offset), amount),
+            Ir::Set {offset, value} =>
+                write!(fmt, "set {} {}", cell(offset), value),
+            Ir::Mul {src, dst, factor} =>
+                write!(fmt, "mul {} {} {}", cell(dst), cell(src), factor),
+            Ir::Move(n) => write!(fmt, "move {}", n),
+            Ir::Scan(n) => write!(fmt, "scan {}", n),
+            Ir::PutChar {offset} => write!(fmt, "putc {}", cell(offset)),
+            Ir::GetChar {offset} => write!(fmt, "getc {}", cell(offset)),
+            Ir::Loop(ref body) => write!(fmt, "loop ({} ops)", body.len()),
+        }
+    }
+
+}
+
+/// Pretty-prints a program, one operation per line with its location, and
+/// loop bodies indented between braces.
+pub fn pretty(ops: &[Node<Ir>]) -> String {
+    let mut out = String::new();
+    pretty_into(&mut out, ops, 0);
+    out
+}
+
+fn pretty_into(out: &mut String, ops: &[Node<Ir>], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for op in ops {
+        let loc = format!("; {}:{}", op.loc.line, op.loc.column);
+        match op.val {
+            Ir::Loop(ref body) => {
+                *out += &format!("{}loop {{ {}\n", indent, loc);
+                pretty_into(out, body, depth + 1);
+                *out += &format!("{}}}\n", indent);
+            },
+            ref val => *out += &format!("{}{} {}\n", indent, val, loc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        lower,
+        pretty,
+    };
+    use front_end::{
+        self,
+        ByteStream,
+    };
+
+    #[test]
+    fn pretty_printing() {
+        let stream = ByteStream::from_str(String::from("test.bf"), "++[>-\n[<]]\n.");
+        let ops = lower(front_end::parse(stream).unwrap());
+        assert_eq!(pretty(&ops), "\
+add [0] 2 ; 1:1
+loop { ; 1:3
+    move 1 ; 1:4
+    add [0] -1 ; 1:5
+    loop { ; 2:1
+        move -1 ; 2:2
+    }
+}
+putc [0] ; 3:1
+");
+    }
+
+}
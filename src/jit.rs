@@ -0,0 +1,494 @@
+use std::io::{
+    Read,
+    Write,
+    Error,
+    ErrorKind,
+};
+use std::os::raw::{
+    c_int,
+    c_long,
+    c_void,
+};
+use std::{
+    iter,
+    mem,
+    ptr,
+    slice,
+};
+use front_end::{
+    AstNode,
+    Node,
+};
+use ir::{
+    self,
+    Ir,
+};
+use opt::{
+    self,
+};
+use config::{
+    Config,
+};
+use interpreter::{
+    INITIAL_TAPE_LEN,
+};
+use back_end::x86::{
+    self,
+    Codegen,
+    Linkage,
+    X86Mode,
+};
+
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const PROT_EXEC: c_int = 4;
+const MAP_PRIVATE: c_int = 2;
+const MAP_ANONYMOUS: c_int = 0x20;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: c_long
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+type Entry = unsafe extern "C" fn(*mut u8, *mut Context) -> *mut u8;
+
+/// The context passed to the generated code. The first fields are read by it
+/// at fixed offsets, see `Linkage::Jit`.
+#[repr(C)]
+struct Context<'a> {
+    start: *mut u8,
+    end: *mut u8,
+    get: extern "C" fn(*mut Context) -> c_int,
+    put: extern "C" fn(*mut Context, c_int) -> c_int,
+    bounds: extern "C" fn(*mut Context, *mut u8, *const u8) -> *mut u8,
+    fail: extern "C" fn(*mut Context, *const u8),
+    /// The memory of the tape. Its elements are not the cells, but keep the
+    /// tape aligned for any cell size.
+    tape: Vec<u64>,
+    cell_bytes: usize,
+    /// Whether the tape grows before its first cell too.
+    bidirectional: bool,
+    /// Whether the output is flushed after each byte.
+    unbuffered: bool,
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    error: Option<Error>,
+}
+
+impl<'a> Context<'a> {
+
+    fn update_bounds(&mut self) {
+        self.start = self.tape.as_mut_ptr() as *mut u8;
+        let len = self.tape.len() * mem::size_of::<u64>();
+        self.end = self.start.wrapping_add(len);
+    }
+
+    /// The index of the cell at the given address.
+    fn index(&self, cell: *mut u8) -> usize {
+        (cell as usize - self.start as usize) / self.cell_bytes
+    }
+
+    /// Reads the cells of the tape.
+    fn cells(&self) -> Vec<u64> {
+        let len = self.tape.len() * mem::size_of::<u64>() / self.cell_bytes;
+        (0 .. len).map(|i| {
+            let mut bytes = [0; 8];
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    self.start.add(i * self.cell_bytes),
+                    bytes.as_mut_ptr(),
+                    self.cell_bytes
+                );
+            }
+            u64::from_le_bytes(bytes)
+        }).collect()
+    }
+
+}
+
+extern "C" fn get(ctx: *mut Context) -> c_int {
+    let ctx = unsafe {&mut *ctx};
+    // Prompts are shown before waiting for input.
+    if let Err(e) = ctx.output.flush() {
+        ctx.error = Some(e);
+        return -2;
+    }
+    let mut byte = [0];
+    loop {
+        match ctx.input.read(&mut byte) {
+            Ok(0) => return -1,
+            Ok(_) => return byte[0] as c_int,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => {
+                ctx.error = Some(e);
+                return -2;
+            },
+        }
+    }
+}
+
+extern "C" fn put(ctx: *mut Context, ch: c_int) -> c_int {
+    let ctx = unsafe {&mut *ctx};
+    let written = ctx.output.write_all(&[ch as u8]).and_then(|_| {
+        if ctx.unbuffered {
+            ctx.output.flush()
+        } else {
+            Ok(())
+        }
+    });
+    match written {
+        Ok(_) => ch as u8 as c_int,
+        Err(e) => {
+            ctx.error = Some(e);
+            -1
+        },
+    }
+}
+
+/// Reads a message laid out as for `_fail`, see `Linkage::Jit`.
+fn message(message: *const u8) -> Error {
+    let text = unsafe {
+        let len = u16::from_le_bytes([*message.add(1), *message.add(2)]);
+        slice::from_raw_parts(message.add(3), len as usize)
+    };
+    Error::other(String::from_utf8_lossy(text).trim_end())
+}
+
+extern "C" fn bounds(
+    ctx: *mut Context,
+    cell: *mut u8,
+    error: *const u8
+) -> *mut u8 {
+    let ctx = unsafe {&mut *ctx};
+    if (cell as usize) < (ctx.start as usize) {
+        if !ctx.bidirectional {
+            ctx.error = Some(message(error));
+            return ptr::null_mut();
+        }
+        let missing = ctx.start as usize - cell as usize;
+        let word = mem::size_of::<u64>();
+        let extra = ctx.tape.len().max(missing.div_ceil(word));
+        ctx.tape.splice(0 .. 0, iter::repeat_n(0, extra));
+        ctx.update_bounds();
+        return ctx.start.wrapping_add(extra * word - missing);
+    }
+    let index = ctx.index(cell);
+    let needed = ((index + 1) * ctx.cell_bytes).div_ceil(mem::size_of::<u64>());
+    let len = (ctx.tape.len() * 2).max(needed);
+    ctx.tape.resize(len, 0);
+    ctx.update_bounds();
+    ctx.start.wrapping_add(index * ctx.cell_bytes)
+}
+
+extern "C" fn fail(ctx: *mut Context, error: *const u8) {
+    let ctx = unsafe {&mut *ctx};
+    ctx.error = Some(message(error));
+}
+
+/// Executable memory holding the amd64 code of a compiled program, which runs
+/// inside the current process. Only available on x86-64 Linux.
+pub struct Jit {
+    code: *mut c_void,
+    len: usize,
+    config: Config,
+}
+
+// The code is never written after compilation.
+unsafe impl Send for Jit {}
+unsafe impl Sync for Jit {}
+
+impl Jit {
+
+    /// Optimizes and compiles the program into executable memory, with the
+    /// default configuration.
+    pub fn compile(ast: Vec<Node<AstNode>>) -> Result<Self, Error> {
+        Self::from_ir(opt::optimize(ir::lower(ast)), &Config::default())
+    }
+
+    /// Compiles an already lowered program into executable memory.
+    pub fn from_ir(ir: Vec<Node<Ir>>, config: &Config) -> Result<Self, Error> {
+        X86Mode::Amd64.check(config)?;
+        let insts = Codegen::new(X86Mode::Amd64, Linkage::Jit, config).generate(ir);
+        let program = x86::assemble(X86Mode::Amd64, &insts)?;
+        let len = program.code.len();
+        let code = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0
+            )
+        };
+        // MAP_FAILED is -1.
+        if code as isize == -1 {
+            return Err(Error::last_os_error());
+        }
+        let jit = Self {
+            code,
+            len,
+            config: config.clone(),
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(program.code.as_ptr(), code as *mut u8, len);
+            if mprotect(code, len, PROT_READ | PROT_EXEC) != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(jit)
+    }
+
+    /// Runs the program, reading `,` from `input` and writing `.` to
+    /// `output`. Returns the tape and the index of the current cell when the
+    /// program ends.
+    pub fn run<R: Read, W: Write>(
+        &self,
+        mut input: R,
+        mut output: W
+    ) -> Result<(Vec<u64>, usize), Error> {
+        let cell_bytes = self.config.cell_size.bytes();
+        let mut ctx = Context {
+            start: ptr::null_mut(),
+            end: ptr::null_mut(),
+            get,
+            put,
+            bounds,
+            fail,
+            tape: vec![0; INITIAL_TAPE_LEN * cell_bytes / mem::size_of::<u64>()],
+            cell_bytes,
+            bidirectional: self.config.tape.bidirectional(),
+            unbuffered: self.config.unbuffered,
+            input: &mut input,
+            output: &mut output,
+            error: None,
+        };
+        ctx.update_bounds();
+        // A bidirectional tape starts in its middle.
+        let first = if ctx.bidirectional {
+            ctx.start.wrapping_add(INITIAL_TAPE_LEN / 2 * cell_bytes)
+        } else {
+            ctx.start
+        };
+        let cell = unsafe {
+            let entry: Entry = mem::transmute(self.code);
+            entry(first, &mut ctx)
+        };
+        // What was written before an error is still flushed.
+        let flushed = ctx.output.flush();
+        if let Some(e) = ctx.error.take() {
+            return Err(e);
+        }
+        flushed?;
+        Ok((ctx.cells(), ctx.index(cell)))
+    }
+
+}
+
+impl Drop for Jit {
+
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.code, self.len);
+        }
+    }
+
+}
+
+/// Compiles and runs the program once. See `Jit::run`.
+pub fn run<R: Read, W: Write>(
+    ast: Vec<Node<AstNode>>,
+    input: R,
+    output: W
+) -> Result<(Vec<u64>, usize), Error> {
+    Jit::compile(ast)?.run(input, output)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        Jit,
+    };
+    use front_end::{
+        self,
+        ByteStream,
+        Node,
+    };
+    use ir::{
+        self,
+        Ir,
+    };
+    use interpreter::{
+        Interpreter,
+        INITIAL_TAPE_LEN,
+    };
+    use opt::{
+        PassManager,
+    };
+    use config::{
+        CellSize,
+        Config,
+        Eof,
+        Overflow,
+        Tape,
+    };
+    use std::io::{
+        self,
+        Read,
+        Write,
+    };
+
+    /// The current cell at the end of the program, or the error, and the
+    /// output written.
+    type Outcome = (Result<u64, String>, Vec<u8>);
+
+    fn optimized(src: &str, config: &Config) -> Vec<Node<Ir>> {
+        let stream = ByteStream::from_str(String::from("test.bf"), src);
+        let ast = front_end::parse(stream).unwrap();
+        PassManager::default().run(ir::lower(ast), config)
+    }
+
+    fn outcome(result: io::Result<(Vec<u64>, usize)>, output: Vec<u8>) -> Outcome {
+        match result {
+            Ok((tape, cell)) => (Ok(tape[cell]), output),
+            Err(e) => (Err(e.to_string()), output),
+        }
+    }
+
+    /// Runs the program with the JIT and with the interpreter, checking that
+    /// they agree, and gives what they did.
+    fn run(src: &str, config: &Config, input: &[u8]) -> Outcome {
+        let ir = optimized(src, config);
+        let jit = Jit::from_ir(ir.clone(), config).unwrap();
+        let mut output = Vec::new();
+        let jitted = outcome(jit.run(input, &mut output), output);
+        let interpreter = Interpreter::from_ir(ir, config);
+        let mut output = Vec::new();
+        let interpreted = outcome(interpreter.run(input, &mut output), output);
+        assert_eq!(jitted, interpreted, "{}", src);
+        jitted
+    }
+
+    /// Fails every read and write.
+    struct Broken;
+
+    impl Read for Broken {
+
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken input"))
+        }
+
+    }
+
+    impl Write for Broken {
+
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken output"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+    }
+
+    #[test]
+    fn io_hooks() {
+        let config = Config::default();
+        let hello = "++++++++[>+++++++++<-]>.<+++[>++++++++++<-]>-.";
+        assert_eq!(run(hello, &config, b""), (Ok(b'e' as u64), b"He".to_vec()));
+        let zero = Config {eof: Eof::Zero, ..Config::default()};
+        assert_eq!(run(",[.,]", &zero, b"abc").1, b"abc");
+        let eofs = [(Eof::MinusOne, 0xFFFF), (Eof::Zero, 0), (Eof::Unchanged, 1)];
+        for &(eof, value) in &eofs {
+            let config = Config {eof, ..Config::default()};
+            assert_eq!(run("+,", &config, b"").0, Ok(value));
+            assert_eq!(run("+,", &config, b"x").0, Ok(b'x' as u64));
+        }
+        let unbuffered = Config {unbuffered: true, ..Config::default()};
+        assert_eq!(run("+.+.", &unbuffered, b"").1, [1, 2]);
+    }
+
+    #[test]
+    fn tape_growth() {
+        let sizes = [
+            CellSize::Bits8,
+            CellSize::Bits16,
+            CellSize::Bits32,
+            CellSize::Bits64,
+        ];
+        for &cell_size in &sizes {
+            let config = Config {cell_size, ..Config::default()};
+            // Marks cells one by one, and finds the first one again.
+            let fill = format!(">{}<[<]>", "+>".repeat(INITIAL_TAPE_LEN * 3));
+            assert_eq!(run(&fill, &config, b"").0, Ok(1));
+            let last = format!("{}-", ">".repeat(INITIAL_TAPE_LEN * 5));
+            assert_eq!(run(&last, &config, b"").0, Ok(cell_size.mask()));
+            let far = ">".repeat(INITIAL_TAPE_LEN * 5);
+            let back = format!("+{}{}", far, "<".repeat(INITIAL_TAPE_LEN * 5));
+            assert_eq!(run(&back, &config, b"").0, Ok(1));
+        }
+    }
+
+    #[test]
+    fn bidirectional() {
+        let config = Config {
+            tape: Tape::Bidirectional(Tape::DEFAULT_BIDIRECTIONAL),
+            ..Config::default()
+        };
+        let far = INITIAL_TAPE_LEN * 3;
+        let left = format!("+{}++", "<".repeat(far));
+        assert_eq!(run(&left, &config, b"").0, Ok(2));
+        let back = format!("{}{}", left, ">".repeat(far));
+        assert_eq!(run(&back, &config, b"").0, Ok(1));
+        // Scans grow the tape to the left too.
+        let scan = format!("{}+[<+{}]", "<".repeat(far), "<".repeat(100));
+        assert_eq!(run(&scan, &config, b"").0, Ok(0));
+        assert_eq!(run("+[<]+", &config, b"").0, Ok(1));
+    }
+
+    #[test]
+    fn errors() {
+        let checked = Config {checked: true, ..Config::default()};
+        let out = run("+.>\n<<+", &checked, b"");
+        let message = "Tape pointer out of bounds in test.bf (2:1).";
+        assert_eq!(out, (Err(String::from(message)), vec![1]));
+        let out = run("+[<]", &Config::default(), b"");
+        let message = "Tape pointer out of bounds in test.bf (1:3).";
+        assert_eq!(out.0, Err(String::from(message)));
+        let trap = Config {
+            cell_size: CellSize::Bits8,
+            overflow: Overflow::Trap,
+            ..Config::default()
+        };
+        let out = run("+.--", &trap, b"");
+        let message = "Cell overflow in test.bf (1:3).";
+        assert_eq!(out, (Err(String::from(message)), vec![1]));
+        let config = Config::default();
+        for src in &["+.", ","] {
+            let ir = optimized(src, &config);
+            let jit = Jit::from_ir(ir.clone(), &config).unwrap();
+            let interpreter = Interpreter::from_ir(ir, &config);
+            let jitted = jit.run(Broken, Broken).unwrap_err().to_string();
+            let interpreted = interpreter.run(Broken, Broken).unwrap_err();
+            let interpreted = interpreted.to_string();
+            assert_eq!(jitted, interpreted, "{}", src);
+        }
+        // Nothing runs after the error.
+        let jit = Jit::from_ir(optimized(",+.", &config), &config).unwrap();
+        let mut output = Vec::new();
+        assert!(jit.run(Broken, &mut output).is_err());
+        assert!(output.is_empty());
+    }
+
+}
@@ -1,24 +1,237 @@
 pub mod front_end;
+pub mod config;
+pub mod ir;
+pub mod opt;
 pub mod back_end;
 pub mod utils;
+pub mod interpreter;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
 
 pub use back_end::Format;
 
 use front_end::{
     ByteStream,
+    AstNode,
+    Node,
+};
+use std::io::{
+    self,
 };
 use back_end::{
     X86Mode,
+    Aarch64,
+    Riscv64,
+    Armv7,
+    Wasm,
+    C,
+    Llvm,
     Arch,
 };
+use interpreter::{
+    Interpreter,
+    Fault,
+};
+use config::{
+    Config,
+    CellSize,
+    Eof,
+    Overflow,
+    Tape,
+};
+use opt::{
+    PassManager,
+    MAX_LEVEL,
+    DEFAULT_LEVEL,
+};
+
+/// Options changing the behavior of programs, accepted both when compiling
+/// and when running.
+struct ConfigArgs {
+    cell_size: Option<CellSize>,
+    eof: Option<Eof>,
+    overflow: Option<Overflow>,
+    tape: Option<Tape>,
+    checked: bool,
+    unbuffered: bool,
+    exit_cell: bool,
+}
+
+impl ConfigArgs {
+
+    fn new() -> Self {
+        Self {
+            cell_size: None,
+            eof: None,
+            overflow: None,
+            tape: None,
+            checked: false,
+            unbuffered: false,
+            exit_cell: false,
+        }
+    }
+
+    /// Takes `arg` if it is a configuration option, giving whether it was.
+    fn parse(&mut self, arg: &str) -> Result<bool, String> {
+        if let Some(bits) = arg.strip_prefix("--cell-size=") {
+            if self.cell_size.is_some() {
+                return Err(String::from("Cell size already passed."));
+            }
+            self.cell_size = match bits.parse().ok().and_then(CellSize::from_bits) {
+                Some(size) => Some(size),
+                _ => return Err(format!("Unsupported cell size {}.", bits)),
+            };
+        } else if let Some(name) = arg.strip_prefix("--eof=") {
+            if self.eof.is_some() {
+                return Err(String::from("EOF behavior already passed."));
+            }
+            self.eof = match Eof::from_name(name) {
+                Some(eof) => Some(eof),
+                _ => return Err(format!("Unsupported EOF behavior {}.", name)),
+            };
+        } else if let Some(name) = arg.strip_prefix("--overflow=") {
+            if self.overflow.is_some() {
+                return Err(String::from("Overflow behavior already passed."));
+            }
+            self.overflow = match Overflow::from_name(name) {
+                Some(overflow) => Some(overflow),
+                _ => return Err(format!("Unsupported overflow behavior {}.", name)),
+            };
+        } else if let Some(name) = arg.strip_prefix("--tape=") {
+            if self.tape.is_some() {
+                return Err(String::from("Tape already passed."));
+            }
+            self.tape = match Tape::from_name(name) {
+                Some(tape) => Some(tape),
+                _ => return Err(format!("Unsupported tape {}.", name)),
+            };
+        } else if arg == "--checked" {
+            if self.checked {
+                return Err(String::from("Checked mode already passed."));
+            }
+            self.checked = true;
+        } else if arg == "--unbuffered" {
+            if self.unbuffered {
+                return Err(String::from("Unbuffered mode already passed."));
+            }
+            self.unbuffered = true;
+        } else if arg == "--exit-cell" {
+            if self.exit_cell {
+                return Err(String::from("Exit cell mode already passed."));
+            }
+            self.exit_cell = true;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn config(&self) -> Config {
+        let mut config = Config::default();
+        if let Some(size) = self.cell_size {
+            config.cell_size = size;
+        }
+        if let Some(eof) = self.eof {
+            config.eof = eof;
+        }
+        if let Some(overflow) = self.overflow {
+            config.overflow = overflow;
+        }
+        if let Some(tape) = self.tape {
+            config.tape = tape;
+        }
+        config.checked = self.checked;
+        config.unbuffered = self.unbuffered;
+        config.exit_cell = self.exit_cell;
+        config
+    }
+
+}
+
+/// Optimization options, accepted both when compiling and when running.
+struct OptArgs {
+    level: Option<u8>,
+    flags: Vec<(String, bool)>,
+    print_after: Vec<String>,
+}
+
+impl OptArgs {
+
+    fn new() -> Self {
+        Self {
+            level: None,
+            flags: Vec::new(),
+            print_after: Vec::new(),
+        }
+    }
+
+    /// Takes `arg` if it is an optimization option, giving whether it was.
+    fn parse(&mut self, arg: &str) -> Result<bool, String> {
+        if let Some(level) = arg.strip_prefix("-O") {
+            if self.level.is_some() {
+                return Err(String::from("Optimization level already passed."));
+            }
+            self.level = match level.parse() {
+                Ok(n) if n <= MAX_LEVEL => Some(n),
+                _ => return Err(format!("Unsupported optimization level {}.", level)),
+            };
+        } else if let Some(pass) = arg.strip_prefix("--print-after=") {
+            self.print_after.push(pass.to_string());
+        } else if let Some(pass) = arg.strip_prefix("-fno-") {
+            self.flags.push((pass.to_string(), false));
+        } else if let Some(pass) = arg.strip_prefix("-f").filter(|p| !p.is_empty()) {
+            self.flags.push((pass.to_string(), true));
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn pass_manager(&self) -> Result<PassManager, io::Error> {
+        let mut manager = PassManager::new(self.level.unwrap_or(DEFAULT_LEVEL));
+        for &(ref pass, enable) in &self.flags {
+            if enable {
+                manager.enable(pass)?;
+            } else {
+                manager.disable(pass)?;
+            }
+        }
+        for pass in &self.print_after {
+            manager.print_after(pass)?;
+        }
+        Ok(manager)
+    }
+
+}
 
 pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
     let mut mfile = None;
     let mut mout = None;
     let mut march = None;
     let mut mformat = None;
+    let mut msymbol = None;
+    let mut opts = OptArgs::new();
+    let mut cfg = ConfigArgs::new();
     args.next();
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("run") {
+        args.next();
+        return run(args);
+    }
     while let Some(arg) = args.next() {
+        let taken = match opts.parse(&arg) {
+            Ok(false) => cfg.parse(&arg),
+            taken => taken,
+        };
+        match taken {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(e) => {
+                println!("{}", e);
+                print_usage();
+                return 1;
+            },
+        }
         match arg.as_ref() {
             "-h" | "--help" => {
                 print_usage();
@@ -27,8 +240,14 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
             "-a" => match args.next() {
                 Some(arg) => match &march {
                     &None => march = Some(match arg.as_ref() {
-                        "x86" => X86Mode::X86,
-                        "x86-64" | "x86_64" | "amd64" | "x64" => X86Mode::Amd64,
+                        "x86" => Box::new(X86Mode::X86) as Box<dyn Arch>,
+                        "x86-64" | "x86_64" | "amd64" | "x64" => Box::new(X86Mode::Amd64),
+                        "aarch64" | "arm64" => Box::new(Aarch64),
+                        "riscv64" => Box::new(Riscv64),
+                        "armv7" | "arm" => Box::new(Armv7),
+                        "wasm32" | "wasm" => Box::new(Wasm),
+                        "c" => Box::new(C),
+                        "llvm" => Box::new(Llvm),
                         a => {
                             println!("Unsupported architecture {}.", a);
                             print_usage();
@@ -67,6 +286,8 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
                     &None => mformat = Some(match arg.as_ref() {
                         "elf" => Format::Elf,
                         "asm" => Format::Asm,
+                        "bin" => Format::Bin,
+                        "obj" => Format::Object(String::from("bf_main")),
                         f => {
                             println!("Unsupported format {}.", f);
                             print_usage();
@@ -85,6 +306,21 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
                     return 1;
                 },
             },
+            "-s" => match args.next() {
+                Some(s) => match &msymbol {
+                    &None => msymbol = Some(s),
+                    _ => {
+                        println!("Symbol name already passed.");
+                        print_usage();
+                        return 1;
+                    },
+                },
+                _ => {
+                    println!("Expecting one more argument after -s");
+                    print_usage();
+                    return 1;
+                },
+            },
             _ => match &mfile {
                 &None => mfile = Some(arg),
                 _ => {
@@ -108,23 +344,42 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
     };
     let arch = match march {
         Some(a) => a,
-        #[cfg(all(target_pointer_width = "64"))]
-        _ => X86Mode::Amd64,
-        #[cfg(all(target_pointer_width = "32"))]
-        _ => X86Mode::X86,
+        #[cfg(target_arch = "aarch64")]
+        _ => Box::new(Aarch64),
+        #[cfg(target_arch = "riscv64")]
+        _ => Box::new(Riscv64),
+        #[cfg(target_arch = "arm")]
+        _ => Box::new(Armv7),
+        #[cfg(all(
+            not(any(target_arch = "aarch64", target_arch = "riscv64", target_arch = "arm")),
+            target_pointer_width = "64"
+        ))]
+        _ => Box::new(X86Mode::Amd64),
+        #[cfg(all(
+            not(any(target_arch = "aarch64", target_arch = "riscv64", target_arch = "arm")),
+            target_pointer_width = "32"
+        ))]
+        _ => Box::new(X86Mode::X86),
     };
-    let format = match mformat {
-        Some(f) => f,
-        _ => Format::Elf,
+    let format = match (mformat, msymbol) {
+        (Some(Format::Object(_)), Some(symbol)) => Format::Object(symbol),
+        (_, Some(_)) => {
+            println!("Symbol name can only be passed with the `obj` format.");
+            print_usage();
+            return 1;
+        },
+        (Some(f), _) => f,
+        _ => arch.default_format(),
     };
-    let bs = match ByteStream::from_file(file.clone()) {
-        Ok(bs) => bs,
+    let manager = match opts.pass_manager() {
+        Ok(manager) => manager,
         Err(e) => {
-            println!("Error opening {}: {}", file, e);
-            return -1;
+            println!("{}", e);
+            print_usage();
+            return 1;
         },
     };
-    let tree = match front_end::parse(bs) {
+    let tree = match load(file) {
         Ok(tree) => tree,
         Err(errs) => {
             for err in errs {
@@ -133,7 +388,8 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
             return -1;
         }
     };
-    match arch.generate(tree, format, out) {
+    let config = cfg.config();
+    match arch.generate(manager.run(ir::lower(tree), &config), &config, format, out) {
         Err(e) => {
             println!("{}", e);
             -1
@@ -142,14 +398,170 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> i32 {
     }
 }
 
+/// Runs a program with the interpreter, over the standard input and output.
+fn run<T: Iterator<Item = String>>(args: T) -> i32 {
+    let mut mfile = None;
+    let mut opts = OptArgs::new();
+    let mut cfg = ConfigArgs::new();
+    for arg in args {
+        let taken = match opts.parse(&arg) {
+            Ok(false) => cfg.parse(&arg),
+            taken => taken,
+        };
+        match taken {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(e) => {
+                eprintln!("{}", e);
+                print_usage();
+                return 1;
+            },
+        }
+        match arg.as_ref() {
+            "-h" | "--help" => {
+                print_usage();
+                return 0;
+            },
+            _ => match &mfile {
+                &None => mfile = Some(arg),
+                _ => {
+                    eprintln!("Input file already passed.");
+                    print_usage();
+                    return 1;
+                },
+            },
+        }
+    }
+    let file = match mfile {
+        Some(f) => f,
+        _ => {
+            print_usage();
+            return 1;
+        }
+    };
+    let manager = match opts.pass_manager() {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_usage();
+            return 1;
+        },
+    };
+    let tree = match load(file) {
+        Ok(tree) => tree,
+        Err(errs) => {
+            for err in errs {
+                eprintln!("{}", err);
+            }
+            return -1;
+        }
+    };
+    let config = cfg.config();
+    let interpreter = Interpreter::from_ir(
+        manager.run(ir::lower(tree), &config),
+        &config
+    );
+    match interpreter.run(io::stdin(), io::stdout()) {
+        // Failing programs exit as compiled ones would.
+        Err(e) => {
+            eprintln!("{}", e);
+            match Fault::of(&e) {
+                Some(fault) => fault.status() as i32,
+                _ => back_end::IO_EXIT as i32,
+            }
+        },
+        Ok((tape, cell)) if config.exit_cell => (tape[cell] & 0xFF) as i32,
+        _ => 0,
+    }
+}
+
+/// Reads and parses a file, giving the error messages on failure.
+fn load(file: String) -> Result<Vec<Node<AstNode>>, Vec<String>> {
+    let bs = match ByteStream::from_file(file.clone()) {
+        Ok(bs) => bs,
+        Err(e) => return Err(vec![format!("Error opening {}: {}", file, e)]),
+    };
+    match front_end::parse(bs) {
+        Ok(tree) => Ok(tree),
+        Err(errs) => Err(errs.iter().map(|e| e.to_string()).collect()),
+    }
+}
+
 fn print_usage() {
     println!("bfc [options] file");
+    println!("bfc run [optimization and configuration options] file");
+    println!("    Runs the file with the interpreter, instead of compiling it.");
     println!("options:");
-    print!  ("    -a X                      Sets the architecture to X, where X can be `x86` or `amd64`.");
-    println!("Instead of `amd64`, `x86_64`, `x86-64` or `x64` could also be written. Must be defined only once.");
-    print!  ("    -f X                      Sets the format to X, where X can be `asm` or `elf`.");
+    print!  ("    -a X                      Sets the architecture to X, where X can be `x86`, `amd64`, `aarch64`, `riscv64`,");
+    print!  (" `armv7`, `wasm32`, `c` or `llvm`. Instead of `amd64`, `x86_64`, `x86-64` or `x64` could also be written, `arm64`");
+    println!(" instead of `aarch64`, `arm` instead of `armv7`, and `wasm` instead of `wasm32`. Must be defined only once.");
+    print!  ("    -f X                      Sets the format to X, where X can be `asm`, `bin`, `elf` or `obj`.");
+    print!  (" Defaults to `elf`, or to `bin` for `wasm32`, whose `asm` is the text format and `bin` a module.");
+    print!  (" For `c` and `llvm`, `asm` is a program and the default, and `obj` a function.");
     println!(" Must be defined only once");
     print!  ("    -h, --help                Shows this help message and exits.");
     println!(" File argument is not necessary in this case.");
     println!("    -o X                      Sets output file to X. Must be defined only once.");
+    print!  ("    -s X                      Sets the name of the function exported by the `obj` format to X.");
+    println!(" Defaults to `bf_main`. Must be defined only once.");
+    println!("configuration options:");
+    print!  ("    --checked                 Makes executables check that the pointer stays in the tape, exiting with status {}", back_end::BOUNDS_EXIT);
+    println!(" and a message naming the faulty instruction if it does not.");
+    println!("    --cell-size=X             Sets the size of cells to X bits, where X can be 8, 16, 32 or 64. Defaults to 16.");
+    print!  ("    --eof=X                   Sets what `,` stores at the end of the input, where X can be `minus-one`, `zero` or `unchanged`.");
+    println!(" Defaults to `minus-one`.");
+    println!("    --exit-cell               Makes programs exit with the low byte of the current cell as status, instead of 0.");
+    print!  ("    --overflow=X              Sets what `+` and `-` do when a cell overflows, where X can be `wrap`, `trap`, which exits");
+    println!(" with status {} naming the instruction, or `saturate`. Defaults to `wrap`.", back_end::OVERFLOW_EXIT);
+    print!  ("    --tape=X                  Sets the tape, where X can be `stack`, `heap[:N]`, where executables reserve N cells with mmap,");
+    print!  (" {} by default, or `bidirectional[:N]`, which also grows left of the first cell and starts in the middle of N cells,", Tape::DEFAULT_HEAP);
+    println!(" {} by default. Defaults to `stack`.", Tape::DEFAULT_BIDIRECTIONAL);
+    print!  ("    --unbuffered              Makes executables write and read one byte at a time, instead of flushing their output");
+    println!(" on newlines, when full, before reading and at exit, and reading their input ahead.");
+    println!("optimization options:");
+    print!  ("    -O0, -O1, -O2, -O3        Sets the optimization level.");
+    println!(" Defaults to -O{}. Must be defined only once.", DEFAULT_LEVEL);
+    println!("    -fX, -fno-X               Enables or disables the pass X, after the level is applied.");
+    println!("    --print-after=X           Prints the program to stderr after the pass X, or after every pass if X is `all`.");
+    print!  ("passes:");
+    for pass in opt::PASSES {
+        print!(" {} (-O{})", pass.name, pass.level);
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        OptArgs,
+    };
+
+    #[test]
+    fn optimization_options() {
+        let mut opts = OptArgs::new();
+        for arg in &["-O3", "-fno-mul", "-fscan", "--print-after=dead"] {
+            assert_eq!(opts.parse(arg), Ok(true), "{}", arg);
+        }
+        assert_eq!(opts.level, Some(3));
+        assert_eq!(opts.flags, [
+            (String::from("mul"), false),
+            (String::from("scan"), true),
+        ]);
+        assert!(opts.pass_manager().is_ok());
+        assert!(opts.parse("-O1").is_err());
+        for arg in &["-f", "-o", "--checked", "file.bf"] {
+            assert_eq!(OptArgs::new().parse(arg), Ok(false), "{}", arg);
+        }
+        for arg in &["-O4", "-O", "-Ofast"] {
+            assert!(OptArgs::new().parse(arg).is_err(), "{}", arg);
+        }
+        let mut opts = OptArgs::new();
+        assert_eq!(opts.parse("-funroll"), Ok(true));
+        assert!(opts.pass_manager().is_err());
+        let mut opts = OptArgs::new();
+        assert_eq!(opts.parse("--print-after=all"), Ok(true));
+        assert!(opts.pass_manager().is_ok());
+    }
+
 }
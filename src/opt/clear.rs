@@ -0,0 +1,168 @@
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Overflow,
+};
+use super::{
+    transform_blocks,
+};
+
+
+/// Whether a loop body only adds an odd amount to the current cell. Since odd
+/// numbers are invertible modulo the cell size, such a loop always ends with
+/// the cell cleared when it wraps. Otherwise, only `[-]` does.
+fn is_clear(body: &[Node<Ir>], overflow: Overflow) -> bool {
+    match (body, overflow) {
+        ([Node {val: Ir::Add {offset: 0, amount}, ..}], Overflow::Wrap) =>
+            amount % 2 != 0,
+        ([Node {val: Ir::Add {offset: 0, amount: -1}, ..}], _) => true,
+        _ => false,
+    }
+}
+
+/// The value of a store followed by an addition, if it can be known. Without
+/// wrapping, it must stay in the range of a cell.
+fn add_to_set(value: i64, amount: i64, config: &Config) -> Option<i64> {
+    if config.overflow == Overflow::Wrap {
+        return Some(value.wrapping_add(amount));
+    }
+    value.checked_add(amount)
+        .filter(|&sum| sum >= 0 && sum as u64 <= config.cell_size.mask())
+}
+
+/// Replaces clear loops such as `[-]` and `[+]` with a store of zero, and
+/// folds arithmetic around stores: an addition right after a store becomes
+/// part of the stored value, and an addition or store right before a store
+/// to the same cell is dropped. Additions that may trap are kept.
+pub fn run(ir: Vec<Node<Ir>>, config: &Config) -> Vec<Node<Ir>> {
+    let traps = config.overflow == Overflow::Trap;
+    transform_blocks(ir, |block| {
+        let mut out: Vec<Node<Ir>> = Vec::with_capacity(block.len());
+        for Node {val, loc} in block {
+            let val = match val {
+                Ir::Loop(ref body) if is_clear(body, config.overflow) => Ir::Set {
+                    offset: 0,
+                    value: 0,
+                },
+                val => val,
+            };
+            let sum = match (out.last().map(|node| &node.val), &val) {
+                (Some(&Ir::Set {offset, value}), &Ir::Add {offset: o, amount})
+                    if o == offset => add_to_set(value, amount, config),
+                _ => None,
+            };
+            let (val, loc) = match (out.last().map(|node| &node.val), val, sum) {
+                (Some(&Ir::Set {offset, ..}), Ir::Add {..}, Some(value)) => {
+                    let set = out.pop().unwrap();
+                    (Ir::Set {offset, value}, set.loc)
+                },
+                (Some(&Ir::Set {offset, ..}), Ir::Set {offset: o, value}, _)
+                    if o == offset => {
+                    out.pop();
+                    (Ir::Set {offset, value}, loc)
+                },
+                (Some(&Ir::Add {offset, ..}), Ir::Set {offset: o, value}, _)
+                    if o == offset && !traps => {
+                    out.pop();
+                    (Ir::Set {offset, value}, loc)
+                },
+                (_, val, _) => (val, loc),
+            };
+            out.push(Node {val, loc});
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        run,
+    };
+    use super::super::test::{
+        lower,
+        nodes,
+        lp,
+    };
+    use ir::{
+        Ir,
+    };
+    use config::{
+        Config,
+        Overflow,
+    };
+
+    fn config(overflow: Overflow) -> Config {
+        Config {overflow, ..Config::default()}
+    }
+
+    fn set(value: i64) -> Ir {
+        Ir::Set {offset: 0, value}
+    }
+
+    fn add(amount: i64) -> Ir {
+        Ir::Add {offset: 0, amount}
+    }
+
+    #[test]
+    fn clear_loops() {
+        for &overflow in &[Overflow::Wrap, Overflow::Trap, Overflow::Saturate] {
+            let config = config(overflow);
+            assert_eq!(run(lower("[-]"), &config), nodes(vec![set(0)]));
+            assert_eq!(run(lower("[>-<]"), &config), lower("[>-<]"));
+            assert_eq!(run(lower("[--]"), &config), lower("[--]"));
+        }
+    }
+
+    #[test]
+    fn odd_amounts_only_wrap() {
+        let wrap = config(Overflow::Wrap);
+        assert_eq!(run(lower("[+]"), &wrap), nodes(vec![set(0)]));
+        assert_eq!(run(lower("[---]"), &wrap), nodes(vec![set(0)]));
+        assert_eq!(run(lower("[++]"), &wrap), lower("[++]"));
+        for &overflow in &[Overflow::Trap, Overflow::Saturate] {
+            let config = config(overflow);
+            assert_eq!(run(lower("[+]"), &config), lower("[+]"));
+            assert_eq!(run(lower("[---]"), &config), lower("[---]"));
+        }
+    }
+
+    #[test]
+    fn nested_loops() {
+        let config = Config::default();
+        assert_eq!(
+            run(lower("[>[-]<-]"), &config),
+            nodes(vec![lp(vec![Ir::Move(1), set(0), Ir::Move(-1), add(-1)])])
+        );
+        // The outer loop only holds a store once the inner one is cleared.
+        assert_eq!(run(lower("[[-]]"), &config), nodes(vec![lp(vec![set(0)])]));
+    }
+
+    #[test]
+    fn folding() {
+        let wrap = config(Overflow::Wrap);
+        assert_eq!(run(lower("[-]+++"), &wrap), nodes(vec![set(3)]));
+        assert_eq!(run(lower("[-]--"), &wrap), nodes(vec![set(-2)]));
+        assert_eq!(run(lower("++[-]"), &wrap), nodes(vec![set(0)]));
+        assert_eq!(run(lower("[-]+[-]"), &wrap), nodes(vec![set(0)]));
+        assert_eq!(
+            run(lower("[-]>+"), &wrap),
+            nodes(vec![set(0), Ir::Move(1), add(1)])
+        );
+        // Without wrapping, the stored value must stay in the cell.
+        let saturate = config(Overflow::Saturate);
+        assert_eq!(run(lower("[-]+"), &saturate), nodes(vec![set(1)]));
+        assert_eq!(run(lower("[-]-"), &saturate), nodes(vec![set(0), add(-1)]));
+        // Additions which may trap are kept.
+        let trap = config(Overflow::Trap);
+        assert_eq!(run(lower("++[-]"), &trap), nodes(vec![add(2), set(0)]));
+        assert_eq!(run(lower("[-]-"), &trap), nodes(vec![set(0), add(-1)]));
+    }
+
+}
@@ -0,0 +1,119 @@
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+};
+use super::{
+    transform_blocks,
+};
+
+
+/// Removes the loops and scans which never run, since the current cell is
+/// known to be zero when they are reached: right after a loop, a scan or a
+/// store of zero. Cells are not assumed to be zero at the start of the
+/// program, since the `obj` format runs on the tape of its caller.
+pub fn run(ir: Vec<Node<Ir>>, config: &Config) -> Vec<Node<Ir>> {
+    transform_blocks(ir, |block| {
+        let mut zero = false;
+        let mut done = Vec::with_capacity(block.len());
+        for node in block {
+            zero = match node.val {
+                Ir::Loop(_) | Ir::Scan(_) if zero => continue,
+                Ir::Loop(_) | Ir::Scan(_) => true,
+                Ir::Set {offset: 0, value} => config.cell_size.wrap(value) == 0,
+                Ir::Add {offset: 0, ..}
+                | Ir::Mul {dst: 0, ..}
+                | Ir::GetChar {offset: 0}
+                | Ir::Move(_) => false,
+                _ => zero,
+            };
+            done.push(node);
+        }
+        done
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        run,
+    };
+    use super::super::test::{
+        lower,
+        nodes,
+        lp,
+    };
+    use ir::{
+        Ir,
+    };
+    use config::{
+        CellSize,
+        Config,
+    };
+
+    fn set(offset: i64, value: i64) -> Ir {
+        Ir::Set {offset, value}
+    }
+
+    fn dec() -> Ir {
+        Ir::Add {offset: 0, amount: -1}
+    }
+
+    #[test]
+    fn after_loops() {
+        let config = Config::default();
+        assert_eq!(run(lower("[-][+]"), &config), lower("[-]"));
+        assert_eq!(run(lower("[-][>][<]."), &config), lower("[-]."));
+        let ops = nodes(vec![Ir::Scan(1), lp(vec![dec()]), Ir::Scan(-1)]);
+        assert_eq!(run(ops, &config), nodes(vec![Ir::Scan(1)]));
+        // The first loop may run, since the tape may not be zeroed.
+        assert_eq!(run(lower("[+]"), &config), lower("[+]"));
+        assert_eq!(run(lower("[[-][-]]"), &config), lower("[[-]]"));
+    }
+
+    #[test]
+    fn after_stores() {
+        let config = Config::default();
+        let ops = nodes(vec![set(0, 0), lp(vec![dec()])]);
+        assert_eq!(run(ops, &config), nodes(vec![set(0, 0)]));
+        let ops = nodes(vec![set(0, 1), lp(vec![dec()])]);
+        assert_eq!(run(ops.clone(), &config), ops);
+        // The store wraps around the cell size.
+        let ops = nodes(vec![set(0, 256), lp(vec![dec()])]);
+        assert_eq!(run(ops.clone(), &config), ops);
+        let bits8 = Config {cell_size: CellSize::Bits8, ..Config::default()};
+        assert_eq!(run(ops, &bits8), nodes(vec![set(0, 256)]));
+    }
+
+    #[test]
+    fn other_cells() {
+        let config = Config::default();
+        let ops = nodes(vec![
+            lp(vec![dec()]),
+            set(1, 5),
+            Ir::Add {offset: -1, amount: 1},
+            Ir::Mul {src: 0, dst: 2, factor: 3},
+            Ir::PutChar {offset: 0},
+            Ir::GetChar {offset: 1},
+            lp(vec![dec()]),
+        ]);
+        let mut kept = ops.clone();
+        kept.pop();
+        assert_eq!(run(ops, &config), kept);
+        for src in &["[-]+[-]", "[-]>[-]", "[-],[-]"] {
+            assert_eq!(run(lower(src), &config), lower(src), "{}", src);
+        }
+        let ops = nodes(vec![
+            lp(vec![dec()]),
+            Ir::Mul {src: 1, dst: 0, factor: 3},
+            lp(vec![dec()]),
+        ]);
+        assert_eq!(run(ops.clone(), &config), ops);
+    }
+
+}
@@ -0,0 +1,220 @@
+use front_end::{
+    Node,
+};
+use ir::{
+    self,
+    Ir,
+};
+use config::{
+    Config,
+};
+use std::io::{
+    Error,
+    ErrorKind,
+};
+use super::{
+    clear,
+    dead,
+    mul,
+    scan,
+    offset,
+};
+
+
+/// The function of a pass.
+pub type PassFn = fn(Vec<Node<Ir>>, &Config) -> Vec<Node<Ir>>;
+
+/// An optimization pass over the whole program. It is given the
+/// configuration the program is built with, and must keep the behavior of
+/// the program under it.
+#[derive(Clone, Copy)]
+pub struct Pass {
+    pub name: &'static str,
+    /// The lowest optimization level which enables the pass.
+    pub level: u8,
+    pub run: PassFn,
+}
+
+/// Every pass, in the order they run.
+pub const PASSES: &[Pass] = &[
+    Pass {name: "clear", level: 1, run: clear::run},
+    Pass {name: "mul", level: 2, run: mul::run},
+    Pass {name: "scan", level: 2, run: scan::run},
+    Pass {name: "dead", level: 3, run: dead::run},
+    Pass {name: "offset", level: 1, run: offset::run},
+];
+
+/// The highest optimization level.
+pub const MAX_LEVEL: u8 = 3;
+
+/// The optimization level used when none is given.
+pub const DEFAULT_LEVEL: u8 = 2;
+
+fn find(name: &str) -> Result<usize, Error> {
+    match PASSES.iter().position(|pass| pass.name == name) {
+        Some(i) => Ok(i),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown pass {}.", name)
+        )),
+    }
+}
+
+/// Runs the enabled passes in order, optionally printing the program to the
+/// standard error after some of them.
+#[derive(Clone, Debug)]
+pub struct PassManager {
+    enabled: Vec<bool>,
+    print_after: Vec<bool>,
+}
+
+impl PassManager {
+
+    /// Enables the passes of the given level, from 0, which enables none, to
+    /// `MAX_LEVEL`.
+    pub fn new(level: u8) -> Self {
+        Self {
+            enabled: PASSES.iter().map(|pass| pass.level <= level).collect(),
+            print_after: vec![false; PASSES.len()],
+        }
+    }
+
+    pub fn enable(&mut self, name: &str) -> Result<(), Error> {
+        self.enabled[find(name)?] = true;
+        Ok(())
+    }
+
+    pub fn disable(&mut self, name: &str) -> Result<(), Error> {
+        self.enabled[find(name)?] = false;
+        Ok(())
+    }
+
+    /// Prints the program after the given pass runs, or after every pass if
+    /// the name is `all`.
+    pub fn print_after(&mut self, name: &str) -> Result<(), Error> {
+        if name == "all" {
+            self.print_after = vec![true; PASSES.len()];
+        } else {
+            self.print_after[find(name)?] = true;
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, mut ir: Vec<Node<Ir>>, config: &Config) -> Vec<Node<Ir>> {
+        for (i, pass) in PASSES.iter().enumerate() {
+            if !self.enabled[i] {
+                continue;
+            }
+            ir = (pass.run)(ir, config);
+            if self.print_after[i] {
+                eprint!("; after {}\n{}", pass.name, ir::pretty(&ir));
+            }
+        }
+        ir
+    }
+
+}
+
+impl Default for PassManager {
+
+    fn default() -> Self {
+        Self::new(DEFAULT_LEVEL)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        PassManager,
+        PASSES,
+        MAX_LEVEL,
+        DEFAULT_LEVEL,
+    };
+    use super::super::test::{
+        lower,
+        nodes,
+        lp,
+    };
+    use ir::{
+        Ir,
+    };
+    use config::{
+        Config,
+    };
+    use std::io::{
+        ErrorKind,
+    };
+
+    fn enabled(manager: &PassManager) -> Vec<&'static str> {
+        PASSES.iter()
+            .zip(&manager.enabled)
+            .filter(|&(_, &on)| on)
+            .map(|(pass, _)| pass.name)
+            .collect()
+    }
+
+    #[test]
+    fn levels() {
+        assert!(enabled(&PassManager::new(0)).is_empty());
+        assert_eq!(enabled(&PassManager::new(1)), ["clear", "offset"]);
+        assert_eq!(
+            enabled(&PassManager::new(2)),
+            ["clear", "mul", "scan", "offset"]
+        );
+        assert_eq!(
+            enabled(&PassManager::new(MAX_LEVEL)),
+            ["clear", "mul", "scan", "dead", "offset"]
+        );
+        assert_eq!(
+            enabled(&PassManager::default()),
+            enabled(&PassManager::new(DEFAULT_LEVEL))
+        );
+    }
+
+    #[test]
+    fn toggling() {
+        let mut manager = PassManager::new(1);
+        manager.enable("scan").unwrap();
+        manager.disable("offset").unwrap();
+        assert_eq!(enabled(&manager), ["clear", "scan"]);
+        manager.print_after("all").unwrap();
+        assert!(manager.print_after.iter().all(|&on| on));
+        for result in [
+            manager.enable("unroll"),
+            manager.disable("all"),
+            manager.print_after(""),
+        ] {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+        let err = manager.enable("unroll").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown pass unroll.");
+    }
+
+    #[test]
+    fn running() {
+        let config = Config::default();
+        let src = "+[->+<][-][<]>.";
+        assert_eq!(PassManager::new(0).run(lower(src), &config), lower(src));
+        let mut ops = vec![
+            Ir::Add {offset: 0, amount: 1},
+            lp(vec![
+                Ir::Mul {src: 0, dst: 1, factor: 1},
+                Ir::Set {offset: 0, value: 0},
+            ]),
+            Ir::Set {offset: 0, value: 0},
+            Ir::Scan(-1),
+            Ir::PutChar {offset: 1},
+            Ir::Move(1),
+        ];
+        assert_eq!(
+            PassManager::new(2).run(lower(src), &config),
+            nodes(ops.clone())
+        );
+        // The scan never runs after the multiplication loop.
+        ops.remove(3);
+        assert_eq!(PassManager::new(3).run(lower(src), &config), nodes(ops));
+    }
+
+}
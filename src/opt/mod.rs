@@ -0,0 +1,126 @@
+pub mod clear;
+pub mod dead;
+pub mod mul;
+pub mod offset;
+pub mod scan;
+pub mod manager;
+
+pub use self::manager::{
+    Pass,
+    PassManager,
+    PASSES,
+    MAX_LEVEL,
+    DEFAULT_LEVEL,
+};
+
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+};
+use utils::{
+    HeadedList,
+};
+use std::vec::{
+    IntoIter,
+};
+
+
+#[derive(Clone, Debug)]
+struct Block {
+    ops: IntoIter<Node<Ir>>,
+    done: Vec<Node<Ir>>,
+    loc: Location,
+}
+
+/// Applies `pass` to every block of the program: the top level and each loop
+/// body. Inner loops are transformed before the block containing them.
+pub fn transform_blocks<F>(ir: Vec<Node<Ir>>, mut pass: F) -> Vec<Node<Ir>>
+where
+    F: FnMut(Vec<Node<Ir>>) -> Vec<Node<Ir>>
+{
+    let mut blocks = HeadedList::new(Block {
+        ops: ir.into_iter(),
+        done: Vec::new(),
+        loc: Location {
+            file: String::new(),
+            line: 0,
+            column: 0,
+        },
+    }, None);
+    loop {
+        let node = match blocks.val_mut().ops.next() {
+            Some(node) => node,
+            _ => match blocks.take() {
+                Some(block) => {
+                    let body = pass(block.done);
+                    blocks.val_mut().done.push(Node {
+                        val: Ir::Loop(body),
+                        loc: block.loc,
+                    });
+                    continue;
+                },
+                _ => break,
+            },
+        };
+        match node.val {
+            Ir::Loop(body) => blocks.receive(Block {
+                ops: body.into_iter(),
+                done: Vec::new(),
+                loc: node.loc,
+            }),
+            _ => blocks.val_mut().done.push(node),
+        }
+    }
+    pass(blocks.reclaim_val().done)
+}
+
+/// Runs the passes of the default optimization level over the program, for
+/// the default configuration.
+pub fn optimize(ir: Vec<Node<Ir>>) -> Vec<Node<Ir>> {
+    PassManager::default().run(ir, &Config::default())
+}
+
+/// Helpers for the tests of the passes.
+#[cfg(test)]
+mod test {
+
+    use front_end::{
+        self,
+        ByteStream,
+        Location,
+        Node,
+    };
+    use ir::{
+        self,
+        Ir,
+    };
+
+    /// Lowers the source without optimizing it.
+    pub fn lower(src: &str) -> Vec<Node<Ir>> {
+        let stream = ByteStream::from_str(String::from("test.bf"), src);
+        ir::lower(front_end::parse(stream).unwrap())
+    }
+
+    /// Gives every operation the same location, which comparisons ignore.
+    pub fn nodes(ops: Vec<Ir>) -> Vec<Node<Ir>> {
+        ops.into_iter().map(|val| Node {
+            val,
+            loc: Location {
+                file: String::from("test.bf"),
+                line: 1,
+                column: 1,
+            },
+        }).collect()
+    }
+
+    pub fn lp(ops: Vec<Ir>) -> Ir {
+        Ir::Loop(nodes(ops))
+    }
+
+}
@@ -0,0 +1,168 @@
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+    Overflow,
+};
+use super::{
+    transform_blocks,
+};
+
+
+/// Finds the additions done by a loop that only adds constants to cells and
+/// moves the pointer back to where it started. Gives the amount added to each
+/// offset, in order of first appearance.
+fn deltas(body: &[Node<Ir>]) -> Option<Vec<(i64, i64)>> {
+    let mut pos = 0i64;
+    let mut deltas: Vec<(i64, i64)> = Vec::new();
+    for node in body {
+        match node.val {
+            Ir::Add {offset, amount} => {
+                let target = pos.checked_add(offset)?;
+                match deltas.iter_mut().find(|&&mut (o, _)| o == target) {
+                    Some(delta) => delta.1 = delta.1.wrapping_add(amount),
+                    _ => deltas.push((target, amount)),
+                }
+            },
+            Ir::Move(n) => pos = pos.checked_add(n)?,
+            _ => return None,
+        }
+    }
+    if pos == 0 {Some(deltas)} else {None}
+}
+
+/// Replaces multiplication loops, such as `[->+>++<<]`, which add or
+/// subtract one to the current cell per iteration and constants to other
+/// cells. Each other cell gets the current cell times its constant, and then
+/// the current cell is cleared. Since arithmetic wraps, a loop counting up
+/// runs `-cell` times and is handled by negating the factors.
+///
+/// The result is kept inside a loop, which runs at most once, so that cells
+/// are only touched when the original loop would run. Multiplications wrap,
+/// so nothing is done unless overflow wraps. Nothing is done either when the
+/// pointer is checked, so that it is the `<` or `>` leaving the tape which
+/// fails.
+pub fn run(ir: Vec<Node<Ir>>, config: &Config) -> Vec<Node<Ir>> {
+    if config.overflow != Overflow::Wrap || config.checked {
+        return ir;
+    }
+    transform_blocks(ir, |block| {
+        block.into_iter().map(|Node {val, loc}| {
+            let body = match val {
+                Ir::Loop(body) => body,
+                val => return Node {val, loc},
+            };
+            let deltas = match deltas(&body) {
+                Some(deltas) => deltas,
+                _ => return Node {val: Ir::Loop(body), loc},
+            };
+            let sign = match deltas.iter().find(|&&(o, _)| o == 0) {
+                Some(&(_, -1)) => 1,
+                Some(&(_, 1)) => -1,
+                _ => return Node {val: Ir::Loop(body), loc},
+            };
+            let mut ops: Vec<Node<Ir>> = deltas.into_iter()
+                .filter(|&(o, amount)| o != 0 && amount != 0)
+                .map(|(dst, amount)| Node {
+                    val: Ir::Mul {
+                        src: 0,
+                        dst,
+                        factor: amount.wrapping_mul(sign),
+                    },
+                    loc: loc.clone(),
+                })
+                .collect();
+            ops.push(Node {
+                val: Ir::Set {offset: 0, value: 0},
+                loc: loc.clone(),
+            });
+            Node {val: Ir::Loop(ops), loc}
+        }).collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        run,
+    };
+    use super::super::test::{
+        lower,
+        nodes,
+        lp,
+    };
+    use ir::{
+        Ir,
+    };
+    use config::{
+        Config,
+        Overflow,
+    };
+
+    fn mul(dst: i64, factor: i64) -> Ir {
+        Ir::Mul {src: 0, dst, factor}
+    }
+
+    fn clear() -> Ir {
+        Ir::Set {offset: 0, value: 0}
+    }
+
+    #[test]
+    fn multiplications() {
+        let config = Config::default();
+        assert_eq!(
+            run(lower("[->+>++<<]"), &config),
+            nodes(vec![lp(vec![mul(1, 1), mul(2, 2), clear()])])
+        );
+        // A loop counting up runs `-cell` times.
+        assert_eq!(
+            run(lower("[<--->+]"), &config),
+            nodes(vec![lp(vec![mul(-1, 3), clear()])])
+        );
+        // Additions to the same cell are summed, and cancelled ones dropped.
+        assert_eq!(
+            run(lower("[>+<->+>+-<<]"), &config),
+            nodes(vec![lp(vec![mul(1, 2), clear()])])
+        );
+        let ops = nodes(vec![lp(vec![
+            Ir::Add {offset: 0, amount: -1},
+            Ir::Add {offset: 3, amount: 5},
+        ])]);
+        assert_eq!(run(ops, &config), nodes(vec![lp(vec![mul(3, 5), clear()])]));
+    }
+
+    #[test]
+    fn only_wrapping_unchecked() {
+        let src = "[->+<]";
+        for &overflow in &[Overflow::Trap, Overflow::Saturate] {
+            let config = Config {overflow, ..Config::default()};
+            assert_eq!(run(lower(src), &config), lower(src));
+        }
+        let checked = Config {checked: true, ..Config::default()};
+        assert_eq!(run(lower(src), &checked), lower(src));
+    }
+
+    #[test]
+    fn other_loops() {
+        let config = Config::default();
+        for src in &["[->+]", "[-->+<]", "[>+<]", "[->.<]", "[->,<]", "[>]"] {
+            assert_eq!(run(lower(src), &config), lower(src), "{}", src);
+        }
+        // The inner loop is replaced, but not the outer one holding it.
+        assert_eq!(
+            run(lower("[->[->+<]<]"), &config),
+            nodes(vec![lp(vec![
+                Ir::Add {offset: 0, amount: -1},
+                Ir::Move(1),
+                lp(vec![mul(1, 1), clear()]),
+                Ir::Move(-1),
+            ])])
+        );
+    }
+
+}
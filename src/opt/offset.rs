@@ -0,0 +1,151 @@
+use front_end::{
+    Node,
+    Location,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+};
+use super::{
+    transform_blocks,
+};
+
+
+/// Emits the pending pointer movement, if any.
+fn flush(out: &mut Vec<Node<Ir>>, pos: &mut i64, loc: &Option<Location>) {
+    if let Some(ref loc) = *loc {
+        if *pos != 0 {
+            out.push(Node {
+                val: Ir::Move(*pos),
+                loc: loc.clone(),
+            });
+        }
+    }
+    *pos = 0;
+}
+
+/// Folds pointer movements into the offsets of the operations between loop
+/// boundaries, so that each straight-line sequence, like `>+>++<<-`, moves
+/// the pointer at most once, at its end. Nothing is done when the pointer is
+/// checked, so that it is the `<` or `>` leaving the tape which fails.
+pub fn run(ir: Vec<Node<Ir>>, config: &Config) -> Vec<Node<Ir>> {
+    if config.checked {
+        return ir;
+    }
+    transform_blocks(ir, |block| {
+        let mut out = Vec::with_capacity(block.len());
+        let mut pos = 0;
+        let mut last = None;
+        for Node {val, loc} in block {
+            let val = match val {
+                Ir::Add {offset, amount} => Ir::Add {offset: offset + pos, amount},
+                Ir::Set {offset, value} => Ir::Set {offset: offset + pos, value},
+                Ir::Mul {src, dst, factor} => Ir::Mul {
+                    src: src + pos,
+                    dst: dst + pos,
+                    factor,
+                },
+                Ir::PutChar {offset} => Ir::PutChar {offset: offset + pos},
+                Ir::GetChar {offset} => Ir::GetChar {offset: offset + pos},
+                Ir::Move(n) => {
+                    pos += n;
+                    last = Some(loc);
+                    continue;
+                },
+                val => {
+                    flush(&mut out, &mut pos, &last);
+                    val
+                },
+            };
+            out.push(Node {val, loc});
+        }
+        flush(&mut out, &mut pos, &last);
+        out
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        run,
+    };
+    use super::super::test::{
+        lower,
+        nodes,
+        lp,
+    };
+    use ir::{
+        Ir,
+    };
+    use config::{
+        Config,
+    };
+
+    fn add(offset: i64, amount: i64) -> Ir {
+        Ir::Add {offset, amount}
+    }
+
+    #[test]
+    fn folding() {
+        let config = Config::default();
+        assert_eq!(
+            run(lower(">+>++<<-"), &config),
+            nodes(vec![add(1, 1), add(2, 2), add(0, -1)])
+        );
+        assert_eq!(
+            run(lower(">.<<,>>>"), &config),
+            nodes(vec![
+                Ir::PutChar {offset: 1},
+                Ir::GetChar {offset: -1},
+                Ir::Move(2),
+            ])
+        );
+        assert_eq!(run(lower(">><<"), &config), nodes(vec![]));
+        let ops = nodes(vec![
+            Ir::Move(2),
+            Ir::Set {offset: 1, value: 4},
+            Ir::Mul {src: 0, dst: -1, factor: 3},
+        ]);
+        assert_eq!(run(ops, &config), nodes(vec![
+            Ir::Set {offset: 3, value: 4},
+            Ir::Mul {src: 2, dst: 1, factor: 3},
+            Ir::Move(2),
+        ]));
+    }
+
+    #[test]
+    fn loop_boundaries() {
+        let config = Config::default();
+        assert_eq!(
+            run(lower(">+[>-<]<+"), &config),
+            nodes(vec![
+                add(1, 1),
+                Ir::Move(1),
+                lp(vec![add(1, -1)]),
+                add(-1, 1),
+                Ir::Move(-1),
+            ])
+        );
+        let ops = nodes(vec![Ir::Move(3), Ir::Scan(1), Ir::Move(-1)]);
+        assert_eq!(run(ops, &config), nodes(vec![
+            Ir::Move(3),
+            Ir::Scan(1),
+            Ir::Move(-1),
+        ]));
+        // The movement takes the location of the last `<` or `>`.
+        let moved = run(lower(">+\n>"), &config).remove(1);
+        assert_eq!((moved.loc.line, moved.loc.column), (2, 1));
+    }
+
+    #[test]
+    fn disabled_when_checked() {
+        let config = Config {checked: true, ..Config::default()};
+        for src in &[">+>++<<-", ">+[>-<]<+", ">><<"] {
+            assert_eq!(run(lower(src), &config), lower(src), "{}", src);
+        }
+    }
+
+}
@@ -0,0 +1,76 @@
+use front_end::{
+    Node,
+};
+use ir::{
+    Ir,
+};
+use config::{
+    Config,
+};
+use super::{
+    transform_blocks,
+};
+
+
+/// Replaces scan loops, such as `[>]` and `[<<]`, which only move the
+/// pointer until they find a zero cell. The scan takes the location of the
+/// movement, which is where a checked pointer leaves the tape.
+pub fn run(ir: Vec<Node<Ir>>, _config: &Config) -> Vec<Node<Ir>> {
+    transform_blocks(ir, |block| {
+        block.into_iter().map(|Node {val, loc}| match val {
+            Ir::Loop(mut body) => match body[..] {
+                [Node {val: Ir::Move(n), ..}] if n != 0 => Node {
+                    val: Ir::Scan(n),
+                    loc: body.remove(0).loc,
+                },
+                _ => Node {val: Ir::Loop(body), loc},
+            },
+            val => Node {val, loc},
+        }).collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        run,
+    };
+    use super::super::test::{
+        lower,
+        nodes,
+        lp,
+    };
+    use ir::{
+        Ir,
+    };
+    use config::{
+        Config,
+    };
+
+    #[test]
+    fn scans() {
+        let config = Config::default();
+        assert_eq!(run(lower("[>]"), &config), nodes(vec![Ir::Scan(1)]));
+        assert_eq!(run(lower("[<<]"), &config), nodes(vec![Ir::Scan(-2)]));
+        assert_eq!(
+            run(lower("+[[>]<]"), &config),
+            nodes(vec![
+                Ir::Add {offset: 0, amount: 1},
+                lp(vec![Ir::Scan(1), Ir::Move(-1)]),
+            ])
+        );
+        // The scan is where the movement is.
+        let scan = run(lower("+\n [>>>]"), &config).remove(1);
+        assert_eq!((scan.loc.line, scan.loc.column), (2, 3));
+    }
+
+    #[test]
+    fn other_loops() {
+        let config = Config::default();
+        for src in &["[>+]", "[]", "[-]", "[><]"] {
+            assert_eq!(run(lower(src), &config), lower(src), "{}", src);
+        }
+    }
+
+}
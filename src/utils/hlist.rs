@@ -19,10 +19,7 @@ impl<T> HeadedList<T> {
     pub fn new(val: T, next: Option<HeadedList<T>>) -> Self {
         HeadedList {
             val,
-            next: match next {
-                Some(x) => Some(Box::new(x)),
-                _ => None,
-            }
+            next: next.map(Box::new),
         }
     }
 
@@ -39,8 +36,8 @@ impl<T> HeadedList<T> {
     }
 
     pub fn next(&self) -> Option<&HeadedList<T>> {
-        match &self.next {
-            &Some(ref x) => Some(x.deref()),
+        match self.next {
+            Some(ref x) => Some(x.deref()),
             _ => None,
         }
     }
@@ -53,13 +50,7 @@ impl<T> HeadedList<T> {
     }
 
     pub fn put_next(&mut self, next: Option<HeadedList<T>>) -> Option<HeadedList<T>> {
-        match mem::replace(&mut self.next, match next {
-            Some(x) => Some(Box::new(x)),
-            _ => None,
-        }) {
-            Some(x) => Some(*x),
-            _ => None,
-        }
+        mem::replace(&mut self.next, next.map(Box::new)).map(|x| *x)
     }
 
     pub fn take(&mut self) -> Option<T> {
@@ -67,7 +58,7 @@ impl<T> HeadedList<T> {
             Some(node) => {
                 let real_node = *node;
                 let HeadedList {val, next} = real_node;
-                mem::replace(&mut self.next, next);
+                self.next = next;
                 Some(mem::replace(&mut self.val, val))
             },
             _ => None,